@@ -1 +1,163 @@
+use std::{fs, path::PathBuf};
 
+use semver::VersionReq;
+use toml_edit::{value, Document, Item};
+
+use crate::{
+    manifest::Manifest,
+    status::{Kind, Status},
+    MANIFEST,
+};
+
+/// The version requirement used when none is specified.
+pub const ANY_VERSION: &str = "*";
+
+/// Adds a dependency to the manifest of the package at `path`,
+/// or updates its version if it is already a dependency.
+pub fn add(path: PathBuf, name: String, version: Option<String>) -> Result<(), String> {
+    let path = Manifest::find(&path)?;
+    let manifest = path.join(MANIFEST);
+    let source =
+        fs::read_to_string(&manifest).map_err(|_| "The manifest file could not be read")?;
+
+    let version = version.unwrap_or_else(|| ANY_VERSION.to_string());
+    let (updated, previous) = add_dependency(&source, &name, &version)?;
+    fs::write(&manifest, updated).map_err(|_| "Could not write manifest file")?;
+
+    match previous {
+        Some(previous) if previous == version => Status::info().log(&format!(
+            "The dependency '{}' is already at version '{}'",
+            name, version
+        )),
+        Some(previous) => Status(Kind::Success, "Updated").log(&format!(
+            "The dependency '{}' was changed from version '{}' to '{}'",
+            name, previous, version
+        )),
+        None => Status(Kind::Success, "Added").log(&format!(
+            "The dependency '{}' was added at version '{}'",
+            name, version
+        )),
+    }
+
+    Ok(())
+}
+
+/// Adds or updates a dependency in the source of a manifest.
+/// Returns the new source of the manifest,
+/// and the previous version of the dependency if it was already present.
+/// The manifest is edited in place, so comments, key order,
+/// and formatting are left as they were.
+pub fn add_dependency(
+    source: &str,
+    name: &str,
+    version: &str,
+) -> Result<(String, Option<String>), String> {
+    VersionReq::parse(version)
+        .map_err(|e| format!("The version '{}' is not valid semver: {}", version, e))?;
+
+    Manifest::check_dependency_name(name)?;
+
+    // make sure this is actually a manifest before changing anything
+    Manifest::parse(source).ok_or("Could not parse the manifest file")?;
+    let mut manifest = source
+        .parse::<Document>()
+        .map_err(|_| "Could not parse the manifest file")?;
+
+    let dependencies = manifest
+        .get_mut("dependencies")
+        .and_then(Item::as_table_mut)
+        .ok_or("The manifest file does not have a dependencies table")?;
+
+    let previous = match dependencies.get_mut(name) {
+        // keep any comment trailing the old version
+        Some(Item::Value(old)) => {
+            let previous = match old.as_str() {
+                Some(previous) => previous.to_string(),
+                None => old.to_string().trim().to_string(),
+            };
+            let decor = old.decor().clone();
+            *old = version.into();
+            *old.decor_mut() = decor;
+            Some(previous)
+        }
+        _ => dependencies
+            .insert(name, value(version))
+            .map(|previous| previous.to_string().trim().to_string()),
+    };
+
+    Ok((manifest.to_string(), previous))
+}
+
+#[cfg(test)]
+mod test {
+    use toml::Value;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"[package]
+name = "example"
+version = "0.1.0"
+authors = ["A. Passerine"]
+license = "MIT"
+
+[dependencies]
+core = "1.0.0"
+"#;
+
+    fn dependencies(source: &str) -> Value {
+        source.parse::<Value>().unwrap()["dependencies"].clone()
+    }
+
+    #[test]
+    fn add_new() {
+        let (updated, previous) = add_dependency(MANIFEST, "list", "^0.2").unwrap();
+        assert_eq!(previous, None);
+
+        let dependencies = dependencies(&updated);
+        assert_eq!(dependencies["list"].as_str(), Some("^0.2"));
+        assert_eq!(dependencies["core"].as_str(), Some("1.0.0"));
+
+        // other fields survive the rewrite
+        let package = &updated.parse::<Value>().unwrap()["package"];
+        assert_eq!(package["license"].as_str(), Some("MIT"));
+    }
+
+    #[test]
+    fn update_existing() {
+        let (updated, previous) = add_dependency(MANIFEST, "core", "1.2.0").unwrap();
+        assert_eq!(previous, Some("1.0.0".to_string()));
+        assert_eq!(dependencies(&updated)["core"].as_str(), Some("1.2.0"));
+    }
+
+    #[test]
+    fn reject_bad_input() {
+        assert!(add_dependency(MANIFEST, "core", "one point oh").is_err());
+        assert!(add_dependency(MANIFEST, "", "1.0.0").is_err());
+        assert!(add_dependency(MANIFEST, "../core", "1.0.0").is_err());
+        assert!(add_dependency("[package\nname = ", "core", "1.0.0").is_err());
+        assert!(add_dependency("[dependencies]\n", "core", "1.0.0").is_err());
+    }
+
+    #[test]
+    fn preserves_formatting() {
+        let source = r#"# the example package
+[package]
+name    = "example"
+version = "0.1.0"
+authors = ["A. Passerine"]
+
+[dependencies]
+zebra = "2.0"  # pinned for now
+core  = "1.0.0"
+"#;
+
+        let (updated, _) = add_dependency(source, "zebra", "2.1").unwrap();
+        assert_eq!(
+            updated,
+            source.replace(r#"zebra = "2.0""#, r#"zebra = "2.1""#)
+        );
+
+        let (updated, _) = add_dependency(source, "list", "0.2").unwrap();
+        assert_eq!(updated, format!("{}list = \"0.2\"\n", source));
+    }
+}
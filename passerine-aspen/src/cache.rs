@@ -0,0 +1,171 @@
+use std::{fs, path::Path, rc::Rc};
+
+use passerine::{
+    common::{lambda::Lambda, stamp::Stamp},
+    compile_source, Closure, Source, VERSION,
+};
+
+use crate::update::{fnv1a, FNV_OFFSET};
+
+/// Compiles some source, reusing bytecode cached in a directory if possible.
+/// Entries are keyed by a hash of the source,
+/// and record the length and a second hash of the source,
+/// so that sources whose keys collide never share an entry.
+/// Entries also record the version of Passerine that compiled them;
+/// entries compiled by another version are ignored and replaced.
+/// Entries also record the stamp of the bytecode they hold,
+/// so an entry that was changed after it was written is ignored too.
+/// Cached bytecode is verified before it is used.
+/// Returns the compiled closure, and whether it was found in the cache.
+pub fn compile_cached(source: Rc<Source>, cache: &Path) -> Result<(Closure, bool), String> {
+    let entry = cache.join(format!(
+        "{:016x}.bin",
+        fnv1a(FNV_OFFSET, source.contents.bytes())
+    ));
+    let check = check(&source);
+
+    if let Some(lambda) = load(&entry, &source, &check) {
+        return Ok((Closure::wrap(Rc::new(lambda)), true));
+    }
+
+    let lambda = compile_source(Rc::clone(&source)).map_err(|e| e.to_string())?;
+    // a run should not fail just because its bytecode could not be cached
+    if let Ok(bytes) = lambda.to_bytes(&source) {
+        let mut contents = format!("{}\n{}\n{}\n", VERSION, check, Stamp::of(&lambda)).into_bytes();
+        contents.extend(bytes);
+        let _ = fs::create_dir_all(cache).and_then(|_| fs::write(&entry, contents));
+    }
+
+    Ok((Closure::wrap(lambda), false))
+}
+
+/// Identifies a source more precisely than the key of its entry,
+/// by its length and a hash of its bytes in reverse.
+fn check(source: &Source) -> String {
+    format!(
+        "{} {:016x}",
+        source.contents.len(),
+        fnv1a(FNV_OFFSET, source.contents.bytes().rev())
+    )
+}
+
+/// Loads a cache entry, if it exists,
+/// was compiled from the same source by this version of Passerine,
+/// matches its stamp, and is valid bytecode, which decoding checks.
+fn load(entry: &Path, source: &Rc<Source>, check: &str) -> Option<Lambda> {
+    let contents = fs::read(entry).ok()?;
+    let rest = contents.strip_prefix(format!("{}\n{}\n", VERSION, check).as_bytes())?;
+    let newline = rest.iter().position(|b| *b == b'\n')?;
+    let (stamp, bytes) = (&rest[..newline], &rest[newline + 1..]);
+    let lambda = Lambda::from_bytes(bytes, source).ok()?;
+    if stamp != Stamp::of(&lambda).to_string().as_bytes() {
+        return None;
+    }
+    Some(lambda)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use passerine::{Data, Fiber};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// An empty cache, removed when the returned directory is dropped.
+    fn cache() -> TempDir {
+        tempfile::tempdir().unwrap()
+    }
+
+    /// The only entry in a cache.
+    fn entry(cache: &Path) -> PathBuf {
+        let mut entries = fs::read_dir(cache).unwrap();
+        let entry = entries.next().unwrap().unwrap().path();
+        assert!(entries.next().is_none());
+        entry
+    }
+
+    const SOURCE: &str = "double = x -> x + x\ndouble (double 2)";
+
+    fn run(closure: Closure) -> Data {
+        let mut fiber = Fiber::init(closure);
+        fiber.run().unwrap();
+        fiber.stack.pop_data()
+    }
+
+    #[test]
+    fn cold_then_warm() {
+        let cache = cache();
+        let cache = cache.path();
+        let source = Source::source(SOURCE);
+        let (cold, hit) = compile_cached(Rc::clone(&source), cache).unwrap();
+        assert!(!hit);
+        let (warm, hit) = compile_cached(Rc::clone(&source), cache).unwrap();
+        assert!(hit);
+
+        assert_eq!(warm.lambda.to_bytes(&source), cold.lambda.to_bytes(&source));
+        assert_eq!(run(warm), Data::Integer(8));
+        assert_eq!(run(cold), Data::Integer(8));
+
+        // a different source misses
+        let (_, hit) = compile_cached(Source::source("x = 3; x"), cache).unwrap();
+        assert!(!hit);
+    }
+
+    #[test]
+    fn stale_entry() {
+        let cache = cache();
+        let cache = cache.path();
+        compile_cached(Source::source(SOURCE), cache).unwrap();
+        let entry = entry(cache);
+
+        // compiled by another version of Passerine
+        let contents = fs::read(&entry).unwrap();
+        let bytes = &contents[VERSION.len()..];
+        fs::write(&entry, [b"0.0.0".as_slice(), bytes].concat()).unwrap();
+        let (_, hit) = compile_cached(Source::source(SOURCE), cache).unwrap();
+        assert!(!hit);
+
+        // the entry was replaced
+        let (_, hit) = compile_cached(Source::source(SOURCE), cache).unwrap();
+        assert!(hit);
+
+        // entries that do not match their stamp are ignored
+        let mut contents = fs::read(&entry).unwrap();
+        let check = check(&Source::source(SOURCE));
+        let start = VERSION.len() + check.len() + 2;
+        let stamp = start..start + 16;
+        assert!(contents[stamp.clone()].iter().all(u8::is_ascii_hexdigit));
+        contents[stamp].copy_from_slice(b"0000000000000000");
+        fs::write(&entry, contents).unwrap();
+        let (_, hit) = compile_cached(Source::source(SOURCE), cache).unwrap();
+        assert!(!hit);
+
+        // corrupt entries are ignored
+        fs::write(&entry, format!("{}\ngarbage", VERSION)).unwrap();
+        let (_, hit) = compile_cached(Source::source(SOURCE), cache).unwrap();
+        assert!(!hit);
+    }
+
+    #[test]
+    fn colliding_key() {
+        let cache = cache();
+        let cache = cache.path();
+        compile_cached(Source::source("x = 3; x"), cache).unwrap();
+        let other = entry(cache);
+
+        // pretend the entry of another source has the same key as this one
+        compile_cached(Source::source(SOURCE), cache).unwrap();
+        let entry = fs::read_dir(cache)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|entry| *entry != other)
+            .unwrap();
+        fs::rename(&other, &entry).unwrap();
+
+        let (closure, hit) = compile_cached(Source::source(SOURCE), cache).unwrap();
+        assert!(!hit);
+        assert_eq!(run(closure), Data::Integer(8));
+    }
+}
@@ -2,6 +2,8 @@ use std::{env::current_dir, ffi::OsStr, path::PathBuf};
 
 use structopt::StructOpt;
 
+use crate::emit::Emit;
+
 pub fn package_dir(path: &OsStr) -> PathBuf {
     return if path == "." {
         current_dir().expect("Can not determine package directory")
@@ -16,18 +18,50 @@ pub struct Package {
     pub path: PathBuf,
 }
 
+#[derive(StructOpt, Debug)]
+pub struct Run {
+    #[structopt(default_value = ".", parse(from_os_str = package_dir))]
+    pub path: PathBuf,
+    /// Prints a representation of the program instead of running it:
+    /// one of tokens, tree, ast, cst, sst, or bytecode
+    #[structopt(long)]
+    pub emit: Option<Emit>,
+    /// Prints the result of the program once it finishes,
+    /// unless it ends in an assignment
+    #[structopt(long)]
+    pub print_result: bool,
+    /// Arguments passed on to the program, after a `--`
+    #[structopt(last = true)]
+    pub args: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct Dependency {
+    /// The name of the dependency
+    pub name: String,
+    /// The version of the dependency, as a semver requirement
+    #[structopt(long)]
+    pub version: Option<String>,
+    #[structopt(long, default_value = ".", parse(from_os_str = package_dir))]
+    pub path: PathBuf,
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(name = "Aspen", bin_name = "aspen", about)]
 pub enum Aspen {
     /// Creates a new Passerine package
     New(Package),
-    // Update,
+    /// Adds a dependency to the package manifest
+    Add(Dependency),
+    /// Resolves the package's dependencies and writes the lockfile
+    Update(Package),
     // Publish,
     /// Runs the specified package
-    Run(Package),
+    Run(Run),
     Repl,
     // Test,
     // Bench,
-    // Doc,
+    /// Lists the core functions and their signatures
+    Doc,
     // Debug,
 }
@@ -1 +1,49 @@
+use passerine::{ffi_core, FFI};
 
+/// Lists the core functions, along with their signatures.
+pub fn doc() -> Result<(), String> {
+    print!("{}", signatures(&ffi_core()));
+    Ok(())
+}
+
+/// Shows each function in an FFI on its own line, sorted by name,
+/// i.e. `gcd : (integer, integer) -> integer`.
+/// Functions that do not declare a signature are shown by name alone.
+pub fn signatures(ffi: &FFI) -> String {
+    let mut shown = String::new();
+    for name in ffi.names() {
+        let function = ffi
+            .get(name)
+            .expect("Each name is that of a function in the FFI");
+        match function.signature() {
+            Some(signature) => shown.push_str(&format!("{} : {}\n", name, signature)),
+            None => shown.push_str(&format!("{}\n", name)),
+        }
+    }
+    shown
+}
+
+#[cfg(test)]
+mod test {
+    use passerine::{Data, FFIFunction, Type};
+
+    use super::*;
+
+    #[test]
+    fn core_signatures() {
+        let shown = signatures(&ffi_core());
+        assert!(shown.contains("add : (number, number) -> number\n"));
+        assert!(shown.contains("slice : (list, integer, integer) -> list\n"));
+        assert!(shown.lines().all(|line| line.contains(" : ")));
+    }
+
+    #[test]
+    fn unsigned() {
+        let mut ffi = FFI::new();
+        let identity = || FFIFunction::new(Box::new(|data: Data| Ok(data)));
+        ffi.add("b", identity().with_signature(vec![Type::Any], Type::Any))
+            .unwrap();
+        ffi.add("a", identity()).unwrap();
+        assert_eq!(signatures(&ffi), "a\nb : any -> any\n");
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fmt, rc::Rc, str::FromStr};
+
+use passerine::{compiler, Source};
+
+/// An intermediate representation of a program,
+/// which can be printed instead of running it, e.g. `aspen run --emit sst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Tree,
+    Ast,
+    Cst,
+    Sst,
+    Bytecode,
+}
+
+impl Emit {
+    /// Every representation, in the order the compiler produces them,
+    /// with how each is named on the command line.
+    const NAMES: &'static [(&'static str, Emit)] = &[
+        ("tokens", Emit::Tokens),
+        ("tree", Emit::Tree),
+        ("ast", Emit::Ast),
+        ("cst", Emit::Cst),
+        ("sst", Emit::Sst),
+        ("bytecode", Emit::Bytecode),
+    ];
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Emit, String> {
+        match Emit::NAMES.iter().find(|(n, _)| *n == name) {
+            Some((_, emit)) => Ok(*emit),
+            None => Err(format!(
+                "Unknown representation '{}', expected one of: {}",
+                name,
+                Emit::NAMES
+                    .iter()
+                    .map(|(n, _)| *n)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Emit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Emit::NAMES.iter().find(|(_, e)| e == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+/// Compiles a source up to some representation, and formats it.
+/// Tokens are listed one per line, trees are pretty-printed,
+/// and bytecode is disassembled.
+pub fn emit(emit: Emit, source: Rc<Source>) -> Result<String, String> {
+    let emitted = match emit {
+        Emit::Tokens => compiler::lex(source)
+            .map(|tokens| {
+                tokens
+                    .item
+                    .iter()
+                    .map(|token| format!("{:?}\n", token))
+                    .collect()
+            })
+            .map_err(|e| e.to_string())?,
+        Emit::Tree => format!("{:#?}", compiler::read(source).map_err(|e| e.to_string())?),
+        Emit::Ast => format!(
+            "{:#?}",
+            compiler::parse(source).map_err(|e| e.to_string())?.0
+        ),
+        Emit::Cst => format!(
+            "{:#?}",
+            compiler::desugar(source).map_err(|e| e.to_string())?.0
+        ),
+        Emit::Sst => format!(
+            "{:#?}",
+            compiler::hoist(source).map_err(|e| e.to_string())?.0
+        ),
+        Emit::Bytecode => format!("{}", compiler::gen(source).map_err(|e| e.to_string())?),
+    };
+    Ok(emitted)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "x = (1, 2)\nx";
+
+    #[test]
+    fn names() {
+        for (name, emit) in Emit::NAMES {
+            assert_eq!(name.parse(), Ok(*emit));
+            assert_eq!(emit.to_string(), *name);
+        }
+
+        let error = "ir".parse::<Emit>().unwrap_err();
+        assert!(error.contains("tokens, tree, ast, cst, sst, bytecode"));
+    }
+
+    #[test]
+    fn each_stage() {
+        let emitted = |stage| emit(stage, Source::source(SOURCE)).unwrap();
+
+        let tokens = emitted(Emit::Tokens);
+        assert!(tokens.lines().next().unwrap().contains("Iden(\"x\")"));
+        assert!(emitted(Emit::Tree).contains("Form("));
+        assert!(emitted(Emit::Ast).contains("Group("));
+        let cst = emitted(Emit::Cst);
+        assert!(cst.contains("Tuple(") && !cst.contains("Group("));
+        assert!(emitted(Emit::Sst).contains("UniqueSymbol("));
+        assert!(emitted(Emit::Bytecode).contains("Return"));
+    }
+
+    #[test]
+    fn syntax_error() {
+        let error = emit(Emit::Sst, Source::source("x = (1, 2")).unwrap_err();
+        assert!(error.contains("Syntax Error"));
+    }
+}
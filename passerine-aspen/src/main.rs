@@ -1,7 +1,9 @@
 use structopt::StructOpt;
 
 // argument parser and configuation
+pub mod cache;
 pub mod cli;
+pub mod emit;
 pub mod manifest;
 pub mod status;
 
@@ -23,14 +25,27 @@ use crate::{cli::Aspen, status::Status};
 pub const MANIFEST: &str = "aspen.toml";
 pub const SOURCE: &str = "src";
 pub const ENTRYPOINT: &str = "main.pn";
+pub const LOCKFILE: &str = "aspen.lock";
+/// The directory compiled bytecode is cached in, relative to the package.
+pub const CACHE: &str = ".aspen-cache";
+/// Overrides the directory dependencies are resolved from.
+pub const REGISTRY_VAR: &str = "ASPEN_REGISTRY";
 
 fn main() {
     let subcommand = Aspen::from_args();
 
     let result = match subcommand {
         Aspen::New(package) => new::new(package.path),
-        Aspen::Run(package) => run::run(package.path),
+        Aspen::Add(dependency) => add::add(dependency.path, dependency.name, dependency.version),
+        Aspen::Update(package) => update::update(package.path),
+        Aspen::Run(options) => run::run(
+            options.path,
+            options.emit,
+            options.print_result,
+            options.args,
+        ),
         Aspen::Repl => repl::repl(),
+        Aspen::Doc => doc::doc(),
         _ => unimplemented!(),
     };
 
@@ -1,4 +1,8 @@
-use std::{fs::File, io::Read, path::Path};
+use std::{
+    fs::File,
+    io::Read,
+    path::{self, Path},
+};
 
 use semver::Version;
 use serde::{Deserialize, Serialize};
@@ -42,26 +46,23 @@ impl Manifest {
         }
     }
 
-    pub fn package(mut path: &Path) -> Result<(Manifest, &Path), String> {
-        let mut source = String::new();
-        let mut file = None;
-
-        // search up path for manifest
-        while let None = file {
-            match File::open(path.join(MANIFEST)) {
-                Err(_) => {
-                    path = path
-                        .parent()
-                        .ok_or("The manifest file could not be found")?;
-                }
-                Ok(f) => {
-                    file = Some(f);
-                }
-            };
+    /// Searches up from a path for the directory containing the manifest.
+    pub fn find(mut path: &Path) -> Result<&Path, String> {
+        while !path.join(MANIFEST).is_file() {
+            path = path
+                .parent()
+                .ok_or("The manifest file could not be found")?;
         }
 
-        file.unwrap()
-            .read_to_string(&mut source)
+        Ok(path)
+    }
+
+    pub fn package(path: &Path) -> Result<(Manifest, &Path), String> {
+        let path = Manifest::find(path)?;
+        let mut source = String::new();
+
+        File::open(path.join(MANIFEST))
+            .and_then(|mut file| file.read_to_string(&mut source))
             .map_err(|_| "The manifest file could not be read")?;
 
         return Ok((
@@ -70,6 +71,28 @@ impl Manifest {
         ));
     }
 
+    /// The dependencies of the package, mapping names to version requirements.
+    pub fn dependencies(&self) -> &Map<String, toml::Value> {
+        &self.dependencies
+    }
+
+    /// Makes sure a dependency name can't escape the registry
+    /// when it's joined onto a path.
+    pub fn check_dependency_name(name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("The name of the dependency can not be empty".to_string());
+        }
+
+        if name.contains(path::is_separator) || name.contains("..") {
+            return Err(format!(
+                "The dependency name '{}' can not contain path separators or '..'",
+                name
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn parse(source: &str) -> Option<Manifest> {
         // TODO: error handling
         toml::from_str(source).ok()
@@ -1,60 +1,182 @@
-// TODO: preserve values using module system.
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
 
-// use std::io::{self, Read};
-// use std::io::Write;
-//
-// use passerine::{
-//     common::{closure::Closure, source::Source, lambda::Lambda},
-//     compiler::{lex, parse, desugar, gen, hoist, syntax::Syntax},
-//     vm::vm::VM,
-// };
+use passerine::{
+    construct::symbol::SharedSymbol, ffi_core, Closure, Compiler, Desugarer, Fiber, Hoister, Lexer,
+    Parser, Reader, Source,
+};
 
 pub fn repl() -> Result<(), String> {
-    // println!("Repl is WIP.");
-    Err("Interactive repl is WIP".into())
-
-    // println!("Hit ^C to quit.\n");
-
-    // loop {
-    //     let mut to_eval = String::new();
-    //
-    //     let lambda: Result<Lambda, Syntax> = 'read: loop {
-    //         print!("| ");
-    //         std::io::stdout().flush().unwrap();
-    //
-    //         let mut exit = false;
-    //         match io::stdin().read_line(&mut to_eval) {
-    //             Ok(l) if l <= 1 => { exit = true; },
-    //             _ => ()
-    //         }
-    //
-    //         let source = Source::source(&to_eval);
-    //         let compiled = lex(source)
-    //             .and_then(parse)
-    //             .and_then(desugar)
-    //             .and_then(hoist)
-    //             .and_then(gen);
-    //
-    //         match compiled {
-    //             Ok(lambda) => { break 'read Ok(lambda); },
-    //             Err(e)     => if exit { break 'read Err(e); },
-    //         };
-    //     };
-    //
-    //     let lambda = match lambda {
-    //         Ok(l)  => l,
-    //         Err(e) => { println!("\n{}\n", e); continue; },
-    //     };
-    //
-    //     let mut vm = VM::init(Closure::wrap(lambda));
-    //     match vm.run() {
-    //         Ok(()) => {
-    //             let data = vm.stack.pop_data();
-    //             println!("= {}\n", data)
-    //         },
-    //         Err(e) => {
-    //             println!("\n{}\n", e);
-    //         },
-    //     }
-    // }
+    println!("Hit ^D to quit.\n");
+    let mut lines = io::stdin().lock().lines();
+    let mut session = Session::new();
+
+    loop {
+        print!("| ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None => return Ok(()),
+        };
+
+        match session.eval(&line) {
+            Ok(Some(shown)) => println!("{}\n", shown),
+            Ok(None) => println!(),
+            Err(error) => println!("\n{}\n", error),
+        }
+    }
+}
+
+/// What is kept from one line entered in the REPL to the next,
+/// so that each line can use the variables assigned by the lines before it.
+pub struct Session {
+    symbols: HashMap<String, SharedSymbol>,
+    hoister: Hoister,
+    /// How many variables the lines so far have declared.
+    declared: usize,
+    /// Runs each line where the last one left off,
+    /// once the first line has been run.
+    fiber: Option<Fiber>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            symbols: HashMap::new(),
+            hoister: Hoister::session(),
+            declared: 0,
+            fiber: None,
+        }
+    }
+
+    /// Compiles and runs a line of input,
+    /// returning the result to show, if any.
+    /// Assignments evaluate to `()`, which is not shown,
+    /// but an explicit `()` still is.
+    /// A line that raises an error leaves the session as it was,
+    /// except for any variables it assigned before the error.
+    pub fn eval(&mut self, line: &str) -> Result<Option<String>, String> {
+        let tokens = Lexer::lex_recover(Source::source(line)).map_err(|e| e.to_string())?;
+        let token_tree = Reader::read(tokens).map_err(|e| e.to_string())?;
+        let (ast, symbols) =
+            Parser::parse_with(token_tree, self.symbols.clone()).map_err(|e| e.to_string())?;
+        let (cst, mut symbols) = Desugarer::desugar(ast, symbols).map_err(|e| e.to_string())?;
+
+        let mut hoister = self.hoister.clone();
+        let (sst, scope) = hoister
+            .hoist_next(cst, &mut symbols, &ffi_core())
+            .map_err(|e| e.to_string())?;
+        let assignment = sst.item.is_assignment();
+        let declared = scope.locals.len();
+        let lambda =
+            Compiler::compile_continuing(sst, scope, ffi_core()).map_err(|e| e.to_string())?;
+
+        let closure = Closure::wrap(lambda);
+        let (fiber, ran) = match &mut self.fiber {
+            Some(fiber) => {
+                let ran = fiber.continue_with(closure);
+                (fiber, ran)
+            }
+            None => {
+                let fiber = self.fiber.insert(Fiber::init(closure));
+                let ran = fiber.run();
+                (fiber, ran)
+            }
+        };
+        if let Err(trace) = ran {
+            // forget the variables declared by this line, which it may not have assigned
+            fiber.stack.stack.truncate(1 + self.declared);
+            return Err(trace.to_string());
+        }
+        self.symbols = symbols;
+        self.hoister = hoister;
+        self.declared = declared;
+
+        // the result is left on the stack, for the next line to drop
+        let result = fiber.stack.stack.last().unwrap().copy().data();
+        Ok((!assignment).then(|| result.to_string()))
+    }
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(line: &str) -> Result<Option<String>, String> {
+        Session::new().eval(line)
+    }
+
+    #[test]
+    fn unit_results() {
+        assert_eq!(eval("x = 3"), Ok(None));
+        assert_eq!(eval("()"), Ok(Some("()".to_string())));
+        assert_eq!(eval("x = 3; x"), Ok(Some("3".to_string())));
+        assert_eq!(eval("x = 3; y = x"), Ok(None));
+    }
+
+    #[test]
+    fn bindings_across_lines() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("x = 3"), Ok(None));
+        assert_eq!(session.eval("x"), Ok(Some("3".to_string())));
+        assert_eq!(session.eval("y = x + 1; x = 10"), Ok(None));
+        assert_eq!(session.eval("(x, y)"), Ok(Some("(10, 4)".to_string())));
+
+        // functions and labels carry over as well
+        session.eval("double = n -> n * 2").unwrap();
+        session.eval("wrapped = Some 4").unwrap();
+        assert_eq!(
+            session.eval("match wrapped { Some n -> double n }"),
+            Ok(Some("8".to_string()))
+        );
+        assert_eq!(session.eval("double y"), Ok(Some("8".to_string())));
+    }
+
+    #[test]
+    fn builtins_across_lines() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.eval("slice [1, 2, 3] 1 3"),
+            Ok(Some("[2, 3]".to_string()))
+        );
+        // a builtin redefined on one line stays redefined on the next
+        session.eval("slice = x -> x").unwrap();
+        assert_eq!(session.eval("slice 7"), Ok(Some("7".to_string())));
+    }
+
+    #[test]
+    fn errors_keep_bindings() {
+        let mut session = Session::new();
+        session.eval("x = 3").unwrap();
+        assert!(session.eval("undefined").is_err());
+        assert!(session.eval("y = 1 / 0").is_err());
+        assert_eq!(session.eval("x"), Ok(Some("3".to_string())));
+
+        // the line that raised the error never assigned `y`
+        assert!(session
+            .eval("y")
+            .unwrap_err()
+            .contains("referenced before assignment"));
+        session.eval("y = 2").unwrap();
+        assert_eq!(session.eval("x + y"), Ok(Some("5".to_string())));
+    }
+
+    #[test]
+    fn yield_is_an_error() {
+        // nothing resumes a line, so it can not yield
+        let mut session = Session::new();
+        session.eval("x = 1").unwrap();
+        let error = session.eval("y = magic \"yield\" 1; (y, 2)").unwrap_err();
+        assert!(error.contains("rather than resumed"), "{}", error);
+        assert_eq!(session.eval("x"), Ok(Some("1".to_string())));
+    }
 }
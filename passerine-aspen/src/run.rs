@@ -1,10 +1,23 @@
-use std::path::PathBuf;
-
-use passerine::{compile, Source};
-
-use crate::{manifest::Manifest, ENTRYPOINT, SOURCE};
-
-pub fn run(path: PathBuf) -> Result<(), String> {
+use std::{path::PathBuf, rc::Rc};
+
+use passerine::{compiler::hoist, Fiber, Source};
+
+use crate::{
+    cache::compile_cached,
+    emit::{emit, Emit},
+    manifest::Manifest,
+    CACHE, ENTRYPOINT, SOURCE,
+};
+
+/// Runs the package at a path, passing on the arguments it is run with.
+/// If `print_result` is set, prints what the program evaluates to,
+/// unless it ends in an assignment, as in the REPL.
+pub fn run(
+    path: PathBuf,
+    emitting: Option<Emit>,
+    print_result: bool,
+    args: Vec<String>,
+) -> Result<(), String> {
     // just one file, for now
     let (_manifest, path) = Manifest::package(&path)?;
     let file = path.join(SOURCE).join(ENTRYPOINT);
@@ -16,13 +29,23 @@ pub fn run(path: PathBuf) -> Result<(), String> {
         )
     })?;
 
-    let bytecode = compile(source).map_err(|e| e.to_string())?;
-
-    println!("{:#?}", bytecode.lambda);
-    println!("{}", bytecode.lambda);
-
-    // let mut vm = VM::init(Closure::wrap(bytecode));
-    // vm.run().map_err(|e| e.to_string())?;
+    if let Some(emitting) = emitting {
+        println!("{}", emit(emitting, source)?);
+        return Ok(());
+    }
+
+    let (bytecode, _) = compile_cached(Rc::clone(&source), &path.join(CACHE))?;
+    let mut fiber = Fiber::init(bytecode);
+    fiber.set_args(args);
+    fiber.run().map_err(|e| e.to_string())?;
+
+    if print_result {
+        // the bytecode may be cached, so check how the program ends in its source
+        let (sst, _) = hoist(source).map_err(|e| e.to_string())?;
+        if !sst.item.is_assignment() {
+            println!("{}", fiber.stack.pop_data());
+        }
+    }
 
     Ok(())
 }
@@ -1 +1,256 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifest::Manifest,
+    status::{Kind, Status},
+    LOCKFILE, MANIFEST, REGISTRY_VAR,
+};
+
+/// The resolved dependencies of a package.
+/// Entries are kept sorted by name, so that the lockfile is deterministic.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Lockfile {
+    dependencies: BTreeMap<String, Locked>,
+}
+
+/// A dependency resolved to a concrete version.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Locked {
+    version: String,
+    /// A hash of the contents of the resolved package.
+    hash: String,
+}
+
+/// Returns the local registry dependencies are resolved from.
+/// Packages are stored in the registry as `<name>/<version>/`.
+pub fn registry() -> Result<PathBuf, String> {
+    if let Some(registry) = env::var_os(REGISTRY_VAR) {
+        return Ok(PathBuf::from(registry));
+    }
+
+    let home = env::var_os("HOME").ok_or("Can not determine the home directory")?;
+    Ok(PathBuf::from(home).join(".aspen").join("registry"))
+}
+
+/// Resolves the dependencies of the package at `path`
+/// and writes them to the lockfile.
+pub fn update(path: PathBuf) -> Result<(), String> {
+    let path = Manifest::find(&path)?;
+    let source = fs::read_to_string(path.join(MANIFEST))
+        .map_err(|_| "The manifest file could not be read")?;
+    let manifest = Manifest::parse(&source).ok_or("Could not parse the manifest file")?;
+
+    let lock = lock(&manifest, &registry()?)?;
+    let previous = fs::read_to_string(path.join(LOCKFILE)).ok();
+
+    if previous.as_deref() == Some(lock.as_str()) {
+        Status::info().log("The lockfile is already up to date");
+    } else {
+        fs::write(path.join(LOCKFILE), lock).map_err(|_| "Could not write lockfile")?;
+        Status(Kind::Success, "Updated").log(&format!("Wrote {}", LOCKFILE));
+    }
+
+    Ok(())
+}
+
+/// Resolves each dependency of a manifest against a registry,
+/// returning the contents of the lockfile.
+pub fn lock(manifest: &Manifest, registry: &Path) -> Result<String, String> {
+    let mut dependencies = BTreeMap::new();
+
+    for (name, requirement) in manifest.dependencies() {
+        let requirement = requirement
+            .as_str()
+            .ok_or_else(|| format!("The version of dependency '{}' must be a string", name))?;
+        dependencies.insert(name.clone(), resolve(name, requirement, registry)?);
+    }
+
+    toml::to_string_pretty(&Lockfile { dependencies })
+        .map_err(|_| "Could not generate lockfile".to_string())
+}
+
+/// Resolves a dependency to the newest version in the registry
+/// that satisfies the version requirement.
+pub fn resolve(name: &str, requirement: &str, registry: &Path) -> Result<Locked, String> {
+    Manifest::check_dependency_name(name)?;
+    let requirement = VersionReq::parse(requirement).map_err(|e| {
+        format!(
+            "The version of dependency '{}' is not valid semver: {}",
+            name, e
+        )
+    })?;
+
+    let versions = fs::read_dir(registry.join(name)).map_err(|_| {
+        format!(
+            "The dependency '{}' could not be found in the registry",
+            name
+        )
+    })?;
+
+    let newest = versions
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<Version>().ok())
+        .filter(|version| requirement.matches(version))
+        .max()
+        .ok_or_else(|| {
+            format!(
+                "No version of dependency '{}' matches '{}'",
+                name, requirement
+            )
+        })?;
+
+    let hash = hash_dir(&registry.join(name).join(newest.to_string()))?;
+    Ok(Locked {
+        version: newest.to_string(),
+        hash: format!("{:016x}", hash),
+    })
+}
+
+/// Hashes the relative paths and contents of every file in a directory.
+/// Uses FNV-1a, so that hashes are stable across platforms and releases.
+fn hash_dir(dir: &Path) -> Result<u64, String> {
+    let mut files = vec![];
+    collect_files(dir, dir, &mut files)?;
+    files.sort();
+
+    let mut hash = FNV_OFFSET;
+    for (relative, path) in files {
+        let contents =
+            fs::read(&path).map_err(|_| format!("Could not read '{}'", path.display()))?;
+        hash = fnv1a(hash, relative.bytes().chain([0]).chain(contents));
+    }
+
+    Ok(hash)
+}
+
+/// The starting hash for `fnv1a`.
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Hashes some bytes with FNV-1a, continuing from a previous hash.
+pub fn fnv1a(mut hash: u64, bytes: impl IntoIterator<Item = u8>) -> u64 {
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|_| format!("Could not read '{}'", dir.display()))?;
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let relative = path
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.push((relative, path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Builds a registry with a few versions of a few packages.
+    /// The registry is removed when the returned directory is dropped.
+    fn registry() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = dir.path();
+
+        for (package, version) in [
+            ("core", "1.0.0"),
+            ("core", "1.2.0"),
+            ("core", "2.0.0"),
+            ("list", "0.1.0"),
+        ] {
+            let dir = registry.join(package).join(version).join("src");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("main.pn"), format!("# {} {}", package, version)).unwrap();
+        }
+
+        dir
+    }
+
+    fn manifest(core: &str) -> Manifest {
+        manifest_with("core", core)
+    }
+
+    fn manifest_with(name: &str, version: &str) -> Manifest {
+        let source = format!(
+            "[package]\nname = \"example\"\nversion = \"0.1.0\"\nauthors = []\n\n\
+             [dependencies]\n\"{}\" = \"{}\"\nlist = \"*\"\n",
+            name, version
+        );
+        Manifest::parse(&source).unwrap()
+    }
+
+    fn parse(lock: &str) -> Lockfile {
+        toml::from_str(lock).unwrap()
+    }
+
+    #[test]
+    fn stable_lockfile() {
+        let registry = registry();
+        let registry = registry.path();
+        let first = lock(&manifest("1"), registry).unwrap();
+        let second = lock(&manifest("1"), registry).unwrap();
+        assert_eq!(first, second);
+
+        let lockfile = parse(&first);
+        assert_eq!(lockfile.dependencies["core"].version, "1.2.0");
+        assert_eq!(lockfile.dependencies["list"].version, "0.1.0");
+    }
+
+    #[test]
+    fn changed_constraint() {
+        let registry = registry();
+        let registry = registry.path();
+        let before = parse(&lock(&manifest("1"), registry).unwrap());
+        let after = parse(&lock(&manifest("2"), registry).unwrap());
+
+        assert_eq!(after.dependencies["core"].version, "2.0.0");
+        assert_ne!(
+            after.dependencies["core"].hash,
+            before.dependencies["core"].hash
+        );
+        assert_eq!(after.dependencies["list"], before.dependencies["list"]);
+    }
+
+    #[test]
+    fn unresolvable() {
+        let registry = registry();
+        let registry = registry.path();
+        assert!(lock(&manifest("3"), registry).is_err());
+        assert!(lock(&manifest("not semver"), registry).is_err());
+    }
+
+    #[test]
+    fn escaping_name() {
+        let registry = registry();
+        let registry = registry.path();
+        for name in ["../core", "core/1.0.0", "..", "a..b"] {
+            let error = lock(&manifest_with(name, "*"), registry).unwrap_err();
+            assert!(error.contains("path separators"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Conversions between `Lambda`s and bytes,
+//! so compiled bytecode can be stored and loaded again later.
+//!
+//! Numbers are written using the same number-streams as the bytecode itself.
+//! Spans are written as offsets into a single `Source`,
+//! which must be provided again when the bytes are loaded.
+//! Bytecode loaded from bytes should be verified before it is run.
+
+use std::rc::Rc;
+
+use crate::{
+    closure::Closure,
+    data::Data,
+    lambda::{Captured, Lambda, LambdaKind},
+    number::{build_number, split_number},
+    source::Source,
+    span::Span,
+};
+
+/// Written at the start of encoded bytecode.
+/// The last byte is the version of the format,
+/// which must be bumped whenever the format changes.
+const MAGIC: &[u8] = b"PSRN\x03";
+
+impl Lambda {
+    /// Encodes a `Lambda` and every function in its constant table as bytes.
+    /// Every span must point into `source`, or be a dummy span.
+    /// Returns an error if a constant can not be encoded, e.g. a closure.
+    pub fn to_bytes(&self, source: &Rc<Source>) -> Result<Vec<u8>, String> {
+        let mut writer = Writer {
+            bytes: MAGIC.to_vec(),
+            source,
+        };
+        writer.lambda(self)?;
+        Ok(writer.bytes)
+    }
+
+    /// Decodes a `Lambda` encoded with `to_bytes`,
+    /// pointing its spans into `source`.
+    /// The decoded bytecode is verified with `Lambda::verify`,
+    /// so corrupted or malicious bytecode is rejected before it can be run.
+    pub fn from_bytes(bytes: &[u8], source: &Rc<Source>) -> Result<Lambda, String> {
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or("The bytes are not bytecode, or were encoded in another format")?;
+        let mut reader = Reader {
+            bytes: rest,
+            source,
+        };
+        let lambda = reader.lambda()?;
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected bytes after the end of the bytecode".to_string());
+        }
+        lambda.verify()?;
+        Ok(lambda)
+    }
+}
+
+struct Writer<'a> {
+    bytes: Vec<u8>,
+    source: &'a Rc<Source>,
+}
+
+impl<'a> Writer<'a> {
+    fn number(&mut self, n: usize) {
+        self.bytes.extend(split_number(n));
+    }
+
+    fn string(&mut self, string: &str) {
+        self.number(string.len());
+        self.bytes.extend(string.as_bytes());
+    }
+
+    fn span(&mut self, span: &Span) -> Result<(), String> {
+        if span.is_dummy() {
+            self.bytes.push(0);
+            return Ok(());
+        }
+        if !Rc::ptr_eq(span.source(), self.source) {
+            return Err(format!(
+                "Spans from '{}' can not be encoded with bytecode compiled from '{}'",
+                span.path(),
+                self.source.path.display()
+            ));
+        }
+        self.bytes.push(1);
+        self.number(span.offset());
+        self.number(span.len());
+        Ok(())
+    }
+
+    fn lambda(&mut self, lambda: &Lambda) -> Result<(), String> {
+        self.number(lambda.decls);
+        self.number(lambda.code.len());
+        self.bytes.extend(&lambda.code);
+
+        self.number(lambda.spans.len());
+        for (index, span) in lambda.spans.iter() {
+            self.number(*index);
+            self.span(span)?;
+        }
+
+        self.number(lambda.constants.len());
+        for constant in lambda.constants.iter() {
+            self.data(constant)?;
+        }
+
+        self.number(lambda.captures.len());
+        for captured in lambda.captures.iter() {
+            let (tag, index) = match captured {
+                Captured::Local(index) => (0, index),
+                Captured::Nonlocal(index) => (1, index),
+            };
+            self.bytes.push(tag);
+            self.number(*index);
+        }
+
+        self.number(lambda.capture_names.len());
+        for name in lambda.capture_names.iter() {
+            self.string(name);
+        }
+
+        self.number(lambda.ffi.len());
+        for name in lambda.ffi.iter() {
+            self.string(name);
+        }
+
+        match &lambda.name {
+            Some(name) => {
+                self.bytes.push(1);
+                self.string(name);
+            }
+            None => self.bytes.push(0),
+        }
+
+        self.bytes.push(match lambda.kind {
+            LambdaKind::Function => 0,
+            LambdaKind::Module => 1,
+            LambdaKind::Script => 2,
+        });
+        Ok(())
+    }
+
+    fn data(&mut self, data: &Data) -> Result<(), String> {
+        match data {
+            Data::Unit => self.bytes.push(0),
+            Data::Float(f) => {
+                self.bytes.push(1);
+                self.bytes.extend(f.to_le_bytes());
+            }
+            Data::Integer(i) => {
+                self.bytes.push(2);
+                self.bytes.extend(i.to_le_bytes());
+            }
+            Data::Boolean(b) => self.bytes.extend([3, *b as u8]),
+            Data::String(s) => {
+                self.bytes.push(4);
+                self.string(s);
+            }
+            Data::Lambda(lambda) => {
+                self.bytes.push(5);
+                self.lambda(lambda)?;
+            }
+            Data::Kind(kind) => {
+                self.bytes.push(6);
+                self.number(*kind);
+            }
+            Data::Tuple(items) => {
+                self.bytes.push(7);
+                self.number(items.len());
+                for item in items.iter() {
+                    self.data(item)?;
+                }
+            }
+            // closures over nothing are compiled to constants
+            Data::Closure(closure) if closure.captures.is_empty() => {
+                self.bytes.push(8);
+                self.lambda(&closure.lambda)?;
+            }
+            // captured variables are shared with the running program,
+            // so a copy of them would silently go out of sync
+            Data::Closure(closure) => {
+                return Err(format!(
+                    "A closure that captures {} can not be encoded, \
+                     because captured variables only exist while the program runs",
+                    captured_names(closure)
+                ))
+            }
+            other => {
+                return Err(format!(
+                    "A constant that is {} can not be encoded",
+                    other.kind_name()
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names the variables captured by a closure, e.g. "`x` and `y`".
+fn captured_names(closure: &Closure) -> String {
+    let names = &closure.lambda.capture_names;
+    match names.split_last() {
+        Some((last, [])) => format!("`{}`", last),
+        Some((last, rest)) => format!(
+            "{} and `{}`",
+            rest.iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            last
+        ),
+        None => format!("{} variables", closure.captures.len()),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    source: &'a Rc<Source>,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < n {
+            return Err("Unexpected end of the bytecode".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        // the last byte of a number-stream has its high bit set
+        let end = self
+            .bytes
+            .iter()
+            .position(|byte| byte & 0b1000_0000 != 0)
+            .ok_or("Unexpected end of the bytecode")?;
+        // seven bits per byte, so longer streams would overflow
+        if end >= 10 {
+            return Err("A number in the bytecode is too large".to_string());
+        }
+        let (number, eaten) = build_number(self.take(end + 1)?);
+        debug_assert_eq!(eaten, end + 1);
+        Ok(number)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.number()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| "A string in the bytecode is not valid UTF-8".to_string())
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        match self.byte()? {
+            0 => Ok(Span::dummy()),
+            1 => {
+                let offset = self.number()?;
+                let length = self.number()?;
+                let contents = &self.source.contents;
+                let inside = match offset.checked_add(length) {
+                    Some(end) => {
+                        contents.is_char_boundary(offset) && contents.is_char_boundary(end)
+                    }
+                    None => false,
+                };
+                if !inside {
+                    return Err("A span in the bytecode lies outside of the source".to_string());
+                }
+                Ok(Span::new(self.source, offset, length))
+            }
+            tag => Err(format!("Invalid span tag {} in the bytecode", tag)),
+        }
+    }
+
+    fn lambda(&mut self) -> Result<Lambda, String> {
+        let mut lambda = Lambda::empty();
+        lambda.decls = self.number()?;
+        let len = self.number()?;
+        lambda.code = self.take(len)?.to_vec();
+
+        for _ in 0..self.number()? {
+            let index = self.number()?;
+            lambda.spans.push((index, self.span()?));
+        }
+
+        for _ in 0..self.number()? {
+            let constant = self.data()?;
+            lambda.constants.push(constant);
+        }
+
+        for _ in 0..self.number()? {
+            let captured = match self.byte()? {
+                0 => Captured::Local(self.number()?),
+                1 => Captured::Nonlocal(self.number()?),
+                tag => return Err(format!("Invalid capture tag {} in the bytecode", tag)),
+            };
+            lambda.captures.push(captured);
+        }
+
+        for _ in 0..self.number()? {
+            let name = self.string()?;
+            lambda.capture_names.push(name);
+        }
+
+        for _ in 0..self.number()? {
+            let name = self.string()?;
+            lambda.ffi.push(name);
+        }
+
+        lambda.name = match self.byte()? {
+            0 => None,
+            1 => Some(self.string()?),
+            tag => return Err(format!("Invalid name tag {} in the bytecode", tag)),
+        };
+
+        lambda.kind = match self.byte()? {
+            0 => LambdaKind::Function,
+            1 => LambdaKind::Module,
+            2 => LambdaKind::Script,
+            tag => return Err(format!("Invalid kind tag {} in the bytecode", tag)),
+        };
+        Ok(lambda)
+    }
+
+    fn data(&mut self) -> Result<Data, String> {
+        let data = match self.byte()? {
+            0 => Data::Unit,
+            1 => Data::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => Data::Integer(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            3 => Data::Boolean(self.byte()? != 0),
+            4 => Data::string(self.string()?),
+            5 => Data::Lambda(Rc::new(self.lambda()?)),
+            6 => Data::Kind(self.number()?),
+            7 => {
+                let mut items = vec![];
+                for _ in 0..self.number()? {
+                    items.push(self.data()?);
+                }
+                Data::tuple(items)
+            }
+            8 => Data::Closure(Box::new(Closure::wrap(Rc::new(self.lambda()?)))),
+            tag => return Err(format!("Invalid constant tag {} in the bytecode", tag)),
+        };
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::opcode::Opcode;
+
+    /// A lambda that does nothing but return.
+    fn returning() -> Lambda {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda
+    }
+
+    fn example(source: &Rc<Source>) -> Lambda {
+        let mut inner = Lambda::empty();
+        inner.decls = 1;
+        inner.emit_span(&Span::new(source, 4, 1));
+        inner.emit(Opcode::Return);
+        inner.emit_bytes(&mut split_number(1));
+        inner.captures.push(Captured::Nonlocal(0));
+        inner.capture_names.push("x".to_string());
+        inner.kind = LambdaKind::Function;
+
+        let mut lambda = Lambda::empty();
+        lambda.name = Some("main".to_string());
+        lambda.emit_span(&Span::dummy());
+        for constant in [
+            Data::Float(-1.5),
+            Data::Integer(i64::MIN),
+            Data::Boolean(true),
+            Data::string("héllo"),
+            Data::tuple(vec![Data::Unit, Data::Kind(3)]),
+            Data::Closure(Box::new(Closure::wrap(Rc::new(returning())))),
+            Data::Lambda(Rc::new(inner)),
+        ] {
+            let index = lambda.index_data(constant);
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(index));
+        }
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda.add_ffi("println");
+        lambda
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = Source::source("x = y -> x");
+        let lambda = example(&source);
+        let bytes = lambda.to_bytes(&source).unwrap();
+        let decoded = Lambda::from_bytes(&bytes, &source).unwrap();
+
+        // closures are compared by identity, so compare the encodings instead
+        assert_eq!(decoded.to_bytes(&source), Ok(bytes));
+        assert_eq!(decoded.code, lambda.code);
+        assert_eq!(decoded.spans, lambda.spans);
+        assert_eq!(decoded.constants[..5], lambda.constants[..5]);
+        assert_eq!(decoded.constants[6], lambda.constants[6]);
+        assert_eq!(decoded.ffi, lambda.ffi);
+        assert_eq!(decoded.name, lambda.name);
+        assert_eq!(decoded.kind, lambda.kind);
+    }
+
+    #[test]
+    fn malformed() {
+        let source = Source::source("x = y -> x");
+        let bytes = example(&source).to_bytes(&source).unwrap();
+
+        // every truncation is caught
+        for end in 0..bytes.len() {
+            assert!(Lambda::from_bytes(&bytes[..end], &source).is_err());
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(Lambda::from_bytes(&extended, &source).is_err());
+
+        // decoded bytecode is verified
+        let mut lambda = example(&source);
+        lambda.code.pop();
+        lambda.code.pop();
+        let bytes = lambda.to_bytes(&source).unwrap();
+        let error = Lambda::from_bytes(&bytes, &source).unwrap_err();
+        assert!(error.contains("does not end in a Return"), "{}", error);
+
+        // spans must fit in the source they're loaded with
+        let shorter = Source::source("x");
+        assert!(Lambda::from_bytes(&bytes, &shorter).is_err());
+    }
+
+    #[test]
+    fn closure_without_captures() {
+        let source = Source::source("x = y -> x");
+        let mut lambda = returning();
+        let closure = Closure::wrap(Rc::new(example(&source)));
+        lambda.index_data(Data::Closure(Box::new(closure)));
+        let bytes = lambda.to_bytes(&source).unwrap();
+
+        let decoded = Lambda::from_bytes(&bytes, &source).unwrap();
+        match &decoded.constants[0] {
+            Data::Closure(closure) => {
+                assert!(closure.captures.is_empty());
+                assert_eq!(closure.lambda.name.as_deref(), Some("main"));
+            }
+            other => panic!("expected a closure, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unencodable() {
+        let source = Source::source("x = y -> x");
+        let mut lambda = Lambda::empty();
+        lambda.index_data(Data::list(vec![]));
+        assert!(lambda.to_bytes(&source).is_err());
+
+        // closures that capture variables can not be encoded
+        let mut inner = Lambda::empty();
+        inner.capture_names = vec!["x".to_string(), "y".to_string()];
+        let mut closure = Closure::wrap(Rc::new(inner));
+        closure.captures = (0..2).map(|_| Rc::new(RefCell::new(None))).collect();
+        let mut lambda = Lambda::empty();
+        lambda.index_data(Data::Closure(Box::new(closure)));
+        let error = lambda.to_bytes(&source).unwrap_err();
+        assert!(error.contains("captures `x` and `y`"), "{}", error);
+
+        // spans must point into the source being encoded
+        let other = Source::source("x = y -> x");
+        assert!(example(&other).to_bytes(&source).is_err());
+    }
+}
@@ -6,10 +6,17 @@ use crate::{data::Data, lambda::Lambda};
 /// Each closure is unique when constructed,
 /// Because it depends on the surrounding environment it was constructed in.
 /// It holds a set of references to variables it captures.
-#[derive(Debug, Clone, PartialEq)]
+/// A captured variable is `None` until it is assigned,
+/// e.g. when a function captures a variable defined after it.
+///
+/// Closures are compared by identity: two closures are equal
+/// if they wrap the same `Lambda` and share the same captured variables.
+/// So a closure is equal to itself and to its clones,
+/// but not to a different closure with identical code.
+#[derive(Debug, Clone)]
 pub struct Closure {
     pub lambda: Rc<Lambda>,
-    pub captures: Vec<Rc<RefCell<Data>>>,
+    pub captures: Vec<Rc<RefCell<Option<Data>>>>,
 }
 
 impl Closure {
@@ -21,19 +28,59 @@ impl Closure {
             captures: vec![],
         }
     }
+
+    /// Returns a snapshot of the variables this closure captures,
+    /// pairing the name of each with a copy of its current value.
+    /// Later changes to the captured variables do not affect the snapshot.
+    /// Variables that have not been assigned yet are left out.
+    pub fn environment(&self) -> Vec<(String, Data)> {
+        self.lambda
+            .capture_names
+            .iter()
+            .zip(self.captures.iter())
+            .filter_map(|(name, captured)| Some((name.clone(), captured.borrow().clone()?)))
+            .collect()
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        Rc::ptr_eq(&self.lambda, &other.lambda)
+            && self.captures.len() == other.captures.len()
+            && self
+                .captures
+                .iter()
+                .zip(other.captures.iter())
+                .all(|(a, b)| Rc::ptr_eq(a, b))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
-    // use crate::common::lambda::Lambda;
-
-    // #[test]
-    // fn unique() {
-    //     let lambda = Lambda::empty();
-    //     let a = Closure::wrap(lambda.clone());
-    //     let b = Closure::wrap(lambda.clone());
-    //
-    //     assert_ne!(a.id, b.id);
-    // }
+    use super::*;
+
+    #[test]
+    fn identity() {
+        let lambda = Rc::new(Lambda::empty());
+        let closure = Closure::wrap(Rc::clone(&lambda));
+        assert_eq!(closure, closure);
+        assert_eq!(closure, closure.clone());
+
+        // identical code, but a different function
+        let fresh = Closure::wrap(Rc::new(Lambda::empty()));
+        assert_ne!(closure, fresh);
+
+        // the same function, but different captured variables
+        let mut a = Closure::wrap(Rc::clone(&lambda));
+        let mut b = Closure::wrap(lambda);
+        a.captures
+            .push(Rc::new(RefCell::new(Some(Data::Integer(1)))));
+        b.captures
+            .push(Rc::new(RefCell::new(Some(Data::Integer(1)))));
+        assert_ne!(a, b);
+
+        b.captures[0] = Rc::clone(&a.captures[0]);
+        assert_eq!(a, b);
+        assert_eq!(Data::Closure(Box::new(a)), Data::Closure(Box::new(b)));
+    }
 }
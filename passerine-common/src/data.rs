@@ -1,7 +1,9 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     f64,
     fmt::{Debug, Display, Formatter, Result},
+    hash::{Hash, Hasher},
+    mem,
     rc::Rc,
 };
 
@@ -9,7 +11,17 @@ use crate::{closure::Closure, lambda::Lambda};
 
 // TODO: separate VM data from parser data
 
+/// The kind of a label, i.e. what distinguishes `Some x` from `None x`.
+/// Used to key things by label, rather than by a bare number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LabelKind(pub usize);
+
 /// Built-in Passerine datatypes.
+/// Variants with large payloads are boxed,
+/// so that each piece of data is at most three words in size.
+/// Strings, tuples, and lists are immutable once built,
+/// so they are shared rather than boxed,
+/// making copies of them, e.g. when loading a variable, cheap.
 #[derive(Clone, PartialEq)]
 pub enum Data {
     // Passerine Data (Atomic)
@@ -22,7 +34,7 @@ pub enum Data {
     /// A boolean, like true or false.
     Boolean(bool),
     /// A UTF-8 encoded string.
-    String(String),
+    String(Rc<String>),
 
     /// Represents a function, ie.e some bytecode without a context.
     Lambda(Rc<Lambda>),
@@ -43,20 +55,248 @@ pub enum Data {
     /// The empty Tuple
     Unit, // an empty typle
     /// A non-empty Tuple.
-    Tuple(Vec<Data>),
+    Tuple(Rc<Vec<Data>>),
+    /// A List, which may be empty.
+    List(Rc<Vec<Data>>),
     // // TODO: Hashmap?
     // // I mean, it's overkill for small things
     // // yet if people have very big records, yk.
-    Record(BTreeMap<usize, Data>),
-    Map(BTreeMap<Data, Data>),
+    Record(Box<BTreeMap<String, Data>>),
+    /// A map from keys to values.
+    /// Only some data can be used as a key, see `Data::check_key`.
+    Map(Box<HashMap<Data, Data>>),
     // ArbInt(ArbInt),
 }
 
-// TODO: manually implement the equality trait
-// NOTE: might have to implement partial equality as well
 // NOTE: equality represents passerine equality, not rust equality
+// NOTE: closures are compared by identity, so equality never looks inside
+// their captures, and terminates even if a closure captures itself.
 impl Eq for Data {}
 
+impl Data {
+    /// Constructs an `Integer`.
+    pub fn int(n: i64) -> Data {
+        Data::Integer(n)
+    }
+
+    /// Constructs a `Float`.
+    pub fn real(n: f64) -> Data {
+        Data::Float(n)
+    }
+
+    /// Constructs a `String`, sharing the string.
+    pub fn string(string: impl Into<String>) -> Data {
+        Data::String(Rc::new(string.into()))
+    }
+
+    /// Constructs a `Tuple`, sharing the items.
+    pub fn tuple(items: Vec<Data>) -> Data {
+        Data::Tuple(Rc::new(items))
+    }
+
+    /// Constructs a `List`, sharing the items.
+    pub fn list(items: Vec<Data>) -> Data {
+        Data::List(Rc::new(items))
+    }
+
+    /// Constructs a `Record`, boxing the fields.
+    pub fn record(fields: BTreeMap<String, Data>) -> Data {
+        Data::Record(Box::new(fields))
+    }
+
+    /// Constructs a `Map` from pairs of keys and values.
+    /// Returns an error if any key can not be used as a key.
+    /// Later pairs replace earlier pairs with the same key.
+    // keys that could change, i.e. closures, are rejected by `check_key`
+    #[allow(clippy::mutable_key_type)]
+    pub fn map(entries: Vec<(Data, Data)>) -> std::result::Result<Data, String> {
+        let mut map = HashMap::new();
+        for (key, value) in entries {
+            key.check_key()?;
+            map.insert(key, value);
+        }
+        Ok(Data::Map(Box::new(map)))
+    }
+
+    /// Checks that some data can be used as a key in a `Map`.
+    /// Keys must be equal to themselves, and can not change once inserted,
+    /// so functions, which are compared by identity and may capture mutable variables,
+    /// and `NaN`, which is not equal to itself, can not be keys.
+    /// Maps can not be keys either.
+    pub fn check_key(&self) -> std::result::Result<(), String> {
+        match self {
+            Data::Float(n) if n.is_nan() => {
+                Err("NaN can not be used as a key, as it is not equal to itself".to_string())
+            }
+            Data::Lambda(_) | Data::Closure(_) => {
+                Err("A function can not be used as a key".to_string())
+            }
+            Data::Map(_) => Err("A map can not be used as a key".to_string()),
+            Data::Label(_, data) => data.check_key(),
+            Data::Tuple(items) | Data::List(items) => items.iter().try_for_each(Data::check_key),
+            Data::Record(fields) => fields.values().try_for_each(Data::check_key),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether some data matches the data expected by a pattern.
+    /// This is the same as `==`, except that floats use a total equality:
+    /// `NaN` matches `NaN`, so that matching against a `NaN` literal works.
+    /// Infinities match infinities of the same sign, and `0.0` matches `-0.0`.
+    /// Ordering comparisons between floats keep their IEEE semantics.
+    /// Numbers only match numbers of the same type, as with `==`:
+    /// `1` does not match `1.0`, so a pattern never converts what it matches.
+    pub fn matches(&self, expected: &Data) -> bool {
+        match (self, expected) {
+            (Data::Float(a), Data::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+            (Data::Tuple(a), Data::Tuple(b)) | (Data::List(a), Data::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.matches(b))
+            }
+            (Data::Label(a, x), Data::Label(b, y)) => a == b && x.matches(y),
+            (Data::Record(a), Data::Record(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|((k, x), (l, y))| k == l && x.matches(y))
+            }
+            // keys are compared with `==`, as that's how they're looked up
+            (Data::Map(a), Data::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(k, x)| b.get(k).is_some_and(|y| x.matches(y)))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Names the variant of some data, e.g. `Integer` or `Closure`,
+    /// as returned by `type_of`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Data::Float(_) => "Float",
+            Data::Integer(_) => "Integer",
+            Data::Boolean(_) => "Boolean",
+            Data::String(_) => "String",
+            Data::Lambda(_) => "Lambda",
+            Data::Closure(_) => "Closure",
+            Data::Kind(_) => "Kind",
+            Data::Label(_, _) => "Label",
+            Data::Unit => "Unit",
+            Data::Tuple(_) => "Tuple",
+            Data::List(_) => "List",
+            Data::Record(_) => "Record",
+            Data::Map(_) => "Map",
+        }
+    }
+
+    /// Names the kind of some data, with an article,
+    /// e.g. `an integer` or `a string`, for use in error messages.
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Data::Float(_) => "a float",
+            Data::Integer(_) => "an integer",
+            Data::Boolean(_) => "a boolean",
+            Data::String(_) => "a string",
+            Data::Lambda(_) | Data::Closure(_) => "a function",
+            Data::Kind(_) => "a kind",
+            Data::Label(_, _) => "a label",
+            Data::Unit => "unit",
+            Data::Tuple(_) => "a tuple",
+            Data::List(_) => "a list",
+            Data::Record(_) => "a record",
+            Data::Map(_) => "a map",
+        }
+    }
+}
+
+impl From<i64> for Data {
+    fn from(n: i64) -> Data {
+        Data::Integer(n)
+    }
+}
+
+impl From<f64> for Data {
+    fn from(n: f64) -> Data {
+        Data::Float(n)
+    }
+}
+
+impl From<bool> for Data {
+    fn from(b: bool) -> Data {
+        Data::Boolean(b)
+    }
+}
+
+impl From<&str> for Data {
+    fn from(string: &str) -> Data {
+        Data::string(string)
+    }
+}
+
+impl From<String> for Data {
+    fn from(string: String) -> Data {
+        Data::string(string)
+    }
+}
+
+impl From<()> for Data {
+    fn from(_: ()) -> Data {
+        Data::Unit
+    }
+}
+
+/// Builds `Data` from something that looks like a Passerine literal,
+/// e.g. `data!([1, "x", (true, 2.5)])`.
+/// Brackets build lists, parentheses build tuples, and `()` is unit.
+/// Anything else is an expression converted with `Data::from`,
+/// so it may be a number, boolean, string, or some `Data` built elsewhere.
+/// Note that `(x)` is a tuple with one item, not `x` in parentheses.
+#[macro_export]
+macro_rules! data {
+    // the items of a tuple or list are munched one at a time,
+    // so that nested brackets and parentheses are not parsed as expressions
+    (@items [$($done:expr),*]) => { vec![$($done),*] };
+    (@items [$($done:expr),*] [$($list:tt)*] $(, $($rest:tt)*)?) => {
+        $crate::data!(@items [$($done,)* $crate::data!([$($list)*])] $($($rest)*)?)
+    };
+    (@items [$($done:expr),*] ($($tuple:tt)*) $(, $($rest:tt)*)?) => {
+        $crate::data!(@items [$($done,)* $crate::data!(($($tuple)*))] $($($rest)*)?)
+    };
+    (@items [$($done:expr),*] $item:expr $(, $($rest:tt)*)?) => {
+        $crate::data!(@items [$($done,)* $crate::data!($item)] $($($rest)*)?)
+    };
+
+    (()) => { $crate::Data::Unit };
+    ([$($items:tt)*]) => { $crate::Data::list($crate::data!(@items [] $($items)*)) };
+    (($($items:tt)+)) => { $crate::Data::tuple($crate::data!(@items [] $($items)+)) };
+    ($leaf:expr) => { $crate::Data::from($leaf) };
+}
+
+impl Hash for Data {
+    /// Hashes data consistently with `==`,
+    /// so that data that passes `Data::check_key` can be used as a key.
+    /// Data that can not be a key only hashes its kind.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            // `0.0 == -0.0`, so both must hash the same
+            Data::Float(n) if *n == 0.0 => 0.0f64.to_bits().hash(state),
+            Data::Float(n) => n.to_bits().hash(state),
+            Data::Integer(n) => n.hash(state),
+            Data::Boolean(b) => b.hash(state),
+            Data::String(s) => s.hash(state),
+            Data::Kind(n) => n.hash(state),
+            Data::Label(n, data) => {
+                n.hash(state);
+                data.hash(state);
+            }
+            Data::Tuple(items) | Data::List(items) => items.hash(state),
+            Data::Record(fields) => fields.hash(state),
+            Data::Unit | Data::Lambda(_) | Data::Closure(_) | Data::Map(_) => (),
+        }
+    }
+}
+
 impl Display for Data {
     /// Displays some Passerine Data in a pretty manner, as if it were printed
     /// to console.
@@ -65,6 +305,8 @@ impl Display for Data {
             // Data::Heaped(_) => unreachable!("Can not display heaped data"),
             // Data::NotInit => unreachable!("Can not display uninitialized
             // data"),
+            // whole floats keep their decimal point, so they aren't mistaken for integers
+            Data::Float(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{}.0", n),
             Data::Float(n) => write!(f, "{}", n),
             Data::Integer(n) => write!(f, "{}", n),
             Data::Boolean(b) => {
@@ -84,6 +326,22 @@ impl Display for Data {
                     .collect::<Vec<String>>()
                     .join(", ")
             ),
+            Data::List(l) => write!(
+                f,
+                "[{}]",
+                l.iter()
+                    .map(|i| format!("{}", i))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Data::Record(r) => write!(
+                f,
+                "{{{}}}",
+                r.iter()
+                    .map(|(k, v)| format!("{} = {}", k, v))
+                    .collect::<Vec<String>>()
+                    .join("; ")
+            ),
             // TODO: create representation
             _ => panic!("Representation is not yet implemented for this"),
         }
@@ -108,8 +366,168 @@ impl Debug for Data {
             Data::Label(n, v) => write!(f, "Label({}, {:?})", n, v),
             Data::Unit => write!(f, "Unit"),
             Data::Tuple(t) => write!(f, "Tuple({:?})", t),
-            // TODO: create representation
-            _ => panic!("Representation is not yet implemented for this"),
+            Data::List(l) => write!(f, "List({:?})", l),
+            Data::Record(r) => write!(f, "Record({:?})", r),
+            Data::Map(m) => write!(f, "Map({:?})", m),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::closure::Closure;
+
+    #[test]
+    fn nan_matches() {
+        let nan = Data::Float(f64::NAN);
+        assert!(nan.matches(&Data::Float(f64::NAN)));
+        assert!(nan.matches(&Data::Float(-f64::NAN)));
+        assert!(!nan.matches(&Data::Float(1.0)));
+        assert!(!Data::Float(1.0).matches(&nan));
+
+        // equality and ordering keep IEEE semantics
+        assert_ne!(nan, nan.clone());
+        assert_eq!(f64::NAN.partial_cmp(&f64::NAN), None);
+    }
+
+    #[test]
+    fn infinity_matches() {
+        let inf = Data::Float(f64::INFINITY);
+        assert!(inf.matches(&Data::Float(f64::INFINITY)));
+        assert!(!inf.matches(&Data::Float(f64::NEG_INFINITY)));
+        assert!(!inf.matches(&Data::Float(f64::MAX)));
+    }
+
+    #[test]
+    fn nested_matches() {
+        let nested = |x| {
+            Data::tuple(vec![
+                Data::Integer(1),
+                Data::Label(0, Box::new(Data::list(vec![Data::Float(x)]))),
+            ])
+        };
+        assert!(nested(f64::NAN).matches(&nested(f64::NAN)));
+        assert!(nested(f64::INFINITY).matches(&nested(f64::INFINITY)));
+        assert!(!nested(f64::NAN).matches(&nested(f64::INFINITY)));
+        assert!(!Data::Integer(1).matches(&Data::Float(1.0)));
+    }
+
+    /// Builds a closure that captures itself,
+    /// returning the cell it is captured in so the cycle can be broken.
+    fn cyclic(lambda: &Rc<Lambda>) -> (Data, Rc<RefCell<Option<Data>>>) {
+        let cell = Rc::new(RefCell::new(None));
+        let mut closure = Closure::wrap(Rc::clone(lambda));
+        closure.captures.push(Rc::clone(&cell));
+        let data = Data::Closure(Box::new(closure));
+        cell.replace(Some(Data::tuple(vec![Data::Integer(1), data.clone()])));
+        (data, cell)
+    }
+
+    #[test]
+    fn cyclic_equality() {
+        let lambda = Rc::new(Lambda::empty());
+        let (a, a_cell) = cyclic(&lambda);
+        let (b, b_cell) = cyclic(&lambda);
+        assert_eq!(a, a);
+        // comparing the contents of the cycle does not follow it
+        let contents = Data::tuple(vec![Data::Integer(1), a.clone()]);
+        assert_eq!(*a_cell.borrow(), Some(contents));
+        // same code, but different captured variables
+        assert_ne!(a, b);
+
+        // break the cycles so the closures are freed
+        a_cell.replace(None);
+        b_cell.replace(None);
+    }
+
+    #[test]
+    fn display_float() {
+        assert_eq!(Data::Float(3.0).to_string(), "3.0");
+        assert_eq!(Data::Float(-0.0).to_string(), "-0.0");
+        assert_eq!(Data::Float(2.5).to_string(), "2.5");
+        assert_eq!(Data::Float(1e20).to_string(), "100000000000000000000.0");
+        assert_eq!(Data::Float(f64::NAN).to_string(), "NaN");
+        assert_eq!(Data::Float(f64::NEG_INFINITY).to_string(), "-inf");
+        assert_eq!(Data::Integer(3).to_string(), "3");
+        assert_eq!(
+            Data::tuple(vec![Data::Float(1.0), Data::Integer(1)]).to_string(),
+            "(1.0, 1)"
+        );
+    }
+
+    fn lookup<'a>(map: &'a Data, key: &Data) -> Option<&'a Data> {
+        match map {
+            Data::Map(map) => map.get(key),
+            other => panic!("expected a map, found {:?}", other),
         }
     }
+
+    #[test]
+    fn map_keys() {
+        let pair = |a, b| Data::tuple(vec![Data::Integer(a), Data::string(b)]);
+        let map = Data::map(vec![
+            (pair(1, "one"), Data::Integer(1)),
+            (Data::string("two"), Data::Integer(2)),
+            (Data::Float(0.0), Data::Integer(3)),
+            (Data::string("two"), Data::Integer(4)),
+        ])
+        .unwrap();
+
+        assert_eq!(lookup(&map, &pair(1, "one")), Some(&Data::Integer(1)));
+        assert_eq!(lookup(&map, &pair(1, "two")), None);
+        // later pairs replace earlier ones
+        assert_eq!(lookup(&map, &Data::string("two")), Some(&Data::Integer(4)));
+        // equal floats are the same key
+        assert_eq!(lookup(&map, &Data::Float(-0.0)), Some(&Data::Integer(3)));
+        assert_eq!(lookup(&map, &Data::Integer(0)), None);
+    }
+
+    #[test]
+    fn unhashable_keys() {
+        let closure = Data::Closure(Box::new(Closure::wrap(Rc::new(Lambda::empty()))));
+        let error = Data::map(vec![(closure.clone(), Data::Unit)]).unwrap_err();
+        assert_eq!(error, "A function can not be used as a key");
+
+        // nested keys are checked too
+        let nested = Data::tuple(vec![Data::Integer(1), closure]);
+        assert!(Data::map(vec![(nested, Data::Unit)]).is_err());
+        let nan = Data::Float(f64::NAN);
+        assert!(Data::map(vec![(nan, Data::Unit)])
+            .unwrap_err()
+            .contains("NaN"));
+    }
+
+    #[test]
+    fn data_macro() {
+        assert_eq!(data!(1), Data::int(1));
+        assert_eq!(data!(-2.5), Data::real(-2.5));
+        assert_eq!(data!("x"), Data::string("x"));
+        assert_eq!(data!(()), Data::Unit);
+        assert_eq!(data!([]), Data::list(vec![]));
+        assert_eq!(
+            data!([1, "x", true]),
+            Data::list(vec![Data::int(1), Data::string("x"), Data::Boolean(true)])
+        );
+
+        // nested, with trailing commas and expressions
+        let name = String::from("passerine");
+        let existing = Data::Kind(0);
+        assert_eq!(
+            data!((1 + 1, [(), [2.0,]], (name, existing.clone()),)),
+            Data::tuple(vec![
+                Data::int(2),
+                Data::list(vec![Data::Unit, Data::list(vec![Data::real(2.0)])]),
+                Data::tuple(vec![Data::string("passerine"), existing]),
+            ])
+        );
+    }
+
+    #[test]
+    fn size() {
+        // a tag and two words, for `Label`
+        assert!(std::mem::size_of::<Data>() <= 3 * std::mem::size_of::<usize>());
+    }
 }
@@ -1,3 +1,5 @@
+use std::rc::Rc;
+
 use crate::data::Data;
 
 /// Indicates that a Rust data structure can be serialized to Passerine data,
@@ -84,10 +86,10 @@ impl_inject! {
 impl_inject! {
     String where
     from => match from {
-        Data::String(s) => Some(s),
+        Data::String(s) => Some(Rc::unwrap_or_clone(s)),
         _ => None,
     },
-    into => Data::String(into),
+    into => Data::string(into),
 }
 
 // Tuples
@@ -0,0 +1,439 @@
+//! Conversions between Passerine `Data` and JSON.
+//! Objects become records, arrays become lists,
+//! numbers become integers or floats depending on whether they have
+//! a fractional part or exponent, and `null` becomes unit.
+
+use std::{collections::BTreeMap, iter::Peekable, str::Chars};
+
+use crate::data::Data;
+
+/// How deeply arrays and objects may be nested in a JSON document
+/// before parsing gives up, so deep input can't overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+impl Data {
+    /// Parses a JSON document into some `Data`.
+    pub fn from_json(json: &str) -> Result<Data, String> {
+        let mut reader = JsonReader {
+            chars: json.chars().peekable(),
+            depth: 0,
+        };
+
+        let data = reader.value()?;
+        reader.whitespace();
+        match reader.chars.next() {
+            None => Ok(data),
+            Some(c) => Err(format!(
+                "Unexpected `{}` after the end of the JSON value",
+                c
+            )),
+        }
+    }
+
+    /// Serializes some `Data` as JSON.
+    /// Returns an error if the data, or any data it contains,
+    /// has no JSON representation, e.g. a closure or a label.
+    pub fn to_json(&self) -> Result<String, String> {
+        let mut json = String::new();
+        write_json(self, &mut json)?;
+        Ok(json)
+    }
+}
+
+fn write_json(data: &Data, json: &mut String) -> Result<(), String> {
+    match data {
+        Data::Unit => json.push_str("null"),
+        Data::Boolean(b) => json.push_str(if *b { "true" } else { "false" }),
+        Data::Integer(n) => json.push_str(&n.to_string()),
+        Data::Float(n) if n.is_finite() => json.push_str(&format!("{:?}", n)),
+        Data::Float(n) => return Err(format!("The float `{}` can not be represented in JSON", n)),
+        Data::String(s) => write_string(s, json),
+        Data::Tuple(items) | Data::List(items) => {
+            json.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index != 0 {
+                    json.push(',');
+                }
+                write_json(item, json)?;
+            }
+            json.push(']');
+        }
+        Data::Record(fields) => {
+            json.push('{');
+            for (index, (name, value)) in fields.iter().enumerate() {
+                if index != 0 {
+                    json.push(',');
+                }
+                write_string(name, json);
+                json.push(':');
+                write_json(value, json)?;
+            }
+            json.push('}');
+        }
+        Data::Lambda(_) | Data::Closure(_) => {
+            return Err("Functions can not be serialized as JSON".to_string())
+        }
+        Data::Kind(_) | Data::Label(_, _) => {
+            return Err("Labels can not be serialized as JSON".to_string())
+        }
+        Data::Map(_) => return Err("Maps can not be serialized as JSON".to_string()),
+    }
+
+    Ok(())
+}
+
+fn write_string(string: &str, json: &mut String) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// A small recursive-descent JSON parser, following RFC 8259.
+struct JsonReader<'a> {
+    chars: Peekable<Chars<'a>>,
+    depth: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    fn whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.chars.peek() {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("Expected `{}`, found `{}`", expected, c)),
+            None => Err(format!(
+                "Expected `{}`, found the end of the JSON",
+                expected
+            )),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, data: Data) -> Result<Data, String> {
+        for c in keyword.chars() {
+            self.expect(c)?;
+        }
+        Ok(data)
+    }
+
+    fn value(&mut self) -> Result<Data, String> {
+        self.whitespace();
+        match self.chars.peek() {
+            Some('n') => self.keyword("null", Data::Unit),
+            Some('t') => self.keyword("true", Data::Boolean(true)),
+            Some('f') => self.keyword("false", Data::Boolean(false)),
+            Some('"') => Ok(Data::string(self.string()?)),
+            Some('[') => self.nested(Self::array),
+            Some('{') => self.nested(Self::object),
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) => Err(format!("Unexpected `{}` in JSON", c)),
+            None => Err("Unexpected end of JSON".to_string()),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Data, String>) -> Result<Data, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!(
+                "JSON is nested more than {} levels deep",
+                MAX_DEPTH
+            ));
+        }
+
+        self.depth += 1;
+        let data = parse(self);
+        self.depth -= 1;
+        data
+    }
+
+    fn array(&mut self) -> Result<Data, String> {
+        self.expect('[')?;
+        let mut items = vec![];
+
+        self.whitespace();
+        if let Some(']') = self.chars.peek() {
+            self.chars.next();
+            return Ok(Data::list(items));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => break,
+                _ => return Err("Expected `,` or `]` in JSON array".to_string()),
+            }
+        }
+
+        Ok(Data::list(items))
+    }
+
+    fn object(&mut self) -> Result<Data, String> {
+        self.expect('{')?;
+        let mut fields = BTreeMap::new();
+
+        self.whitespace();
+        if let Some('}') = self.chars.peek() {
+            self.chars.next();
+            return Ok(Data::record(fields));
+        }
+
+        loop {
+            self.whitespace();
+            let name = self.string()?;
+            self.whitespace();
+            self.expect(':')?;
+            let value = self.value()?;
+            fields.insert(name, value);
+
+            self.whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("Expected `,` or `}` in JSON object".to_string()),
+            }
+        }
+
+        Ok(Data::record(fields))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            let c = match self.chars.next() {
+                Some('"') => break,
+                Some('\\') => self.escape()?,
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err("Unescaped control character in JSON string".to_string())
+                }
+                Some(c) => c,
+                None => return Err("Unclosed string in JSON".to_string()),
+            };
+            string.push(c);
+        }
+
+        Ok(string)
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let high = self.hex()?;
+                let code = match high {
+                    // a surrogate pair, so the low half must follow
+                    0xD800..=0xDBFF => {
+                        if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                            return Err("Unpaired surrogate in JSON string".to_string());
+                        }
+                        let low = self.hex()?;
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err("Unpaired surrogate in JSON string".to_string());
+                        }
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    }
+                    0xDC00..=0xDFFF => return Err("Unpaired surrogate in JSON string".to_string()),
+                    code => code,
+                };
+                char::from_u32(code).ok_or("Invalid unicode escape in JSON string")?
+            }
+            _ => return Err("Invalid escape in JSON string".to_string()),
+        };
+
+        Ok(c)
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or("Expected four hex digits in JSON unicode escape")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    /// Pushes a run of digits onto `number`,
+    /// returning an error if there were none.
+    fn digits(&mut self, number: &mut String) -> Result<(), String> {
+        let start = number.len();
+        while let Some(c @ '0'..='9') = self.chars.peek() {
+            number.push(*c);
+            self.chars.next();
+        }
+
+        if number.len() == start {
+            return Err(format!("Expected a digit in the JSON number `{}`", number));
+        }
+        Ok(())
+    }
+
+    /// Reads a number, following the grammar
+    /// `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`.
+    fn number(&mut self) -> Result<Data, String> {
+        let mut number = String::new();
+        if let Some('-') = self.chars.peek() {
+            number.push('-');
+            self.chars.next();
+        }
+
+        // no leading zeros
+        if let Some('0') = self.chars.peek() {
+            number.push('0');
+            self.chars.next();
+            if let Some('0'..='9') = self.chars.peek() {
+                return Err("Leading zeros are not allowed in JSON numbers".to_string());
+            }
+        } else {
+            self.digits(&mut number)?;
+        }
+
+        let mut float = false;
+        if let Some('.') = self.chars.peek() {
+            float = true;
+            number.push('.');
+            self.chars.next();
+            self.digits(&mut number)?;
+        }
+
+        if let Some(e @ ('e' | 'E')) = self.chars.peek() {
+            float = true;
+            number.push(*e);
+            self.chars.next();
+            if let Some(sign @ ('+' | '-')) = self.chars.peek() {
+                number.push(*sign);
+                self.chars.next();
+            }
+            self.digits(&mut number)?;
+        }
+
+        if !float {
+            if let Ok(integer) = number.parse::<i64>() {
+                return Ok(Data::Integer(integer));
+            }
+        }
+
+        // integers too large for 64 bits fall back to floats
+        number
+            .parse::<f64>()
+            .map(Data::Float)
+            .map_err(|_| format!("Invalid number `{}` in JSON", number))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{closure::Closure, lambda::Lambda};
+
+    #[test]
+    fn nested_round_trip() {
+        let json = r#"{"name":"passerine","tags":["small",true,null],"version":{"major":0,"minor":9.5},"empty":[]}"#;
+        let data = Data::from_json(json).unwrap();
+
+        let fields = match &data {
+            Data::Record(fields) => fields,
+            other => panic!("expected a record, found {:?}", other),
+        };
+        assert_eq!(fields["name"], Data::string("passerine"));
+        assert_eq!(
+            fields["tags"],
+            Data::list(vec![Data::string("small"), Data::Boolean(true), Data::Unit])
+        );
+
+        // records are ordered by key, so the output is canonical
+        let expected = r#"{"empty":[],"name":"passerine","tags":["small",true,null],"version":{"major":0,"minor":9.5}}"#;
+        assert_eq!(data.to_json().unwrap(), expected);
+        assert_eq!(Data::from_json(expected).unwrap(), data);
+    }
+
+    #[test]
+    fn numbers_and_strings() {
+        let data = Data::from_json(r#" [1, -2.5, 1e3, "a\"bé\n"] "#).unwrap();
+        assert_eq!(
+            data,
+            Data::list(vec![
+                Data::Integer(1),
+                Data::Float(-2.5),
+                Data::Float(1000.0),
+                Data::string("a\"bé\n"),
+            ])
+        );
+        assert_eq!(Data::from_json(&data.to_json().unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Data::from_json("[1, 2").is_err());
+        assert!(Data::from_json("{\"a\" 1}").is_err());
+        assert!(Data::from_json("1 2").is_err());
+    }
+
+    #[test]
+    fn number_grammar() {
+        assert_eq!(Data::from_json("0").unwrap(), Data::Integer(0));
+        assert_eq!(Data::from_json("-0.5e-2").unwrap(), Data::Float(-0.005));
+        assert_eq!(Data::from_json("2E+2").unwrap(), Data::Float(200.0));
+
+        for invalid in ["01", "-", "1.", ".5", "1e", "+1", "1.e3", "-01", "0x10"] {
+            assert!(Data::from_json(invalid).is_err(), "accepted `{}`", invalid);
+        }
+    }
+
+    #[test]
+    fn surrogates() {
+        assert_eq!(
+            Data::from_json(r#""\ud83d\ude00""#).unwrap(),
+            Data::string("😀")
+        );
+        assert!(Data::from_json(r#""\ud83d""#).is_err());
+        assert!(Data::from_json(r#""\ud83dx""#).is_err());
+        assert!(Data::from_json(r#""\ud83d\u0041""#).is_err());
+        assert!(Data::from_json(r#""\ude00""#).is_err());
+        assert!(Data::from_json("\"a\nb\"").is_err());
+    }
+
+    #[test]
+    fn too_deep() {
+        let deep = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+        assert!(Data::from_json(&deep).unwrap_err().contains("nested"));
+
+        let ok = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Data::from_json(&ok).is_ok());
+    }
+
+    #[test]
+    fn closure_error() {
+        let closure = Data::Closure(Box::new(Closure::wrap(Rc::new(Lambda::empty()))));
+        let data = Data::list(vec![Data::Integer(1), closure]);
+        assert!(data.to_json().is_err());
+        assert!(Data::Label(0, Box::new(Data::Unit)).to_json().is_err());
+    }
+}
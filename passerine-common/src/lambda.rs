@@ -1,6 +1,11 @@
 use std::fmt;
 
-use crate::{data::Data, number::build_number, opcode::Opcode, span::Span};
+use crate::{
+    data::Data,
+    number::{build_number, split_number},
+    opcode::Opcode,
+    span::Span,
+};
 
 /// Represents a variable visible in the current scope.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -11,6 +16,18 @@ pub enum Captured {
     Nonlocal(usize),
 }
 
+/// What a `Lambda` was compiled from,
+/// so that e.g. a module can be told apart from a function that returns a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LambdaKind {
+    /// A function, i.e. `x -> ...`.
+    Function,
+    /// A module, i.e. `mod { ... }`, which returns a record of its fields.
+    Module,
+    /// The top level of a program.
+    Script,
+}
+
 /// Represents a single interpretable chunk of bytecode,
 /// think a function.
 #[derive(Debug, Clone, PartialEq)]
@@ -21,23 +38,32 @@ pub struct Lambda {
     pub decls: usize,
     /// Each byte is an opcode or a number-stream.
     pub code: Vec<u8>,
-    /// Each usize indexes the bytecode op that begins each line.
+    /// Each usize indexes the first bytecode op of a run of ops
+    /// that share the same span, sorted by index.
     pub spans: Vec<(usize, Span)>,
     /// Number-stream indexed, used to load constants.
     pub constants: Vec<Data>,
     /// List of positions of locals in the scope where this lambda is defined,
     /// indexes must be gauranteed to be data on the heap.
     pub captures: Vec<Captured>,
-    // TODO: delete FFI
-    // / List of FFI functions (i.e. Rust functions)
-    // / that can be called from this function.
-    // pub ffi: Vec<FFIFunction>,
+    /// The name of each captured variable, in the same order as `captures`.
+    pub capture_names: Vec<String>,
+    /// Names of the FFI functions (i.e. Rust functions)
+    /// that can be called from this function.
+    /// The functions themselves are provided by the `Fiber`.
+    pub ffi: Vec<String>,
+    /// The name of the variable this function was assigned to, if any,
+    /// used to identify the function when profiling.
+    pub name: Option<String>,
+    /// What this lambda was compiled from.
+    pub kind: LambdaKind,
     // TODO: add effects
     // pub effects: Vec<usize>,
 }
 
 impl Lambda {
     /// Creates a new empty `Lambda` to be filled.
+    /// It is a `Script` until the compiler says otherwise.
     pub fn empty() -> Lambda {
         Lambda {
             decls: 0,
@@ -45,7 +71,10 @@ impl Lambda {
             spans: vec![],
             constants: vec![],
             captures: vec![],
-            // ffi:       vec![],
+            capture_names: vec![],
+            ffi: vec![],
+            name: None,
+            kind: LambdaKind::Script,
         }
     }
 
@@ -68,68 +97,173 @@ impl Lambda {
         return Some((numbers, offset));
     }
 
-    pub fn bounds(&self, opcode: Opcode) -> Vec<usize> {
-        match opcode {
+    /// Returns the exclusive upper bound of each operand an opcode takes,
+    /// or `None` if the opcode can not be run yet.
+    pub fn bounds(&self, opcode: Opcode) -> Option<Vec<usize>> {
+        let bounds = match opcode {
             Opcode::Con => vec![self.constants.len()],
             Opcode::NotInit => vec![],
             Opcode::Del => vec![],
-            Opcode::FFICall => panic!(),
+            Opcode::FFICall => vec![self.ffi.len()],
             Opcode::Copy => vec![],
-            // Opcode::Capture => vec![self.decls],
-
-            // TODO: correct bounds check?
+            Opcode::Capture => vec![self.decls],
             Opcode::Save => vec![self.decls],
             Opcode::SaveCap => vec![self.captures.len()],
             Opcode::Load => vec![self.decls],
             Opcode::LoadCap => vec![self.captures.len()],
             Opcode::Call => vec![],
-            Opcode::Return => vec![self.decls],
-            // TODO: correct bounds check?
+            // the number of locals to clear, which may be all of them
+            Opcode::Return => vec![self.decls + 1],
             Opcode::Closure => vec![self.constants.len()],
-            Opcode::Print => vec![],
             Opcode::Label => vec![],
             Opcode::Tuple => vec![usize::MAX], // TODO: stricter bounds
+            Opcode::Record => vec![usize::MAX], // TODO: stricter bounds
             Opcode::UnData => vec![],
             Opcode::UnLabel => vec![],
             Opcode::UnTuple => vec![usize::MAX], // TODO: stricter bounds
+            Opcode::TupleExtend => vec![],
+            Opcode::Test => vec![],
+            Opcode::TestTuple => vec![usize::MAX],
+            Opcode::TestLabel => vec![],
+            // jumping to the end of the code is allowed
+            Opcode::Jump | Opcode::JumpFalse => vec![self.code.len() + 1],
+            Opcode::NoMatch => vec![],
+            Opcode::RecordUpdate => vec![usize::MAX], // TODO: stricter bounds
+            // the index of the local is checked by `verify`
+            Opcode::Load0 | Opcode::Load1 | Opcode::Load2 | Opcode::Load3 => vec![],
+            Opcode::Save0 | Opcode::Save1 | Opcode::Save2 | Opcode::Save3 => vec![],
             Opcode::Noop => vec![],
-            e => panic!("not implemented {:?}", e),
+            _ => return None,
+        };
+
+        Some(bounds)
+    }
+
+    /// Safely reads the number-stream operand starting at `index`.
+    /// Returns the operand and the number of bytes it takes up,
+    /// or `None` if the operand runs past the end of the code.
+    fn operand_safe(&self, index: usize) -> Option<(usize, usize)> {
+        let rest = self.code.get(index..)?;
+        let end = rest.iter().position(|byte| byte & 0b1000_0000 != 0)?;
+        // a usize can be split into at most 10 bytes
+        if end >= 10 {
+            return None;
         }
+        Some(build_number(&rest[..=end]))
     }
 
-    /// NOTE: WIP, do not use.
-    /// Statically verifies some bytecode safely,
-    /// By ensuring bytecode ops are within bounds,
-    /// As well as the arguments those ops take.
-    /// Returns `false` if the bytecode is invalid.
-    pub fn verify(&self) -> bool {
-        // go through each opcode
-        // check the number of arguments
-        // check that the arguments are valid
+    /// Statically verifies some bytecode before it is run.
+    /// Checks that every opcode is valid, that no operand reads past the
+    /// end of the code, and that every constant, local, capture, and FFI
+    /// index is within bounds. Functions in the constant table are verified
+    /// as well. This should be run on any bytecode not produced by the
+    /// compiler, e.g. bytecode loaded from disk.
+    /// Every `Label`, `UnLabel`, and `TestLabel` must directly follow the load of a constant kind,
+    /// and can not be jumped to, so that labels are always well-formed.
+    /// Returns a description of the first problem found.
+    pub fn verify(&self) -> Result<(), String> {
         let mut index = 0;
+        let mut boundaries = vec![];
+        let mut jumps = vec![];
+        let mut labels = vec![];
+        // whether the previous instruction loaded a constant kind
+        let mut after_kind = false;
 
         while index < self.code.len() {
-            // safely decode an opcode
-            let opcode = match Opcode::from_byte_safe(self.code[index]) {
-                Some(o) => o,
-                None => {
-                    return false;
+            boundaries.push(index);
+            let opcode = Opcode::from_byte_safe(self.code[index])
+                .ok_or_else(|| format!("Invalid opcode {} at byte {}", self.code[index], index))?;
+
+            if let Opcode::Label | Opcode::UnLabel | Opcode::TestLabel = opcode {
+                if !after_kind {
+                    return Err(format!(
+                        "{:?} at byte {} must directly follow the load of a constant kind",
+                        opcode, index
+                    ));
                 }
-            };
+                labels.push(index);
+            }
+            after_kind = false;
+            if let Some(local) = opcode.local_index() {
+                if local >= self.decls {
+                    return Err(format!(
+                        "{:?} at byte {} is out of bounds, expected less than {} locals",
+                        opcode, index, self.decls
+                    ));
+                }
+            }
+            let bounds = self
+                .bounds(opcode)
+                .ok_or_else(|| format!("Unsupported opcode {:?} at byte {}", opcode, index))?;
 
+            let start = index;
             index += 1;
-            let bounds = self.bounds(opcode);
-            let args_result = self.args_safe(index, &bounds);
+            for bound in bounds {
+                let (operand, consumed) = self.operand_safe(index).ok_or_else(|| {
+                    format!(
+                        "Operand of {:?} at byte {} runs past the end of the code",
+                        opcode, start
+                    )
+                })?;
+
+                if operand >= bound {
+                    return Err(format!(
+                        "Operand {} of {:?} at byte {} is out of bounds, expected less than {}",
+                        operand, opcode, start, bound
+                    ));
+                }
 
-            index += match args_result {
-                Some((_args, consumed)) => consumed,
-                None => {
-                    return false;
+                if let Opcode::Jump | Opcode::JumpFalse = opcode {
+                    jumps.push((start, operand));
                 }
+
+                if opcode == Opcode::Con {
+                    after_kind = matches!(self.constants[operand], Data::Kind(_));
+                }
+
+                if opcode == Opcode::Closure {
+                    if let Data::Lambda(_) = self.constants[operand] {
+                    } else {
+                        return Err(format!(
+                            "Closure at byte {} must close over a function constant",
+                            start
+                        ));
+                    }
+                }
+
+                index += consumed;
+            }
+        }
+
+        for (start, target) in jumps {
+            if boundaries.binary_search(&target).is_err() {
+                return Err(format!(
+                    "Jump at byte {} targets byte {}, which is not the start of an instruction",
+                    start, target
+                ));
+            }
+            if labels.binary_search(&target).is_ok() {
+                return Err(format!(
+                    "Jump at byte {} targets the label at byte {}, \
+                     which must directly follow the load of its kind",
+                    start, target
+                ));
+            }
+        }
+
+        for constant in self.constants.iter() {
+            match constant {
+                Data::Lambda(lambda) => lambda.verify()?,
+                Data::Closure(closure) => closure.lambda.verify()?,
+                _ => (),
             }
         }
 
-        return true;
+        // the fiber stops at a return rather than checking bounds
+        match boundaries.last() {
+            Some(last) if self.code[*last] == Opcode::Return as u8 => Ok(()),
+            _ => Err("The code does not end in a Return".to_string()),
+        }
     }
 
     /// Emits an opcode as a byte.
@@ -145,13 +279,42 @@ impl Lambda {
     /// Emits a span, should be called before an opcode is emmited.
     /// This function ties opcodes to spans in source.
     /// See index_span as well.
+    /// Only the last span emitted before an opcode is kept,
+    /// and consecutive opcodes with the same span share a single entry.
     pub fn emit_span(&mut self, span: &Span) {
-        self.spans.push((self.code.len(), span.clone()))
+        let index = self.code.len();
+        // no opcode has been emitted since the last span, so it covers nothing
+        while matches!(self.spans.last(), Some((i, _)) if *i == index) {
+            self.spans.pop();
+        }
+        if !matches!(self.spans.last(), Some((_, last)) if last == span) {
+            self.spans.push((index, span.clone()))
+        }
+    }
+
+    /// Emits a jump whose target is not yet known,
+    /// returning the index of its operand so that it can be patched later.
+    /// The operand is a fixed number of bytes wide, so patching it
+    /// does not move any code after it.
+    pub fn emit_jump(&mut self, op: Opcode) -> usize {
+        self.emit(op);
+        let operand = self.code.len();
+        self.emit_bytes(&mut split_fixed(0));
+        operand
     }
 
-    /// Removes the last emitted byte.
-    pub fn demit(&mut self) {
-        self.code.pop();
+    /// Patches the operand of a jump emitted with `emit_jump`
+    /// to target the next instruction emitted.
+    pub fn patch_jump(&mut self, operand: usize) {
+        let target = split_fixed(self.code.len());
+        self.code[operand..operand + JUMP_WIDTH].copy_from_slice(&target);
+    }
+
+    /// Emits a jump to an instruction that has already been emitted,
+    /// e.g. back to the start of a loop.
+    pub fn emit_jump_to(&mut self, op: Opcode, target: usize) {
+        self.emit(op);
+        self.emit_bytes(&mut split_fixed(target));
     }
 
     /// Given some data, this function adds it to the constants table,
@@ -160,7 +323,7 @@ impl Lambda {
     /// index. The resulting usize can be split up into a number byte
     /// stream, and be inserted into the bytecode.
     pub fn index_data(&mut self, data: Data) -> usize {
-        match self.constants.iter().position(|d| d == &data) {
+        match self.constants.iter().position(|d| same_constant(d, &data)) {
             Some(d) => d,
             None => {
                 self.constants.push(data);
@@ -172,26 +335,22 @@ impl Lambda {
     /// Look up the nearest span at or before the index of a specific bytecode
     /// op.
     pub fn index_span(&self, index: usize) -> Span {
-        let mut best = None;
+        let after = self.spans.partition_point(|(i, _)| *i <= index);
+        self.spans[after - 1].1.clone()
+    }
 
-        for (i, span) in self.spans.iter() {
-            if i > &index {
-                break;
+    /// Adds the name of an FFI function to the FFI table,
+    /// and returns its index.
+    /// The `Compiler` ensures that the function exists.
+    pub fn add_ffi(&mut self, name: &str) -> usize {
+        match self.ffi.iter().position(|n| n == name) {
+            Some(index) => index,
+            None => {
+                self.ffi.push(name.to_string());
+                self.ffi.len() - 1
             }
-            best = Some(span);
         }
-
-        return best.unwrap().clone();
     }
-
-    // /// Adds a ffi function to the ffi table,
-    // /// without checking for duplicates.
-    // /// The `Compiler` ensures that functions are valid
-    // /// and not duplicated during codegen.
-    // pub fn add_ffi(&mut self, function: FFIFunction) -> usize {
-    //     self.ffi.push(function);
-    //     self.ffi.len() - 1
-    // }
 }
 
 impl fmt::Display for Lambda {
@@ -208,6 +367,11 @@ impl fmt::Display for Lambda {
             writeln!(f, "{:?}", capture)?;
         }
 
+        writeln!(f, "Dumping FFI:")?;
+        for name in self.ffi.iter() {
+            writeln!(f, "{}", name)?;
+        }
+
         writeln!(f, "Dumping Variables: {:?}", self.decls)?;
 
         writeln!(f, "Dumping Bytecode:")?;
@@ -228,7 +392,13 @@ impl fmt::Display for Lambda {
             write!(f, "{:?}\t", opcode)?;
 
             index += 1;
-            let bounds = self.bounds(opcode);
+            let bounds = match self.bounds(opcode) {
+                Some(bounds) => bounds,
+                None => {
+                    writeln!(f, "\nUnsupported Opcode at index {}", index)?;
+                    break;
+                }
+            };
             let args_result = self.args_safe(index, &bounds);
 
             let (args, consumed) = match args_result {
@@ -253,3 +423,272 @@ impl fmt::Display for Lambda {
         return fmt::Result::Ok(());
     }
 }
+
+/// The number of bytes the operand of a jump takes up.
+const JUMP_WIDTH: usize = 4;
+
+/// Splits a jump target into exactly `JUMP_WIDTH` bytes,
+/// padding the front of the number with zeros.
+fn split_fixed(target: usize) -> Vec<u8> {
+    let bytes = split_number(target);
+    assert!(
+        bytes.len() <= JUMP_WIDTH,
+        "Function too large to jump within"
+    );
+
+    let mut fixed = vec![0; JUMP_WIDTH - bytes.len()];
+    fixed.extend(bytes);
+    fixed
+}
+
+/// Whether two constants can share an index in the constants table.
+/// Floats are compared bitwise, so `0.0` and `-0.0` stay distinct
+/// and identical `NaN`s are shared.
+/// Functions are never shared, as each closure is unique when constructed.
+fn same_constant(a: &Data, b: &Data) -> bool {
+    match (a, b) {
+        (Data::Float(a), Data::Float(b)) => a.to_bits() == b.to_bits(),
+        (Data::Tuple(a), Data::Tuple(b)) | (Data::List(a), Data::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| same_constant(a, b))
+        }
+        (Data::Label(a, x), Data::Label(b, y)) => a == b && same_constant(x, y),
+        (Data::Record(a), Data::Record(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b.iter())
+                    .all(|((k, x), (l, y))| k == l && same_constant(x, y))
+        }
+        (Data::Lambda(_), _) | (Data::Closure(_), _) | (Data::Map(_), _) => false,
+        (a, b) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::closure::Closure;
+
+    /// Loads a constant into a variable, then returns it.
+    fn valid() -> Lambda {
+        let mut lambda = Lambda::empty();
+        lambda.decls = 1;
+        let index = lambda.index_data(Data::Integer(7));
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(index));
+        lambda.emit(Opcode::Save);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda.emit(Opcode::Load);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(1));
+        lambda
+    }
+
+    fn error(lambda: &Lambda) -> String {
+        lambda.verify().unwrap_err()
+    }
+
+    #[test]
+    fn verify_valid() {
+        assert_eq!(valid().verify(), Ok(()));
+    }
+
+    #[test]
+    fn verify_invalid_opcode() {
+        let mut lambda = valid();
+        lambda.code.push(Opcode::Noop as u8 + 1);
+        assert!(error(&lambda).starts_with("Invalid opcode"));
+    }
+
+    #[test]
+    fn verify_constant_out_of_bounds() {
+        let mut lambda = valid();
+        lambda.constants.clear();
+        assert!(error(&lambda).contains("Con at byte 0 is out of bounds"));
+    }
+
+    #[test]
+    fn verify_local_out_of_bounds() {
+        let mut lambda = valid();
+        lambda.decls = 0;
+        assert!(error(&lambda).contains("Save at byte 2 is out of bounds"));
+    }
+
+    #[test]
+    fn verify_small_local_out_of_bounds() {
+        let mut lambda = valid();
+        lambda.emit(Opcode::Load0);
+        lambda.emit(Opcode::Save3);
+        assert!(error(&lambda).contains("Save3 at byte 9 is out of bounds"));
+    }
+
+    #[test]
+    fn verify_capture_out_of_bounds() {
+        let mut lambda = valid();
+        lambda.emit(Opcode::LoadCap);
+        lambda.emit_bytes(&mut split_number(0));
+        assert!(error(&lambda).contains("LoadCap at byte 8 is out of bounds"));
+    }
+
+    #[test]
+    fn verify_jump_target() {
+        // jump over a constant to the return
+        let mut lambda = Lambda::empty();
+        let index = lambda.index_data(Data::Unit);
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(index));
+        let operand = lambda.emit_jump(Opcode::Jump);
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(index));
+        lambda.patch_jump(operand);
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        assert_eq!(lambda.verify(), Ok(()));
+
+        // jump into the operand of the first instruction
+        let mut lambda = valid();
+        lambda.emit(Opcode::Jump);
+        lambda.emit_bytes(&mut split_number(1));
+        assert!(error(&lambda).contains("not the start of an instruction"));
+    }
+
+    #[test]
+    fn verify_label_kind() {
+        // data, then its kind, then the label
+        let labeled = |kind: Data, op: Opcode| {
+            let mut lambda = Lambda::empty();
+            for constant in [Data::Integer(1), kind] {
+                let index = lambda.index_data(constant);
+                lambda.emit(Opcode::Con);
+                lambda.emit_bytes(&mut split_number(index));
+            }
+            lambda.emit(op);
+            lambda.emit(Opcode::Return);
+            lambda.emit_bytes(&mut split_number(0));
+            lambda
+        };
+        assert_eq!(labeled(Data::Kind(0), Opcode::Label).verify(), Ok(()));
+        assert_eq!(labeled(Data::Kind(0), Opcode::UnLabel).verify(), Ok(()));
+        assert_eq!(labeled(Data::Kind(0), Opcode::TestLabel).verify(), Ok(()));
+
+        let lambda = labeled(Data::Integer(2), Opcode::Label);
+        assert!(error(&lambda).contains("Label at byte 4 must directly follow"));
+        let lambda = labeled(Data::Integer(2), Opcode::UnLabel);
+        assert!(error(&lambda).contains("UnLabel at byte 4 must directly follow"));
+
+        // the kind is loaded, but not directly before the label
+        let mut lambda = Lambda::empty();
+        let kind = lambda.index_data(Data::Kind(0));
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(kind));
+        lambda.emit(Opcode::Copy);
+        lambda.emit(Opcode::Label);
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        assert!(error(&lambda).contains("Label at byte 3 must directly follow"));
+
+        // jumping to a label skips the load of its kind
+        let mut lambda = labeled(Data::Kind(0), Opcode::Label);
+        lambda.emit(Opcode::Jump);
+        lambda.emit_bytes(&mut split_number(4));
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        assert!(error(&lambda).contains("targets the label at byte 4"));
+    }
+
+    #[test]
+    fn verify_trailing_return() {
+        assert!(error(&Lambda::empty()).contains("does not end in a Return"));
+
+        let mut lambda = valid();
+        lambda.emit(Opcode::Del);
+        assert!(error(&lambda).contains("does not end in a Return"));
+    }
+
+    #[test]
+    fn verify_truncated() {
+        let mut lambda = valid();
+        // an operand byte without the end bit set
+        lambda.code.push(Opcode::Load as u8);
+        lambda.code.push(0);
+        assert!(error(&lambda).contains("runs past the end"));
+
+        lambda.code.truncate(lambda.code.len() - 1);
+        assert!(error(&lambda).contains("runs past the end"));
+    }
+
+    #[test]
+    fn verify_closure_constant() {
+        let mut lambda = valid();
+        lambda.emit(Opcode::Closure);
+        lambda.emit_bytes(&mut split_number(0));
+        assert!(error(&lambda).contains("must close over a function"));
+
+        // nested functions are verified too
+        let mut nested = valid();
+        nested.constants.clear();
+        let mut lambda = valid();
+        let index = lambda.index_data(Data::Lambda(Rc::new(nested)));
+        lambda.emit(Opcode::Closure);
+        lambda.emit_bytes(&mut split_number(index));
+        assert!(error(&lambda).contains("Con at byte 0"));
+    }
+
+    #[test]
+    fn dedup_constants() {
+        let mut lambda = Lambda::empty();
+        let zero = lambda.index_data(Data::Float(0.0));
+        let nan = lambda.index_data(Data::Float(f64::NAN));
+        let pair = lambda.index_data(Data::tuple(vec![Data::Integer(1), Data::Unit]));
+
+        assert_eq!(lambda.index_data(Data::Float(0.0)), zero);
+        assert_eq!(lambda.index_data(Data::Float(f64::NAN)), nan);
+        assert_eq!(
+            lambda.index_data(Data::tuple(vec![Data::Integer(1), Data::Unit])),
+            pair
+        );
+        assert_ne!(lambda.index_data(Data::Float(-0.0)), zero);
+        assert_eq!(lambda.constants.len(), 4);
+
+        // each closure gets its own constant
+        let closure = || Data::Closure(Box::new(Closure::wrap(Rc::new(valid()))));
+        let first = lambda.index_data(closure());
+        assert_ne!(lambda.index_data(closure()), first);
+    }
+
+    #[test]
+    fn compact_spans() {
+        let source = crate::source::Source::source("x = 1; y = x; z = y");
+        let span = |offset| Span::new(&source, offset, 5);
+
+        // every span emitted, as the table used to store them
+        let mut lambda = Lambda::empty();
+        let mut all = vec![];
+        let mut emit_span = |lambda: &mut Lambda, span: Span| {
+            all.push((lambda.code.len(), span.clone()));
+            lambda.emit_span(&span);
+        };
+        for offset in [0, 7, 14] {
+            emit_span(&mut lambda, span(offset));
+            emit_span(&mut lambda, span(offset));
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(0));
+            emit_span(&mut lambda, span(offset));
+            lambda.emit(Opcode::Save);
+            lambda.emit_bytes(&mut split_number(0));
+            emit_span(&mut lambda, span(14 - offset));
+            emit_span(&mut lambda, span(offset));
+            lambda.emit(Opcode::Del);
+        }
+
+        // the nearest span at or before each op is unchanged
+        for index in 0..lambda.code.len() {
+            let expected = all.iter().rev().find(|(i, _)| *i <= index).unwrap();
+            assert_eq!(lambda.index_span(index), expected.1);
+        }
+        assert_eq!(lambda.spans.len(), 3);
+        assert!(lambda.spans.len() < all.len());
+    }
+}
@@ -5,10 +5,13 @@
 //! - Opcodes and number splicing.
 //! - Source code representation and span annotations.
 
+pub mod bytecode;
 pub mod closure;
 pub mod data;
 pub mod effect;
 pub mod inject;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod lambda;
 pub mod lit;
 pub mod module;
@@ -16,7 +19,9 @@ pub mod number;
 pub mod opcode;
 pub mod source;
 pub mod span;
+pub mod stamp;
 pub mod ty;
+pub mod width;
 
 pub use closure::Closure;
 pub use data::Data;
@@ -42,7 +42,7 @@ impl Lit {
         match self {
             Lit::Float(f) => Data::Float(f),
             Lit::Integer(i) => Data::Integer(i),
-            Lit::String(s) => Data::String(s),
+            Lit::String(s) => Data::string(s),
             Lit::Label(_, _) => todo!(),
             Lit::Unit => Data::Unit,
             Lit::Boolean(b) => Data::Boolean(b),
@@ -51,13 +51,34 @@ impl Lit {
 }
 
 impl Display for Lit {
-    /// Displays some Passerine Data in a pretty manner, as if it were printed
-    /// to console.
+    /// Displays a literal as it would be written in source,
+    /// so that lexing the result produces the same literal.
+    /// Negative numbers are written with a leading `-`,
+    /// which is read back as a negation.
+    /// `NaN` and the infinities have no source form,
+    /// so are shown as `NaN`, `inf`, and `-inf`.
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
+            // floats are shown in full, with as many digits as needed,
+            // and always with a decimal point so they aren't read as integers
+            Lit::Float(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{}.0", n),
             Lit::Float(n) => write!(f, "{}", n),
             Lit::Integer(n) => write!(f, "{}", n),
-            Lit::String(s) => write!(f, "{}", s),
+            Lit::String(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        '\0' => write!(f, "\\0")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
             // TODO: better representation for Labels
             Lit::Label(n, v) => write!(f, "#{}({})", n, v),
             Lit::Unit => write!(f, "()"),
@@ -67,3 +88,22 @@ impl Display for Lit {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_source_form() {
+        assert_eq!(Lit::Float(1.0).to_string(), "1.0");
+        assert_eq!(Lit::Float(0.1).to_string(), "0.1");
+        assert_eq!(Lit::Float(1e21).to_string(), "1000000000000000000000.0");
+        assert_eq!(Lit::Integer(-3).to_string(), "-3");
+        assert_eq!(
+            Lit::String("say \"hi\"\n\t\\".to_string()).to_string(),
+            "\"say \\\"hi\\\"\\n\\t\\\\\""
+        );
+        assert_eq!(Lit::Unit.to_string(), "()");
+        assert_eq!(Lit::Boolean(false).to_string(), "False");
+    }
+}
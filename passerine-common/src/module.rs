@@ -1,7 +1,8 @@
-use std::{fs, path::Path, rc::Rc};
+use std::{path::Path, rc::Rc};
 
-use crate::source::Source;
+use crate::source::{FileSystem, Source, SourceProvider};
 
+#[derive(Debug)]
 pub struct Module {
     source: Rc<Source>,
     children: Vec<Module>,
@@ -13,9 +14,16 @@ pub const EXTENSION: &str = "pn";
 // TODO: handle symlinks, ugh
 
 impl Module {
+    /// Loads the module in a directory on disk, along with its children.
     pub fn new_from_dir(entry_path: &Path) -> Result<Module, String> {
+        Module::provide(&FileSystem, entry_path)
+    }
+
+    /// Loads the module in a directory, along with its children,
+    /// reading all files and directories through a `SourceProvider`.
+    pub fn provide(provider: &impl SourceProvider, entry_path: &Path) -> Result<Module, String> {
         // grab the entries in the directory
-        let paths = fs::read_dir(entry_path).map_err(|_| {
+        let paths = provider.entries(entry_path).map_err(|_| {
             format!(
                 "The path `{}` could not be read as a directory",
                 entry_path.display()
@@ -28,29 +36,19 @@ impl Module {
         let mut children = vec![];
 
         for path in paths {
-            // get an actual entry
-            let path = path
-                .map_err(|_| {
-                    format!(
-                        "Err while scanning the module located at `{}`",
-                        entry_path.display()
-                    )
-                })?
-                .path();
-
             // classify the entry
             let is_source_file = path.extension().map(|x| x == EXTENSION).unwrap_or(false);
             let is_entry_point = path.file_stem().map(|x| x == ENTRY_POINT).unwrap_or(false);
 
             // grab the module at the given path
-            let module = if path.is_dir() {
-                if path.join(&entry_point_name).exists() {
-                    Module::new_from_dir(&path)?
+            let module = if provider.is_dir(&path) {
+                if provider.is_file(&path.join(&entry_point_name)) {
+                    Module::provide(provider, &path)?
                 } else {
                     continue;
                 }
-            } else if path.is_file() && is_source_file {
-                let source = Source::path(&path)
+            } else if provider.is_file(&path) && is_source_file {
+                let source = Source::provide(provider, &path)
                     .map_err(|_| format!("Could not read source file `{}`", path.display()))?;
 
                 if is_entry_point {
@@ -89,3 +87,52 @@ impl Module {
         Ok(Module { source, children })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source::InMemory;
+
+    /// The paths of the sources in a module and its children, depth-first.
+    fn paths(module: &Module) -> Vec<String> {
+        let mut all = vec![module.source.path.display().to_string()];
+        for child in module.children.iter() {
+            all.extend(paths(child));
+        }
+        all
+    }
+
+    #[test]
+    fn module_graph() {
+        let provider = InMemory::new([
+            ("pkg/main.pn", "x = 1"),
+            ("pkg/util.pn", "y = 2"),
+            ("pkg/notes.txt", "not a source file"),
+            ("pkg/net/main.pn", "z = 3"),
+            ("pkg/net/http.pn", "w = 4"),
+            ("pkg/assets/logo.svg", "a directory without an entry point"),
+        ]);
+
+        let module = Module::provide(&provider, Path::new("pkg")).unwrap();
+        assert_eq!(module.source.contents, "x = 1");
+        assert_eq!(
+            paths(&module),
+            vec![
+                "pkg/main.pn",
+                "pkg/net/main.pn",
+                "pkg/net/http.pn",
+                "pkg/util.pn"
+            ],
+        );
+    }
+
+    #[test]
+    fn missing_entry_point() {
+        let provider = InMemory::new([("pkg/util.pn", "y = 2")]);
+        let error = Module::provide(&provider, Path::new("pkg")).unwrap_err();
+        assert!(error.contains("No entry point"));
+
+        let error = Module::provide(&provider, Path::new("missing")).unwrap_err();
+        assert!(error.contains("could not be read as a directory"));
+    }
+}
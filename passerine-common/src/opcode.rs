@@ -2,7 +2,7 @@
 /// Under the hood, it's just a byte.
 /// This allows non opcode bytes to be inserted in bytecode streams.
 #[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     /// Load a constant.
     Con = 0,
@@ -30,41 +30,78 @@ pub enum Opcode {
     Return = 11,
     /// Creates a closure over the current local environment.
     Closure = 12,
-    /// Prints a value.
-    Print = 13,
     ///
-    Handler = 14,
+    Handler = 13,
     ///
-    Effect = 15,
+    Effect = 14,
     /// Constructs a label.
-    Label = 16,
+    Label = 15,
     /// Constructs a tuple.
-    Tuple = 17,
-    ///
-    Record = 18,
+    Tuple = 16,
+    /// Constructs a record from pairs of field names and values.
+    Record = 17,
     /// Destructures atomic data by asserting it matches exactly.
-    UnData = 19,
+    UnData = 18,
     // TODO: make unlabel take the label index as an arg.
     /// Destructures a label.
-    UnLabel = 20,
+    UnLabel = 19,
     /// Destructures a tuple.
-    UnTuple = 21,
+    UnTuple = 20,
     /// Add two numbers on the stack.
-    Add = 22,
+    Add = 21,
     /// Subtract two numbers on the stack.
-    Sub = 23,
+    Sub = 22,
     /// Negate a number.
-    Neg = 24,
+    Neg = 23,
     /// Multiple two numbers on the stack.
-    Mul = 25,
+    Mul = 24,
     /// Divide two numbers, raising ZeroDiv side effect
-    Div = 26,
+    Div = 25,
     /// Take the remainder of two numbers, raising ZeroDiv side effect
-    Rem = 27,
+    Rem = 26,
     /// Take a number to a power.
-    Pow = 28,
+    Pow = 27,
+    /// Splices the topmost tuple into the tuple below it.
+    TupleExtend = 28,
+    /// Tests whether some data matches the expected data exactly,
+    /// replacing both with a boolean.
+    Test = 29,
+    /// Tests whether the topmost value is a tuple of a certain length,
+    /// pushing a boolean. Does not remove the value tested.
+    TestTuple = 30,
+    /// Jumps to an instruction.
+    Jump = 31,
+    /// Jumps to an instruction if the topmost value is false,
+    /// removing the boolean tested.
+    JumpFalse = 32,
+    /// Raises an error because no arm of a match matched the topmost value.
+    NoMatch = 33,
+    /// Copies a record, replacing the values of `n` of its fields
+    /// from pairs of field names and values.
+    RecordUpdate = 34,
+    /// Push a copy of the variable at index 0 onto the stack.
+    /// Like `Load`, but without an operand to decode.
+    Load0 = 35,
+    /// Push a copy of the variable at index 1 onto the stack.
+    Load1 = 36,
+    /// Push a copy of the variable at index 2 onto the stack.
+    Load2 = 37,
+    /// Push a copy of the variable at index 3 onto the stack.
+    Load3 = 38,
+    /// Save a value into the variable at index 0.
+    /// Like `Save`, but without an operand to decode.
+    Save0 = 39,
+    /// Save a value into the variable at index 1.
+    Save1 = 40,
+    /// Save a value into the variable at index 2.
+    Save2 = 41,
+    /// Save a value into the variable at index 3.
+    Save3 = 42,
+    /// Tests whether the value beneath a kind is a label of that kind,
+    /// replacing the kind with a boolean. Does not remove the value tested.
+    TestLabel = 43,
     /// Does nothing. Must always be last.
-    Noop = 29,
+    Noop = 44,
 }
 
 impl Opcode {
@@ -90,6 +127,39 @@ impl Opcode {
             None
         }
     }
+
+    /// The `Load` without an operand for a variable, if its index is small enough.
+    pub fn load_local(index: usize) -> Option<Opcode> {
+        match index {
+            0 => Some(Opcode::Load0),
+            1 => Some(Opcode::Load1),
+            2 => Some(Opcode::Load2),
+            3 => Some(Opcode::Load3),
+            _ => None,
+        }
+    }
+
+    /// The `Save` without an operand for a variable, if its index is small enough.
+    pub fn save_local(index: usize) -> Option<Opcode> {
+        match index {
+            0 => Some(Opcode::Save0),
+            1 => Some(Opcode::Save1),
+            2 => Some(Opcode::Save2),
+            3 => Some(Opcode::Save3),
+            _ => None,
+        }
+    }
+
+    /// The index of the variable a `Load` or `Save` without an operand refers to.
+    pub fn local_index(self) -> Option<usize> {
+        match self {
+            Opcode::Load0 | Opcode::Save0 => Some(0),
+            Opcode::Load1 | Opcode::Save1 => Some(1),
+            Opcode::Load2 | Opcode::Save2 => Some(2),
+            Opcode::Load3 | Opcode::Save3 => Some(3),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -104,4 +174,17 @@ mod tests {
             Opcode::from_byte_safe(Opcode::Noop as u8)
         );
     }
+
+    #[test]
+    fn small_locals() {
+        for index in 0..4 {
+            let load = Opcode::load_local(index).unwrap();
+            let save = Opcode::save_local(index).unwrap();
+            assert_eq!(load.local_index(), Some(index));
+            assert_eq!(save.local_index(), Some(index));
+        }
+        assert_eq!(Opcode::load_local(4), None);
+        assert_eq!(Opcode::save_local(4), None);
+        assert_eq!(Opcode::Load.local_index(), None);
+    }
 }
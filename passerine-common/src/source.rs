@@ -1,6 +1,7 @@
 use std::{
-    fs::File,
-    io::Read,
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    ops::Range,
     path::{Path, PathBuf},
     rc::Rc,
 };
@@ -36,11 +37,14 @@ impl Source {
 
     /// Build a `Source` from a path.
     /// This will read a file to create a new source.
-    pub fn path(path: &Path) -> std::io::Result<Rc<Source>> {
-        let mut source = String::new();
-        let mut file = File::open(path)?;
-        file.read_to_string(&mut source)?;
+    pub fn path(path: &Path) -> io::Result<Rc<Source>> {
+        Source::provide(&FileSystem, path)
+    }
 
+    /// Build a `Source` from a path,
+    /// reading the file through a `SourceProvider`.
+    pub fn provide(provider: &impl SourceProvider, path: &Path) -> io::Result<Rc<Source>> {
+        let source = provider.read(path)?;
         Ok(Source::new(&source, path))
     }
 
@@ -49,4 +53,161 @@ impl Source {
     pub fn source(source: &str) -> Rc<Source> {
         Source::new(source, &PathBuf::from("./source"))
     }
+
+    /// Returns the lines that a byte range of the source covers,
+    /// each paired with its line number, counting from 1.
+    /// An empty range covers the line it is on.
+    /// Lines do not include their trailing newline.
+    pub fn lines(&self, range: Range<usize>) -> Vec<(usize, &str)> {
+        let last = range.end.saturating_sub(1).max(range.start);
+        let mut lines = vec![];
+        let mut start = 0;
+
+        for (index, line) in self.contents.split('\n').enumerate() {
+            // the end of a line includes its newline
+            let end = start + line.len();
+            if start > last {
+                break;
+            }
+            if end >= range.start {
+                lines.push((index + 1, line));
+            }
+            start = end + 1;
+        }
+
+        lines
+    }
+}
+
+/// Provides access to source files and the directories that hold them,
+/// so that sources and modules can be loaded from somewhere other than disk.
+pub trait SourceProvider {
+    /// Reads the contents of the file at a path.
+    fn read(&self, path: &Path) -> io::Result<String>;
+
+    /// Lists the paths of the entries in the directory at a path.
+    fn entries(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Whether there is a file at a path.
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Whether there is a directory at a path.
+    fn is_dir(&self, path: &Path) -> bool;
+}
+
+/// Provides sources from the real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+impl SourceProvider for FileSystem {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn entries(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+}
+
+/// Provides sources from memory, mapping the path of each file to its contents.
+/// Directories are implied by the paths of the files they contain.
+/// Useful for testing.
+#[derive(Debug, Clone, Default)]
+pub struct InMemory {
+    pub files: HashMap<PathBuf, String>,
+}
+
+impl InMemory {
+    /// Creates an in-memory provider from pairs of paths and contents.
+    pub fn new<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> InMemory {
+        InMemory {
+            files: files
+                .into_iter()
+                .map(|(path, contents)| (PathBuf::from(path), contents.to_string()))
+                .collect(),
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("`{}` does not exist", path.display()),
+        )
+    }
+}
+
+impl SourceProvider for InMemory {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| InMemory::not_found(path))
+    }
+
+    fn entries(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return Err(InMemory::not_found(path));
+        }
+
+        // the first component of each file path under this directory
+        let entries: BTreeSet<PathBuf> = self
+            .files
+            .keys()
+            .filter_map(|file| file.strip_prefix(path).ok())
+            .filter_map(|rest| rest.components().next())
+            .map(|first| path.join(first))
+            .collect();
+
+        Ok(entries.into_iter().collect())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.files
+            .keys()
+            .any(|file| file != path && file.starts_with(path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = "first\nsecond\nthird\nfourth";
+
+    #[test]
+    fn lines_middle() {
+        let source = Source::source(SOURCE);
+        // `cond\nthi`
+        let start = SOURCE.find("cond").unwrap();
+        let end = SOURCE.find("rd").unwrap();
+        assert_eq!(source.lines(start..end), vec![(2, "second"), (3, "third")]);
+    }
+
+    #[test]
+    fn lines_single() {
+        let source = Source::source(SOURCE);
+        assert_eq!(source.lines(0..5), vec![(1, "first")]);
+        // an empty range at the start of a line
+        let fourth = SOURCE.find("fourth").unwrap();
+        assert_eq!(source.lines(fourth..fourth), vec![(4, "fourth")]);
+        // a range ending just after a newline does not include the next line
+        assert_eq!(source.lines(0..6), vec![(1, "first")]);
+        // the end of the source
+        let end = SOURCE.len();
+        assert_eq!(source.lines(end..end), vec![(4, "fourth")]);
+    }
 }
@@ -1,11 +1,19 @@
 use std::{
     fmt::{self, Debug, Display, Formatter},
-    ops::Add,
+    path::Path,
     rc::Rc,
     usize,
 };
 
-use crate::source::Source;
+use crate::{source::Source, width::str_width};
+
+/// The path of the source dummy `Span`s point into.
+pub const GENERATED: &str = "(generated code)";
+
+thread_local! {
+    /// The empty source all dummy `Span`s share.
+    static DUMMY_SOURCE: Rc<Source> = Source::new("", Path::new(GENERATED));
+}
 
 /// A `Span` refers to a section of a source,
 /// much like a `&str`, but with a reference to a `Source`
@@ -42,6 +50,38 @@ impl Span {
         }
     }
 
+    /// A zero-length `Span` for code that has no location in any source,
+    /// i.e. nodes synthesized during desugaring or macro expansion.
+    /// Errors pointing at a dummy `Span` are reported as generated code,
+    /// rather than as a snippet of a real source.
+    pub fn dummy() -> Span {
+        Span {
+            source: DUMMY_SOURCE.with(Rc::clone),
+            offset: 0,
+            length: 0,
+        }
+    }
+
+    /// A zero-length `Span` just past the end of this `Span`.
+    pub fn end_point(&self) -> Span {
+        Span::point(&self.source, self.end())
+    }
+
+    /// Whether this `Span` was created with `Span::dummy`.
+    pub fn is_dummy(&self) -> bool {
+        DUMMY_SOURCE.with(|dummy| Rc::ptr_eq(dummy, &self.source))
+    }
+
+    /// Return the `Source` the `Span` points into.
+    pub fn source(&self) -> &Rc<Source> {
+        &self.source
+    }
+
+    /// Return the index of the start of the `Span`.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     /// Return the index of the end of the `Span`.
     pub fn end(&self) -> usize {
         self.offset + self.length
@@ -53,13 +93,20 @@ impl Span {
     }
 
     /// Creates a new `Span` which spans the space of the
-    /// previous two. ```plain
+    /// previous two. Combining with a dummy `Span`
+    /// returns the other `Span`. ```plain
     /// hello this is cool
     /// ^^^^^              | Span a
     ///            ^^      | Span b
     /// ^^^^^^^^^^^^^      | combined
     /// ```
     pub fn combine(a: &Span, b: &Span) -> Span {
+        if a.is_dummy() {
+            return b.clone();
+        } else if b.is_dummy() {
+            return a.clone();
+        }
+
         if a.source != b.source {
             panic!("Can't combine two Spans with separate sources");
         }
@@ -73,7 +120,7 @@ impl Span {
 
     /// Combines a set of `Span`s (think fold-left over
     /// `Span::combine`). If the vector of spans passed
-    /// in is empty, this method panics.
+    /// in is empty, this method returns `None`.
     pub fn join(mut spans: Vec<Span>) -> Option<Span> {
         let mut combined = spans.pop()?;
 
@@ -125,24 +172,63 @@ impl Span {
         return *lines;
     }
 
+    /// Like `col`, but counts the columns the line takes up when displayed,
+    /// rather than the characters in it,
+    /// so that wide characters, e.g. CJK or emoji, count twice.
+    pub fn width(&self, index: usize) -> usize {
+        str_width(
+            self.source.contents[..index]
+                .rsplit('\n')
+                .next()
+                .unwrap_or(""),
+        )
+    }
+
+    /// Works out the lines and columns a `Span` covers, so it can be shown.
+    /// A point at the very end of the source, e.g. for an unexpected end,
+    /// is moved back past any trailing whitespace,
+    /// so that it points just after the last thing in the source.
     pub fn format(&self) -> FormattedSpan {
+        let contents = &self.source.contents;
+        if self.length == 0 && self.offset == contents.len() {
+            let last = contents.trim_end().len();
+            if last != self.offset {
+                return Span::point(&self.source, last).format();
+            }
+        }
+
+        let start = self.line(self.offset);
+        let lines = self.lines();
+
+        // one line of context on either side of the span
+        let all = self.source.lines(0..self.source.contents.len());
+        let context = |line: usize| all.get(line).map(|(_, line)| line.to_string());
+        let before = start.checked_sub(1).and_then(context);
+        let after = context(start + lines.len());
+
         FormattedSpan {
             path: self.path(),
-            start: self.line(self.offset),
-            lines: self.lines(),
+            start,
+            lines,
             start_col: self.col(self.offset),
             end_col: self.col(self.end()),
+            start_width: self.width(self.offset),
+            end_width: self.width(self.end()),
+            before,
+            after,
         }
     }
 }
 
 impl Debug for Span {
+    /// Prints where a `Span` is, i.e. `Span(./source:4..18)`,
+    /// leaving out the source, so that debug dumps of trees stay readable.
+    /// Use `contents` or `Display` to see what a `Span` points at.
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Span")
-            .field("contents", &self.contents())
-            .field("start", &self.offset)
-            .field("end", &self.end())
-            .finish()
+        if self.is_dummy() {
+            return write!(f, "Span({})", GENERATED);
+        }
+        write!(f, "Span({}:{}..{})", self.path(), self.offset, self.end())
     }
 }
 
@@ -164,6 +250,9 @@ impl Display for Span {
     /// 15 > }
     /// ```
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_dummy() {
+            return writeln!(f, "In {}", GENERATED);
+        }
         write!(f, "{}", self.format())
     }
 }
@@ -178,6 +267,15 @@ pub struct FormattedSpan {
     pub lines: Vec<String>,
     pub start_col: usize,
     pub end_col: usize,
+    /// The display width of the line before the span starts,
+    /// i.e. how far carrots pointing at the span must be indented.
+    pub start_width: usize,
+    /// The display width of the line before the span ends.
+    pub end_width: usize,
+    /// The line before the span, if there is one.
+    pub before: Option<String>,
+    /// The line after the span, if there is one.
+    pub after: Option<String>,
 }
 
 impl FormattedSpan {
@@ -186,22 +284,56 @@ impl FormattedSpan {
     }
 
     pub fn end(&self) -> usize {
-        (self.start - 1) + self.lines.len()
+        self.start + self.lines.len() - 1
     }
 
+    /// The width of the widest line number shown.
     pub fn gutter_padding(&self) -> usize {
-        self.start.add(1).to_string().len()
+        let last = if self.after.is_some() {
+            self.end() + 2
+        } else {
+            self.end() + 1
+        };
+        last.to_string().len()
     }
 
     /// If a single line span, returns the number of carrots
-    /// between cols.
+    /// needed to underline it, going by display width.
+    /// There is always at least one, so that points are still shown.
     pub fn carrots(&self) -> Option<usize> {
         if self.lines.len() == 1 {
-            Some(self.end_col - self.start_col)
+            Some((self.end_width - self.start_width).max(1))
         } else {
             None
         }
     }
+
+    /// Writes the line before the span as context, if there is one.
+    pub fn write_before(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.before {
+            Some(line) => self.write_line(f, self.start, line),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the line after the span as context, if there is one.
+    pub fn write_after(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.after {
+            Some(line) => self.write_line(f, self.end() + 2, line),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes a numbered line of source, i.e. `12 | x = 1`.
+    pub fn write_line(&self, f: &mut Formatter<'_>, number: usize, line: &str) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>width$} | {}",
+            number,
+            line,
+            width = self.gutter_padding()
+        )
+    }
 }
 
 impl Display for FormattedSpan {
@@ -214,25 +346,30 @@ impl Display for FormattedSpan {
             self.start_col + 1
         )?;
         writeln!(f, "{} |", " ".repeat(self.gutter_padding()))?;
+        self.write_before(f)?;
 
         if !self.is_multiline() {
-            writeln!(f, "{} | {}", self.start + 1, self.lines[0])?;
+            self.write_line(f, self.start + 1, &self.lines[0])?;
             writeln!(
                 f,
                 "{} | {}{}",
                 " ".repeat(self.gutter_padding()),
-                " ".repeat(self.start_col),
-                "^".repeat(self.carrots().unwrap().max(1)),
+                " ".repeat(self.start_width),
+                "^".repeat(self.carrots().unwrap()),
             )?;
         } else {
             for (index, line) in self.lines.iter().enumerate() {
-                let line_no = (self.start + index).to_string();
-                let padding = " ".repeat(self.gutter_padding() - line_no.len());
-                writeln!(f, "{}{} > {}", line_no, padding, line)?;
+                writeln!(
+                    f,
+                    "{:>width$} > {}",
+                    self.start + index + 1,
+                    line,
+                    width = self.gutter_padding()
+                )?;
             }
         }
 
-        Ok(())
+        self.write_after(f)
     }
 }
 
@@ -261,6 +398,7 @@ impl<T> Spanned<T> {
     }
 
     /// Joins a Vector of spanned items into a single span.
+    /// Returns `None` if there are no items.
     pub fn build(spanneds: &[Spanned<T>]) -> Option<Span> {
         let spans = spanneds
             .iter()
@@ -269,6 +407,12 @@ impl<T> Spanned<T> {
         Span::join(spans)
     }
 
+    /// Joins a Vector of spanned items into a single span,
+    /// using a fallback span if there are no items.
+    pub fn build_or(spanneds: &[Spanned<T>], fallback: Span) -> Span {
+        Spanned::build(spanneds).unwrap_or(fallback)
+    }
+
     /// Applies a function a `Spanned`'s item.
     pub fn try_map<B, E>(self, f: fn(T) -> Result<B, E>) -> Result<Spanned<B>, E> {
         Ok(Spanned::new(f(self.item)?, self.span))
@@ -319,10 +463,93 @@ mod test {
         assert_eq!(Span::join(spans).unwrap().contents(), result.contents());
     }
 
+    #[test]
+    fn build() {
+        let source = Source::source("one two three");
+        let fallback = Span::new(&source, 0, 13);
+        let words = vec![
+            Spanned::new("one", Span::new(&source, 0, 3)),
+            Spanned::new("two", Span::new(&source, 4, 3)),
+            Spanned::new("three", Span::new(&source, 8, 5)),
+        ];
+
+        assert_eq!(Spanned::<&str>::build(&[]), None);
+        assert_eq!(
+            Spanned::build_or(&[] as &[Spanned<&str>], fallback.clone()),
+            fallback
+        );
+        assert_eq!(Spanned::build(&words[1..2]), Some(Span::new(&source, 4, 3)));
+        assert_eq!(Spanned::build(&words), Some(Span::new(&source, 0, 13)));
+        assert_eq!(
+            Spanned::build_or(&words[..2], fallback).contents(),
+            "one two"
+        );
+    }
+
+    #[test]
+    fn dummy() {
+        let source = Source::source("x = 1");
+        let real = Span::new(&source, 0, 1);
+
+        assert!(Span::dummy().is_dummy());
+        assert!(!real.is_dummy());
+        assert_eq!(Span::combine(&Span::dummy(), &real), real);
+        assert_eq!(Span::combine(&real, &Span::dummy()), real);
+        assert_eq!(format!("{}", Span::dummy()), "In (generated code)\n");
+    }
+
     #[test]
     fn empty() {
         let source = Source::source("");
         let span = Span::point(&source, 0);
         format!("{}", span);
     }
+
+    #[test]
+    fn point_at_end() {
+        let source = Source::source("x = (1,\n  2\n\n");
+        let end = Span::point(&source, source.contents.len()).format();
+        assert_eq!((end.start, end.start_col, end.carrots()), (1, 3, Some(1)));
+        assert_eq!(end.lines, vec!["  2"]);
+
+        // points elsewhere are left where they are
+        let start = Span::point(&source, 8).format();
+        assert_eq!(
+            (start.start, start.start_col, start.carrots()),
+            (1, 0, Some(1))
+        );
+    }
+
+    #[test]
+    fn wide_characters() {
+        let source = Source::source("字 = 日本 x");
+        let span = Span::new(&source, 6, 6);
+        assert_eq!(span.contents(), "日本");
+
+        let formatted = span.format();
+        assert_eq!((formatted.start_col, formatted.end_col), (4, 6));
+        assert_eq!((formatted.start_width, formatted.end_width), (5, 9));
+
+        // each wide character is two columns across, so gets two carrots
+        let shown = format!("{}", span);
+        let lines = shown.lines().collect::<Vec<_>>();
+        assert_eq!(lines[2], "1 | 字 = 日本 x");
+        assert_eq!(lines[3], "  |      ^^^^");
+    }
+
+    #[test]
+    fn compact_debug() {
+        let source = Source::source("x = \"Hello, world\" -> y + 1");
+        let span = Span::new(&source, 4, 14);
+        assert_eq!(format!("{:?}", span), "Span(./source:4..18)");
+        assert_eq!(format!("{:#?}", span), "Span(./source:4..18)");
+        assert_eq!(format!("{:?}", Span::dummy()), "Span((generated code))");
+
+        // nothing holding a span prints its source
+        let spans = vec![span.clone(), span.clone()];
+        let debug = format!("{:#?}", spans);
+        assert!(!debug.contains("contents"));
+        assert!(!debug.contains("Hello"));
+        assert_eq!(format!("{:?}", Spanned::new(7, span)), "7 @ 1:5");
+    }
 }
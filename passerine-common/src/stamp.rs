@@ -0,0 +1,143 @@
+//! Stamps identify the compiled form of a `Lambda` by its contents,
+//! so that it can be told whether some bytecode is still current,
+//! e.g. whether a cached module matches what was compiled.
+
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    data::Data,
+    lambda::{Captured, Lambda},
+};
+
+/// A hash of a `Lambda`, and of every function it depends on,
+/// i.e. the functions in its constant table.
+/// Two lambdas have the same stamp if they would run the same way.
+/// Spans are not part of a stamp,
+/// so moving code around in its source does not change its stamp.
+/// Stamps are only stable across builds of the same version of Passerine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stamp(u64);
+
+impl Stamp {
+    /// Stamps a `Lambda` and the functions it depends on.
+    pub fn of(lambda: &Lambda) -> Stamp {
+        let mut hasher = Fnv::default();
+        Stamp::write(lambda, &mut hasher);
+        Stamp(hasher.finish())
+    }
+
+    /// The stamp as a number, e.g. to be written out.
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    fn write(lambda: &Lambda, hasher: &mut Fnv) {
+        lambda.decls.hash(hasher);
+        lambda.code.hash(hasher);
+        lambda.ffi.hash(hasher);
+        lambda.name.hash(hasher);
+        (lambda.kind as u8).hash(hasher);
+
+        lambda.captures.len().hash(hasher);
+        for captured in lambda.captures.iter() {
+            match captured {
+                Captured::Local(index) => (0u8, index).hash(hasher),
+                Captured::Nonlocal(index) => (1u8, index).hash(hasher),
+            }
+        }
+        lambda.capture_names.hash(hasher);
+
+        // functions are compared by identity, so are not hashed by `Data`
+        lambda.constants.len().hash(hasher);
+        for constant in lambda.constants.iter() {
+            match constant {
+                Data::Lambda(lambda) => Stamp::write(lambda, hasher),
+                Data::Closure(closure) => Stamp::write(&closure.lambda, hasher),
+                other => other.hash(hasher),
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Hashes bytes with FNV-1a,
+/// which, unlike the default hasher, never changes between releases.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{number::split_number, opcode::Opcode, source::Source, span::Span};
+
+    fn lambda(constant: Data) -> Lambda {
+        let mut lambda = Lambda::empty();
+        lambda.decls = 1;
+        lambda.constants.push(constant);
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(1));
+        lambda
+    }
+
+    #[test]
+    fn identical() {
+        let a = lambda(Data::Integer(1));
+        let mut b = lambda(Data::Integer(1));
+        assert_eq!(Stamp::of(&a), Stamp::of(&b));
+
+        // spans are not part of the stamp
+        let source = Source::source("1");
+        b.spans.push((0, Span::new(&source, 0, 1)));
+        assert_eq!(Stamp::of(&a), Stamp::of(&b));
+        assert_eq!(Stamp::of(&a).to_string().len(), 16);
+    }
+
+    #[test]
+    fn changed() {
+        let a = lambda(Data::Integer(1));
+        assert_ne!(Stamp::of(&a), Stamp::of(&lambda(Data::Integer(2))));
+        assert_ne!(Stamp::of(&a), Stamp::of(&lambda(Data::Float(1.0))));
+
+        let mut b = lambda(Data::Integer(1));
+        b.decls = 2;
+        assert_ne!(Stamp::of(&a), Stamp::of(&b));
+    }
+
+    #[test]
+    fn dependencies() {
+        let outer = |inner| lambda(Data::Lambda(Rc::new(inner)));
+        let a = outer(lambda(Data::Integer(1)));
+        assert_eq!(Stamp::of(&a), Stamp::of(&outer(lambda(Data::Integer(1)))));
+        assert_ne!(Stamp::of(&a), Stamp::of(&outer(lambda(Data::Integer(2)))));
+    }
+}
@@ -1,42 +1,110 @@
 use proc_macro::TokenStream;
-use quote::{quote, quote_spanned};
-use syn::{parse_macro_input, spanned::Spanned, DeriveInput, Ident, Index};
+use quote::{format_ident, quote, quote_spanned};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, DeriveInput, Ident, Index, Lit,
+    Meta, NestedMeta,
+};
 
 /// A derive macro that generates an implementation of the `Inject` trait,
 /// which allows a Rust type to be converted to Passerine data and back again.
 /// This type is very important for building interfaces between Rust and
 /// Passerine using system injection.
-#[proc_macro_derive(Effect)]
+/// Generic types are supported,
+/// as long as each type parameter is itself `Inject`.
+///
+/// Structs are converted to tuples of their fields, in order.
+/// The conversion can be tweaked with `#[passerine(...)]` attributes:
+/// - `#[passerine(record)]` on a struct with named fields
+///   converts it to a record keyed by field name instead.
+/// - `#[passerine(skip)]` on a field leaves it out of the converted data;
+///   it is set to its `Default` when converting back.
+/// - `#[passerine(rename = "...")]` on a field of a record
+///   changes the name of the field in the record.
+#[proc_macro_derive(Effect, attributes(passerine))]
 pub fn derive_effect(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
     let input = parse_macro_input!(input as DeriveInput);
+
+    // Hand the output tokens back to the compiler
+    match expand(input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(error) => TokenStream::from(error.to_compile_error()),
+    }
+}
+
+type Conversions = (proc_macro2::TokenStream, proc_macro2::TokenStream);
+
+fn expand(mut input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let type_name = input.ident;
+    let record = struct_options(&input.attrs)?;
 
-    let (from, into) = match input.data {
-        syn::Data::Struct(ref data) => match data.fields {
-            syn::Fields::Named(ref fields) => derive_struct_named(&type_name, fields),
-            syn::Fields::Unnamed(ref fields) => derive_struct_unnamed(&type_name, fields),
-            syn::Fields::Unit => {
-                let from = quote! {
-                    if let passerine_common::Data::Unit = param {
-                        Some(#type_name)
-                    } else {
-                        None
-                    }
-                };
-                let into = quote! { passerine_common::Data::Unit };
-                (from, into)
-            }
-        },
+    let (from, into, skipped) = match input.data {
+        syn::Data::Struct(ref data) => {
+            let options = data
+                .fields
+                .iter()
+                .map(|f| field_options(f, record))
+                .collect::<syn::Result<Vec<_>>>()?;
+
+            let (from, into) = match data.fields {
+                syn::Fields::Named(ref fields) if record => {
+                    derive_struct_record(&type_name, fields, &options)?
+                }
+                syn::Fields::Named(ref fields) => derive_struct_named(&type_name, fields, &options),
+                syn::Fields::Unnamed(ref fields) => {
+                    derive_struct_unnamed(&type_name, fields, &options)
+                }
+                syn::Fields::Unit => {
+                    let from = quote! {
+                        if let passerine_common::Data::Unit = param {
+                            Some(#type_name)
+                        } else {
+                            None
+                        }
+                    };
+                    let into = quote! { passerine_common::Data::Unit };
+                    (from, into)
+                }
+            };
+
+            let skipped: Vec<syn::Type> = data
+                .fields
+                .iter()
+                .zip(&options)
+                .filter(|(_, options)| options.skip)
+                .map(|(f, _)| f.ty.clone())
+                .collect();
+            (from, into, skipped)
+        }
         syn::Data::Enum(ref _data) => todo!(),
         syn::Data::Union(ref _data) => {
             unimplemented!("Unions are not supported")
         }
     };
 
+    // bound each type parameter by `Inject`, so fields of that type can be converted,
+    // and each skipped field by `Default`, so it can be filled in
+    let type_params: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote! { #param: passerine_common::Inject });
+    }
+    for ty in skipped {
+        where_clause
+            .predicates
+            .push(parse_quote! { #ty: ::std::default::Default });
+    }
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
     // Build the output, possibly using quasi-quotation
     let expanded = quote! {
-        impl passerine_common::Inject for #type_name {
+        impl #impl_generics passerine_common::Inject for #type_name #type_generics #where_clause {
             fn serialize(param: Self) -> passerine_common::Data { #into }
             fn deserialize(param: passerine_common::Data) -> Option<Self> { #from }
         }
@@ -57,70 +125,223 @@ pub fn derive_effect(input: TokenStream) -> TokenStream {
         // impl passerine_common::Inject for #type_name {}
     };
 
-    // Hand the output tokens back to the compiler
-    TokenStream::from(expanded)
+    Ok(expanded)
+}
+
+/// The options set on a field with `#[passerine(...)]`.
+#[derive(Default)]
+struct FieldOptions {
+    skip: bool,
+    rename: Option<String>,
+}
+
+/// Collects the items of every `#[passerine(...)]` attribute.
+fn passerine_attrs(attrs: &[Attribute]) -> syn::Result<Vec<NestedMeta>> {
+    let mut items = vec![];
+    for attr in attrs.iter().filter(|a| a.path.is_ident("passerine")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            other => {
+                return Err(syn::Error::new_spanned(
+                    other,
+                    "expected a list of options, e.g. `#[passerine(skip)]`",
+                ))
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Returns whether a struct is converted to a record.
+fn struct_options(attrs: &[Attribute]) -> syn::Result<bool> {
+    let mut record = false;
+    for item in passerine_attrs(attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("record") => record = true,
+            other => return Err(syn::Error::new_spanned(other, "unknown struct option")),
+        }
+    }
+    Ok(record)
+}
+
+fn field_options(field: &syn::Field, record: bool) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for item in passerine_attrs(&field.attrs)? {
+        match item {
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => options.skip = true,
+            NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("rename") => {
+                let name = match &pair.lit {
+                    Lit::Str(name) => name.value(),
+                    other => {
+                        return Err(syn::Error::new_spanned(other, "expected a string literal"))
+                    }
+                };
+                if !record {
+                    return Err(syn::Error::new_spanned(
+                        pair,
+                        "only fields of records can be renamed, \
+                         add `#[passerine(record)]` to the struct",
+                    ));
+                }
+                options.rename = Some(name);
+            }
+            other => return Err(syn::Error::new_spanned(other, "unknown field option")),
+        }
+    }
+    Ok(options)
 }
 
 fn derive_struct_named(
     type_name: &Ident,
     fields: &syn::FieldsNamed,
-) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    let num_fields = fields.named.len();
-    let from = fields.named.iter().rev().map(|f| {
+    options: &[FieldOptions],
+) -> Conversions {
+    let num_fields = options.iter().filter(|o| !o.skip).count();
+    let from = fields.named.iter().zip(options).rev().map(|(f, options)| {
         let name = &f.ident;
-        quote_spanned! { f.span() =>
-            #name: passerine_common::Inject::deserialize(param.pop()?)?
+        if options.skip {
+            return quote_spanned! { f.span() =>
+                #name: ::std::default::Default::default()
+            };
         }
-    });
-    let into = fields.named.iter().map(|f| {
-        let name = &f.ident;
         quote_spanned! { f.span() =>
-            passerine_common::Inject::serialize(param.#name)
+            #name: passerine_common::Inject::deserialize(param.pop()?)?
         }
     });
+    let into = fields
+        .named
+        .iter()
+        .zip(options)
+        .filter(|(_, options)| !options.skip)
+        .map(|(f, _)| {
+            let name = &f.ident;
+            quote_spanned! { f.span() =>
+                passerine_common::Inject::serialize(param.#name)
+            }
+        });
 
     let from = quote! {
-        if let passerine_common::Data::Tuple(mut param) = param {
+        if let passerine_common::Data::Tuple(param) = param {
             if param.len() != #num_fields { return None; }
+            let mut param = ::std::rc::Rc::unwrap_or_clone(param);
             Some(#type_name { #(#from,)* })
         } else {
             None
         }
     };
     let into = quote! {
-        passerine_common::Data::Tuple(vec![#(#into,)*])
+        passerine_common::Data::tuple(vec![#(#into,)*])
     };
 
     (from, into)
 }
 
-fn derive_struct_unnamed(
+fn derive_struct_record(
     type_name: &Ident,
-    fields: &syn::FieldsUnnamed,
-) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    let num_fields = fields.unnamed.len();
-    let from = fields.unnamed.iter().rev().map(|f| {
-        quote_spanned! { f.span() =>
-            passerine_common::Inject::deserialize(param.pop()?)?
+    fields: &syn::FieldsNamed,
+    options: &[FieldOptions],
+) -> syn::Result<Conversions> {
+    let mut keys: Vec<String> = vec![];
+    let mut from = vec![];
+    let mut into = vec![];
+
+    for (f, options) in fields.named.iter().zip(options) {
+        let name = f.ident.as_ref().unwrap();
+        if options.skip {
+            from.push(quote_spanned! { f.span() =>
+                #name: ::std::default::Default::default()
+            });
+            continue;
         }
-    });
-    let into = fields.unnamed.iter().enumerate().map(|(index, f)| {
-        let index = Index::from(index);
-        quote_spanned! { f.span() =>
-            passerine_common::Inject::serialize(param.#index)
+
+        let key = options.rename.clone().unwrap_or_else(|| name.to_string());
+        if keys.contains(&key) {
+            return Err(syn::Error::new_spanned(
+                f,
+                format!("two fields of the record are named `{}`", key),
+            ));
         }
-    });
+
+        from.push(quote_spanned! { f.span() =>
+            #name: passerine_common::Inject::deserialize(param.remove(#key)?)?
+        });
+        into.push(quote_spanned! { f.span() =>
+            record.insert(#key.to_string(), passerine_common::Inject::serialize(param.#name));
+        });
+        keys.push(key);
+    }
+
+    let num_fields = keys.len();
+    let from = quote! {
+        if let passerine_common::Data::Record(mut param) = param {
+            if param.len() != #num_fields { return None; }
+            Some(#type_name { #(#from,)* })
+        } else {
+            None
+        }
+    };
+    let into = quote! {
+        let mut record = ::std::collections::BTreeMap::new();
+        #(#into)*
+        passerine_common::Data::record(record)
+    };
+
+    Ok((from, into))
+}
+
+fn derive_struct_unnamed(
+    type_name: &Ident,
+    fields: &syn::FieldsUnnamed,
+    options: &[FieldOptions],
+) -> Conversions {
+    let num_fields = options.iter().filter(|o| !o.skip).count();
+    // fields are popped off the end of the tuple,
+    // so they're bound last to first, then passed in order
+    let bindings: Vec<Ident> = (0..fields.unnamed.len())
+        .map(|index| format_ident!("field_{}", index))
+        .collect();
+    let pops =
+        fields
+            .unnamed
+            .iter()
+            .zip(&bindings)
+            .zip(options)
+            .rev()
+            .map(|((f, binding), options)| {
+                if options.skip {
+                    return quote_spanned! { f.span() =>
+                        let #binding = ::std::default::Default::default();
+                    };
+                }
+                quote_spanned! { f.span() =>
+                    let #binding = passerine_common::Inject::deserialize(param.pop()?)?;
+                }
+            });
+    let into = fields
+        .unnamed
+        .iter()
+        .enumerate()
+        .zip(options)
+        .filter(|(_, options)| !options.skip)
+        .map(|((index, f), _)| {
+            let index = Index::from(index);
+            quote_spanned! { f.span() =>
+                passerine_common::Inject::serialize(param.#index)
+            }
+        });
 
     let from = quote! {
-        if let passerine_common::Data::Tuple(mut param) = param {
+        if let passerine_common::Data::Tuple(param) = param {
             if param.len() != #num_fields { return None; }
-            Some(#type_name (#(#from,)*))
+            let mut param = ::std::rc::Rc::unwrap_or_clone(param);
+            #(#pops)*
+            Some(#type_name (#(#bindings,)*))
         } else {
             None
         }
     };
     let into = quote! {
-        passerine_common::Data::Tuple(vec![#(#into,)*])
+        passerine_common::Data::tuple(vec![#(#into,)*])
     };
 
     (from, into)
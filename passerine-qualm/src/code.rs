@@ -1,4 +1,4 @@
-use crate::Pointer;
+use crate::Slot;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -62,10 +62,14 @@ pub enum OpCode {
     PopTriple,  // R1 R2 R3
 
     // Stack
-    DelSingle,  // R1
-    DelPair,    // R1 R2
-    DelTriple,  // R1 R2 R3
-    StackConst, // Idx
+    DelSingle,    // R1
+    DelPair,      // R1 R2
+    DelTriple,    // R1 R2 R3
+    StackConst,   // Idx
+    LoadLocal,    // Idx -> onto stack
+    SaveLocal,    // Idx <- off stack
+    CaptureLocal, // Idx -> borrowed pointer onto stack
+    LoadCaptured, // Idx -> onto stack
 
     // Register
     RegConst, // Idx _ -> R3
@@ -75,7 +79,8 @@ pub enum OpCode {
     JumpTrue,   // loc R1 -> _
     JumpFalse,  // loc R1 -> _
     JumpBranch, // loc1 R1 loc2
-    Call,       // code num_args num_captures
+    Jump,       // loc -> _
+    Call,       // loc num_args
     Return,     // R1 _ -> _
     ReturnMany, // R1 num_ret -> _
 
@@ -89,6 +94,85 @@ pub enum OpCode {
     FiberYield,  // arg _ -> R3
     HandlerAdd,  // effect fiber -> _
     HandlerCall, // effect -> R3
+
+    // Stops execution. Must always be last.
+    Halt,
+}
+
+impl OpCode {
+    /// Converts a raw byte to an opcode.
+    /// Panics if the byte is not a valid opcode.
+    pub fn from_byte(byte: u8) -> OpCode {
+        assert!(byte <= OpCode::Halt as u8, "Invalid opcode {}", byte);
+        // SAFETY: we just did a bounds check on the byte
+        unsafe { std::mem::transmute(byte) }
+    }
+}
+
+/// A chunk of bytecode, along with the constants it uses.
+/// Operands are stored inline as 4-byte little-endian numbers.
+#[derive(Debug)]
+pub struct Code {
+    bytes:     Vec<u8>,
+    constants: Vec<u64>,
+}
+
+impl Code {
+    /// Creates a new empty `Code` to be filled.
+    pub fn empty() -> Code {
+        Code { bytes: vec![], constants: vec![] }
+    }
+
+    /// Emits an opcode.
+    pub fn emit(&mut self, op: OpCode) {
+        self.bytes.push(op as u8);
+    }
+
+    /// Emits an operand.
+    pub fn emit_operand(&mut self, operand: u32) {
+        self.bytes.extend_from_slice(&operand.to_le_bytes());
+    }
+
+    /// Adds a constant, returning its index.
+    pub fn add_constant(&mut self, constant: u64) -> u32 {
+        self.constants.push(constant);
+        (self.constants.len() - 1) as u32
+    }
+
+    /// The index of the next byte that will be emitted.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether no bytes have been emitted yet.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns the opcode at `ip`, advancing `ip` past it.
+    /// Running off the end of the code halts.
+    pub fn prefetch(&self, ip: &mut usize) -> OpCode {
+        match self.bytes.get(*ip) {
+            Some(byte) => {
+                *ip += 1;
+                OpCode::from_byte(*byte)
+            },
+            None => OpCode::Halt,
+        }
+    }
+
+    /// Returns the operand at `ip`, advancing `ip` past it.
+    pub fn operand(&self, ip: &mut usize) -> usize {
+        let bytes = self.bytes[*ip..*ip + 4].try_into().unwrap();
+        *ip += 4;
+        u32::from_le_bytes(bytes) as usize
+    }
+
+    /// Returns the constant at a given index, as a raw slot.
+    pub fn constant(&self, index: usize) -> Slot {
+        // SAFETY: constants are raw bits, the ops using them decide what they mean
+        unsafe { Slot::from_bits(self.constants[index]) }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -10,6 +10,12 @@ use range_set::RangeSet;
 pub struct Heap {
     data: Vec<Slot>,
     free: RangeSet,
+    /// The generation of each slot, `0` if the slot is free.
+    #[cfg(debug_assertions)]
+    generations: Vec<u16>,
+    /// The generation given to the next allocation.
+    #[cfg(debug_assertions)]
+    generation: u16,
 }
 
 impl Heap {
@@ -18,6 +24,57 @@ impl Heap {
         Heap {
             data: vec![],
             free: RangeSet::new(),
+            #[cfg(debug_assertions)]
+            generations: vec![],
+            #[cfg(debug_assertions)]
+            generation: 1,
+        }
+    }
+
+    /// Marks a range of slots as belonging to an allocation of some generation.
+    /// Does nothing in release builds.
+    #[allow(unused_variables)]
+    fn stamp(&mut self, pointer: Pointer, slots: usize, generation: u16) {
+        #[cfg(debug_assertions)]
+        {
+            self.generations.resize(self.data.len(), 0);
+            let start = pointer.to_idx().to_usize();
+            for slot in &mut self.generations[start..(start + slots)] {
+                *slot = generation;
+            }
+        }
+    }
+
+    /// Returns a fresh generation for a new allocation.
+    fn next_generation(&mut self) -> u16 {
+        #[cfg(debug_assertions)]
+        {
+            let generation = self.generation;
+            // skip 0, which is never checked
+            self.generation = generation.checked_add(1).unwrap_or(1);
+            generation
+        }
+
+        #[cfg(not(debug_assertions))]
+        0
+    }
+
+    /// Panics if any of the `slots` slots starting at a pointer
+    /// do not belong to the allocation the pointer was made for,
+    /// e.g. because the data was freed and the slot reused.
+    /// Only checked in debug builds, and only for pointers with a generation.
+    #[allow(unused_variables)]
+    fn check(&self, pointer: Pointer, slots: usize) {
+        #[cfg(debug_assertions)]
+        if pointer.generation() != 0 {
+            let start = pointer.to_idx().to_usize();
+            for index in start..(start + slots) {
+                let current = self.generations.get(index).copied().unwrap_or(0);
+                assert_eq!(
+                    current, pointer.generation(),
+                    "Use of pointer to slot {} after it was freed", index,
+                );
+            }
         }
     }
 
@@ -59,7 +116,10 @@ impl Heap {
         // increase the size of the allocation if needed.
         self.data
             .extend((0..extra_capacity).map(|_| unsafe { Slot::zero() }));
-        return pointer;
+
+        let generation = self.next_generation();
+        self.stamp(pointer, slots, generation);
+        return pointer.with_generation(generation);
     }
 
     /// Reallocates an allocation to a larger size
@@ -84,6 +144,7 @@ impl Heap {
         new: usize,
     ) -> Pointer {
         assert!(pointer.is_owned());
+        self.check(pointer, old);
 
         if new > old {
             // try allocation continiously
@@ -91,6 +152,7 @@ impl Heap {
             if self.free.is_free(tail, new - old) {
                 // increase the size of the current allocation
                 self.free.mark_smaller(tail.to_idx(), new - old);
+                self.stamp(tail, new - old, pointer.generation());
                 return pointer;
             }
 
@@ -117,28 +179,39 @@ impl Heap {
 
     // Reads a single slot relative to a pointer.
     pub fn read_slot(&self, pointer: Pointer, slot: usize) -> &Slot {
+        self.check(pointer.add(slot as u64), 1);
         &self.data[pointer.to_idx().to_usize() + slot]
     }
 
     // Reads a range of data.
     pub fn read(&self, pointer: Pointer, slots: usize) -> &[Slot] {
+        self.check(pointer, slots);
         let start = pointer.to_idx().to_usize() as usize;
         &self.data[start..(start + slots)]
     }
 
-    // TODO: figure out apis for reading and writing
-
-    pub fn write_slot(&self, pointer: Pointer, slots: usize) -> () { todo!() }
-
+    /// Writes a range of data, starting at a pointer.
+    /// The slots in `item` are swapped with the old contents of the heap.
     pub fn write(&mut self, pointer: Pointer, item: &mut [Slot]) -> Option<()> {
         // can't write to a pointer we don't own! make a copy first.
         if !pointer.is_owned() {
             return None;
         }
+        self.check(pointer, item.len());
+
+        let start = pointer.to_idx().to_usize();
+        for (offset, slot) in item.iter_mut().enumerate() {
+            std::mem::swap(&mut self.data[start + offset], slot);
+        }
+        Some(())
     }
 
+    /// Frees an allocation.
+    /// Panics if the pointer does not own the data it points to.
     pub fn free(&mut self, pointer: Pointer, slots: usize) {
         assert!(pointer.is_owned());
+        self.check(pointer, slots);
+        self.stamp(pointer, slots, 0);
         let unneeded_capacity = self.free.free(pointer, slots);
         self.data.truncate(self.data.len() - unneeded_capacity);
     }
@@ -150,6 +223,114 @@ pub mod tests {
 
     use super::*;
 
+    #[test]
+    pub fn borrowed_write() {
+        let mut heap = Heap::new();
+        // SAFETY: immediately written to
+        let pointer = unsafe { heap.alloc(2) };
+        let mut data = [Slot::unit(), Slot::boolean(true)];
+        assert_eq!(heap.write(pointer.borrow(), &mut data), None);
+        assert_eq!(heap.write(pointer, &mut data), Some(()));
+        assert_eq!(heap.read_slot(pointer.borrow(), 1).as_bool(), Some(true));
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn borrowed_free() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let pointer = unsafe { heap.alloc(1) };
+        heap.free(pointer.borrow(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn borrowed_realloc() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let pointer = unsafe { heap.alloc(1) };
+        unsafe { heap.realloc(pointer.borrow(), 1, 2) };
+    }
+
+    #[test]
+    pub fn generations() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let a = unsafe { heap.alloc(1) };
+        let b = unsafe { heap.alloc(1) };
+        // generations are only given out in debug builds
+        #[cfg(debug_assertions)]
+        assert_ne!(a.generation(), b.generation());
+        // a reallocated pointer is still valid for its whole length
+        let c = unsafe { heap.realloc(b, 1, 4) };
+        heap.read(c, 4);
+        heap.read_slot(a, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    pub fn generations_wrap() {
+        let mut heap = Heap::new();
+        heap.generation = u16::MAX;
+        // SAFETY: data is never read
+        let last = unsafe { heap.alloc(1) };
+        let first = unsafe { heap.alloc(1) };
+        // 0 is skipped, as it is never checked
+        assert_eq!(last.generation(), u16::MAX);
+        assert_eq!(first.generation(), 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "after it was freed")]
+    pub fn use_after_free() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let old = unsafe { heap.alloc(1) };
+        heap.free(old, 1);
+        // the slot is reused by a new allocation
+        let new = unsafe { heap.alloc(1) };
+        assert_eq!(old.to_idx(), new.to_idx());
+        heap.read_slot(old.borrow(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "after it was freed")]
+    pub fn use_after_free_through_slot() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let old = unsafe { heap.alloc(1) };
+        let slot = Slot::pointer(old.borrow());
+        heap.free(old, 1);
+        let _new = unsafe { heap.alloc(1) };
+        heap.read_slot(slot.as_pointer().unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "slot 1 after it was freed")]
+    pub fn use_after_free_past_start() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let pointer = unsafe { heap.alloc(3) };
+        // shrinking frees the tail, which is then reused
+        let pointer = unsafe { heap.realloc(pointer, 3, 1) };
+        let _other = unsafe { heap.alloc(2) };
+        heap.read(pointer.borrow(), 3);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "after it was freed")]
+    pub fn double_free() {
+        let mut heap = Heap::new();
+        // SAFETY: data is never read
+        let pointer = unsafe { heap.alloc(3) };
+        heap.free(pointer, 3);
+        heap.free(pointer, 3);
+    }
+
     fn random_alloc_size(rng: &mut attorand::Rng) -> usize {
         rng.next_byte() as usize + 1
     }
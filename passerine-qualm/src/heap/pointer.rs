@@ -1,31 +1,57 @@
 /// A tagged copy-on-write pointer to some data in a managed heap.
+///
+/// ```plain
+/// OGGGGGGGGGGGGGGGIIIIIIII...
+/// ```
+///
+/// The top bit marks whether the pointer owns the data it points to.
+/// Only owned pointers may be written through, reallocated, or freed.
+/// The next 16 bits are the generation of the allocation,
+/// used to catch uses of a pointer after it has been freed in debug builds.
+/// The generation `0` is never checked.
+/// The rest of the bits are the index of the pointer into the heap.
 #[derive(Debug, Clone, Copy)]
 pub struct Pointer(u64);
 
-const OWNED:   u64 = 0x8000000000000000;
-const POINTER: u64 = 0x7fffffffffffffff;
+const OWNED:      u64 = 0x8000_0000_0000_0000;
+const GENERATION: u64 = 0x7fff_8000_0000_0000;
+const POINTER:    u64 = 0x0000_7fff_ffff_ffff;
+
+/// The number of bits the generation is shifted over.
+const GENERATION_SHIFT: u64 = 47;
 
 impl Pointer {
     /// Create an owned pointer from an index.
-    pub(super) fn new(idx: PointerIdx) -> Pointer {
+    /// The pointer has generation `0`, so is never checked.
+    pub(crate) fn new(idx: PointerIdx) -> Pointer {
         let idx = idx.to_u64();
         assert!(idx <= POINTER);
         Pointer(OWNED | (POINTER & idx))
     }
 
     /// Pointer arithmetic.
-    /// Maintains ownership.
+    /// Maintains ownership and generation.
     pub(super) fn add(&self, slots: u64) -> Pointer {
         let new_index: u64 = self.to_idx().to_u64() + slots;
         assert!(new_index <= POINTER);
-        Pointer((self.0 & OWNED) | new_index)
+        Pointer((self.0 & !POINTER) | new_index)
     }
 
     /// Return the internal index of the pointer.
-    pub(super) fn to_idx(&self) -> PointerIdx {
+    pub(crate) fn to_idx(&self) -> PointerIdx {
         PointerIdx(self.0 & POINTER)
     }
 
+    /// Returns the generation of the allocation this pointer points to.
+    pub(crate) fn generation(&self) -> u16 {
+        ((self.0 & GENERATION) >> GENERATION_SHIFT) as u16
+    }
+
+    /// Returns a copy of this pointer with a different generation.
+    pub(crate) fn with_generation(&self, generation: u16) -> Pointer {
+        Pointer((self.0 & !GENERATION) | ((generation as u64) << GENERATION_SHIFT))
+    }
+
     /// Check whether a reference is borrowing the data it points to.
     pub fn is_borrowed(&self) -> bool {
         self.0 & OWNED == 0
@@ -38,24 +64,16 @@ impl Pointer {
 
     /// Demote an owned pointer to a borrowed pointer.
     pub fn borrow(&self) -> Pointer {
-        Pointer(self.0 & POINTER)
-    }
-
-    pub unsafe fn from_bits(bits: u64) -> Pointer {
-        Pointer(bits)
-    }
-
-    pub unsafe fn to_bits(self) -> u64 {
-        self.0
+        Pointer(self.0 & !OWNED)
     }
 }
 
 /// Used as a key in the maps.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Hash)]
-pub(super) struct PointerIdx(u64);
+pub(crate) struct PointerIdx(u64);
 
 impl PointerIdx {
-    pub(super) fn new(idx: u64) -> PointerIdx {
+    pub(crate) fn new(idx: u64) -> PointerIdx {
         PointerIdx(idx)
     }
 
@@ -63,7 +81,7 @@ impl PointerIdx {
         self.0 as usize
     }
 
-    pub(super) fn to_u64(self) -> u64 {
+    pub(crate) fn to_u64(self) -> u64 {
         self.0
     }
 }
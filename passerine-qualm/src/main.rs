@@ -7,72 +7,34 @@ mod stack;
 // mod fiber;
 mod code;
 mod slot;
+mod ops;
 
 pub use slot::Slot;
+pub use code::{Code, OpCode};
+use stack::Stack;
 
-// pub struct Code {
-//     bytes: Vec<u8>,
-// }
-//
 // pub struct Worker {
 //     code_pool:     BTreeMap<CodeId, Code>,
 //     constant_pool: BTreeMap<ConstantId, Constant>,
 //     process_pool:  BTreeMap<FiberId, Fiber>,
 // }
-//
-// macro_rules! op {
-//     {
-//         fn $name:ident(
-//             $ip:ident,
-//             $next_op:ident,
-//             $stack:ident,
-//             $heap:ident,
-//             $code:ident,
-//         ) $body:expr
-//     } => {
-//         pub fn name(
-//             ip: &mut usize,
-//             next_op: &mut OpCode,
-//             stack: &mut Stack,
-//             heap: &mut Heap,
-//             code: &Code,
-//         ) -> std::option::Option<HandlerId> {
-//             body
-//         }
-//     };
-// }
-//
-// op! {
-//     fn add_u64(
-//         ip,
-//         next_op,
-//         stack,
-//         heap,
-//         code,
-//     ) {
-//         *next_op = code.prefetch(ip);
-//         let a = stack.pop();
-//         let b = stack.pop();
-//         stack.push(a + b);
-//         None
-//     }
-//
-// }
-//
-// pub fn add_u64(
-//     ip: &mut usize,
-//     next_op: &mut OpCode,
-//     stack: &mut Stack,
-//     heap: &mut Heap,
-//     code: &Code,
-// ) -> Option<HandlerId> {
-//     *next_op = code.prefetch(ip);
-//     let a = stack.pop();
-//     let b = stack.pop();
-//     stack.push(a + b);
-//     None
-// }
 
 pub fn main() {
-    todo!();
+    let mut code = Code::empty();
+    for number in [2, 3] {
+        let index = code.add_constant(number);
+        code.emit(OpCode::StackConst);
+        code.emit_operand(index);
+    }
+    code.emit(OpCode::NatAdd);
+
+    let mut stack = Stack::new();
+    let mut heap = Heap::new();
+    if let Err(error) = ops::run(&code, &mut stack, &mut heap) {
+        eprintln!("{}", error);
+        return;
+    }
+
+    // SAFETY: NatAdd leaves a raw natural on the stack
+    println!("{}", unsafe { stack.pop().to_u64() });
 }
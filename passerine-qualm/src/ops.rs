@@ -0,0 +1,266 @@
+use crate::{Code, OpCode, Heap, Slot};
+use crate::stack::Stack;
+
+/// Identifies the handler an op has yielded to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId(pub usize);
+
+/// Defines an op.
+/// Each op is handed the instruction pointer, which points just past its opcode,
+/// and must prefetch the next op to run before returning.
+/// If the op needs to yield to a handler, it returns the handler's id.
+macro_rules! op {
+    {
+        fn $name:ident(
+            $ip:ident,
+            $next_op:ident,
+            $stack:ident,
+            $heap:ident,
+            $code:ident $(,)?
+        ) $body:block
+    } => {
+        #[allow(unused_variables)]
+        pub fn $name(
+            $ip: &mut usize,
+            $next_op: &mut OpCode,
+            $stack: &mut Stack,
+            $heap: &mut Heap,
+            $code: &Code,
+        ) -> Option<HandlerId> $body
+    };
+}
+
+op! {
+    fn add_u64(ip, next_op, stack, heap, code) {
+        *next_op = code.prefetch(ip);
+        // SAFETY: the compiler gaurantees these are raw naturals
+        let (b, a) = unsafe { (stack.pop().to_u64(), stack.pop().to_u64()) };
+        stack.push(unsafe { Slot::from_bits(a.wrapping_add(b)) });
+        None
+    }
+}
+
+op! {
+    fn push_const(ip, next_op, stack, heap, code) {
+        let index = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        stack.push(code.constant(index));
+        None
+    }
+}
+
+op! {
+    fn load_local(ip, next_op, stack, heap, code) {
+        let index = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        // SAFETY: the local is copied bit for bit
+        stack.push(unsafe { Slot::from_bits(stack.local(index).to_u64()) });
+        None
+    }
+}
+
+op! {
+    fn save_local(ip, next_op, stack, heap, code) {
+        let index = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        let slot = stack.pop();
+        stack.set_local(index, slot);
+        None
+    }
+}
+
+op! {
+    fn capture_local(ip, next_op, stack, heap, code) {
+        let index = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        let pointer = stack.capture(index, heap);
+        stack.push(Slot::pointer(pointer));
+        None
+    }
+}
+
+op! {
+    fn load_captured(ip, next_op, stack, heap, code) {
+        let index = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        let captured = stack.local_captured(index, heap);
+        // SAFETY: the captured value is copied bit for bit
+        stack.push(unsafe { Slot::from_bits(captured.to_u64()) });
+        None
+    }
+}
+
+op! {
+    fn jump(ip, next_op, stack, heap, code) {
+        *ip = code.operand(ip);
+        *next_op = code.prefetch(ip);
+        None
+    }
+}
+
+op! {
+    fn call(ip, next_op, stack, heap, code) {
+        let target = code.operand(ip);
+        let args = code.operand(ip);
+        // the arguments become the first locals of the callee
+        stack.push_frame(*ip, args);
+        *ip = target;
+        *next_op = code.prefetch(ip);
+        None
+    }
+}
+
+op! {
+    fn return_value(ip, next_op, stack, heap, code) {
+        let value = stack.pop();
+        *ip = stack.pop_frame();
+        stack.push(value);
+        *next_op = code.prefetch(ip);
+        None
+    }
+}
+
+/// Runs some code from the start until it halts,
+/// or an op yields to a handler.
+/// Returns an error if the code uses an op that is not yet implemented.
+pub fn run(
+    code: &Code,
+    stack: &mut Stack,
+    heap: &mut Heap,
+) -> Result<Option<HandlerId>, String> {
+    let mut ip = 0;
+    let mut next_op = code.prefetch(&mut ip);
+
+    loop {
+        let op = match next_op {
+            OpCode::Halt         => return Ok(None),
+            OpCode::NatAdd       => add_u64,
+            OpCode::StackConst   => push_const,
+            OpCode::LoadLocal    => load_local,
+            OpCode::SaveLocal    => save_local,
+            OpCode::CaptureLocal => capture_local,
+            OpCode::LoadCaptured => load_captured,
+            OpCode::Jump         => jump,
+            OpCode::Call         => call,
+            OpCode::Return       => return_value,
+            other => return Err(format!(
+                "Op {:?} at byte {} is not yet implemented", other, ip - 1,
+            )),
+        };
+
+        if let Some(handler) = op(&mut ip, &mut next_op, stack, heap, code) {
+            return Ok(Some(handler));
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn push_const(code: &mut Code, constant: u64) {
+        let index = code.add_constant(constant);
+        code.emit(OpCode::StackConst);
+        code.emit_operand(index);
+    }
+
+    fn run_code(code: &Code) -> Stack {
+        let mut stack = Stack::new();
+        let mut heap = Heap::new();
+        assert_eq!(run(code, &mut stack, &mut heap), Ok(None));
+        stack
+    }
+
+    #[test]
+    pub fn add_two_numbers() {
+        let mut code = Code::empty();
+        push_const(&mut code, 40);
+        push_const(&mut code, 2);
+        code.emit(OpCode::NatAdd);
+
+        let mut stack = run_code(&code);
+        assert_eq!(stack.len(), 1);
+        assert_eq!(unsafe { stack.pop().to_u64() }, 42);
+    }
+
+    #[test]
+    pub fn jump_over() {
+        let mut code = Code::empty();
+        push_const(&mut code, 1);
+        code.emit(OpCode::Jump);
+        // jump past the opcode, operand, and the push that follows
+        let target = code.len() + 4 + 5;
+        code.emit_operand(target as u32);
+        push_const(&mut code, 2);
+        push_const(&mut code, 3);
+        code.emit(OpCode::NatAdd);
+
+        let mut stack = run_code(&code);
+        assert_eq!(unsafe { stack.pop().to_u64() }, 4);
+    }
+
+    #[test]
+    pub fn call_and_return() {
+        let mut code = Code::empty();
+        push_const(&mut code, 10);
+        push_const(&mut code, 20);
+        code.emit(OpCode::Call);
+        // call the function after the jump below with one argument
+        let function = code.len() + 8 + 5;
+        code.emit_operand(function as u32);
+        code.emit_operand(1);
+        code.emit(OpCode::Jump);
+        let end = function + 5 + 5 + 1 + 5 + 5 + 1 + 1;
+        code.emit_operand(end as u32);
+
+        // x -> { y = x + 1; y + x }
+        assert_eq!(code.len(), function);
+        code.emit(OpCode::LoadLocal);
+        code.emit_operand(0);
+        push_const(&mut code, 1);
+        code.emit(OpCode::NatAdd);
+        code.emit(OpCode::LoadLocal);
+        code.emit_operand(1);
+        code.emit(OpCode::LoadLocal);
+        code.emit_operand(0);
+        code.emit(OpCode::NatAdd);
+        code.emit(OpCode::Return);
+        assert_eq!(code.len(), end);
+
+        // the argument is replaced by the return value
+        let mut stack = run_code(&code);
+        assert_eq!(stack.len(), 2);
+        assert_eq!(unsafe { stack.pop().to_u64() }, 41);
+        assert_eq!(unsafe { stack.pop().to_u64() }, 10);
+    }
+
+    #[test]
+    pub fn save_and_capture() {
+        let mut code = Code::empty();
+        push_const(&mut code, 1);
+        push_const(&mut code, 2);
+        code.emit(OpCode::SaveLocal);
+        code.emit_operand(0);
+        code.emit(OpCode::CaptureLocal);
+        code.emit_operand(0);
+        code.emit(OpCode::LoadCaptured);
+        code.emit_operand(0);
+
+        let mut stack = run_code(&code);
+        assert_eq!(unsafe { stack.pop().to_u64() }, 2);
+        assert!(stack.pop().as_pointer().unwrap().is_borrowed());
+        assert!(stack.local(0).as_pointer().unwrap().is_owned());
+    }
+
+    #[test]
+    pub fn unimplemented_op() {
+        let mut code = Code::empty();
+        push_const(&mut code, 1);
+        code.emit(OpCode::BoolNot);
+
+        let mut stack = Stack::new();
+        let mut heap = Heap::new();
+        let error = run(&code, &mut stack, &mut heap).unwrap_err();
+        assert_eq!(error, "Op BoolNot at byte 5 is not yet implemented");
+    }
+}
@@ -1,9 +1,57 @@
 use std::mem::transmute;
 use crate::Pointer;
+use crate::heap::pointer::PointerIdx;
 
+/// A `Slot` is a single 8-byte cell on the stack or heap.
+/// Slots can be used raw, as bits, or tagged,
+/// in which case NaN-tagging is used to tell the kind of value apart:
+///
+/// ```plain
+/// SExponent---QTTTPayload-----------------------------------------
+/// 0111111111111TTT................................................
+/// ```
+///
+/// Any float that is not a NaN is stored as-is.
+/// Every other kind of value lives in the space of quiet NaNs:
+/// `TTT` is the tag, and the lower 48 bits are the payload.
+/// Because NaNs are not allowed in Qualm, all NaNs collapse
+/// into the single canonical NaN, which has a tag of `0`.
 #[derive(Debug)]
 pub struct Slot(u64);
 
+const QNAN:    u64 = 0x7ff8_0000_0000_0000;
+const TAG:     u64 = 0x0007_0000_0000_0000;
+const PAYLOAD: u64 = 0x0000_ffff_ffff_ffff;
+
+const INT_TAG:       u64 = 0x0001_0000_0000_0000;
+const POINTER_TAG:   u64 = 0x0002_0000_0000_0000;
+const IMMEDIATE_TAG: u64 = 0x0003_0000_0000_0000;
+
+// Pointers keep their ownership in the top bit of the payload,
+// followed by 16 bits of generation, then the index.
+const POINTER_OWNED:      u64 = 0x0000_8000_0000_0000;
+const POINTER_GENERATION: u64 = 0x0000_7fff_8000_0000;
+const POINTER_INDEX:      u64 = 0x0000_0000_7fff_ffff;
+const POINTER_GENERATION_SHIFT: u64 = 31;
+
+const UNIT:  u64 = 0;
+const FALSE: u64 = 1;
+const TRUE:  u64 = 2;
+
+/// The smallest integer that can be stored in a tagged `Slot`.
+pub const INT_MIN: i64 = -(1 << 47);
+/// The largest integer that can be stored in a tagged `Slot`.
+pub const INT_MAX: i64 = (1 << 47) - 1;
+
+/// The kind of value held by a tagged `Slot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Float,
+    Int,
+    Pointer,
+    Immediate,
+}
+
 impl Slot {
     pub unsafe fn zero() -> Slot {
         Slot(0)
@@ -13,12 +61,108 @@ impl Slot {
         Slot(bits)
     }
 
-    pub unsafe fn to_borrowed_pointer(&self) -> Pointer {
-        Pointer::from_bits(self.0).borrow()
+    /// Creates a tagged float.
+    /// NaNs are collapsed into a single canonical NaN.
+    pub fn float(float: f64) -> Slot {
+        if float.is_nan() {
+            Slot(QNAN)
+        } else {
+            Slot(float.to_bits())
+        }
+    }
+
+    /// Creates a tagged integer.
+    /// Returns `None` if the integer does not fit in 48 bits,
+    /// i.e. is not within `INT_MIN..=INT_MAX`.
+    pub fn int(int: i64) -> Option<Slot> {
+        if !(INT_MIN..=INT_MAX).contains(&int) {
+            return None;
+        }
+        Some(Slot(QNAN | INT_TAG | (int as u64 & PAYLOAD)))
+    }
+
+    /// Creates a tagged pointer, preserving ownership and generation.
+    /// Panics if the pointer's index does not fit in 31 bits.
+    pub fn pointer(pointer: Pointer) -> Slot {
+        let index = pointer.to_idx().to_u64();
+        assert!(index <= POINTER_INDEX);
+        let owned = if pointer.is_owned() { POINTER_OWNED } else { 0 };
+        let generation = (pointer.generation() as u64) << POINTER_GENERATION_SHIFT;
+        Slot(QNAN | POINTER_TAG | owned | generation | index)
+    }
+
+    /// Creates a tagged unit.
+    pub fn unit() -> Slot {
+        Slot(QNAN | IMMEDIATE_TAG | UNIT)
+    }
+
+    /// Creates a tagged boolean.
+    pub fn boolean(boolean: bool) -> Slot {
+        Slot(QNAN | IMMEDIATE_TAG | if boolean { TRUE } else { FALSE })
+    }
+
+    /// Returns the kind of value held by a tagged slot.
+    /// Note that raw slots will likely be misinterpreted.
+    pub fn kind(&self) -> Kind {
+        if self.0 & QNAN != QNAN {
+            return Kind::Float;
+        }
+
+        match self.0 & TAG {
+            INT_TAG => Kind::Int,
+            POINTER_TAG => Kind::Pointer,
+            IMMEDIATE_TAG => Kind::Immediate,
+            // the canonical NaN
+            _ => Kind::Float,
+        }
+    }
+
+    /// Returns the tagged float, if this slot holds one.
+    pub fn as_float(&self) -> Option<f64> {
+        match self.kind() {
+            Kind::Float => Some(f64::from_bits(self.0)),
+            _ => None,
+        }
     }
 
-    pub unsafe fn to_pointer(&self) -> Pointer {
-        Pointer::from_bits(self.0)
+    /// Returns the tagged integer, if this slot holds one.
+    pub fn as_int(&self) -> Option<i64> {
+        match self.kind() {
+            // shift up and back down to sign-extend the payload
+            Kind::Int => Some(((self.0 << 16) as i64) >> 16),
+            _ => None,
+        }
+    }
+
+    /// Returns the tagged pointer, if this slot holds one.
+    pub fn as_pointer(&self) -> Option<Pointer> {
+        match self.kind() {
+            Kind::Pointer => {
+                let generation = (self.0 & POINTER_GENERATION) >> POINTER_GENERATION_SHIFT;
+                let pointer = Pointer::new(PointerIdx::new(self.0 & POINTER_INDEX))
+                    .with_generation(generation as u16);
+                if self.0 & POINTER_OWNED == POINTER_OWNED {
+                    Some(pointer)
+                } else {
+                    Some(pointer.borrow())
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the tagged boolean, if this slot holds one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match (self.kind(), self.0 & PAYLOAD) {
+            (Kind::Immediate, TRUE)  => Some(true),
+            (Kind::Immediate, FALSE) => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this slot holds a tagged unit.
+    pub fn is_unit(&self) -> bool {
+        self.kind() == Kind::Immediate && self.0 & PAYLOAD == UNIT
     }
 
     pub unsafe fn to_u64(&self) -> u64 {
@@ -59,3 +203,66 @@ impl Slot {
         (shifted & 0xFF) as u8
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    pub fn round_trip_float() {
+        for float in [0.0, -1.5, 2.71, f64::INFINITY, f64::MIN, f64::MAX] {
+            let slot = Slot::float(float);
+            assert_eq!(slot.kind(), Kind::Float);
+            assert_eq!(slot.as_float(), Some(float));
+            assert_eq!(slot.as_int(), None);
+        }
+
+        assert!(Slot::float(f64::NAN).as_float().unwrap().is_nan());
+    }
+
+    #[test]
+    pub fn round_trip_int() {
+        for int in [0, 1, -1, 42, INT_MIN, INT_MAX] {
+            let slot = Slot::int(int).unwrap();
+            assert_eq!(slot.kind(), Kind::Int);
+            assert_eq!(slot.as_int(), Some(int));
+            assert_eq!(slot.as_float(), None);
+        }
+
+        assert!(Slot::int(INT_MAX + 1).is_none());
+        assert!(Slot::int(INT_MIN - 1).is_none());
+    }
+
+    #[test]
+    pub fn round_trip_pointer() {
+        let owned = Pointer::new(PointerIdx::new(1234));
+        let slot = Slot::pointer(owned);
+        assert_eq!(slot.kind(), Kind::Pointer);
+        let pointer = slot.as_pointer().unwrap();
+        assert!(pointer.is_owned());
+        assert_eq!(pointer.to_idx(), owned.to_idx());
+
+        let borrowed = Slot::pointer(owned.borrow()).as_pointer().unwrap();
+        assert!(borrowed.is_borrowed());
+        assert_eq!(borrowed.to_idx(), owned.to_idx());
+        assert_eq!(slot.as_int(), None);
+
+        for generation in [0, 1, 0x1234, u16::MAX] {
+            let tagged = owned.with_generation(generation);
+            let pointer = Slot::pointer(tagged.borrow()).as_pointer().unwrap();
+            assert_eq!(pointer.generation(), generation);
+            assert_eq!(pointer.to_idx(), owned.to_idx());
+            assert!(pointer.is_borrowed());
+        }
+    }
+
+    #[test]
+    pub fn round_trip_immediate() {
+        assert_eq!(Slot::boolean(true).as_bool(), Some(true));
+        assert_eq!(Slot::boolean(false).as_bool(), Some(false));
+        assert!(Slot::unit().is_unit());
+        assert_eq!(Slot::unit().as_bool(), None);
+        assert_eq!(Slot::boolean(true).kind(), Kind::Immediate);
+        assert!(!Slot::boolean(false).is_unit());
+    }
+}
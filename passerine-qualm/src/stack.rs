@@ -1,8 +1,180 @@
+use crate::{Heap, Pointer, Slot};
+
+/// A call frame on the stack.
+#[derive(Debug)]
 pub struct Frame {
-    
+    /// The index of the first local of this frame.
+    base: usize,
+    /// The instruction pointer to resume at once this frame is popped.
+    return_ip: usize,
 }
 
+/// A stack of `Slot`s, split into frames.
+/// Like the stack of the main VM, each frame is a block of locals
+/// followed by temporaries:
+///
+/// ```plain
+/// L...T... L...T... ...
+/// ```
+///
+/// Locals are addressed relative to the start of the current frame.
+#[derive(Debug)]
 pub struct Stack {
-    data: Vec<u64>,
+    data:   Vec<Slot>,
     frames: Vec<Frame>,
 }
+
+impl Stack {
+    /// Creates a new `Stack` with a single root frame.
+    pub fn new() -> Stack {
+        Stack {
+            data:   vec![],
+            frames: vec![Frame { base: 0, return_ip: 0 }],
+        }
+    }
+
+    /// Returns the current, i.e. topmost, frame.
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("The root frame should never be popped")
+    }
+
+    /// Returns the index of a local on the stack.
+    fn local_index(&self, index: usize) -> usize {
+        let local = self.frame().base + index;
+        assert!(local < self.len(), "Local {} is not on the stack", index);
+        local
+    }
+
+    /// The total number of slots on the stack.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Pushes a slot onto the stack.
+    pub fn push(&mut self, slot: Slot) {
+        self.data.push(slot);
+    }
+
+    /// Pops the topmost slot off the stack.
+    /// Panics if there is nothing in the current frame to pop.
+    pub fn pop(&mut self) -> Slot {
+        assert!(self.len() > self.frame().base, "Can not pop past the current frame");
+        self.data.pop().unwrap()
+    }
+
+    /// Pushes a new frame onto the stack.
+    /// The topmost `args` slots become the first locals of the new frame.
+    pub fn push_frame(&mut self, return_ip: usize, args: usize) {
+        assert!(args <= self.len() - self.frame().base);
+        let base = self.len() - args;
+        self.frames.push(Frame { base, return_ip });
+    }
+
+    /// Pops the current frame, dropping all its locals and temporaries.
+    /// Returns the instruction pointer to resume at.
+    /// Panics if called on the root frame.
+    pub fn pop_frame(&mut self) -> usize {
+        assert!(self.frames.len() > 1, "Can not pop the root frame");
+        let frame = self.frames.pop().unwrap();
+        self.data.truncate(frame.base);
+        frame.return_ip
+    }
+
+    /// Returns a local in the current frame.
+    pub fn local(&self, index: usize) -> &Slot {
+        &self.data[self.local_index(index)]
+    }
+
+    /// Replaces a local in the current frame, returning the old value.
+    pub fn set_local(&mut self, index: usize, slot: Slot) -> Slot {
+        let local = self.local_index(index);
+        std::mem::replace(&mut self.data[local], slot)
+    }
+
+    /// Moves a local onto the heap, so that it can be captured.
+    /// The local is replaced with an owned pointer to the heaped value,
+    /// and a borrowed pointer is returned for the capturing closure.
+    pub fn capture(&mut self, index: usize, heap: &mut Heap) -> Pointer {
+        // SAFETY: the allocation is immediately written to
+        let pointer = unsafe { heap.alloc(1) };
+        let value = self.set_local(index, Slot::pointer(pointer));
+        heap.write(pointer, &mut [value]).unwrap();
+        pointer.borrow()
+    }
+
+    /// Reads a local that has been moved onto the heap by `capture`.
+    pub fn local_captured<'a>(&self, index: usize, heap: &'a Heap) -> &'a Slot {
+        let pointer = self.local(index)
+            .as_pointer()
+            .expect("Expected a captured local");
+        heap.read_slot(pointer, 0)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn int(slot: Slot) -> i64 {
+        slot.as_int().unwrap()
+    }
+
+    #[test]
+    pub fn push_pop_order() {
+        let mut stack = Stack::new();
+        for i in 0..3 {
+            stack.push(Slot::int(i).unwrap());
+        }
+
+        assert_eq!(stack.len(), 3);
+        assert_eq!(int(stack.pop()), 2);
+        assert_eq!(int(stack.pop()), 1);
+        assert_eq!(int(stack.pop()), 0);
+        assert_eq!(stack.len(), 0);
+    }
+
+    #[test]
+    pub fn frame_locals() {
+        let mut stack = Stack::new();
+        stack.push(Slot::int(10).unwrap());
+        stack.push(Slot::int(20).unwrap());
+        assert_eq!(stack.local(1).as_int(), Some(20));
+
+        // call with one argument, which becomes local 0
+        stack.push(Slot::int(30).unwrap());
+        stack.push_frame(7, 1);
+        assert_eq!(stack.local(0).as_int(), Some(30));
+
+        stack.push(Slot::int(40).unwrap());
+        let old = stack.set_local(1, Slot::int(41).unwrap());
+        assert_eq!(int(old), 40);
+        assert_eq!(stack.local(1).as_int(), Some(41));
+
+        // returning drops the frame and restores the old locals
+        assert_eq!(stack.pop_frame(), 7);
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.local(0).as_int(), Some(10));
+    }
+
+    #[test]
+    #[should_panic]
+    pub fn pop_past_frame() {
+        let mut stack = Stack::new();
+        stack.push(Slot::int(1).unwrap());
+        stack.push_frame(0, 0);
+        stack.pop();
+    }
+
+    #[test]
+    pub fn capture_local() {
+        let mut stack = Stack::new();
+        let mut heap = Heap::new();
+        stack.push(Slot::int(5).unwrap());
+
+        let captured = stack.capture(0, &mut heap);
+        assert!(captured.is_borrowed());
+        assert!(stack.local(0).as_pointer().unwrap().is_owned());
+        assert_eq!(stack.local_captured(0, &heap).as_int(), Some(5));
+        assert_eq!(heap.read_slot(captured, 0).as_int(), Some(5));
+    }
+}
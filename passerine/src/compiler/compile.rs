@@ -3,19 +3,22 @@ use std::{mem, rc::Rc};
 // TODO: hoist and resolve types
 use crate::{
     common::{
-        lambda::{Captured, Lambda},
+        closure::Closure,
+        lambda::{Captured, Lambda, LambdaKind},
         lit::Lit,
         number::split_number,
         opcode::Opcode,
         span::{Span, Spanned},
         Data,
     },
-    compiler::syntax::Syntax,
+    compiler::syntax::{Note, Syntax},
     construct::{
         scope::Scope,
         symbol::UniqueSymbol,
-        tree::{Base, Pattern, ScopedLambda, SST},
+        tree::{Base, Pattern, ScopedLambda, ScopedModule, SST},
     },
+    error_codes,
+    kernel::{ffi_core, FFI},
 };
 
 /// Compiler is a bytecode generator that walks an SST and produces
@@ -30,30 +33,114 @@ pub struct Compiler {
     /// Names of symbols,
     // symbol_table: Vec<String>,
     /// The foreign functional interface used to bind values
-    // ffi:       FFI,
-    /// The FFI functions that have been bound in this scope.
-    // ffi_names: Vec<String>,
+    ffi: FFI,
     // determined in hoisting
     scope: Scope,
+    /// The name given to the next lambda compiled,
+    /// set when a lambda is assigned directly to a variable.
+    name: Option<String>,
+    /// The loops enclosing the expression being compiled, innermost last.
+    loops: Vec<Loop>,
+    /// The number of values enclosing expressions have left on the stack
+    /// beneath the expression being compiled, e.g. earlier items of a tuple.
+    /// These are dropped when a `break` or `continue` leaves them behind.
+    temps: usize,
+}
+
+/// A loop being compiled.
+struct Loop {
+    /// The start of the body, where each iteration begins.
+    start: usize,
+    /// The value of `temps` when the loop was entered.
+    temps: usize,
+    /// The operands of the jumps emitted for each `break`,
+    /// patched to the end of the loop once it is known.
+    breaks: Vec<usize>,
+}
+
+/// Whether an expression evaluates directly to a lambda,
+/// e.g. `x -> x` or `{ 0 -> 1, n -> n }`.
+fn is_lambda(sst: &SST) -> bool {
+    match sst {
+        SST::ScopedLambda(_) => true,
+        SST::Base(Base::Block(block)) if block.len() == 1 => is_lambda(&block[0].item),
+        _ => false,
+    }
+}
+
+/// If an expression can never evaluate to a function,
+/// e.g. a number or a tuple, names the kind of data it evaluates to.
+/// Expressions that may evaluate to a function at runtime are left to the `Fiber`.
+fn not_callable(sst: &SST) -> Option<&'static str> {
+    match sst {
+        SST::Base(Base::Lit(Lit::Label(_, _))) => None,
+        SST::Base(Base::Lit(lit)) => Some(lit.clone().to_data().kind_name()),
+        SST::Base(Base::Tuple(_)) => Some("a tuple"),
+        SST::Base(Base::Block(block)) if block.len() == 1 => not_callable(&block[0].item),
+        _ => None,
+    }
 }
 
 impl Compiler {
+    /// Compiles an SST, allowing calls to the core FFI functions.
     pub fn compile(tree: Spanned<SST>, scope: Scope) -> Result<Rc<Lambda>, Syntax> {
-        dbg!(&tree);
-        // let ffi = ffi_core();
-        let mut compiler = Compiler::base(scope);
+        Compiler::compile_with_ffi(tree, scope, ffi_core())
+    }
+
+    /// Compiles an SST, allowing calls to the functions in a specific FFI.
+    pub fn compile_with_ffi(
+        tree: Spanned<SST>,
+        scope: Scope,
+        ffi: FFI,
+    ) -> Result<Rc<Lambda>, Syntax> {
+        Compiler::compile_root(tree, scope, ffi, false)
+    }
+
+    /// Like `compile_with_ffi`, but the program leaves its variables on the stack,
+    /// beneath its result, so that the next program of a session can use them,
+    /// see `Hoister::hoist_next` and `Fiber::continue_with`.
+    pub fn compile_continuing(
+        tree: Spanned<SST>,
+        scope: Scope,
+        ffi: FFI,
+    ) -> Result<Rc<Lambda>, Syntax> {
+        Compiler::compile_root(tree, scope, ffi, true)
+    }
+
+    fn compile_root(
+        tree: Spanned<SST>,
+        scope: Scope,
+        ffi: FFI,
+        continuing: bool,
+    ) -> Result<Rc<Lambda>, Syntax> {
+        let mut compiler = Compiler::base(ffi, scope);
         compiler.walk(&tree)?;
+
+        // every program ends by returning its result,
+        // which is how the fiber knows to stop
+        let locals = if continuing {
+            // a call right before the return would be a tail call,
+            // which drops the variables before the called function runs
+            compiler.lambda.emit(Opcode::Noop);
+            0
+        } else {
+            compiler.scope.locals.len()
+        };
+        compiler.lambda.emit(Opcode::Return);
+        compiler.lambda.emit_bytes(&mut split_number(locals));
         return Ok(Rc::new(compiler.lambda));
     }
 
     /// Construct a new `Compiler`.
-    fn base(scope: Scope) -> Compiler {
+    fn base(ffi: FFI, scope: Scope) -> Compiler {
         Compiler {
             enclosing: None,
             lambda: Lambda::empty(),
-            // ffi,
-            // ffi_names: vec![],
+            ffi,
             scope,
+            name: None,
+            loops: vec![],
+            temps: 0,
         }
     }
 
@@ -61,8 +148,8 @@ impl Compiler {
     /// keeping a reference to the old one in `self.enclosing`,
     /// and moving the FFI into the current compiler.
     fn enter_scope(&mut self, scope: Scope) {
-        // let ffi = mem::replace(&mut self.ffi, FFI::new());
-        let nested = Compiler::base(scope);
+        let ffi = mem::replace(&mut self.ffi, FFI::new());
+        let nested = Compiler::base(ffi, scope);
         let enclosing = mem::replace(self, nested);
         self.enclosing = Some(Box::new(enclosing));
     }
@@ -71,13 +158,13 @@ impl Compiler {
     /// returning the nested one for data (Lambda) extraction,
     /// and moving the FFI mappings back into the enclosing compiler.
     fn exit_scope(&mut self) -> Compiler {
-        // let ffi = mem::replace(&mut self.ffi, FFI::new());
+        let ffi = mem::replace(&mut self.ffi, FFI::new());
         let enclosing = mem::replace(&mut self.enclosing, None);
         let nested = match enclosing {
             Some(compiler) => mem::replace(self, *compiler),
             None => unreachable!("Can not go back past root copiler"),
         };
-        // self.ffi = ffi;
+        self.ffi = ffi;
         return nested;
     }
 
@@ -98,25 +185,54 @@ impl Compiler {
             SST::Base(Base::Lit(lit)) => Ok(self.lit(lit)),
             SST::Base(Base::Symbol(unique)) => Ok(self.symbol(unique)),
             SST::Base(Base::Block(block)) => self.block(block),
-            // SST::Base(Base::Label(name, expression)) => {
-            //     self.label(name, *expression)
-            // },
-            SST::Base(Base::Label(_)) => todo!(),
+            SST::Base(Base::Label(_)) => Err(Syntax::error_with_note(
+                "A label must be applied to some data",
+                Note::new_with_hint(
+                    "to label nothing, apply the label to unit, i.e. `None ()`",
+                    &sst.span,
+                ),
+            )),
             SST::Base(Base::Tuple(tuple)) => self.tuple(tuple),
+            SST::Base(Base::Spread(_)) => Err(Syntax::error(
+                "The spread operator `..` can only be used inside a tuple",
+                &sst.span,
+            )
+            .with_code(error_codes::MISPLACED_SPREAD)),
             SST::Base(Base::Assign(pattern, expression)) => self.assign(pattern, *expression),
             SST::ScopedLambda(ScopedLambda { arg, body, scope }) => self.lambda(arg, *body, scope),
-            SST::Base(Base::Call(fun, arg)) => self.call(*fun, *arg),
-            SST::Base(Base::Module(_)) => todo!("need to handle modules"),
+            SST::Base(Base::Call(fun, arg, _)) => self.call(*fun, *arg),
+            SST::ScopedModule(ScopedModule { body, scope }) => self.module(*body, scope),
+            SST::Base(Base::Module(_)) => unreachable!("Modules are scoped during hoisting"),
             SST::Base(Base::Effect(_)) => todo!("need to handle effects"),
+            SST::Base(Base::FFI(name, expression)) => self.ffi(name, *expression, sst.span.clone()),
+            SST::Base(Base::Match(value, arms)) => self.match_arms(*value, arms, sst.span.clone()),
+            SST::Base(Base::Update(base, fields)) => self.update(*base, fields, sst.span.clone()),
+            SST::Base(Base::Record(fields)) => self.record(fields),
+            SST::Base(Base::Loop(body)) => self.loop_body(*body),
+            SST::Base(Base::Break(value)) => self.break_value(*value, sst.span.clone()),
+            SST::Base(Base::Continue) => self.continue_loop(sst.span.clone()),
         };
     }
 
+    /// Walks an expression while some values are kept on the stack beneath it.
+    fn walk_above(&mut self, sst: &Spanned<SST>, temps: usize) -> Result<(), Syntax> {
+        self.temps += temps;
+        let result = self.walk(sst);
+        self.temps -= temps;
+        result
+    }
+
     // TODO: closures are just lambdas + records
     // refactor as such?
 
     /// Resovles a symbol lookup, e.g. something like `x`.
+    /// Variables with small indices are loaded without an operand.
     fn symbol(&mut self, unique_symbol: UniqueSymbol) {
         let index = if let Some(i) = self.scope.local_index(unique_symbol) {
+            if let Some(load) = Opcode::load_local(i) {
+                self.lambda.emit(load);
+                return;
+            }
             self.lambda.emit(Opcode::Load);
             i
         } else if let Some(i) = self.scope.nonlocal_index(unique_symbol) {
@@ -145,44 +261,49 @@ impl Compiler {
             return Ok(());
         }
 
-        for child in children {
+        for (index, child) in children.into_iter().enumerate() {
+            // only the value of the last expression is kept
+            if index > 0 {
+                self.lambda.emit(Opcode::Del);
+            }
             self.walk(&child)?;
-            self.lambda.emit(Opcode::Del);
         }
-
-        // remove the last delete instruction
-        self.lambda.demit();
         Ok(())
     }
 
-    /// Generates a print expression
-    /// Note that currently printing is a baked-in language feature,
-    /// but the second the FFI becomes a thing
-    /// it'll no longer be one.
-    fn print(&mut self, expression: Spanned<SST>) -> Result<(), Syntax> {
-        self.walk(&expression)?;
-        self.lambda.emit(Opcode::Print);
-        Ok(())
+    /// Loads the kind of a label, which is the index of its symbol.
+    /// Must directly precede the opcode that uses the kind.
+    fn kind(&mut self, name: UniqueSymbol) {
+        self.lambda.emit(Opcode::Con);
+        let mut split = split_number(self.lambda.index_data(Data::Kind(name.0)));
+        self.lambda.emit_bytes(&mut split);
     }
 
-    /// Generates a Label construction
-    /// that loads the variant, then wraps some data
+    /// Generates a Label construction,
+    /// i.e. `Some x`, that pushes some data, loads the kind,
+    /// then wraps the data in a label of that kind.
     fn label(&mut self, name: UniqueSymbol, expression: Spanned<SST>) -> Result<(), Syntax> {
-        todo!()
-        // self.walk(&expression)?;
-        // self.lit(Lit::Kind(name.0));
-        // self.lambda.emit(Opcode::Label);
-        // Ok(())
+        self.walk(&expression)?;
+        self.kind(name);
+        self.lambda.emit(Opcode::Label);
+        Ok(())
     }
 
     /// Generates a Tuple construction
     /// that loads all fields in the tuple
     /// then rips them off the stack into a vec.
     fn tuple(&mut self, tuple: Vec<Spanned<SST>>) -> Result<(), Syntax> {
+        if tuple
+            .iter()
+            .any(|item| matches!(item.item, SST::Base(Base::Spread(_))))
+        {
+            return self.spread_tuple(tuple);
+        }
+
         let length = tuple.len();
 
-        for item in tuple.into_iter() {
-            self.walk(&item)?;
+        for (index, item) in tuple.into_iter().enumerate() {
+            self.walk_above(&item, index)?;
         }
 
         self.lambda.emit(Opcode::Tuple);
@@ -190,50 +311,107 @@ impl Compiler {
         Ok(())
     }
 
-    // TODO: remove FFI!
-
-    // // TODO: make a macro to map Passerine's data model to Rust's
-    // /// Makes a Rust function callable from Passerine,
-    // /// by keeping a reference to that function.
-    // fn ffi(
-    //     &mut self,
-    //     name: String,
-    //     expression: Spanned<SST>,
-    //     span: Span,
-    // ) -> Result<(), Syntax> {
-    //     self.walk(&expression)?;
-
-    //     let function =
-    //         self.ffi.get(&name).map_err(|s| Syntax::error(&s, &span))?;
-
-    //     let index = match self.ffi_names.iter().position(|n| n == &name) {
-    //         Some(p) => p,
-    //         None => {
-    //             // TODO: switch ffi to symbol, just use unique symbol?
-    //             // TODO: keeping track of state
-    //             // in two different places is a code smell imo
-    //             // Reason: don't want to include strings in lambda
-    //             // optimal solutions:
-    //             // have an earlier step that normalizes AST,
-    //             // determines scope of all names/symbols,
-    //             // and replaces all names/symbols with indicies
-    //             // before codgen.
-    //             self.ffi_names.push(name);
-    //             todo!("add FFI function")
-    //             // self.lambda.add_ffi(function)
-    //         },
-    //     };
-
-    //     self.lambda.emit_span(&span);
-    //     self.lambda.emit(Opcode::FFICall);
-    //     self.lambda.emit_bytes(&mut split_number(index));
-    //     Ok(())
-    // }
+    /// Generates a record update, i.e. `{ r | x = 1 }`.
+    /// Pushes the record, then a pair of name and value for each field.
+    fn update(
+        &mut self,
+        base: Spanned<SST>,
+        fields: Vec<(String, Spanned<SST>)>,
+        span: Span,
+    ) -> Result<(), Syntax> {
+        self.walk(&base)?;
+        let length = fields.len();
+        for (index, (name, value)) in fields.into_iter().enumerate() {
+            self.lit(Lit::String(name));
+            self.walk_above(&value, 2 + 2 * index)?;
+        }
+
+        self.lambda.emit_span(&span);
+        self.lambda.emit(Opcode::RecordUpdate);
+        self.lambda.emit_bytes(&mut split_number(length));
+        Ok(())
+    }
+
+    /// Generates a record, i.e. `{ x = 1, y = 2 }`.
+    /// Pushes a pair of name and value for each field.
+    fn record(&mut self, fields: Vec<(String, Spanned<SST>)>) -> Result<(), Syntax> {
+        let length = fields.len();
+        for (index, (name, value)) in fields.into_iter().enumerate() {
+            self.lit(Lit::String(name));
+            self.walk_above(&value, 1 + 2 * index)?;
+        }
+
+        self.lambda.emit(Opcode::Record);
+        self.lambda.emit_bytes(&mut split_number(length));
+        Ok(())
+    }
+
+    /// Generates a Tuple construction where some items are spread.
+    /// Starts with an empty tuple, then extends it with
+    /// each run of regular items and each spread tuple, in order.
+    fn spread_tuple(&mut self, tuple: Vec<Spanned<SST>>) -> Result<(), Syntax> {
+        self.lambda.emit(Opcode::Tuple);
+        self.lambda.emit_bytes(&mut split_number(0));
+
+        let mut run = 0;
+        for item in tuple.into_iter() {
+            if let SST::Base(Base::Spread(spread)) = item.item {
+                self.extend_tuple(run);
+                run = 0;
+
+                self.walk_above(&spread, 1)?;
+                self.lambda.emit_span(&item.span);
+                self.lambda.emit(Opcode::TupleExtend);
+            } else {
+                self.walk_above(&item, 1 + run)?;
+                run += 1;
+            }
+        }
+
+        self.extend_tuple(run);
+        Ok(())
+    }
+
+    /// Packs the last `run` items on the stack into a tuple,
+    /// then splices it into the tuple being built.
+    fn extend_tuple(&mut self, run: usize) {
+        if run == 0 {
+            return;
+        }
+
+        self.lambda.emit(Opcode::Tuple);
+        self.lambda.emit_bytes(&mut split_number(run));
+        self.lambda.emit(Opcode::TupleExtend);
+    }
+
+    /// Makes a Rust function callable from Passerine,
+    /// by keeping track of the name of that function.
+    fn ffi(&mut self, name: String, expression: Spanned<SST>, span: Span) -> Result<(), Syntax> {
+        self.walk(&expression)?;
+
+        if !self.ffi.contains(&name) {
+            return Err(Syntax::error(
+                &format!("The FFI function `{}` does not exist", name),
+                &span,
+            )
+            .with_code(error_codes::UNKNOWN_FFI));
+        }
+        let index = self.lambda.add_ffi(&name);
+
+        self.lambda.emit_span(&span);
+        self.lambda.emit(Opcode::FFICall);
+        self.lambda.emit_bytes(&mut split_number(index));
+        Ok(())
+    }
 
     /// Resolves the assignment of a variable
     /// returns true if the variable was declared.
     fn resolve_assign(&mut self, unique_symbol: UniqueSymbol) {
         let index = if let Some(i) = self.scope.local_index(unique_symbol) {
+            if let Some(save) = Opcode::save_local(i) {
+                self.lambda.emit(save);
+                return;
+            }
             self.lambda.emit(Opcode::Save);
             i
         } else if let Some(i) = self.scope.nonlocal_index(unique_symbol) {
@@ -263,10 +441,9 @@ impl Compiler {
                 self.lambda.emit(Opcode::UnData);
             }
             Pattern::Label(name, pattern) => {
-                todo!()
-                // self.lit(Lit::Kind(name.0));
-                // self.lambda.emit(Opcode::UnLabel);
-                // self.destructure(*pattern, redeclare);
+                self.kind(name.item);
+                self.lambda.emit(Opcode::UnLabel);
+                self.destructure(*pattern, redeclare);
             }
             Pattern::Tuple(tuple) => {
                 for (index, sub_pattern) in tuple.into_iter().enumerate() {
@@ -277,16 +454,123 @@ impl Compiler {
                 // Delete the tuple moved to the top of the stack.
                 self.lambda.emit(Opcode::Del);
             }
-            Pattern::Chain(_) => todo!("handle pattern chains"),
         }
     }
 
+    /// Tests whether the topmost value matches a pattern,
+    /// replacing it with a boolean. Does not bind any variables.
+    fn test(&mut self, pattern: &Spanned<Pattern<UniqueSymbol>>) {
+        match &pattern.item {
+            Pattern::Symbol(_) => {
+                self.lambda.emit(Opcode::Del);
+                self.lit(Lit::Boolean(true));
+            }
+            Pattern::Lit(expected) => {
+                self.lit(expected.clone());
+                self.lambda.emit(Opcode::Test);
+            }
+            Pattern::Tuple(tuple) => {
+                // each check jumps to the failure case if it does not pass,
+                // leaving just the tuple being tested on the stack
+                self.lambda.emit(Opcode::TestTuple);
+                self.lambda.emit_bytes(&mut split_number(tuple.len()));
+                let mut fails = vec![self.lambda.emit_jump(Opcode::JumpFalse)];
+
+                for (index, sub_pattern) in tuple.iter().enumerate() {
+                    self.lambda.emit(Opcode::UnTuple);
+                    self.lambda.emit_bytes(&mut split_number(index));
+                    self.test(sub_pattern);
+                    fails.push(self.lambda.emit_jump(Opcode::JumpFalse));
+                }
+
+                self.lambda.emit(Opcode::Del);
+                self.lit(Lit::Boolean(true));
+                let end = self.lambda.emit_jump(Opcode::Jump);
+
+                for fail in fails {
+                    self.lambda.patch_jump(fail);
+                }
+                self.lambda.emit(Opcode::Del);
+                self.lit(Lit::Boolean(false));
+                self.lambda.patch_jump(end);
+            }
+            Pattern::Label(name, pattern) => {
+                // if the value is a label of the right kind,
+                // it is unwrapped and the data inside it is tested
+                self.kind(name.item);
+                self.lambda.emit(Opcode::TestLabel);
+                let fail = self.lambda.emit_jump(Opcode::JumpFalse);
+
+                self.kind(name.item);
+                self.lambda.emit(Opcode::UnLabel);
+                self.test(pattern);
+                let end = self.lambda.emit_jump(Opcode::Jump);
+
+                self.lambda.patch_jump(fail);
+                self.lambda.emit(Opcode::Del);
+                self.lit(Lit::Boolean(false));
+                self.lambda.patch_jump(end);
+            }
+        }
+    }
+
+    /// Compiles a match.
+    /// The value is kept on the stack while each arm is tried in turn:
+    /// a copy of the value is tested against the pattern of the arm,
+    /// and if it matches, the value is destructured and the body is run.
+    /// Otherwise, the next arm is tried.
+    /// If no arm matches, a runtime error is raised.
+    fn match_arms(
+        &mut self,
+        value: Spanned<SST>,
+        arms: Vec<(Spanned<Pattern<UniqueSymbol>>, Spanned<SST>)>,
+        span: Span,
+    ) -> Result<(), Syntax> {
+        self.walk(&value)?;
+        let mut ends = vec![];
+
+        for (pattern, body) in arms {
+            // a symbol always matches, so there is nothing to test
+            let next = if let Pattern::Symbol(_) = pattern.item {
+                None
+            } else {
+                self.lambda.emit(Opcode::Copy);
+                self.test(&pattern);
+                Some(self.lambda.emit_jump(Opcode::JumpFalse))
+            };
+
+            // the value is consumed by destructuring,
+            // so nothing is left beneath the body for a `break` to drop
+            self.destructure(pattern, true);
+            self.walk(&body)?;
+            ends.push(self.lambda.emit_jump(Opcode::Jump));
+
+            if let Some(next) = next {
+                self.lambda.patch_jump(next);
+            }
+        }
+
+        self.lambda.emit_span(&span);
+        self.lambda.emit(Opcode::NoMatch);
+        for end in ends {
+            self.lambda.patch_jump(end);
+        }
+        Ok(())
+    }
+
     /// Assign a value to a variable.
     fn assign(
         &mut self,
         pattern: Spanned<Pattern<UniqueSymbol>>,
         expression: Spanned<SST>,
     ) -> Result<(), Syntax> {
+        // name functions after the variable they're assigned to
+        if let Pattern::Symbol(unique) = &pattern.item {
+            if is_lambda(&expression.item) {
+                self.name = self.scope.name(*unique).map(str::to_string);
+            }
+        }
+
         // eval the expression
         self.walk(&expression)?;
         self.destructure(pattern, false);
@@ -294,14 +578,9 @@ impl Compiler {
         Ok(())
     }
 
-    /// Recursively compiles a lambda declaration in a new scope.
-    fn lambda(
-        &mut self,
-        pattern: Spanned<Pattern<UniqueSymbol>>,
-        expression: Spanned<SST>,
-        scope: Scope,
-    ) -> Result<(), Syntax> {
-        // build a list of captures at the boundary
+    /// Builds a list of the captures of a nested scope at the boundary,
+    /// moving any captured locals onto the heap.
+    fn captures(&mut self, scope: &Scope) -> Vec<Captured> {
         let mut captures = vec![];
         for nonlocal in scope.nonlocals.items().iter() {
             let captured = if self.scope.is_local(*nonlocal) {
@@ -314,45 +593,358 @@ impl Compiler {
             };
             captures.push(captured);
         }
+        captures
+    }
+
+    /// Returns the names of the variables captured in the current scope.
+    fn capture_names(&self) -> Vec<String> {
+        self.scope
+            .nonlocals
+            .items()
+            .iter()
+            .map(|nonlocal| self.scope.name(*nonlocal).unwrap_or("_").to_string())
+            .collect()
+    }
+
+    /// Pushes a closure over a compiled lambda onto the stack.
+    fn closure(&mut self, lambda: Lambda) {
+        // a lambda that captures nothing does not depend on its environment,
+        // so it can be closed once here and loaded as a constant.
+        if lambda.captures.is_empty() {
+            let closure = Closure::wrap(Rc::new(lambda));
+            let index = self.lambda.index_data(Data::Closure(Box::new(closure)));
+            self.lambda.emit(Opcode::Con);
+            self.lambda.emit_bytes(&mut split_number(index));
+            return;
+        }
+
+        // push the lambda object onto the callee's stack.
+        let lambda_index = self.lambda.index_data(Data::Lambda(Rc::new(lambda)));
+        self.lambda.emit(Opcode::Closure);
+        self.lambda.emit_bytes(&mut split_number(lambda_index));
+    }
+
+    /// Recursively compiles a lambda declaration in a new scope.
+    fn lambda(
+        &mut self,
+        pattern: Spanned<Pattern<UniqueSymbol>>,
+        expression: Spanned<SST>,
+        scope: Scope,
+    ) -> Result<(), Syntax> {
+        let captures = self.captures(&scope);
+        let name = self.name.take();
 
         // just so the parallel is visually apparent
         self.enter_scope(scope);
-        {
+        let walked = {
             // push locals and captures into lambda
+            self.lambda.kind = LambdaKind::Function;
+            self.lambda.name = name;
             self.lambda.captures = captures;
+            self.lambda.capture_names = self.capture_names();
 
             // match the argument against the pattern, binding variables
             self.destructure(pattern, true);
 
             // enter a new scope and walk the function body
-            self.walk(&expression)?;
+            let walked = self.walk(&expression);
 
             // return the result
             self.lambda.emit(Opcode::Return);
             self.lambda
                 .emit_bytes(&mut split_number(self.scope.locals.len()));
-        }
+            walked
+        };
+        // the enclosing compiler is restored even if the body is invalid
         let lambda = self.exit_scope().lambda;
+        walked?;
+        self.closure(lambda);
 
-        // push the lambda object onto the callee's stack.
-        // todo!("insert lambda as data");
-        let lambda_index = self.lambda.index_data(Data::Lambda(Rc::new(lambda)));
-        self.lambda.emit(Opcode::Closure);
-        self.lambda.emit_bytes(&mut split_number(lambda_index));
+        Ok(())
+    }
 
+    /// Compiles a module in a new scope.
+    /// A module is compiled as a lambda that runs the body of the module,
+    /// then collects the variables declared in its scope into a record.
+    /// This lambda is called immediately, leaving the record on the stack.
+    fn module(&mut self, body: Spanned<SST>, scope: Scope) -> Result<(), Syntax> {
+        let captures = self.captures(&scope);
+
+        self.enter_scope(scope);
+        let walked = {
+            self.lambda.kind = LambdaKind::Module;
+            self.lambda.captures = captures;
+            self.lambda.capture_names = self.capture_names();
+
+            // modules are called with unit, which is ignored
+            self.lambda.emit(Opcode::Del);
+            let walked = self.walk(&body);
+            self.lambda.emit(Opcode::Del);
+
+            // push the name and value of each field, then build the record
+            let fields = self.scope.locals.items();
+            for field in fields.iter() {
+                let name = self.scope.name(*field).unwrap_or("_").to_string();
+                self.lit(Lit::String(name));
+                self.symbol(*field);
+            }
+            self.lambda.emit(Opcode::Record);
+            self.lambda.emit_bytes(&mut split_number(fields.len()));
+
+            self.lambda.emit(Opcode::Return);
+            self.lambda
+                .emit_bytes(&mut split_number(self.scope.locals.len()));
+            walked
+        };
+        // the enclosing compiler is restored even if the body is invalid
+        let lambda = self.exit_scope().lambda;
+        walked?;
+
+        self.lit(Lit::Unit);
+        self.closure(lambda);
+        self.lambda.emit(Opcode::Call);
         Ok(())
     }
 
     /// When a function is called, the top two items are taken off the stack,
     /// The topmost item is expected to be a function.
+    /// Calls to data that is never a function are caught here, rather than at runtime.
     fn call(&mut self, fun: Spanned<SST>, arg: Spanned<SST>) -> Result<(), Syntax> {
+        // applying a label to some data labels it
+        if let SST::Base(Base::Label(name)) = fun.item {
+            return self.label(name, arg);
+        }
+
+        if let Some(kind) = not_callable(&fun.item) {
+            return Err(Syntax::error(
+                &format!(
+                    "This is {}, which is not a function and can not be called",
+                    kind
+                ),
+                &Span::combine(&fun.span, &arg.span),
+            )
+            .with_code(error_codes::NOT_CALLABLE));
+        }
+
         self.walk(&arg)?;
-        self.walk(&fun)?;
+        self.walk_above(&fun, 1)?;
 
         self.lambda.emit_span(&Span::combine(&fun.span, &arg.span));
         self.lambda.emit(Opcode::Call);
         Ok(())
     }
+
+    /// Compiles a loop.
+    /// The value of the body is dropped after each iteration,
+    /// then the body is run again.
+    /// Each `break` leaves its value on the stack and jumps past the loop.
+    fn loop_body(&mut self, body: Spanned<SST>) -> Result<(), Syntax> {
+        let start = self.lambda.code.len();
+        self.loops.push(Loop {
+            start,
+            temps: self.temps,
+            breaks: vec![],
+        });
+
+        let walked = self.walk(&body);
+        self.lambda.emit(Opcode::Del);
+        self.lambda.emit_jump_to(Opcode::Jump, start);
+
+        let compiled = self.loops.pop().unwrap();
+        walked?;
+        for operand in compiled.breaks {
+            self.lambda.patch_jump(operand);
+        }
+        Ok(())
+    }
+
+    /// Drops the values left on the stack since the innermost loop was entered,
+    /// returning that loop.
+    /// Loops in enclosing functions can not be reached.
+    fn leave_to_loop(&mut self, keyword: &str, span: &Span) -> Result<&mut Loop, Syntax> {
+        let temps = match self.loops.last() {
+            Some(innermost) => self.temps - innermost.temps,
+            None => {
+                return Err(Syntax::error(
+                    &format!("`{}` can only be used inside a loop", keyword),
+                    span,
+                )
+                .with_code(error_codes::OUTSIDE_LOOP))
+            }
+        };
+        for _ in 0..temps {
+            self.lambda.emit(Opcode::Del);
+        }
+        Ok(self.loops.last_mut().unwrap())
+    }
+
+    /// Compiles a break, which finishes the innermost loop with a value.
+    fn break_value(&mut self, value: Spanned<SST>, span: Span) -> Result<(), Syntax> {
+        let temps = self.leave_to_loop("break", &span)?.temps;
+        let enclosing = mem::replace(&mut self.temps, temps);
+        let result = self.walk(&value);
+        self.temps = enclosing;
+        result?;
+
+        let operand = self.lambda.emit_jump(Opcode::Jump);
+        self.loops.last_mut().unwrap().breaks.push(operand);
+        Ok(())
+    }
+
+    /// Compiles a continue, which starts the next iteration of the innermost loop.
+    fn continue_loop(&mut self, span: Span) -> Result<(), Syntax> {
+        let start = self.leave_to_loop("continue", &span)?.start;
+        self.lambda.emit_jump_to(Opcode::Jump, start);
+        Ok(())
+    }
 }
 
-// TODO: tests
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{common::Source, compiler::compile_source};
+
+    #[test]
+    fn dedup_literals() {
+        let lambda = compile_source(Source::source("x = 0; y = 0; z = 0; (x, y, z, 0)")).unwrap();
+
+        for (index, constant) in lambda.constants.iter().enumerate() {
+            assert!(
+                !lambda.constants[..index].contains(constant),
+                "{:?} is duplicated in {:?}",
+                constant,
+                lambda.constants,
+            );
+        }
+        assert_eq!(lambda.constants, vec![Data::Integer(0), Data::Unit]);
+    }
+
+    #[test]
+    fn call_non_function() {
+        let error = |source| compile_source(Source::source(source)).unwrap_err();
+        assert!(error("3 4")
+            .reason
+            .contains("This is an integer, which is not a function"));
+        assert!(error("x = (1, 2) 3").reason.contains("This is a tuple"));
+        assert!(error("(\"a\") ()").reason.contains("This is a string"));
+
+        // the value of a variable is only known at runtime
+        assert!(compile_source(Source::source("f = 3; f 4")).is_ok());
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        let error = |source| compile_source(Source::source(source)).unwrap_err();
+        assert!(error("break 1")
+            .reason
+            .contains("`break` can only be used inside a loop"));
+        assert!(error("continue")
+            .reason
+            .contains("`continue` can only be used inside a loop"));
+
+        // a loop can not be left from a function defined inside it
+        let source = "loop { f = x -> break x; f 1 }";
+        assert!(error(source)
+            .reason
+            .contains("`break` can only be used inside a loop"));
+    }
+
+    /// Builds the code of a program from opcodes and their operands.
+    fn code(ops: &[(Opcode, Option<usize>)]) -> Vec<u8> {
+        let mut lambda = Lambda::empty();
+        for (op, operand) in ops {
+            lambda.emit(*op);
+            if let Some(operand) = operand {
+                lambda.emit_bytes(&mut split_number(*operand));
+            }
+        }
+        lambda.code
+    }
+
+    #[test]
+    fn block_code() {
+        use Opcode::{Con, Del, Return};
+        let compile = |source| compile_source(Source::source(source)).unwrap().code.clone();
+
+        assert_eq!(compile(""), code(&[(Con, Some(0)), (Return, Some(0))]));
+        assert_eq!(compile("1"), code(&[(Con, Some(0)), (Return, Some(0))]));
+        assert_eq!(
+            compile("1; 2\n3"),
+            code(&[
+                (Con, Some(0)),
+                (Del, None),
+                (Con, Some(1)),
+                (Del, None),
+                (Con, Some(2)),
+                (Return, Some(0)),
+            ])
+        );
+        // a nested block leaves one value, like any other expression
+        assert_eq!(
+            compile("{ 1; 2 }; 3"),
+            code(&[
+                (Con, Some(0)),
+                (Del, None),
+                (Con, Some(1)),
+                (Del, None),
+                (Con, Some(2)),
+                (Return, Some(0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn label_code() {
+        use Opcode::{Con, Label, Return};
+        let lambda = compile_source(Source::source("Some 1")).unwrap();
+        assert_eq!(lambda.verify(), Ok(()));
+
+        // the data, then its kind, which directly precedes the label
+        assert_eq!(
+            lambda.code,
+            code(&[
+                (Con, Some(0)),
+                (Con, Some(1)),
+                (Label, None),
+                (Return, Some(0)),
+            ])
+        );
+        assert_eq!(lambda.constants[0], Data::Integer(1));
+        assert!(matches!(lambda.constants[1], Data::Kind(_)));
+    }
+
+    #[test]
+    fn lambda_names() {
+        let lambda = compile_source(Source::source(
+            "f = x -> x; g = { 0 -> 1, n -> n }; h = f (y -> y)",
+        ))
+        .unwrap();
+
+        let names: Vec<_> = lambda
+            .constants
+            .iter()
+            .filter_map(|constant| match constant {
+                Data::Closure(closure) => Some(closure.lambda.name.as_deref()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec![Some("f"), Some("g"), None]);
+    }
+
+    #[test]
+    fn lambda_kinds() {
+        let lambda = compile_source(Source::source("m = mod { x = 1 }; f = x -> x; m")).unwrap();
+        assert_eq!(lambda.kind, LambdaKind::Script);
+
+        let kinds: Vec<_> = lambda
+            .constants
+            .iter()
+            .filter_map(|constant| match constant {
+                Data::Closure(closure) => Some(closure.lambda.kind),
+                Data::Lambda(lambda) => Some(lambda.kind),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds, vec![LambdaKind::Module, LambdaKind::Function]);
+    }
+}
@@ -1,57 +1,107 @@
+use std::collections::HashMap;
+
 use crate::{
     common::{Span, Spanned},
+    compiler::syntax::Syntax,
     construct::{
         symbol::SharedSymbol,
-        tree::{Base, Lambda, Sugar, AST, CST},
+        tree::{Base, Lambda, Pattern, Sugar, AST, CST},
     },
 };
 
-pub struct Desugarer;
+/// The name of the argument of a function defined by clauses.
+/// Comments start with `#`, so this can not clash with a name in the source.
+const CLAUSES_ARG: &str = "#arg";
+
+pub struct Desugarer {
+    /// The symbol interning table built by the parser,
+    /// extended with any symbols introduced while desugaring.
+    symbols: HashMap<String, SharedSymbol>,
+}
 
 type SharedBase<T> = Base<Spanned<T>, SharedSymbol>;
 
 impl Desugarer {
     // TODO: just rename walk to desugar?
-    // TODO: is this pass infallible?
-    // Maybe make Result once more things are added...
-    pub fn desugar(ast: Spanned<AST>) -> Spanned<CST> {
-        Desugarer::walk(ast)
+    /// Desugars an `AST` into a `CST`,
+    /// returning the symbol table with any new symbols added.
+    pub fn desugar(
+        ast: Spanned<AST>,
+        symbols: HashMap<String, SharedSymbol>,
+    ) -> Result<(Spanned<CST>, HashMap<String, SharedSymbol>), Syntax> {
+        let mut desugarer = Desugarer { symbols };
+        let cst = desugarer.walk(ast)?;
+        Ok((cst, desugarer.symbols))
     }
 
-    fn walk(ast: Spanned<AST>) -> Spanned<CST> {
+    fn walk(&mut self, ast: Spanned<AST>) -> Result<Spanned<CST>, Syntax> {
         // TODO: use this destructuring pattern throughout codebase!
         let Spanned { item, span } = ast;
         let item = match item {
-            AST::Base(b) => CST::Base(Desugarer::walk_base(b)),
-            AST::Lambda(l) => CST::Lambda(Desugarer::walk_lambda(l)),
-            AST::Sugar(s) => Desugarer::walk_sugar(s),
+            // parentheses only group, so they're dropped along with their span
+            AST::Sugar(Sugar::Group(g)) => return self.walk(*g),
+            AST::Base(b) => CST::Base(self.walk_base(b)?),
+            AST::Lambda(l) => CST::Lambda(self.walk_lambda(l)?),
+            AST::Sugar(s) => self.walk_sugar(s, &span)?,
         };
-        return Spanned::new(item, span);
+        return Ok(Spanned::new(item, span));
     }
 
-    fn walk_base(b: SharedBase<AST>) -> SharedBase<CST> {
-        match b {
+    fn walk_all(&mut self, asts: Vec<Spanned<AST>>) -> Result<Vec<Spanned<CST>>, Syntax> {
+        asts.into_iter().map(|ast| self.walk(ast)).collect()
+    }
+
+    fn walk_base(&mut self, b: SharedBase<AST>) -> Result<SharedBase<CST>, Syntax> {
+        Ok(match b {
             Base::Symbol(s) => Base::Symbol(s),
             Base::Label(l) => Base::Label(l),
             Base::Lit(l) => Base::Lit(l),
-            Base::Tuple(t) => Base::Tuple(t.into_iter().map(Desugarer::walk).collect()),
-            Base::Module(m) => Base::module(Desugarer::walk(*m)),
-            Base::Block(b) => Base::Block(b.into_iter().map(Desugarer::walk).collect()),
-            Base::Call(f, a) => Base::call(Desugarer::walk(*f), Desugarer::walk(*a)),
-            Base::Assign(p, e) => Base::assign(p, Desugarer::walk(*e)),
+            Base::Tuple(t) => Base::Tuple(self.walk_all(t)?),
+            Base::Spread(t) => Base::spread(self.walk(*t)?),
+            Base::Module(m) => Base::module(self.walk(*m)?),
+            Base::Block(b) => Base::Block(self.walk_all(b)?),
+            Base::Call(f, a, _) => Base::call(self.walk(*f)?, self.walk(*a)?),
+            Base::Assign(p, e) => Base::assign(p, self.walk(*e)?),
             Base::Effect(_) => todo!("need to handle effects"),
-        }
+            Base::FFI(n, e) => Base::ffi(&n, self.walk(*e)?),
+            Base::Match(v, arms) => {
+                let value = self.walk(*v)?;
+                let mut walked = vec![];
+                for (pattern, body) in arms {
+                    walked.push((pattern, self.walk(body)?));
+                }
+                Base::match_arms(value, walked)
+            }
+            Base::Update(b, fields) => {
+                let base = self.walk(*b)?;
+                let mut walked = vec![];
+                for (name, value) in fields {
+                    walked.push((name, self.walk(value)?));
+                }
+                Base::update(base, walked)
+            }
+            Base::Record(fields) => {
+                let mut walked = vec![];
+                for (name, value) in fields {
+                    walked.push((name, self.walk(value)?));
+                }
+                Base::Record(walked)
+            }
+            Base::Loop(b) => Base::loop_body(self.walk(*b)?),
+            Base::Break(v) => Base::break_value(self.walk(*v)?),
+            Base::Continue => Base::Continue,
+        })
     }
 
-    fn walk_lambda(l: Lambda<Spanned<AST>>) -> Lambda<Spanned<CST>> {
+    fn walk_lambda(&mut self, l: Lambda<Spanned<AST>>) -> Result<Lambda<Spanned<CST>>, Syntax> {
         let Lambda { arg, body } = l;
-        let body = Desugarer::walk(*body);
-        return Lambda::new(arg, body);
+        let body = self.walk(*body)?;
+        return Ok(Lambda::new(arg, body));
     }
 
-    fn walk_sugar(s: Sugar<Spanned<AST>>) -> CST {
-        match s {
-            Sugar::Group(g) => Desugarer::walk(*g).item,
+    fn walk_sugar(&mut self, s: Sugar<Spanned<AST>>, span: &Span) -> Result<CST, Syntax> {
+        Ok(match s {
+            Sugar::Group(_) => unreachable!("Groups are removed before walking sugar"),
             // TODO: just do this during parsing haha
             // turn a form into a call:
             Sugar::Form(f) => {
@@ -59,10 +109,10 @@ impl Desugarer {
                 // and must have at least two items...
                 assert!(f.len() >= 2);
                 let mut form_items = f.into_iter();
-                let mut fun = Desugarer::walk(form_items.next().unwrap());
+                let mut fun = self.walk(form_items.next().unwrap())?;
 
                 for arg in form_items {
-                    let arg = Desugarer::walk(arg);
+                    let arg = self.walk(arg)?;
                     let span = Span::combine(&fun.span, &arg.span);
                     let call = SharedBase::call(fun, arg);
                     fun = Spanned::new(CST::Base(call), span);
@@ -76,13 +126,144 @@ impl Desugarer {
                     "type annotations will be implemented when the type checker is implemented"
                 )
             }
-            Sugar::Comp(arg, fun) => {
-                CST::Base(Base::call(Desugarer::walk(*fun), Desugarer::walk(*arg)))
-            }
+            Sugar::Comp(arg, fun) => CST::Base(Base::call(self.walk(*fun)?, self.walk(*arg)?)),
             Sugar::Field(_, _) => {
                 unimplemented!("field access will be implemented when structs are implemented")
             }
             Sugar::Keyword(_) => todo!(),
+            Sugar::Clauses(clauses) => self.clauses(clauses, span)?,
+        })
+    }
+
+    /// Turns a function defined by clauses, i.e. `{ 0 -> 1, n -> n }`,
+    /// into a single function that matches its argument against each clause:
+    /// ```ignore
+    /// #arg -> match #arg { 0 -> 1, n -> n }
+    /// ```
+    fn clauses(&mut self, clauses: Vec<Lambda<Spanned<AST>>>, span: &Span) -> Result<CST, Syntax> {
+        if clauses.is_empty() {
+            return Err(Syntax::error(
+                "A function defined by clauses must have at least one clause",
+                span,
+            ));
+        }
+
+        let arg = self.clauses_arg();
+        let mut arms = vec![];
+        for Lambda { arg, body } in clauses {
+            arms.push((arg, self.walk(*body)?));
         }
+
+        // the argument does not appear in the source, so it is marked as generated,
+        // but the match stands for the clauses as written, so it keeps their span
+        let value = Spanned::new(CST::Base(Base::Symbol(arg)), Span::dummy());
+        let body = Spanned::new(CST::Base(Base::match_arms(value, arms)), span.clone());
+        let pattern = Spanned::new(Pattern::Symbol(arg), Span::dummy());
+        Ok(CST::Lambda(Lambda::new(pattern, body)))
+    }
+
+    /// Returns the symbol used for the argument of functions defined by clauses.
+    /// Each such function declares its own argument,
+    /// so the same symbol can be shared between all of them.
+    fn clauses_arg(&mut self) -> SharedSymbol {
+        let next = SharedSymbol(self.symbols.len());
+        *self.symbols.entry(CLAUSES_ARG.to_string()).or_insert(next)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::{lit::Lit, Source},
+        compiler::parse,
+    };
+
+    fn desugar(source: &str) -> Result<Spanned<CST>, Syntax> {
+        let (ast, symbols) = parse(Source::source(source)).unwrap();
+        Desugarer::desugar(ast, symbols).map(|(cst, _)| cst)
+    }
+
+    #[test]
+    fn factorial_clauses() {
+        let cst = desugar("{ 0 -> 1, n -> n * fac (n - 1) }").unwrap();
+        let lambda = match cst.item {
+            CST::Base(Base::Block(mut block)) => block.remove(0).item,
+            other => panic!("expected a block, found {:?}", other),
+        };
+        let (arg, arg_span, body) = match lambda {
+            CST::Lambda(Lambda {
+                arg:
+                    Spanned {
+                        item: Pattern::Symbol(arg),
+                        span,
+                    },
+                body,
+            }) => (arg, span, body),
+            other => panic!("expected a lambda, found {:?}", other),
+        };
+
+        // only the nodes that do not appear in the source are generated
+        assert!(arg_span.is_dummy());
+        assert!(!body.span.is_dummy());
+
+        let (value, arms) = match body.item {
+            CST::Base(Base::Match(value, arms)) => (value, arms),
+            other => panic!("expected a match, found {:?}", other),
+        };
+        assert_eq!(value.item, CST::Base(Base::Symbol(arg)));
+        assert!(value.span.is_dummy());
+        assert!(!arms[0].0.span.is_dummy());
+        assert_eq!(arms.len(), 2);
+        assert_eq!(arms[0].0.item, Pattern::Lit(Lit::Integer(0)));
+        assert_eq!(arms[0].1.item, CST::Base(Base::Lit(Lit::Integer(1))));
+        assert!(matches!(arms[1].0.item, Pattern::Symbol(_)));
+        assert!(matches!(arms[1].1.item, CST::Base(Base::FFI(ref name, _)) if name == "mul"));
+    }
+
+    #[test]
+    fn single_lambda_block() {
+        // a block holding one lambda is just a block
+        let cst = desugar("{ n -> n }").unwrap();
+        let inner = match cst.item {
+            CST::Base(Base::Block(mut block)) => block.remove(0).item,
+            other => panic!("expected a block, found {:?}", other),
+        };
+        assert!(matches!(inner, CST::Base(Base::Block(_))));
+
+        // the top level of a program is never a function
+        let cst = desugar("0 -> 1, n -> n").unwrap();
+        assert!(matches!(cst.item, CST::Base(Base::Block(_))));
+    }
+
+    #[test]
+    fn generated_error() {
+        let cst = desugar("f = { 0 -> 1, n -> n }").unwrap();
+        let lambda = match cst.item {
+            CST::Base(Base::Block(mut block)) => match block.remove(0).item {
+                CST::Base(Base::Assign(_, lambda)) => lambda.item,
+                other => panic!("expected an assignment, found {:?}", other),
+            },
+            other => panic!("expected a block, found {:?}", other),
+        };
+        let arg = match lambda {
+            CST::Lambda(Lambda { arg, .. }) => arg,
+            other => panic!("expected a lambda, found {:?}", other),
+        };
+
+        // an error raised at a generated node is reported as such
+        let error = Syntax::error("Unexpected argument", &arg.span);
+        assert_eq!(
+            format!("{}", error),
+            "In (generated code)\nSyntax Error: Unexpected argument",
+        );
+    }
+
+    #[test]
+    fn empty_clauses() {
+        let span = Span::dummy();
+        let ast = Spanned::new(AST::Sugar(Sugar::Clauses(vec![])), span);
+        let result = Desugarer::desugar(ast, HashMap::new());
+        assert!(result.is_err());
     }
 }
@@ -1,42 +1,163 @@
 use std::collections::HashMap;
-use std::rc::Rc;
 
-use crate::common::{lambda::Lambda, Spanned};
-use crate::compiler::{compile_token_tree, Syntax};
-use crate::construct::token::{Token, TokenTree};
+use crate::common::{Span, Spanned};
+use crate::compiler::{syntax::Note, Syntax};
+use crate::construct::token::{split_on_op, TokenTree, TokenTrees};
+
+/// The default number of macro applications allowed per compilation.
+/// A macro that expands into an invocation of itself will hit this
+/// limit rather than expanding forever.
+pub const DEFAULT_FUEL: usize = 10_000;
+
+/// How deeply macro expansions may nest, i.e. how many expansions
+/// may produce further invocations before expansion is aborted.
+/// Keeps a self-recursive macro from overflowing the stack
+/// before its fuel runs out.
+pub const MAX_DEPTH: usize = 256;
+
+/// A macro rule of the form `a b -> template`.
+/// Applying it replaces each parameter in the template
+/// with the corresponding argument of the invocation.
+struct Rule {
+    params: Vec<String>,
+    template: TokenTrees,
+}
+
+impl Rule {
+    /// Reads a rule from a single line of the form `a b -> template`,
+    /// where each parameter is an identifier.
+    fn new(rule: Spanned<TokenTree>) -> Result<Rule, Syntax> {
+        let invalid = |span: &Span| {
+            Syntax::error(
+                "A macro rule must be of the form `params -> template`",
+                span,
+            )
+        };
+
+        let trees = match rule.item {
+            TokenTree::Block(mut lines) if lines.len() == 1 => lines.remove(0).item,
+            _ => return Err(invalid(&rule.span)),
+        };
+
+        let (params, template) = match split_on_op(&trees, "->").as_slice() {
+            [params, template] if !template.is_empty() => (params.to_vec(), template.to_vec()),
+            _ => return Err(invalid(&rule.span)),
+        };
+
+        let params = params
+            .into_iter()
+            .map(|param| match param.item {
+                TokenTree::Iden(iden) => Ok(iden),
+                _ => Err(Syntax::error(
+                    "A macro parameter must be an identifier",
+                    &param.span,
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Rule { params, template })
+    }
+
+    /// Replaces each parameter in a tree with its argument.
+    fn substitute(
+        tree: &Spanned<TokenTree>,
+        bindings: &HashMap<&str, &Spanned<TokenTree>>,
+    ) -> Spanned<TokenTree> {
+        let substitute_all = |trees: &TokenTrees| {
+            trees
+                .iter()
+                .map(|tree| Rule::substitute(tree, bindings))
+                .collect::<Vec<_>>()
+        };
+
+        let item = match &tree.item {
+            TokenTree::Iden(iden) => match bindings.get(iden.as_str()) {
+                Some(arg) => return (*arg).clone(),
+                None => TokenTree::Iden(iden.clone()),
+            },
+            TokenTree::Form(trees) => TokenTree::Form(substitute_all(trees)),
+            TokenTree::List(trees) => TokenTree::List(substitute_all(trees)),
+            TokenTree::Block(lines) => TokenTree::Block(
+                lines
+                    .iter()
+                    .map(|line| Spanned::new(substitute_all(&line.item), line.span.clone()))
+                    .collect(),
+            ),
+            other => other.clone(),
+        };
+
+        Spanned::new(item, tree.span.clone())
+    }
+}
 
 pub struct Expander {
-    rules: HashMap<String, Rc<Lambda>>,
+    rules: HashMap<String, Rule>,
+    /// How many macro applications are left before expansion is aborted.
+    fuel: usize,
+    /// The outermost macro invocation currently being expanded,
+    /// used to point the user at the source of a runaway expansion.
+    invocation: Option<Span>,
+    /// How many macro applications are currently being expanded.
+    depth: usize,
 }
 
 impl Expander {
+    /// Expands all macro invocations in a token tree,
+    /// using the default fuel budget.
     pub fn expand(
+        token_tree: Spanned<TokenTree>,
+        rules: HashMap<String, Spanned<TokenTree>>,
+    ) -> Result<Spanned<TokenTree>, Syntax> {
+        Expander::expand_with_fuel(token_tree, rules, DEFAULT_FUEL)
+    }
+
+    /// Expands all macro invocations in a token tree,
+    /// allowing at most `fuel` macro applications.
+    pub fn expand_with_fuel(
         token_tree: Spanned<TokenTree>,
         mut rules: HashMap<String, Spanned<TokenTree>>,
+        fuel: usize,
     ) -> Result<Spanned<TokenTree>, Syntax> {
         let mut expander = Expander {
             rules: HashMap::new(),
+            fuel,
+            invocation: None,
+            depth: 0,
         };
 
         for (name, rule) in rules.drain() {
-            let bytecode = compile_token_tree(rule)?;
-            expander.rules.insert(name, bytecode);
+            expander.rules.insert(name, Rule::new(rule)?);
         }
 
         expander.walk(token_tree)
     }
 
-    pub fn walk(
-        &self,
-        token_tree: Spanned<TokenTree>,
-    ) -> Result<Spanned<TokenTree>, Syntax> {
+    /// Spends one unit of fuel on a macro application at `span`.
+    /// Returns an error pointing at the outermost invocation
+    /// once the budget has been exhausted.
+    fn consume_fuel(&mut self, span: &Span) -> Result<(), Syntax> {
+        let outermost = self.invocation.get_or_insert_with(|| span.clone());
+
+        if self.fuel == 0 {
+            return Err(Syntax::error_with_note(
+                "Macro expansion ran out of fuel; \
+                is a macro expanding into itself?",
+                Note::new_with_hint("while expanding this macro invocation", outermost),
+            ));
+        }
+
+        self.fuel -= 1;
+        Ok(())
+    }
+
+    pub fn walk(&mut self, token_tree: Spanned<TokenTree>) -> Result<Spanned<TokenTree>, Syntax> {
         let Spanned {
             item: token_tree,
             span,
         } = token_tree;
 
         let result = match token_tree {
-            TokenTree::Form(form) => self.expand_form(form)?,
+            TokenTree::Form(form) => self.expand_form(form, &span)?,
 
             // trivial conversion
             TokenTree::Block(block) => {
@@ -50,7 +171,7 @@ impl Expander {
                     new_block.push(Spanned::new(new_trees, span));
                 }
                 TokenTree::Block(new_block)
-            },
+            }
             TokenTree::List(trees) => TokenTree::List(
                 trees
                     .into_iter()
@@ -67,10 +188,7 @@ impl Expander {
         Ok(Spanned::new(result, span))
     }
 
-    pub fn walk_form(
-        &self,
-        form: Vec<Spanned<TokenTree>>,
-    ) -> Result<TokenTree, Syntax> {
+    pub fn walk_form(&mut self, form: Vec<Spanned<TokenTree>>) -> Result<TokenTree, Syntax> {
         Ok(TokenTree::Form(
             form.into_iter()
                 .map(|tree| self.walk(tree))
@@ -79,20 +197,172 @@ impl Expander {
     }
 
     pub fn expand_form(
-        &self,
-        mut form: Vec<Spanned<TokenTree>>,
+        &mut self,
+        form: Vec<Spanned<TokenTree>>,
+        span: &Span,
     ) -> Result<TokenTree, Syntax> {
-        assert!(form.len() >= 2);
-        let first = form.remove(0);
-
-        if let TokenTree::Iden(iden) = first.item {
-            if let Some(rule) = self.rules.get(&iden) {
-                // TODO: call compiled function somehow
-                let expanded = todo!();
-                return expanded;
-            }
+        let is_rule = match form.first() {
+            Some(Spanned {
+                item: TokenTree::Iden(iden),
+                ..
+            }) => self.rules.contains_key(iden),
+            _ => false,
+        };
+
+        if !is_rule {
+            return self.walk_form(form);
+        }
+
+        let outermost = self.invocation.is_none();
+        self.consume_fuel(span)?;
+
+        if self.depth == MAX_DEPTH {
+            return Err(Syntax::error_with_note(
+                "Macro expansion nested too deeply; \
+                is a macro expanding into itself?",
+                Note::new_with_hint(
+                    "while expanding this macro invocation",
+                    self.invocation.as_ref().unwrap(),
+                ),
+            ));
+        }
+
+        self.depth += 1;
+        let expanded = self.apply(form, span);
+        self.depth -= 1;
+
+        if outermost {
+            self.invocation = None;
+        }
+        expanded
+    }
+
+    /// Applies the macro rule named by the head of `form`,
+    /// then walks the expansion so nested invocations also spend fuel.
+    fn apply(&mut self, form: Vec<Spanned<TokenTree>>, span: &Span) -> Result<TokenTree, Syntax> {
+        let (name, args) = match form.split_first() {
+            Some((
+                Spanned {
+                    item: TokenTree::Iden(name),
+                    ..
+                },
+                args,
+            )) => (name, args),
+            _ => unreachable!("only forms headed by a rule are applied"),
+        };
+        let rule = &self.rules[name];
+
+        if rule.params.len() != args.len() {
+            return Err(Syntax::error(
+                &format!(
+                    "The macro `{}` takes {} argument(s), but {} were given",
+                    name,
+                    rule.params.len(),
+                    args.len(),
+                ),
+                span,
+            ));
         }
 
-        self.walk_form(form)
+        let bindings = rule
+            .params
+            .iter()
+            .map(|param| param.as_str())
+            .zip(args.iter())
+            .collect::<HashMap<_, _>>();
+
+        let mut expansion = rule
+            .template
+            .iter()
+            .map(|tree| Rule::substitute(tree, &bindings))
+            .collect::<Vec<_>>();
+
+        // a template of a single tree expands to just that tree
+        let expansion = if expansion.len() == 1 {
+            expansion.remove(0)
+        } else {
+            Spanned::new(TokenTree::Form(expansion), span.clone())
+        };
+
+        Ok(self.walk(expansion)?.item)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::Source;
+    use crate::compiler::read;
+
+    fn tree(source: &str) -> Spanned<TokenTree> {
+        read(Source::source(source)).unwrap()
+    }
+
+    #[test]
+    fn no_rules_no_fuel() {
+        let source = tree("(a (b c)) d");
+        let expanded = Expander::expand_with_fuel(source.clone(), HashMap::new(), 0);
+        assert_eq!(expanded, Ok(source));
+    }
+
+    #[test]
+    fn apply_rule() {
+        let source = tree("x = (twice 1)");
+        let mut rules = HashMap::new();
+        rules.insert("twice".to_string(), tree("a -> (a, a)"));
+
+        let expanded = Expander::expand_with_fuel(source, rules, 1).unwrap();
+        let expected = tree("x = (1, 1)");
+        assert_eq!(
+            format!("{:?}", strip(expanded)),
+            format!("{:?}", strip(expected))
+        );
+    }
+
+    #[test]
+    fn wrong_arity() {
+        let mut rules = HashMap::new();
+        rules.insert("twice".to_string(), tree("a -> (a, a)"));
+        assert!(Expander::expand(tree("(twice 1 2)"), rules).is_err());
+    }
+
+    #[test]
+    fn out_of_fuel() {
+        let source = tree("x = (boom 1)");
+        let mut rules = HashMap::new();
+        rules.insert("boom".to_string(), tree("x -> (boom x)"));
+
+        let error = Expander::expand_with_fuel(source.clone(), rules.clone(), 100).unwrap_err();
+        assert!(error.reason.contains("fuel"));
+        assert_eq!(error.notes.len(), 1);
+        assert_eq!(error.notes[0].span.contents(), "(boom 1)");
+
+        // with the default budget, the nesting limit is reached first
+        let error = Expander::expand(source, rules).unwrap_err();
+        assert!(error.reason.contains("nested too deeply"));
+        assert_eq!(error.notes[0].span.contents(), "(boom 1)");
+    }
+
+    /// Drops spans, which differ between a template and the source it is
+    /// compared against.
+    fn strip(tree: Spanned<TokenTree>) -> TokenTree {
+        let strip_all = |trees: TokenTrees| {
+            trees
+                .into_iter()
+                .map(|tree| Spanned::new(strip(tree), Span::dummy()))
+                .collect::<Vec<_>>()
+        };
+
+        match tree.item {
+            TokenTree::Form(trees) => TokenTree::Form(strip_all(trees)),
+            TokenTree::List(trees) => TokenTree::List(strip_all(trees)),
+            TokenTree::Block(lines) => TokenTree::Block(
+                lines
+                    .into_iter()
+                    .map(|line| Spanned::new(strip_all(line.item), Span::dummy()))
+                    .collect(),
+            ),
+            other => other,
+        }
     }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, env};
 
 use crate::{
     common::span::{Span, Spanned},
@@ -6,8 +6,10 @@ use crate::{
     construct::{
         scope::Scope,
         symbol::{SharedSymbol, SymbolTable, UniqueSymbol},
-        tree::{Base, Lambda, Pattern, ScopedLambda, CST, SST},
+        tree::{Base, Lambda, Pattern, ScopedLambda, ScopedModule, CST, SST},
     },
+    error_codes,
+    kernel::{ffi_core, FFI},
 };
 
 // TODO: hoisting before expansion??
@@ -24,10 +26,16 @@ use crate::{
 // integer 2. Build up a table of which symbols are
 // accessible in what scopes.
 
+/// The default for how deeply functions and modules may be nested.
+/// Scopes are resolved recursively,
+/// so this keeps deeply nested programs from overflowing the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
 /// Keeps track of:
 /// 1. Local and nonlocal variables in each scope.
 /// 2. All variables declared.
 /// 3. Variables that have been used but not declared.
+#[derive(Clone)]
 pub struct Hoister {
     /// The unique local symbols in the current scope.
     scopes: Vec<Scope>,
@@ -37,16 +45,29 @@ pub struct Hoister {
     /// Keeps track of variables that were referenced before
     /// assignment.
     unresolved_hoists: HashMap<SharedSymbol, Spanned<UniqueSymbol>>,
+    /// Maps each symbol back to its name in the source.
+    names: HashMap<SharedSymbol, String>,
+    /// The unique symbol of each label, see `label`.
+    labels: HashMap<SharedSymbol, UniqueSymbol>,
+    /// How many scopes may be nested within the root scope.
+    max_depth: usize,
+    /// Whether to trace each scope as it is exited, to standard error.
+    /// Set by the `PASSERINE_TRACE_HOIST` environment variable.
+    trace: bool,
 }
 
 impl Hoister {
     /// Creates a new hoisted in a root scope.
     /// Note that the hoister will always have a root scope.
-    fn new() -> Hoister {
+    fn new(max_depth: usize) -> Hoister {
         Hoister {
             scopes: vec![Scope::new()],
             symbol_table: SymbolTable::new(),
             unresolved_hoists: HashMap::new(),
+            names: HashMap::new(),
+            labels: HashMap::new(),
+            max_depth,
+            trace: env::var_os("PASSERINE_TRACE_HOIST").is_some(),
         }
     }
 
@@ -55,26 +76,116 @@ impl Hoister {
     /// identifiers; symbols by the same name in
     /// different scopes will get different identifiers.
     /// Also resolves closure captures and closure hoisting.
+    /// The functions in the core FFI can be called by name, see `hoist_with_ffi`.
     pub fn hoist(
         tree: Spanned<CST>,
         symbols: HashMap<String, SharedSymbol>,
     ) -> Result<(Spanned<SST>, Scope), Syntax> {
-        let mut hoister = Hoister::new();
+        Hoister::hoist_with_ffi(tree, symbols, &ffi_core())
+    }
 
-        let sst = hoister.walk(tree)?;
-        let scope = hoister.scopes.pop().unwrap();
+    /// Like `hoist`, but allows the functions in a specific FFI
+    /// to be called by name, e.g. `slice list 1 3`.
+    /// Each function the program refers to, but does not define,
+    /// is defined at the start of the program.
+    /// Functions that declare a signature with several arguments are curried,
+    /// so each argument is passed in turn.
+    pub fn hoist_with_ffi(
+        tree: Spanned<CST>,
+        symbols: HashMap<String, SharedSymbol>,
+        ffi: &FFI,
+    ) -> Result<(Spanned<SST>, Scope), Syntax> {
+        Hoister::hoist_with(tree, symbols, ffi, DEFAULT_MAX_DEPTH)
+    }
 
-        if !hoister.unresolved_hoists.is_empty() {
-            let num_unresolved = hoister.unresolved_hoists.len();
+    /// Like `hoist`, but allows functions and modules
+    /// to be nested at most `max_depth` deep.
+    /// Programs nested more deeply raise an error,
+    /// rather than overflowing the stack.
+    pub fn hoist_with_max_depth(
+        tree: Spanned<CST>,
+        symbols: HashMap<String, SharedSymbol>,
+        max_depth: usize,
+    ) -> Result<(Spanned<SST>, Scope), Syntax> {
+        Hoister::hoist_with(tree, symbols, &ffi_core(), max_depth)
+    }
+
+    fn hoist_with(
+        tree: Spanned<CST>,
+        mut symbols: HashMap<String, SharedSymbol>,
+        ffi: &FFI,
+        max_depth: usize,
+    ) -> Result<(Spanned<SST>, Scope), Syntax> {
+        let mut hoister = Hoister::new(max_depth);
+        let sst = hoister.hoist_root(tree, &mut symbols, ffi)?;
+        let mut scope = hoister.scopes.pop().unwrap();
+        hoister.name_scope(&mut scope);
+        Ok((sst, scope))
+    }
+
+    /// Creates a hoister for a session,
+    /// which hoists programs one after another in the same root scope,
+    /// e.g. each line entered in a REPL, see `hoist_next`.
+    pub fn session() -> Hoister {
+        Hoister::new(DEFAULT_MAX_DEPTH)
+    }
+
+    /// Hoists the next program of a session, see `session`.
+    /// The program can use the variables and labels of the programs before it,
+    /// and variables keep their place in the root scope,
+    /// so the program can be run where the last one left off,
+    /// see `Compiler::compile_continuing`.
+    /// The symbols must continue from those of the earlier programs,
+    /// see `Parser::parse_with`, and are updated to include any added here.
+    /// Returns the root scope along with the program.
+    /// If the program can not be hoisted, the session is left as it was.
+    pub fn hoist_next(
+        &mut self,
+        tree: Spanned<CST>,
+        symbols: &mut HashMap<String, SharedSymbol>,
+        ffi: &FFI,
+    ) -> Result<(Spanned<SST>, Scope), Syntax> {
+        let session = self.clone();
+        match self.hoist_root(tree, symbols, ffi) {
+            Ok(sst) => {
+                let mut scope = self.borrow_local_scope().clone();
+                self.name_scope(&mut scope);
+                Ok((sst, scope))
+            }
+            Err(error) => {
+                *self = session;
+                Err(error)
+            }
+        }
+    }
+
+    /// Hoists a program in the root scope,
+    /// raising an error if any variable is used but never assigned.
+    fn hoist_root(
+        &mut self,
+        tree: Spanned<CST>,
+        symbols: &mut HashMap<String, SharedSymbol>,
+        ffi: &FFI,
+    ) -> Result<Spanned<SST>, Syntax> {
+        let tree = self.builtins(tree, symbols, ffi);
+        for (name, symbol) in symbols.iter() {
+            self.names.insert(*symbol, name.clone());
+        }
+
+        let sst = self.walk(tree)?;
+
+        if !self.unresolved_hoists.is_empty() {
+            let num_unresolved = self.unresolved_hoists.len();
 
             let mut error = Syntax::error_no_note(&format!(
                 "{} variable{} referenced before assignment",
                 num_unresolved,
                 if num_unresolved == 1 { "" } else { "s" }
-            ));
+            ))
+            .with_code(error_codes::USE_BEFORE_ASSIGNMENT);
 
             // TODO: sort by occurence, earliest first?
-            for (_symbol, spanned) in hoister.unresolved_hoists.iter() {
+            for (_symbol, spanned) in self.unresolved_hoists.iter() {
                 // TODO: hints to correct to similar names, etc.
                 // dbg!(&spanned.span);
                 error = error.add_note(Note::new(spanned.span.clone()));
@@ -82,14 +193,99 @@ impl Hoister {
 
             Err(error)
         } else {
-            Ok((sst, scope))
+            Ok(sst)
+        }
+    }
+
+    /// Defines each function in the FFI that the program refers to by name,
+    /// by prepending an assignment to the program, i.e.
+    /// `slice = a -> b -> c -> magic "slice" (a, b, c)`.
+    /// A program that defines a function by the same name
+    /// replaces the builtin from that point on.
+    /// Functions already defined in the root scope,
+    /// e.g. by an earlier program in a session, are left as they are.
+    fn builtins(
+        &self,
+        tree: Spanned<CST>,
+        symbols: &mut HashMap<String, SharedSymbol>,
+        ffi: &FFI,
+    ) -> Spanned<CST> {
+        let mut referenced = symbols
+            .iter()
+            .filter(|(name, symbol)| ffi.contains(name) && self.local_symbol(**symbol).is_none())
+            .map(|(name, symbol)| (name.clone(), *symbol))
+            .collect::<Vec<_>>();
+        if referenced.is_empty() {
+            return tree;
         }
+        // so that programs always compile the same way
+        referenced.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        fn generated<T>(item: T) -> Spanned<T> {
+            Spanned::new(item, Span::dummy())
+        }
+        let mut definitions = vec![];
+        for (name, symbol) in referenced {
+            let arity = match ffi.get(&name).unwrap().signature() {
+                Some(signature) if signature.args.len() > 1 => signature.args.len(),
+                _ => 1,
+            };
+
+            // arguments use names that can't be written in a program
+            let args = (0..arity)
+                .map(|index| {
+                    let next = SharedSymbol(symbols.len());
+                    *symbols.entry(format!("#{}", index)).or_insert(next)
+                })
+                .collect::<Vec<_>>();
+
+            let arg = match args.as_slice() {
+                [arg] => generated(CST::Base(Base::Symbol(*arg))),
+                args => generated(CST::Base(Base::Tuple(
+                    args.iter()
+                        .map(|arg| generated(CST::Base(Base::Symbol(*arg))))
+                        .collect(),
+                ))),
+            };
+            let mut function = generated(CST::Base(Base::ffi(&name, arg)));
+            for arg in args.into_iter().rev() {
+                function = generated(CST::Lambda(Lambda::new(
+                    generated(Pattern::Symbol(arg)),
+                    function,
+                )));
+            }
+
+            definitions.push(generated(CST::Base(Base::assign(
+                generated(Pattern::Symbol(symbol)),
+                function,
+            ))));
+        }
+
+        let span = tree.span.clone();
+        match tree.item {
+            CST::Base(Base::Block(block)) => definitions.extend(block),
+            _ => definitions.push(tree),
+        }
+        Spanned::new(CST::Base(Base::Block(definitions)), span)
     }
 
     /// Enters a new scope, called when entering a new
-    /// function.
-    fn enter_scope(&mut self) {
+    /// function. Raises an error if the new scope
+    /// would be nested too deeply.
+    fn enter_scope(&mut self, span: &Span) -> Result<(), Syntax> {
+        // the root scope does not count towards the depth
+        if self.scopes.len() > self.max_depth {
+            return Err(Syntax::error(
+                &format!(
+                    "Nesting too deep, functions and modules can be nested at most {} deep",
+                    self.max_depth
+                ),
+                span,
+            )
+            .with_code(error_codes::NESTING_TOO_DEEP));
+        }
         self.scopes.push(Scope::new());
+        Ok(())
     }
     /// Enters an existing scope, called when resolving
     /// variables.
@@ -110,8 +306,11 @@ impl Hoister {
                     scope.locals.remove(&local);
                 }
             }
-            dbg!(&scope);
-            dbg!(&self.unresolved_hoists);
+            if self.trace {
+                eprintln!("hoist: exited {:?}", scope);
+                eprintln!("hoist: unresolved {:?}", self.unresolved_hoists);
+            }
+            self.name_scope(&mut scope);
             Some(scope)
         } else {
             unreachable!("no scopes left on stack?");
@@ -119,6 +318,19 @@ impl Hoister {
         }
     }
 
+    /// Records the source names of all variables in a scope,
+    /// so that they can be recovered after compilation.
+    fn name_scope(&self, scope: &mut Scope) {
+        let mut symbols = scope.locals.items();
+        symbols.extend(scope.nonlocals.items());
+        for symbol in symbols {
+            let shared = self.symbol_table.name(&symbol);
+            if let Some(name) = self.names.get(&shared) {
+                scope.names.insert(symbol, name.clone());
+            }
+        }
+    }
+
     /// Returns the topmost, i.e. local, scope, mutably.
     fn local_scope(&mut self) -> &mut Scope {
         let last = self.scopes.len() - 1;
@@ -139,20 +351,23 @@ impl Hoister {
             CST::Base(Base::Lit(data)) => SST::Base(Base::Lit(data)),
             CST::Base(Base::Symbol(name)) => self.symbol(name, tree.span.clone()),
             CST::Base(Base::Block(block)) => self.block(block)?,
-            // TODO: hoist as well
-            CST::Base(Base::Label(name)) => {
-                todo!()
-                // SST::label(
-                //     // TODO: change this to the following lines after types:
-                //     self.resolve_symbol(name, cst.span.clone()),
-                // )
-            }
+            CST::Base(Base::Label(name)) => SST::Base(Base::Label(self.label(name))),
             CST::Base(Base::Tuple(tuple)) => self.tuple(tuple)?,
+            CST::Base(Base::Spread(tuple)) => SST::Base(Base::spread(self.walk(*tuple)?)),
             CST::Base(Base::Assign(pattern, expression)) => self.assign(pattern, *expression)?,
-            CST::Lambda(Lambda { arg, body }) => self.lambda(arg, *body)?,
-            CST::Base(Base::Call(fun, arg)) => self.call(*fun, *arg)?,
-            CST::Base(Base::Module(_)) => todo!(),
+            CST::Lambda(Lambda { arg, body }) => self.lambda(arg, *body, &tree.span)?,
+            CST::Base(Base::Call(fun, arg, _)) => self.call(*fun, *arg)?,
+            CST::Base(Base::Module(module)) => self.module(*module, &tree.span)?,
             CST::Base(Base::Effect(_)) => todo!(),
+            CST::Base(Base::FFI(name, expression)) => {
+                SST::Base(Base::ffi(&name, self.walk(*expression)?))
+            }
+            CST::Base(Base::Match(value, arms)) => self.match_arms(*value, arms)?,
+            CST::Base(Base::Update(base, fields)) => self.update(*base, fields)?,
+            CST::Base(Base::Record(fields)) => self.record(fields)?,
+            CST::Base(Base::Loop(body)) => SST::Base(Base::loop_body(self.walk(*body)?)),
+            CST::Base(Base::Break(value)) => SST::Base(Base::break_value(self.walk(*value)?)),
+            CST::Base(Base::Continue) => SST::Base(Base::Continue),
         };
 
         return Ok(Spanned::new(sst, tree.span));
@@ -170,11 +385,7 @@ impl Hoister {
             Pattern::Symbol(name) => Pattern::Symbol(self.resolve_assign(name, declare)),
             Pattern::Lit(l) => Pattern::Lit(l),
             Pattern::Label(n, p) => Pattern::Label(
-                // TODO: This is temoprary. Makes first use the definition.
-                // Once we have types, change the following line to:
-                // self.resolve_symbol(n, pattern.span.clone()),
-                // until then, this will do:
-                Spanned::new(self.resolve_assign(n.item, false), n.span),
+                Spanned::new(self.label(n.item), n.span),
                 Box::new(self.walk_pattern(*p, declare)),
             ),
             Pattern::Tuple(t) => Pattern::Tuple(
@@ -182,7 +393,6 @@ impl Hoister {
                     .map(|c| self.walk_pattern(c, declare))
                     .collect::<Vec<_>>(),
             ),
-            Pattern::Chain(_) => todo!("Chained Patterns not yet implemented"),
         };
 
         return Spanned::new(item, pattern.span);
@@ -192,8 +402,10 @@ impl Hoister {
 
     /// Looks to see whether a name is defined as a local in
     /// the current scope.
+    /// If a name is declared more than once, e.g. in different arms of a match,
+    /// the most recent declaration is used.
     fn local_symbol(&self, name: SharedSymbol) -> Option<UniqueSymbol> {
-        for local in self.borrow_local_scope().locals.items().iter() {
+        for local in self.borrow_local_scope().locals.items().iter().rev() {
             let local_name = self.symbol_table.name(local);
             if local_name == name {
                 return Some(*local);
@@ -338,6 +550,19 @@ impl Hoister {
         return SST::Base(Base::Symbol(self.resolve_symbol(name, span)));
     }
 
+    /// Returns the unique symbol of a label.
+    /// Labels are not scoped, so every use of a label by the same name
+    /// shares one symbol, which the compiler uses as the kind of the label.
+    // TODO: declare labels once there are types.
+    fn label(&mut self, name: SharedSymbol) -> UniqueSymbol {
+        if let Some(unique_symbol) = self.labels.get(&name) {
+            return *unique_symbol;
+        }
+        let unique_symbol = self.symbol_table.push(name);
+        self.labels.insert(name, unique_symbol);
+        unique_symbol
+    }
+
     /// Walks a block, nothing fancy here.
     fn block(&mut self, block: Vec<Spanned<CST>>) -> Result<SST, Syntax> {
         let mut expressions = vec![];
@@ -360,12 +585,31 @@ impl Hoister {
 
     /// Walks an assignment.
     /// Delegates to `walk_pattern` for capturing.
-    /// Assignments can capture existing variables
+    /// Assignments can capture existing variables.
+    /// Raises an error if the whole pattern is a literal or a label,
+    /// as a plain assignment can only bind to variables;
+    /// those patterns are only meaningful in the arms of a match.
     fn assign(
         &mut self,
         pattern: Spanned<Pattern<SharedSymbol>>,
         expression: Spanned<CST>,
     ) -> Result<SST, Syntax> {
+        let reason = match pattern.item {
+            Pattern::Lit(_) => Some("Can not assign to a literal, as it is a constant"),
+            Pattern::Label(_, _) => Some("Can not assign to a label outside of a match"),
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            return Err(Syntax::error_with_note(
+                reason,
+                Note::new_with_hint(
+                    "use `match` to compare a value against a pattern",
+                    &pattern.span,
+                ),
+            )
+            .with_code(error_codes::ASSIGN_TO_CONSTANT));
+        }
+
         let sst_pattern = self.walk_pattern(pattern, false);
         let sst_expression = self.walk(expression)?;
 
@@ -380,8 +624,9 @@ impl Hoister {
         &mut self,
         pattern: Spanned<Pattern<SharedSymbol>>,
         expression: Spanned<CST>,
+        span: &Span,
     ) -> Result<SST, Syntax> {
-        self.enter_scope();
+        self.enter_scope(span)?;
         let arg = self.walk_pattern(pattern, true);
         let body = Box::new(self.walk(expression)?);
         let scope = self.exit_scope().unwrap();
@@ -389,6 +634,61 @@ impl Hoister {
         return Ok(SST::ScopedLambda(ScopedLambda { arg, body, scope }));
     }
 
+    /// Walks a match.
+    /// Like a lambda, the patterns of each arm shadow
+    /// variables in outer scopes, but are declared in the current scope.
+    fn match_arms(
+        &mut self,
+        value: Spanned<CST>,
+        arms: Vec<(Spanned<Pattern<SharedSymbol>>, Spanned<CST>)>,
+    ) -> Result<SST, Syntax> {
+        let value = self.walk(value)?;
+        let mut sst_arms = vec![];
+        for (pattern, body) in arms {
+            let pattern = self.walk_pattern(pattern, true);
+            sst_arms.push((pattern, self.walk(body)?));
+        }
+
+        return Ok(SST::Base(Base::match_arms(value, sst_arms)));
+    }
+
+    /// Walks a record update.
+    /// Field names are not variables, so only the values are resolved.
+    fn update(
+        &mut self,
+        base: Spanned<CST>,
+        fields: Vec<(String, Spanned<CST>)>,
+    ) -> Result<SST, Syntax> {
+        let base = self.walk(base)?;
+        let mut sst_fields = vec![];
+        for (name, value) in fields {
+            sst_fields.push((name, self.walk(value)?));
+        }
+
+        return Ok(SST::Base(Base::update(base, sst_fields)));
+    }
+
+    /// Walks a record, nothing fancy here.
+    fn record(&mut self, fields: Vec<(String, Spanned<CST>)>) -> Result<SST, Syntax> {
+        let mut sst_fields = vec![];
+        for (name, value) in fields {
+            sst_fields.push((name, self.walk(value)?));
+        }
+
+        return Ok(SST::Base(Base::Record(sst_fields)));
+    }
+
+    /// Walks a module definition.
+    /// Like a lambda, a module has its own scope,
+    /// the variables declared in which become its fields.
+    fn module(&mut self, body: Spanned<CST>, span: &Span) -> Result<SST, Syntax> {
+        self.enter_scope(span)?;
+        let body = self.walk(body)?;
+        let scope = self.exit_scope().unwrap();
+
+        return Ok(SST::ScopedModule(ScopedModule::new(body, scope)));
+    }
+
     /// Walks a function call.
     fn call(&mut self, fun: Spanned<CST>, arg: Spanned<CST>) -> Result<SST, Syntax> {
         return Ok(SST::Base(Base::call(self.walk(fun)?, self.walk(arg)?)));
@@ -405,12 +705,16 @@ mod test_super {
         compiler::{Desugarer, Lexer, Parser, Reader},
     };
 
-    fn test_source(source: &str) -> bool {
+    fn hoist_source(source: &str) -> Result<(Spanned<SST>, Scope), Syntax> {
         let tokens = Lexer::lex(Source::source(source)).unwrap();
         let token_tree = Reader::read(tokens).unwrap();
         let (ast, symbols) = Parser::parse(token_tree).unwrap();
-        let cst = Desugarer::desugar(ast);
-        let result = Hoister::hoist(cst, symbols);
+        let (cst, symbols) = Desugarer::desugar(ast, symbols).unwrap();
+        Hoister::hoist(cst, symbols)
+    }
+
+    fn test_source(source: &str) -> bool {
+        let result = hoist_source(source);
         dbg!(&result);
         return result.is_ok();
     }
@@ -434,4 +738,46 @@ mod test_super {
     fn nested_capture() {
         assert!(test_source("_ -> { x = _ -> pi; pi = 3 }; pi = 3.14"));
     }
+
+    #[test]
+    fn assign_to_literal() {
+        let error = hoist_source("x = 2; 1 = x").unwrap_err();
+        assert!(error.reason.starts_with("Can not assign to a literal"));
+        assert_eq!(error.code, Some(error_codes::ASSIGN_TO_CONSTANT));
+        assert_eq!(error.notes[0].span.contents(), "1");
+        assert!(test_source("\"x\" = \"x\"").not());
+
+        assert!(test_source("x = 3"));
+        assert!(test_source("t = (1, 2); (a, b) = t"));
+        // literals in a match are fine
+        assert!(test_source("match 1 { 1 -> True, _ -> False }"));
+    }
+
+    /// Hoists `depth` nested functions, i.e. `x -> x -> ... x`,
+    /// built directly, as the parser can not handle very deep nesting.
+    fn nested(depth: usize, max_depth: usize) -> Result<(Spanned<SST>, Scope), Syntax> {
+        let x = SharedSymbol(0);
+        let spanned = |item| Spanned::new(item, Span::dummy());
+        let mut cst = spanned(CST::Base(Base::Symbol(x)));
+        for _ in 0..depth {
+            cst = spanned(CST::Lambda(Lambda::new(
+                Spanned::new(Pattern::Symbol(x), Span::dummy()),
+                cst,
+            )));
+        }
+        let symbols = HashMap::from([("x".to_string(), x)]);
+        Hoister::hoist_with_max_depth(cst, symbols, max_depth)
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        assert!(nested(20, 20).is_ok());
+        let error = nested(21, 20).unwrap_err();
+        assert!(error.reason.starts_with("Nesting too deep"));
+        assert!(error.reason.contains("at most 20 deep"));
+
+        // far deeper than the limit, but the error is raised at the limit
+        assert!(nested(DEFAULT_MAX_DEPTH, DEFAULT_MAX_DEPTH).is_ok());
+        assert!(nested(1000, DEFAULT_MAX_DEPTH).is_err());
+    }
 }
@@ -12,7 +12,8 @@ use crate::{
         span::{Span, Spanned},
     },
     compiler::syntax::{Note, Syntax},
-    construct::token::{Delim, Token, Tokens},
+    construct::token::{Delim, ResOp, Token, Tokens},
+    error_codes,
 };
 
 const OP_CHARS: &str = "!$%&*+,-./:<=>?@^|~";
@@ -21,17 +22,73 @@ macro_rules! RemainingIter {
     () => { Peekable<impl Iterator<Item = char>> };
 }
 
+/// Options that change how a source is lexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexOptions {
+    /// Whether unrecognized characters become `Token::Error`s,
+    /// rather than stopping the lexer.
+    pub recover: bool,
+    /// Whether newlines separate expressions.
+    /// When off, newlines are treated like any other whitespace,
+    /// and only `;` produces a `Token::Sep`.
+    pub significant_newlines: bool,
+    /// Whether whitespace and comments become `Token::Trivia`,
+    /// rather than being discarded.
+    pub trivia: bool,
+}
+
+impl Default for LexOptions {
+    fn default() -> LexOptions {
+        LexOptions {
+            recover: false,
+            significant_newlines: true,
+            trivia: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Lexer {
     source: Rc<Source>,
     index: usize,
     tokens: Tokens,
+    options: LexOptions,
 }
 
 impl Lexer {
     // TODO: lexer needs to return all macro declarations
     /// Lexes a source file into a stream of tokens.
+    /// Stops at the first character that is not recognized.
     pub fn lex(source: Rc<Source>) -> Result<Spanned<Tokens>, Syntax> {
+        Lexer::lex_with(source, LexOptions::default())
+    }
+
+    /// Lexes a source file into a stream of tokens,
+    /// emitting a `Token::Error` for each unrecognized character
+    /// and carrying on. The `Reader` reports all of these at once.
+    pub fn lex_recover(source: Rc<Source>) -> Result<Spanned<Tokens>, Syntax> {
+        let options = LexOptions {
+            recover: true,
+            ..LexOptions::default()
+        };
+        Lexer::lex_with(source, options)
+    }
+
+    /// Lexes a source file into a stream of tokens,
+    /// keeping whitespace and comments as `Token::Trivia`.
+    /// Used by tools like formatters that need to reproduce the source;
+    /// the `Reader` ignores trivia, so the result can still be compiled.
+    pub fn lex_with_trivia(source: Rc<Source>) -> Result<Spanned<Tokens>, Syntax> {
+        let options = LexOptions {
+            trivia: true,
+            ..LexOptions::default()
+        };
+        Lexer::lex_with(source, options)
+    }
+
+    /// Lexes a source file into a stream of tokens,
+    /// using a specific set of `LexOptions`.
+    pub fn lex_with(source: Rc<Source>, options: LexOptions) -> Result<Spanned<Tokens>, Syntax> {
         // get a span that spans the entire source file:
         let span = Span::new(&source, 0, source.contents.len());
 
@@ -40,6 +97,7 @@ impl Lexer {
             source,
             index: 0,
             tokens: vec![],
+            options,
         };
 
         // prime the lexer
@@ -77,13 +135,14 @@ impl Lexer {
 
             // strip whitespace...
             while let Some(c) = remaining.peek() {
-                // ...but don't strip newlines!
-                if !c.is_whitespace() || *c == '\n' {
+                // ...but don't strip newlines if they separate expressions!
+                if !c.is_whitespace() || (*c == '\n' && self.options.significant_newlines) {
                     break;
                 }
                 new_index += c.len_utf8();
                 remaining.next();
             }
+            let comment_index = new_index;
 
             // Strip single line comment
             if let Some('#') = remaining.next() {
@@ -97,6 +156,8 @@ impl Lexer {
                     new_index += c.len_utf8();
                 }
             }
+            self.trivia(old_index, comment_index);
+            self.trivia(comment_index, new_index);
 
             // If nothing was stripped, we're done
             self.index = new_index;
@@ -106,6 +167,16 @@ impl Lexer {
         }
     }
 
+    /// Records the stripped characters between two indices as trivia,
+    /// if trivia is being kept and anything was stripped.
+    fn trivia(&mut self, start: usize, end: usize) {
+        if self.options.trivia && start != end {
+            let trivia = Token::Trivia(self.source.contents[start..end].to_string());
+            let span = Span::new(&self.source, start, end - start);
+            self.tokens.push(Spanned::new(trivia, span));
+        }
+    }
+
     /// Starting at the parser's current index.
     /// consumes characters one at a time according to a
     /// `pred`icate. after the predicate returns false,
@@ -162,8 +233,9 @@ impl Lexer {
                                     "To include a single backslash `\\`, escape it first: `\\\\`",
                                     &Span::new(&self.source, self.index + len - bytes, bytes),
                                 ),
-                            ), // TODO: add help note about backslash escape
-                        )
+                            )
+                            .with_code(error_codes::UNKNOWN_ESCAPE), // TODO: add help note about backslash escape
+                        );
                     }
                 })
             } else {
@@ -178,7 +250,8 @@ impl Lexer {
         Err(Syntax::error(
             "Unexpected end of source while parsing string literal",
             &Span::point(&self.source, self.index + len),
-        ))
+        )
+        .with_code(error_codes::UNTERMINATED_STRING))
     }
 
     /// Must start with two-byte prefix `0?`, where `?`
@@ -192,6 +265,24 @@ impl Lexer {
             .take_while(&mut remaining, |_| (), |n| n.is_digit(radix))
             .1;
 
+        if len == 2 {
+            return Err(Syntax::error(
+                &format!("Expected digits after `{}`", self.grab_from_index(2)),
+                &Span::new(&self.source, self.index, len),
+            )
+            .with_code(error_codes::MALFORMED_NUMBER));
+        }
+
+        // `p` exponents are reserved for hexadecimal floats, e.g. `0x1.8p3`
+        if radix == 16 && remaining.peek() == Some(&'p') {
+            return Err(Syntax::error(
+                "Hexadecimal float literals are not supported yet",
+                &Span::new(&self.source, self.index, len + 1),
+            )
+            .with_code(error_codes::MALFORMED_NUMBER));
+        }
+
+        // there is no arbitrary-precision integer to fall back on yet
         let integer = i64::from_str_radix(&self.grab_from_index(len)[2..], radix).map_err(|_| {
             Syntax::error(
                 "Integer literal too large to fit in a signed 64-bit integer",
@@ -199,6 +290,7 @@ impl Lexer {
                 // + 2 chars to take the `0?` into account
                 &Span::new(&self.source, self.index, len),
             )
+            .with_code(error_codes::NUMBER_TOO_LARGE)
         });
 
         Ok((Token::Lit(Lit::Integer(integer?)), len))
@@ -227,42 +319,67 @@ impl Lexer {
         }
     }
 
+    /// Parses a decimal integer or float.
+    /// Floats have a decimal point, an exponent, or both,
+    /// e.g. `1.5`, `1e5`, or `1.5E-3`.
     fn decimal_literal(&self, mut remaining: RemainingIter!()) -> Result<(Token, usize), Syntax> {
         let mut len = self
             .take_while(&mut remaining, |_| (), |n| n.is_digit(10))
             .1;
+        let mut float = false;
 
-        match remaining.next() {
-            // There's a decimal point, so we parse as a float
-            Some('.') => {
-                len += 1; // for the '.'
-                len += self
-                    .take_while(&mut remaining, |_| (), |n| n.is_digit(10))
-                    .1;
-                let float = f64::from_str(self.grab_from_index(len)).map_err(|_| {
-                    Syntax::error(
-                        "Float literal does not fit in a 64-bit floating-point number",
-                        &Span::new(&self.source, self.index, len),
-                    )
-                })?;
-                Ok((Token::Lit(Lit::Float(float)), len))
+        // There's a decimal point, so we parse as a float
+        if let Some('.') = remaining.peek() {
+            remaining.next();
+            len += 1; // for the '.'
+            len += self
+                .take_while(&mut remaining, |_| (), |n| n.is_ascii_digit())
+                .1;
+            float = true;
+        }
+
+        // There's an exponent, so we parse using scientific notation
+        if let Some('e' | 'E') = remaining.peek() {
+            remaining.next();
+            len += 1; // for the 'e'
+            if let Some('+' | '-') = remaining.peek() {
+                remaining.next();
+                len += 1;
             }
-            // There's an 'e', so we parse using scientific notation
-            Some('e') => Err(Syntax::error(
-                "Scientific notation for floating-point is WIP!",
-                &Span::point(&self.source, self.index),
-            )),
-            // Nothing of use, wrap up what we have so far
-            _ => {
-                let integer = i64::from_str(self.grab_from_index(len)).map_err(|_| {
-                    Syntax::error(
-                        "Decimal literal too large to fit in a signed 64-bit integer",
-                        &Span::new(&self.source, self.index, len),
-                    )
-                })?;
-                Ok((Token::Lit(Lit::Integer(integer)), len))
+            let digits = self
+                .take_while(&mut remaining, |_| (), |n| n.is_ascii_digit())
+                .1;
+            if digits == 0 {
+                return Err(Syntax::error(
+                    "Expected digits in the exponent of this float literal",
+                    &Span::new(&self.source, self.index, len),
+                )
+                .with_code(error_codes::MALFORMED_NUMBER));
             }
+            len += digits;
+            float = true;
+        }
+
+        if float {
+            let float = f64::from_str(self.grab_from_index(len)).map_err(|_| {
+                Syntax::error(
+                    "Float literal does not fit in a 64-bit floating-point number",
+                    &Span::new(&self.source, self.index, len),
+                )
+                .with_code(error_codes::NUMBER_TOO_LARGE)
+            })?;
+            return Ok((Token::Lit(Lit::Float(float)), len));
         }
+
+        // Nothing of use, wrap up what we have so far
+        let integer = i64::from_str(self.grab_from_index(len)).map_err(|_| {
+            Syntax::error(
+                "Decimal literal too large to fit in a signed 64-bit integer",
+                &Span::new(&self.source, self.index, len),
+            )
+            .with_code(error_codes::NUMBER_TOO_LARGE)
+        })?;
+        Ok((Token::Lit(Lit::Integer(integer)), len))
     }
 
     /// Parses the next token.
@@ -342,21 +459,22 @@ impl Lexer {
             // don't have both a list and `is_ascii_punctuation`
             // Op
             c if OP_CHARS.contains(c) => {
-                self.take_while(
+                let (run, _) = self.take_while(
                     &mut once(c).chain(remaining).peekable(),
-                    |s| Token::Op(s.to_string()),
+                    |s| s.to_string(),
                     |n| OP_CHARS.contains(n),
-                )
+                );
+                let len = Lexer::operator_len(&run);
+                (Token::Op(run[..len].to_string()), len)
             },
 
             // Unrecognized char
+            unknown if self.options.recover => (Token::Error(unknown), unknown.len_utf8()),
             unknown => return Err(Syntax::error(
-                &format!(
-                    "Hmm... The character `{}` is not recognized in this context - check for encoding issues or typos",
-                    unknown,
-                ),
+                &Lexer::unrecognized(unknown),
                 &Span::point(&self.source, self.index),
-            )),
+            )
+            .with_code(error_codes::UNRECOGNIZED_TOKEN)),
         };
 
         let spanned = Spanned::new(token, Span::new(&self.source, self.index, len));
@@ -364,6 +482,42 @@ impl Lexer {
         self.index += len;
         Ok(spanned)
     }
+
+    /// Returns the length of the first operator in a run of operator characters.
+    /// A run is usually a single operator, even if it is not a known one.
+    /// But if a run that is not a known operator can be split into known operators,
+    /// it is split greedily, taking the longest known operator first:
+    /// e.g. the `=-` in `a=-b` is lexed as `=` followed by `-`.
+    fn operator_len(run: &str) -> usize {
+        if ResOp::try_new(run).is_some() {
+            return run.len();
+        }
+
+        let mut first = None;
+        let mut rest = run;
+        while !rest.is_empty() {
+            // operator characters are ascii, so every index is a char boundary
+            let len = match (1..=rest.len())
+                .rev()
+                .find(|len| ResOp::try_new(&rest[..*len]).is_some())
+            {
+                Some(len) => len,
+                None => return run.len(),
+            };
+            first.get_or_insert(len);
+            rest = &rest[len..];
+        }
+
+        first.unwrap_or(run.len())
+    }
+
+    /// The error message for a character that is not recognized.
+    pub fn unrecognized(character: char) -> String {
+        format!(
+            "Hmm... The character `{}` is not recognized in this context - check for encoding issues or typos",
+            character,
+        )
+    }
 }
 
 #[cfg(test)]
@@ -393,9 +547,15 @@ mod test {
         fn operators(s in "[!$%&*+,-./:<=>?@^|~]+") {
             let result = Lexer::lex(Source::source(&s));
             prop_assert!(result.is_ok());
-            if let Token::Op(op) = &result.unwrap().item[0].item {
-                prop_assert_eq!(op, &s);
+            // a run may be split into known operators, but nothing is lost
+            let mut ops = String::new();
+            for token in result.unwrap().item {
+                match token.item {
+                    Token::Op(op) => ops.push_str(&op),
+                    other => prop_assert!(false, "expected an operator, found {:?}", other),
+                }
             }
+            prop_assert_eq!(ops, s);
         }
 
         #[test]
@@ -409,6 +569,24 @@ mod test {
             prop_assert!(unwrapped.len() == 1);
             prop_assert_eq!(&unwrapped[0].item, &Token::Lit(Lit::Float(x)));
         }
+
+        #[test]
+        fn display_round_trip(
+            lit in any::<Lit>().prop_filter("literal has no single-token source form", |lit| {
+                match lit {
+                    Lit::Float(x) => x.is_finite() && x.is_sign_positive(),
+                    Lit::Integer(n) => *n >= 0,
+                    _ => true,
+                }
+            })
+        ) {
+            let formatted = lit.to_string();
+            let result = Lexer::lex(Source::source(&formatted));
+            prop_assert!(result.is_ok(), "{}", formatted);
+            let unwrapped = result.unwrap().item;
+            prop_assert!(unwrapped.len() == 1, "{}", formatted);
+            prop_assert_eq!(&unwrapped[0].item, &Token::Lit(lit));
+        }
     }
 
     #[test]
@@ -422,6 +600,28 @@ mod test {
         assert_eq!(unwrapped[0].item, Token::Lit(Lit::Float(x)));
     }
 
+    #[test]
+    fn scientific_float() {
+        let lex = |source| {
+            Lexer::lex(Source::source(source)).unwrap().item[0]
+                .item
+                .clone()
+        };
+        assert_eq!(lex("1e5"), Token::Lit(Lit::Float(1e5)));
+        assert_eq!(lex("1E5"), lex("1e5"));
+        assert_eq!(lex("1e+5"), lex("1e5"));
+        assert_eq!(lex("1.5e-3"), Token::Lit(Lit::Float(0.0015)));
+        assert_eq!(lex("1.5E-3"), lex("1.5e-3"));
+        assert_eq!(lex("15e-4"), lex("1.5e-3"));
+    }
+
+    #[test]
+    fn malformed_exponents() {
+        for source in ["1e", "1.5e-", "2Ex", "0x1p3"] {
+            assert!(Lexer::lex(Source::source(source)).is_err(), "{}", source);
+        }
+    }
+
     #[test]
     fn zero_float() {
         let result = Lexer::lex(Source::source("0.0"));
@@ -453,6 +653,170 @@ mod test {
         format!("{:?}", result);
     }
 
+    #[test]
+    fn recover_unknown() {
+        let result = Lexer::lex_recover(Source::source("x = 1 `\ny = '"))
+            .unwrap()
+            .item;
+        let tokens = result.into_iter().map(|t| t.item).collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Iden("x".to_string()),
+                Token::Op("=".to_string()),
+                Token::Lit(Lit::Integer(1)),
+                Token::Error('`'),
+                Token::Sep,
+                Token::Iden("y".to_string()),
+                Token::Op("=".to_string()),
+                Token::Error('\''),
+            ]
+        );
+    }
+
+    fn integer(source: &str) -> Result<i64, Syntax> {
+        match &Lexer::lex(Source::source(source))?.item[0].item {
+            Token::Lit(Lit::Integer(integer)) => Ok(*integer),
+            other => panic!("expected an integer, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn radix_literals() {
+        let cases = [
+            ("0b1011", 0b1011),
+            ("0o777", 0o777),
+            ("0d1234", 1234),
+            ("0xFF", 0xFF),
+            (
+                "0b111111111111111111111111111111111111111111111111111111111111111",
+                i64::MAX,
+            ),
+            ("0o777777777777777777777", i64::MAX),
+            ("0d9223372036854775807", i64::MAX),
+            ("0x7FFFFFFFFFFFFFFF", i64::MAX),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(integer(source), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn radix_overflow() {
+        for source in [
+            "0b1000000000000000000000000000000000000000000000000000000000000000",
+            "0o1000000000000000000000",
+            "0d9223372036854775808",
+            "0x8000000000000000",
+            // 128 bits
+            "0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ] {
+            let error = integer(source).unwrap_err();
+            assert!(error.reason.contains("too large"), "{}", source);
+            assert_eq!(error.notes[0].span.len(), source.len(), "{}", source);
+        }
+
+        let error = integer("0x").unwrap_err();
+        assert_eq!(error.reason, "Expected digits after `0x`");
+    }
+
+    fn ops(source: &str) -> Vec<String> {
+        Lexer::lex(Source::source(source))
+            .unwrap()
+            .item
+            .into_iter()
+            .filter_map(|t| match t.item {
+                Token::Op(op) => Some(op),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_operators() {
+        assert_eq!(ops("a=-b"), vec!["=", "-"]);
+        assert_eq!(ops("a =- b"), vec!["=", "-"]);
+        assert_eq!(ops("f=x->-x"), vec!["=", "->", "-"]);
+        assert_eq!(ops("a**-b"), vec!["**", "-"]);
+        assert_eq!(ops("a++-b..c"), vec!["++", "-", ".."]);
+        assert_eq!(ops("a==-b,-c"), vec!["==", "-", ",", "-"]);
+
+        // known operators are never split
+        assert_eq!(ops("a ++ b == c"), vec!["++", "=="]);
+        // runs that can not be split into known operators are kept whole
+        assert_eq!(ops("a <> b"), vec!["<>"]);
+        assert_eq!(ops("a =! b"), vec!["=!"]);
+    }
+
+    fn separators(options: LexOptions) -> Vec<Token> {
+        let source = Source::source("x = 1\ny = 2; z = 3\n");
+        Lexer::lex_with(source, options)
+            .unwrap()
+            .item
+            .into_iter()
+            .map(|t| t.item)
+            .filter(|t| matches!(t, Token::Sep | Token::Iden(_)))
+            .collect()
+    }
+
+    #[test]
+    fn significant_newlines() {
+        let x = Token::Iden("x".to_string());
+        let y = Token::Iden("y".to_string());
+        let z = Token::Iden("z".to_string());
+
+        assert_eq!(
+            separators(LexOptions::default()),
+            vec![
+                x.clone(),
+                Token::Sep,
+                y.clone(),
+                Token::Sep,
+                z.clone(),
+                Token::Sep
+            ]
+        );
+
+        let insignificant = LexOptions {
+            significant_newlines: false,
+            ..LexOptions::default()
+        };
+        assert_eq!(separators(insignificant), vec![x, y, Token::Sep, z]);
+    }
+
+    #[test]
+    fn trivia() {
+        let source = Source::source("x = 1 # one\n  # two\ny");
+        let tokens = Lexer::lex_with_trivia(source).unwrap().item;
+
+        let comments = tokens
+            .iter()
+            .filter(|t| matches!(&t.item, Token::Trivia(s) if s.starts_with('#')))
+            .map(|t| (t.item.clone(), t.span.contents()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comments,
+            vec![
+                (Token::Trivia("# one".to_string()), "# one".to_string()),
+                (Token::Trivia("# two".to_string()), "# two".to_string()),
+            ]
+        );
+
+        // all tokens together reproduce the source
+        let rebuilt = tokens.iter().map(|t| t.span.contents()).collect::<String>();
+        assert_eq!(rebuilt, "x = 1 # one\n  # two\ny");
+
+        // without trivia, the same tokens are produced
+        let plain = Lexer::lex(Source::source("x = 1 # one\n  # two\ny"))
+            .unwrap()
+            .item;
+        let kept = tokens
+            .into_iter()
+            .filter(|t| !matches!(t.item, Token::Trivia(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, plain);
+    }
+
     #[test]
     fn new_empty() {
         Lexer::lex(Source::source("")).unwrap();
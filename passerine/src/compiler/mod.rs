@@ -6,13 +6,13 @@
 //! may be implemented in the future.
 
 pub mod lex;
-pub use lex::Lexer;
+pub use lex::{LexOptions, Lexer};
 
 pub mod read;
 pub use read::Reader;
 
-// pub mod expand;
-// pub use expand::Expander;
+pub mod expand;
+pub use expand::Expander;
 
 pub mod parse;
 pub use parse::Parser;
@@ -23,6 +23,9 @@ pub use desugar::Desugarer;
 pub mod hoist;
 pub use hoist::Hoister;
 
+pub mod tail;
+pub use tail::Tail;
+
 // pub mod unify;
 
 pub mod compile;
@@ -41,6 +44,7 @@ use crate::{
         token::{TokenTree, Tokens},
         tree::{AST, CST, SST},
     },
+    kernel::FFI,
 };
 
 #[inline(always)]
@@ -50,7 +54,7 @@ pub fn lex(source: Rc<Source>) -> Result<Spanned<Tokens>, Syntax> {
 
 #[inline(always)]
 pub fn read(source: Rc<Source>) -> Result<Spanned<TokenTree>, Syntax> {
-    let tokens = lex(source)?;
+    let tokens = Lexer::lex_recover(source)?;
     Reader::read(tokens)
 }
 
@@ -65,7 +69,7 @@ pub fn desugar(
     source: Rc<Source>,
 ) -> Result<(Spanned<CST>, HashMap<String, SharedSymbol>), Syntax> {
     let (ast, symbols) = parse(source)?;
-    Ok((Desugarer::desugar(ast), symbols))
+    Desugarer::desugar(ast, symbols)
 }
 
 #[inline(always)]
@@ -80,11 +84,32 @@ pub fn gen(source: Rc<Source>) -> Result<Rc<Lambda>, Syntax> {
     Compiler::compile(sst, scope)
 }
 
+/// Compiles a source, allowing calls to the functions in a specific FFI.
+#[inline(always)]
+pub fn compile_source_with_ffi(source: Rc<Source>, ffi: FFI) -> Result<Rc<Lambda>, Syntax> {
+    let (cst, symbols) = desugar(source)?;
+    let (sst, scope) = Hoister::hoist_with_ffi(cst, symbols, &ffi)?;
+    Compiler::compile_with_ffi(sst, scope, ffi)
+}
+
 #[inline(always)]
 pub fn compile_sst(sst: Spanned<SST>, scope: Scope) -> Result<Rc<Lambda>, Syntax> {
     Compiler::compile(sst, scope)
 }
 
+/// Compiles an SST along with the scope it declares,
+/// allowing calls to the functions in a specific FFI.
+/// Useful when the SST was built or hoisted separately,
+/// e.g. by an embedder providing its own FFI.
+#[inline(always)]
+pub fn compile_sst_with_ffi(
+    sst: Spanned<SST>,
+    scope: Scope,
+    ffi: FFI,
+) -> Result<Rc<Lambda>, Syntax> {
+    Compiler::compile_with_ffi(sst, scope, ffi)
+}
+
 // TODO: convert symbols to type alias somewhere
 #[inline(always)]
 pub fn compile_cst(
@@ -100,7 +125,7 @@ pub fn compile_ast(
     ast: Spanned<AST>,
     symbols: HashMap<String, SharedSymbol>,
 ) -> Result<Rc<Lambda>, Syntax> {
-    let cst = Desugarer::desugar(ast);
+    let (cst, symbols) = Desugarer::desugar(ast, symbols)?;
     compile_cst(cst, symbols)
 }
 
@@ -118,6 +143,181 @@ pub fn compile_tokens(tokens: Spanned<Tokens>) -> Result<Rc<Lambda>, Syntax> {
 
 #[inline(always)]
 pub fn compile_source(source: Rc<Source>) -> Result<Rc<Lambda>, Syntax> {
-    let tokens = Lexer::lex(source)?;
+    let tokens = Lexer::lex_recover(source)?;
     compile_tokens(tokens)
 }
+
+/// A stage of the compiler, in the order they're run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Lex,
+    Read,
+    Parse,
+    Desugar,
+    Hoist,
+    Compile,
+}
+
+/// Compiles a source through every stage in one call,
+/// allowing calls to the functions in a specific FFI.
+/// Stops at the first stage that fails,
+/// returning the error along with the stage it was raised in.
+pub fn compile_staged(source: Rc<Source>, ffi: FFI) -> Result<Rc<Lambda>, (Stage, Syntax)> {
+    let at = |stage| move |error| (stage, error);
+    let tokens = Lexer::lex_recover(source).map_err(at(Stage::Lex))?;
+    let token_tree = Reader::read(tokens).map_err(at(Stage::Read))?;
+    let (ast, symbols) = Parser::parse(token_tree).map_err(at(Stage::Parse))?;
+    let (cst, symbols) = Desugarer::desugar(ast, symbols).map_err(at(Stage::Desugar))?;
+    let (sst, scope) = Hoister::hoist_with_ffi(cst, symbols, &ffi).map_err(at(Stage::Hoist))?;
+    Compiler::compile_with_ffi(sst, scope, ffi).map_err(at(Stage::Compile))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process::Command, thread};
+
+    use super::*;
+    use crate::{
+        common::{closure::Closure, Data},
+        error_codes,
+        kernel::{ffi_core, FFIFunction},
+        vm::fiber::Fiber,
+    };
+
+    fn stage(source: &str) -> Stage {
+        compile_staged(Source::source(source), ffi_core())
+            .unwrap_err()
+            .0
+    }
+
+    #[test]
+    fn staged() {
+        let lambda = compile_staged(Source::source("x = 1; x + 2"), ffi_core()).unwrap();
+        assert_eq!(
+            lambda,
+            compile_source(Source::source("x = 1; x + 2")).unwrap()
+        );
+
+        assert_eq!(stage("\"unterminated"), Stage::Lex);
+        assert_eq!(stage("(1, 2"), Stage::Read);
+        assert_eq!(stage("x = "), Stage::Parse);
+        assert_eq!(stage("undefined"), Stage::Hoist);
+        assert_eq!(stage("3 4"), Stage::Compile);
+    }
+
+    fn ffi_triple() -> FFI {
+        let mut ffi = FFI::new();
+        let triple = |data| match data {
+            Data::Integer(n) => Ok(Data::Integer(n * 3)),
+            other => Err(format!("Expected an integer, found '{}'", other)),
+        };
+        ffi.add("triple", FFIFunction::new(Box::new(triple)))
+            .unwrap();
+        ffi
+    }
+
+    #[test]
+    fn sst_with_ffi() {
+        let (sst, scope) = hoist(Source::source("magic \"triple\" 4")).unwrap();
+        let lambda = compile_sst_with_ffi(sst.clone(), scope.clone(), ffi_triple()).unwrap();
+
+        let mut fiber = Fiber::init_with_ffi(Closure::wrap(lambda), ffi_triple());
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::Integer(12));
+
+        // the core FFI does not have the function
+        assert!(compile_sst_with_ffi(sst, scope, ffi_core()).is_err());
+    }
+
+    #[test]
+    fn ffi_by_name() {
+        let source = Source::source("triple 4");
+        let lambda = compile_source_with_ffi(source.clone(), ffi_triple()).unwrap();
+        let mut fiber = Fiber::init_with_ffi(Closure::wrap(lambda), ffi_triple());
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::Integer(12));
+
+        // only functions in the FFI are defined
+        assert_eq!(
+            compile_staged(source, ffi_core()).unwrap_err().0,
+            Stage::Hoist
+        );
+    }
+
+    fn nesting_error(source: &str) -> Syntax {
+        let error = compile_source(Source::source(source)).unwrap_err();
+        assert!(error.reason.starts_with("Nesting too deep"), "{}", error);
+        assert_eq!(error.code, Some(error_codes::NESTING_TOO_DEEP));
+        error
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        // deep nesting is an error rather than a stack overflow in any stage
+        let lambdas = nesting_error(&format!("{}x", "x -> ".repeat(3000)));
+        assert!(lambdas.reason.contains("expressions"));
+        let sums = nesting_error(&format!("{}1", "1 + ".repeat(3000)));
+        assert!(sums.reason.contains("expressions"));
+
+        for (open, close) in [("(", ")"), ("{", "}"), ("[", "]")] {
+            let source = format!("{}1{}", open.repeat(3000), close.repeat(3000));
+            assert!(nesting_error(&source).reason.contains("delimiters"));
+        }
+    }
+
+    #[test]
+    fn nesting_at_limit() {
+        // unoptimized builds use a lot of stack per level,
+        // so compile on a thread with as much stack as the main thread
+        let compile = |source: String| {
+            thread::Builder::new()
+                .stack_size(8 * 1024 * 1024)
+                .spawn(move || {
+                    compile_source(Source::source(&source))
+                        .map(|_| ())
+                        .map_err(|error| error.reason)
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+
+        let depth = read::MAX_DEPTH - 1;
+        compile(format!("{}1{}", "(".repeat(depth), ")".repeat(depth))).unwrap();
+        compile(format!("{}1", "1 + ".repeat(parse::MAX_DEPTH - 1))).unwrap();
+        compile(format!("{}1", "1 + ".repeat(parse::MAX_DEPTH))).unwrap_err();
+        compile(format!("{}x", "x -> ".repeat(hoist::DEFAULT_MAX_DEPTH))).unwrap();
+
+        // commas build a flat tuple, so long tuples are not deep
+        compile(format!("[{}]", vec!["1"; 1000].join(", "))).unwrap();
+    }
+
+    /// Set when this test binary is run again by `silent`.
+    const SILENT_CHILD: &str = "PASSERINE_SILENT_CHILD";
+
+    #[test]
+    fn silent() {
+        // the test runner captures output, so compile in a child process instead,
+        // between two markers that should end up right next to each other
+        if env::var_os(SILENT_CHILD).is_some() {
+            print!("<before>");
+            let source = "f = x -> { y = mod { z = x }; y }\nundefined; f 1";
+            compile_source(Source::source(source)).unwrap_err();
+            compile_source(Source::source("f = x -> { y = x }; f 1")).unwrap();
+            print!("<after>");
+            return;
+        }
+
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "compiler::test::silent", "--nocapture"])
+            .args(["--test-threads", "1"])
+            .env(SILENT_CHILD, "1")
+            .env_remove("PASSERINE_TRACE_HOIST")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("<before><after>"), "{}", stdout);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    }
+}
@@ -1,18 +1,24 @@
-use std::{collections::HashMap, convert::TryFrom, mem, rc::Rc};
+use std::{collections::HashMap, iter::once, mem};
 
 use crate::{
     common::{
         lit::Lit,
         span::{Span, Spanned},
     },
-    compiler::{read::Reader, syntax::Syntax},
+    compiler::syntax::Syntax,
     construct::{
         symbol::SharedSymbol,
-        token::{Delim, ResIden, ResOp, TokenTree, TokenTrees},
+        token::{ResIden, ResOp, TokenTree, TokenTrees},
         tree::{Base, Lambda, Pattern, Sugar, AST},
     },
+    error_codes,
 };
 
+/// How deeply expressions may be nested.
+/// Each later stage of the compiler walks the tree recursively,
+/// so this keeps deeply nested programs from overflowing the stack.
+pub const MAX_DEPTH: usize = 128;
+
 // TODO: Document how parser advances
 // perhaps move tree idx into struct itself
 
@@ -77,6 +83,9 @@ pub struct Parser {
     /// We don't do this during lexing so that token-based
     /// macros can work with strings.
     symbols: HashMap<String, SharedSymbol>,
+    /// How many expressions are being parsed,
+    /// each inside the last.
+    depth: usize,
 }
 
 impl Parser {
@@ -85,13 +94,28 @@ impl Parser {
     /// Also returns the symbol interning table.
     pub fn parse(
         token_tree: Spanned<TokenTree>,
+    ) -> Result<(Spanned<AST>, HashMap<String, SharedSymbol>), Syntax> {
+        Parser::parse_with(token_tree, HashMap::new())
+    }
+
+    /// Like `parse`, but continues from the symbols returned by an earlier parse,
+    /// e.g. of the previous line entered in a REPL,
+    /// so that each name is the same symbol in both.
+    pub fn parse_with(
+        token_tree: Spanned<TokenTree>,
+        symbols: HashMap<String, SharedSymbol>,
     ) -> Result<(Spanned<AST>, HashMap<String, SharedSymbol>), Syntax> {
         // build base parser
-        let mut parser = Parser {
-            symbols: HashMap::new(),
-        };
+        let mut parser = Parser { symbols, depth: 0 };
 
-        let ast = parser.rule_prefix(&token_tree)?;
+        // the top level is always a block, never a function defined by clauses
+        let ast = match &token_tree.item {
+            TokenTree::Block(trees) => {
+                let block = AST::Base(Base::Block(parser.expressions(trees)?));
+                Spanned::new(block, token_tree.span.clone())
+            }
+            _ => parser.rule_prefix(&token_tree)?,
+        };
 
         Ok((ast, parser.symbols))
     }
@@ -103,7 +127,11 @@ impl Parser {
             TokenTree::Lit(_) => self.literal(token_tree)?,
             TokenTree::Op(name) => {
                 if let ResOp::Sub = Parser::to_op(name, &token_tree.span)? {
-                    todo!()
+                    // TODO: negation
+                    return Err(Syntax::error(
+                        "Negation is not supported yet, subtract from zero instead, i.e. `0 - x`",
+                        &token_tree.span,
+                    ));
                 } else {
                     return Err(Syntax::error(
                         &format!("Unexpected operator `{}`", name),
@@ -113,38 +141,9 @@ impl Parser {
             }
             TokenTree::Label(_) => self.label(token_tree)?,
             TokenTree::Iden(_) => self.symbol(token_tree)?,
-            TokenTree::Form(trees) => {
-                dbg!(&trees);
-                // TODO: handle builtin keywords
-                if let Some(Spanned { item, .. }) = trees.first() {
-                    if let TokenTree::Iden(iden) = item {
-                        if let Some(keyword) = ResIden::try_new(iden) {
-                            // TODO: this deep clone isn't necessary,
-                            // we could either pass the vectors owned
-                            // or use slices instead.
-                            dbg!(&trees);
-                            self.keyword(
-                                &trees[1..]
-                                    .into_iter()
-                                    .map(|x| x.clone())
-                                    .collect::<Vec<_>>(),
-                                keyword,
-                            )?;
-                        }
-                    }
-                }
-
-                self.expr(trees, &mut 0, Prec::None)?
-            }
-            // TODO: instead of expr, use prefix.
-            TokenTree::Block(trees) => {
-                let mut expressions = vec![];
-                for tree in trees {
-                    expressions.push(self.expr(&tree.item, &mut 0, Prec::None)?);
-                }
-                Spanned::new(AST::Base(Base::Block(expressions)), token_tree.span.clone())
-            }
-            TokenTree::List(_) => unimplemented!(),
+            TokenTree::Form(trees) => self.group(trees, &token_tree.span)?,
+            TokenTree::Block(trees) => self.block(trees, &token_tree.span)?,
+            TokenTree::List(trees) => self.list(trees, &token_tree.span)?,
         };
         Ok(result)
     }
@@ -163,14 +162,53 @@ impl Parser {
             ));
         }
 
-        let mut left = self.rule_prefix(&trees[*trees_idx])?;
-        *trees_idx += 1;
+        if self.depth >= MAX_DEPTH {
+            return Err(Syntax::error(
+                &format!(
+                    "Nesting too deep, expressions can be nested at most {} deep",
+                    MAX_DEPTH
+                ),
+                &trees[*trees_idx].span,
+            )
+            .with_code(error_codes::NESTING_TOO_DEEP));
+        }
+
+        let depth = self.depth;
+        self.depth += 1;
+        let result = self.expr_nested(trees, trees_idx, prec);
+        self.depth = depth;
+        result
+    }
+
+    /// Parses an expression, see `Parser::expr`,
+    /// once it is known not to be nested too deeply.
+    fn expr_nested(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+        prec: Prec,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let mut left = if Parser::is_op(&trees[*trees_idx], ResOp::Spread) {
+            self.spread(trees, trees_idx)?
+        } else if let Some(keyword) = self.keyword(trees, trees_idx)? {
+            keyword
+        } else {
+            let left = self.rule_prefix(&trees[*trees_idx])?;
+            *trees_idx += 1;
+            left
+        };
 
         while *trees_idx < trees.len() {
             if self.prec(&trees[*trees_idx])? < prec {
                 break;
             }
+            let is_pair = Parser::is_op(&trees[*trees_idx], ResOp::Pair);
             left = self.rule_infix(left, trees, trees_idx)?;
+            // each operator nests the expression to its left one deeper,
+            // apart from commas, which build a single flat tuple
+            if !is_pair {
+                self.depth += 1;
+            }
         }
 
         Ok(left)
@@ -226,13 +264,41 @@ impl Parser {
                 }
 
                 // Builtins
-                Add => todo!(),
-                Sub => todo!(),
-                Mul => todo!(),
-                Div => todo!(),
-                Rem => todo!(),
-                Equal => todo!(),
-                Pow => todo!(),
+                Add => self.binop(left, trees, trees_idx, true, Add, |l, r| {
+                    Parser::ffi_pair("add", l, r)
+                }),
+                Sub => self.binop(left, trees, trees_idx, true, Sub, |l, r| {
+                    Parser::ffi_pair("sub", l, r)
+                }),
+                Mul => self.binop(left, trees, trees_idx, true, Mul, |l, r| {
+                    Parser::ffi_pair("mul", l, r)
+                }),
+                Div => self.binop(left, trees, trees_idx, true, Div, |l, r| {
+                    Parser::ffi_pair("div", l, r)
+                }),
+                Rem => self.binop(left, trees, trees_idx, true, Rem, |l, r| {
+                    Parser::ffi_pair("rem", l, r)
+                }),
+                Pow => self.binop(left, trees, trees_idx, false, Pow, |l, r| {
+                    Parser::ffi_pair("pow", l, r)
+                }),
+                Equal => self.binop(left, trees, trees_idx, true, Equal, |l, r| {
+                    Parser::ffi_pair("equal", l, r)
+                }),
+
+                // Lists
+                Concat => self.binop(left, trees, trees_idx, true, Concat, |l, r| {
+                    Parser::ffi_pair("concat", l, r)
+                }),
+
+                Spread => Err(Syntax::error(
+                    "The spread operator `..` must come before a tuple item",
+                    &tree.span,
+                )),
+                Update => Err(Syntax::error(
+                    "`|` can only be used in a record update, i.e. `{ r | x = 1 }`",
+                    &tree.span,
+                )),
             },
 
             _ => self.call(left, trees, trees_idx),
@@ -256,11 +322,23 @@ impl Parser {
             ResOp::Compose => Prec::Compose,
             ResOp::Is => Prec::Is,
 
-            ResOp::Add | ResOp::Sub => Prec::AddSub,
+            ResOp::Add | ResOp::Sub | ResOp::Concat => Prec::AddSub,
             ResOp::Mul | ResOp::Div | ResOp::Rem => Prec::MulDiv,
 
             ResOp::Equal => Prec::Logic,
             ResOp::Pow => Prec::Pow,
+            // only valid in prefix position
+            ResOp::Spread => Prec::End,
+            // only valid in a record update
+            ResOp::Update => Prec::End,
+        }
+    }
+
+    /// Returns whether a token tree is a specific operator.
+    fn is_op(tree: &Spanned<TokenTree>, op: ResOp) -> bool {
+        match &tree.item {
+            TokenTree::Op(name) => ResOp::try_new(name) == Some(op),
+            _ => false,
         }
     }
 
@@ -283,31 +361,263 @@ impl Parser {
         Ok(result)
     }
 
-    /// Try to parse a keyword expression
-    fn keyword(&mut self, trees: &TokenTrees, keyword: ResIden) -> Result<Spanned<AST>, Syntax> {
+    /// Try to parse a keyword expression,
+    /// starting at the keyword itself.
+    /// Returns `None` if the current token is not a keyword,
+    /// or is a keyword that is not yet parsed specially,
+    /// in which case it is parsed as a bare `Sugar::Keyword`.
+    /// A parsed keyword expression is a single operand,
+    /// so it can be used anywhere an expression can,
+    /// i.e. `x + if c { 1 } else { 2 }`.
+    fn keyword(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Option<Spanned<AST>>, Syntax> {
         use ResIden::*;
-        match keyword {
-            Macro => todo!(),
-            Type => todo!(),
-            Effect => {
-                dbg!(trees);
-                let rest = self.expr(trees, &mut 0, Prec::End);
-
-                dbg!(rest);
-
-                // if rest.len() != 1 {
-                //     return Err(Syntax::error(
-                //         "Expected a single type",
-                //         rest.span,
-                //     ));
-                // }
-
-                todo!()
+        let keyword = match &trees[*trees_idx].item {
+            TokenTree::Iden(iden) => ResIden::try_new(iden),
+            _ => None,
+        };
+
+        let parsed = match keyword {
+            Some(Mod) => self.module(trees, trees_idx)?,
+            Some(Magic) => self.magic(trees, trees_idx)?,
+            Some(If) => self.if_else(trees, trees_idx)?,
+            Some(Match) => self.match_arms(trees, trees_idx)?,
+            Some(Loop) => self.loop_body(trees, trees_idx)?,
+            Some(While) => self.while_loop(trees, trees_idx)?,
+            Some(Break) => self.break_value(trees, trees_idx)?,
+            Some(Continue) => {
+                let span = trees[*trees_idx].span.clone();
+                *trees_idx += 1; // move on from keyword
+                Spanned::new(AST::Base(Base::Continue), span)
+            }
+            Some(Else) => {
+                return Err(Syntax::error(
+                    "Found `else` without a matching `if`",
+                    &trees[*trees_idx].span,
+                ))
+            }
+            // TODO: parse the remaining keywords
+            Some(Macro | Type | Effect) | None => return Ok(None),
+        };
+        Ok(Some(parsed))
+    }
+
+    /// Parses a module, i.e. `mod { ... }`.
+    fn module(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+        if *trees_idx >= trees.len() {
+            return Err(Syntax::error(
+                "Expected a module body after `mod`",
+                &keyword_span,
+            ));
+        }
+        let body = self.expr(trees, trees_idx, Prec::Call.left())?;
+
+        let combined = Span::combine(&keyword_span, &body.span);
+        Ok(Spanned::new(AST::Base(Base::module(body)), combined))
+    }
+
+    /// Parses the operand of a keyword, i.e. the `c` in `if c { ... }`.
+    /// Like a function argument, the operand is a single expression.
+    fn keyword_operand(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+        expected: &str,
+    ) -> Result<Spanned<AST>, Syntax> {
+        if *trees_idx >= trees.len() {
+            return Err(Syntax::error(
+                &format!("Expected {}", expected),
+                &trees[*trees_idx - 1].span,
+            ));
+        }
+        self.expr(trees, trees_idx, Prec::Call.left())
+    }
+
+    /// Parses a conditional, i.e. `if c { 1 } else { 2 }`.
+    /// The `else` branch is optional, and defaults to `()`.
+    /// A conditional is a match on a boolean:
+    /// ```ignore
+    /// match c { True -> { 1 }, False -> { 2 } }
+    /// ```
+    fn if_else(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+        let condition = self.keyword_operand(trees, trees_idx, "a condition after `if`")?;
+        let then = self.keyword_operand(trees, trees_idx, "a block after the condition")?;
+
+        let is_else = matches!(
+            trees.get(*trees_idx).map(|tree| &tree.item),
+            Some(TokenTree::Iden(iden)) if ResIden::try_new(iden) == Some(ResIden::Else)
+        );
+        let otherwise = if is_else {
+            *trees_idx += 1; // move on from `else`
+            self.keyword_operand(trees, trees_idx, "a block after `else`")?
+        } else {
+            Spanned::new(AST::Base(Base::Lit(Lit::Unit)), then.span.clone())
+        };
+
+        let combined = Span::combine(&keyword_span, &otherwise.span);
+        let arm = |value, body: Spanned<AST>| {
+            let pattern = Pattern::Lit(Lit::Boolean(value));
+            (Spanned::new(pattern, body.span.clone()), body)
+        };
+        let arms = vec![arm(true, then), arm(false, otherwise)];
+        Ok(Spanned::new(
+            AST::Base(Base::match_arms(condition, arms)),
+            combined,
+        ))
+    }
+
+    /// Parses a loop, i.e. `loop { ... }`.
+    fn loop_body(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+        let body = self.keyword_operand(trees, trees_idx, "a block after `loop`")?;
+
+        let combined = Span::combine(&keyword_span, &body.span);
+        Ok(Spanned::new(AST::Base(Base::loop_body(body)), combined))
+    }
+
+    /// Parses a while loop, i.e. `while c { ... }`.
+    /// A while loop is a loop that breaks once the condition is false:
+    /// ```ignore
+    /// loop { if c { ... } else { break } }
+    /// ```
+    fn while_loop(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+        let condition = self.keyword_operand(trees, trees_idx, "a condition after `while`")?;
+        let body = self.keyword_operand(trees, trees_idx, "a block after the condition")?;
+
+        let combined = Span::combine(&keyword_span, &body.span);
+        let unit = Spanned::new(AST::Base(Base::Lit(Lit::Unit)), keyword_span.clone());
+        let stop = Spanned::new(AST::Base(Base::break_value(unit)), keyword_span);
+        let arm = |value, body: Spanned<AST>| {
+            let pattern = Pattern::Lit(Lit::Boolean(value));
+            (Spanned::new(pattern, body.span.clone()), body)
+        };
+        let arms = vec![arm(true, body), arm(false, stop)];
+        let check = Spanned::new(
+            AST::Base(Base::match_arms(condition, arms)),
+            combined.clone(),
+        );
+        Ok(Spanned::new(AST::Base(Base::loop_body(check)), combined))
+    }
+
+    /// Parses a break, i.e. `break` or `break x`.
+    /// Without a value, the loop evaluates to `()`.
+    /// The value stops at a comma, so `break` can end an arm of a match,
+    /// i.e. `match x { 0 -> break 1, n -> n }`; use `break (a, b)` to break with a pair.
+    fn break_value(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+
+        let has_value = matches!(
+            trees.get(*trees_idx).map(|tree| &tree.item),
+            Some(tree) if !matches!(tree, TokenTree::Op(_))
+        );
+        let value = if has_value {
+            self.expr(trees, trees_idx, Prec::Pair.left())?
+        } else {
+            Spanned::new(AST::Base(Base::Lit(Lit::Unit)), keyword_span.clone())
+        };
+
+        let combined = Span::combine(&keyword_span, &value.span);
+        Ok(Spanned::new(AST::Base(Base::break_value(value)), combined))
+    }
+
+    /// Parses a match, i.e. `match x { 0 -> 1, n -> n }`.
+    /// Each arm is a lambda, whose pattern is matched against the value.
+    fn match_arms(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+        let value = self.keyword_operand(trees, trees_idx, "a value to match after `match`")?;
+        let body = self.keyword_operand(trees, trees_idx, "the arms of the match")?;
+
+        let lambdas = match body.item {
+            AST::Sugar(Sugar::Clauses(clauses)) => clauses,
+            AST::Base(Base::Block(mut block)) if block.len() == 1 => match block.remove(0).item {
+                AST::Lambda(lambda) => vec![lambda],
+                _ => vec![],
+            },
+            _ => vec![],
+        };
+        if lambdas.is_empty() {
+            return Err(Syntax::error(
+                "Expected the arms of the match, i.e. `{ pattern -> expression, ... }`",
+                &body.span,
+            ));
+        }
+
+        let arms = lambdas
+            .into_iter()
+            .map(|Lambda { arg, body }| (arg, *body))
+            .collect();
+        let combined = Span::combine(&keyword_span, &body.span);
+        Ok(Spanned::new(
+            AST::Base(Base::match_arms(value, arms)),
+            combined,
+        ))
+    }
+
+    /// Parses an FFI call, i.e. `magic "name" arg`.
+    fn magic(&mut self, trees: &TokenTrees, trees_idx: &mut usize) -> Result<Spanned<AST>, Syntax> {
+        let keyword_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from keyword
+
+        let name = match trees.get(*trees_idx).map(|tree| &tree.item) {
+            Some(TokenTree::Lit(Lit::String(name))) => name.clone(),
+            _ => {
+                return Err(Syntax::error(
+                    "Expected the name of an FFI function as a string after `magic`",
+                    &keyword_span,
+                ))
             }
-            If => todo!(),
-            Match => todo!(),
-            Mod => todo!(),
+        };
+        *trees_idx += 1; // move on from name
+
+        if *trees_idx >= trees.len() {
+            return Err(Syntax::error(
+                "Expected an argument to the FFI function",
+                &Span::combine(&keyword_span, &trees[*trees_idx - 1].span),
+            ));
         }
+        let argument = self.expr(trees, trees_idx, Prec::Call.left())?;
+
+        let combined = Span::combine(&keyword_span, &argument.span);
+        Ok(Spanned::new(
+            AST::Base(Base::ffi(&name, argument)),
+            combined,
+        ))
     }
 
     /// Constructs the AST for a literal, such as a number
@@ -350,7 +660,7 @@ impl Parser {
             ));
         };
         Ok(Spanned::new(
-            AST::Base(Base::Symbol(symbol)),
+            AST::Base(Base::Label(symbol)),
             tree.span.clone(),
         ))
     }
@@ -439,6 +749,320 @@ impl Parser {
         Ok(Spanned::new(make_ast(left, right), combined))
     }
 
+    /// Builds a call to a core FFI function with a pair of arguments,
+    /// which is how builtin binary operators are implemented.
+    fn ffi_pair(name: &str, left: Spanned<AST>, right: Spanned<AST>) -> AST {
+        let span = Span::combine(&left.span, &right.span);
+        let pair = Spanned::new(AST::Base(Base::Tuple(vec![left, right])), span);
+        AST::Base(Base::ffi(name, pair))
+    }
+
+    /// Parses a block, i.e. `{ a; b; c }`.
+    /// A block made of a single tuple of lambdas, i.e. `{ 0 -> 1, n -> n }`,
+    /// is instead a function defined by clauses.
+    /// A block whose first line contains a `|`, i.e. `{ r | x = 1 }`,
+    /// is instead a record update.
+    /// A block of fields separated by commas, i.e. `{ x = 1, y = 2 }`,
+    /// is instead a record, see `Parser::record`.
+    fn block(
+        &mut self,
+        trees: &[Spanned<TokenTrees>],
+        span: &Span,
+    ) -> Result<Spanned<AST>, Syntax> {
+        if let Some(update) = self.update(trees, span)? {
+            return Ok(update);
+        }
+        if let Some(record) = self.record(trees, span)? {
+            return Ok(record);
+        }
+
+        let mut expressions = self.expressions(trees)?;
+
+        if let [Spanned {
+            item: AST::Base(Base::Tuple(items)),
+            ..
+        }] = expressions.as_mut_slice()
+        {
+            if items.iter().all(|item| matches!(item.item, AST::Lambda(_))) {
+                let clauses = mem::take(items)
+                    .into_iter()
+                    .filter_map(|item| match item.item {
+                        AST::Lambda(lambda) => Some(lambda),
+                        _ => None,
+                    })
+                    .collect();
+                let clauses = AST::Sugar(Sugar::Clauses(clauses));
+                return Ok(Spanned::new(clauses, span.clone()));
+            }
+        }
+
+        let block = AST::Base(Base::Block(expressions));
+        Ok(Spanned::new(block, span.clone()))
+    }
+
+    /// Parses a record update, i.e. `{ r | x = 1, y = 2 }`.
+    /// Fields are separated by commas or newlines.
+    /// Returns `None` if the block is not a record update.
+    fn update(
+        &mut self,
+        trees: &[Spanned<TokenTrees>],
+        span: &Span,
+    ) -> Result<Option<Spanned<AST>>, Syntax> {
+        let first = match trees.first() {
+            Some(first) => &first.item,
+            None => return Ok(None),
+        };
+        let bar = match first.iter().position(|t| Parser::is_op(t, ResOp::Update)) {
+            Some(bar) => bar,
+            None => return Ok(None),
+        };
+        if bar == 0 {
+            return Err(Syntax::error(
+                "Expected a record to update before `|`",
+                &first[bar].span,
+            ));
+        }
+        let base = self.expr(&first[..bar].to_vec(), &mut 0, Prec::None)?;
+
+        let lines = once(&first[bar + 1..]).chain(trees[1..].iter().map(|l| l.item.as_slice()));
+        let mut fields: Vec<(String, Spanned<AST>)> = vec![];
+        for line in lines {
+            for segment in line.split(|t| Parser::is_op(t, ResOp::Pair)) {
+                if segment.is_empty() {
+                    continue;
+                }
+                let (name, value) = self.field_update(segment, span)?;
+                if fields.iter().any(|(other, _)| *other == name) {
+                    return Err(Syntax::error(
+                        &format!("The field `{}` is updated more than once", name),
+                        &Spanned::build_or(segment, span.clone()),
+                    ));
+                }
+                fields.push((name, value));
+            }
+        }
+
+        if fields.is_empty() {
+            return Err(Syntax::error(
+                "Expected at least one field to update after `|`",
+                &first[bar].span,
+            ));
+        }
+
+        let update = AST::Base(Base::update(base, fields));
+        Ok(Some(Spanned::new(update, span.clone())))
+    }
+
+    /// Parses a record, i.e. `{ x = 1, y = 2 }`.
+    /// Fields are separated by commas or newlines.
+    /// A block is only a record if it contains at least one comma,
+    /// and everything between the commas is a field.
+    /// So a block with a single assignment, i.e. `{ x = 1 }`, is a block,
+    /// which assigns `x` and evaluates to unit;
+    /// a record with a single field needs a trailing comma, i.e. `{ x = 1, }`.
+    /// Returns `None` if the block is not a record.
+    fn record(
+        &mut self,
+        trees: &[Spanned<TokenTrees>],
+        span: &Span,
+    ) -> Result<Option<Spanned<AST>>, Syntax> {
+        let has_comma = trees
+            .iter()
+            .any(|line| line.item.iter().any(|t| Parser::is_op(t, ResOp::Pair)));
+        let segments = trees
+            .iter()
+            .flat_map(|line| line.item.split(|t| Parser::is_op(t, ResOp::Pair)))
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        if !has_comma || segments.is_empty() || !segments.iter().all(|s| Parser::is_field(s)) {
+            return Ok(None);
+        }
+
+        let mut fields: Vec<(String, Spanned<AST>)> = vec![];
+        for segment in segments {
+            let (name, value) = self.field_update(segment, span)?;
+            if fields.iter().any(|(other, _)| *other == name) {
+                return Err(Syntax::error(
+                    &format!("The field `{}` is defined more than once", name),
+                    &Spanned::build_or(segment, span.clone()),
+                ));
+            }
+            fields.push((name, value));
+        }
+
+        let record = AST::Base(Base::Record(fields));
+        Ok(Some(Spanned::new(record, span.clone())))
+    }
+
+    /// Whether some tokens look like a field, i.e. `x = 1`.
+    fn is_field(segment: &[Spanned<TokenTree>]) -> bool {
+        matches!(
+            segment,
+            [Spanned {
+                item: TokenTree::Iden(_),
+                ..
+            }, assign, value @ ..]
+                if Parser::is_op(assign, ResOp::Assign) && !value.is_empty()
+        )
+    }
+
+    /// Parses a single field of a record update, i.e. `x = 1`.
+    fn field_update(
+        &mut self,
+        segment: &[Spanned<TokenTree>],
+        span: &Span,
+    ) -> Result<(String, Spanned<AST>), Syntax> {
+        match segment {
+            [Spanned {
+                item: TokenTree::Iden(name),
+                ..
+            }, assign, value @ ..]
+                if Parser::is_op(assign, ResOp::Assign) && !value.is_empty() =>
+            {
+                let value = self.expr(&value.to_vec(), &mut 0, Prec::None)?;
+                Ok((name.clone(), value))
+            }
+            _ => Err(Syntax::error(
+                "Expected a field to update, i.e. `x = 1`",
+                &Spanned::build_or(segment, span.clone()),
+            )),
+        }
+    }
+
+    /// Parses each of the expressions in a block.
+    fn expressions(&mut self, trees: &[Spanned<TokenTrees>]) -> Result<Vec<Spanned<AST>>, Syntax> {
+        // TODO: instead of expr, use prefix.
+        let mut expressions = vec![];
+        for tree in trees {
+            expressions.push(self.expr(&tree.item, &mut 0, Prec::None)?);
+        }
+        Ok(expressions)
+    }
+
+    /// Parses a parenthesized expression, i.e. `(a + b)`.
+    /// The parentheses are kept as a `Sugar::Group`,
+    /// so that grouped expressions can be told apart from ungrouped ones.
+    /// A builtin operator missing operands, i.e. `(+ 1)`,
+    /// is instead a section, see `Parser::section`.
+    fn group(&mut self, trees: &TokenTrees, span: &Span) -> Result<Spanned<AST>, Syntax> {
+        if let Some(section) = self.section(trees, span)? {
+            return Ok(section);
+        }
+
+        let inner = self.expr(trees, &mut 0, Prec::None)?;
+        Ok(Spanned::new(AST::Sugar(Sugar::group(inner)), span.clone()))
+    }
+
+    /// The core FFI function a builtin binary operator calls, if it is one.
+    fn builtin(tree: &Spanned<TokenTree>) -> Option<&'static str> {
+        let op = match &tree.item {
+            TokenTree::Op(name) => ResOp::try_new(name)?,
+            _ => return None,
+        };
+
+        let name = match op {
+            ResOp::Add => "add",
+            ResOp::Sub => "sub",
+            ResOp::Mul => "mul",
+            ResOp::Div => "div",
+            ResOp::Rem => "rem",
+            ResOp::Pow => "pow",
+            ResOp::Equal => "equal",
+            ResOp::Concat => "concat",
+            _ => return None,
+        };
+        Some(name)
+    }
+
+    /// Parses an operator section,
+    /// which turns a builtin binary operator into a function:
+    /// - `(+)` is `a -> b -> a + b`, taking one operand at a time.
+    /// - `(+ 1)` is `x -> x + 1`, taking its left operand.
+    /// - `(1 +)` is `x -> 1 + x`, taking its right operand.
+    ///
+    /// `(- 1)` is not a section, as a leading `-` is reserved for negation,
+    /// which is not supported yet, so it raises an error.
+    /// Returns `None` if the parenthesized trees are not a section.
+    fn section(&mut self, trees: &TokenTrees, span: &Span) -> Result<Option<Spanned<AST>>, Syntax> {
+        let (first, last) = match (trees.first(), trees.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(None),
+        };
+        let lambda = |arg, body| Spanned::new(AST::Lambda(Lambda::new(arg, body)), span.clone());
+
+        let section = match (Parser::builtin(first), Parser::builtin(last)) {
+            (Some(name), _) if trees.len() == 1 => {
+                let (left, l) = self.operand("#left", &first.span);
+                let (right, r) = self.operand("#right", &first.span);
+                let body = Spanned::new(Parser::ffi_pair(name, l, r), span.clone());
+                lambda(left, lambda(right, body))
+            }
+            (Some(name), _) if name != "sub" => {
+                let right = self.expr(trees, &mut 1, Prec::None)?;
+                let (left, l) = self.operand("#left", &first.span);
+                let body = Spanned::new(Parser::ffi_pair(name, l, right), span.clone());
+                lambda(left, body)
+            }
+            (_, Some(name)) => {
+                let left = self.expr(&trees[..trees.len() - 1].to_vec(), &mut 0, Prec::None)?;
+                let (right, r) = self.operand("#right", &last.span);
+                let body = Spanned::new(Parser::ffi_pair(name, left, r), span.clone());
+                lambda(right, body)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(section))
+    }
+
+    /// Builds the missing operand of a section,
+    /// as the pattern that binds it and the variable that refers to it.
+    /// Operands are named so that they can not clash with any variable in the source.
+    fn operand(
+        &mut self,
+        name: &str,
+        span: &Span,
+    ) -> (Spanned<Pattern<SharedSymbol>>, Spanned<AST>) {
+        let symbol = self.intern_symbol(name);
+        let pattern = Spanned::new(Pattern::Symbol(symbol), span.clone());
+        let variable = Spanned::new(AST::Base(Base::Symbol(symbol)), span.clone());
+        (pattern, variable)
+    }
+
+    /// Parses a list literal, i.e. `[a, b, c]`.
+    /// Lists are built from a tuple of their items by the core `list` function.
+    fn list(&mut self, trees: &TokenTrees, span: &Span) -> Result<Spanned<AST>, Syntax> {
+        let items = if trees.is_empty() {
+            vec![]
+        } else {
+            let has_pair = trees.iter().any(|tree| Parser::is_op(tree, ResOp::Pair));
+            let items = self.expr(trees, &mut 0, Prec::None)?;
+            match items.item {
+                AST::Base(Base::Tuple(items)) if has_pair => items,
+                _ => vec![items],
+            }
+        };
+
+        let tuple = Spanned::new(AST::Base(Base::Tuple(items)), span.clone());
+        let list = AST::Base(Base::ffi("list", tuple));
+        Ok(Spanned::new(list, span.clone()))
+    }
+
+    /// Parses a tuple spread, i.e. the `..b` in `(a, ..b, c)`.
+    /// The spread applies to everything up to the next item.
+    fn spread(
+        &mut self,
+        trees: &TokenTrees,
+        trees_idx: &mut usize,
+    ) -> Result<Spanned<AST>, Syntax> {
+        let op_span = trees[*trees_idx].span.clone();
+        *trees_idx += 1; // move on from operator
+        let tuple = self.expr(trees, trees_idx, Prec::Pair.left())?;
+
+        let combined = Span::combine(&op_span, &tuple.span);
+        Ok(Spanned::new(AST::Base(Base::spread(tuple)), combined))
+    }
+
     /// Parses a lambda definition, associates right.
     fn lambda(
         &mut self,
@@ -446,10 +1070,7 @@ impl Parser {
         trees: &TokenTrees,
         trees_idx: &mut usize,
     ) -> Result<Spanned<AST>, Syntax> {
-        let left_span = left.span.clone();
-        let pattern = left
-            .try_map(Pattern::try_from)
-            .map_err(|e| Syntax::error(&e, &left_span))?;
+        let pattern = Pattern::from_ast(left)?;
         self.binop(pattern, trees, trees_idx, false, ResOp::Lambda, |l, r| {
             AST::Lambda(Lambda::new(l, r))
         })
@@ -462,10 +1083,7 @@ impl Parser {
         trees: &TokenTrees,
         trees_idx: &mut usize,
     ) -> Result<Spanned<AST>, Syntax> {
-        let left_span = left.span.clone();
-        let pattern = left
-            .try_map(Pattern::<SharedSymbol>::try_from)
-            .map_err(|e| Syntax::error(&e, &left_span))?;
+        let pattern = Pattern::from_ast(left)?;
         self.binop(pattern, trees, trees_idx, false, ResOp::Assign, |l, r| {
             AST::Base(Base::assign(l, r))
         })
@@ -477,7 +1095,10 @@ mod tests {
     use proptest::prelude::*;
 
     use super::*;
-    use crate::{common::source::Source, compiler::lex::Lexer};
+    use crate::{
+        common::source::Source,
+        compiler::{lex::Lexer, read::Reader},
+    };
 
     fn test_source(source: &str) {
         let tokens = Lexer::lex(Source::source(source)).unwrap();
@@ -491,6 +1112,26 @@ mod tests {
         // let (_ast, _symbols) = result.unwrap();
     }
 
+    #[test]
+    fn invalid_patterns() {
+        let cases = [
+            ("f x = 3", "function call here", "f x"),
+            ("(a, f x) = (1, 2)", "function call here", "f x"),
+            ("(a, b -> b) -> a", "function can not be used", "b -> b"),
+            ("{ x } = 3", "block can not be used", "{ x }"),
+            ("(a, ..b) = (1, 2)", "spread operator", "..b"),
+            ("(a, mod { b = 1 }) = 3", "Records can not", "mod { b = 1 }"),
+        ];
+
+        for (source, message, offending) in cases {
+            let tokens = Lexer::lex(Source::source(source)).unwrap();
+            let token_tree = Reader::read(tokens).unwrap();
+            let error = Parser::parse(token_tree).unwrap_err();
+            assert!(error.reason.contains(message), "{}", error);
+            assert_eq!(error.notes[0].span.contents(), offending, "{}", source);
+        }
+    }
+
     #[test]
     fn literal() {
         test_source("2")
@@ -532,6 +1173,251 @@ mod tests {
         test_source("effect Write\n")
     }
 
+    #[test]
+    fn spread() {
+        test_source("(1, ..x, 2)")
+    }
+
+    #[test]
+    fn module() {
+        test_source("m = mod { x = 1; y = 2 }")
+    }
+
+    #[test]
+    fn magic() {
+        test_source("magic \"add\" (1, 2)")
+    }
+
+    fn parse_source(source: &str) -> AST {
+        let tokens = Lexer::lex(Source::source(source)).unwrap();
+        let token_tree = Reader::read(tokens).unwrap();
+        let (ast, _) = Parser::parse(token_tree).unwrap();
+        match ast.item {
+            AST::Base(Base::Block(mut block)) => block.remove(0).item,
+            other => panic!("expected a block, found {:?}", other),
+        }
+    }
+
+    fn is_if(ast: &AST) -> bool {
+        match ast {
+            AST::Base(Base::Match(_, arms)) => {
+                arms.len() == 2 && arms[0].0.item == Pattern::Lit(Lit::Boolean(true))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the items of the pair passed to a binary operator.
+    fn operands(ast: AST, op: &str) -> Vec<AST> {
+        match ast {
+            AST::Base(Base::FFI(name, pair)) if name == op => match pair.item {
+                AST::Base(Base::Tuple(items)) => items.into_iter().map(|i| i.item).collect(),
+                other => panic!("expected a pair, found {:?}", other),
+            },
+            other => panic!("expected a call to `{}`, found {:?}", op, other),
+        }
+    }
+
+    #[test]
+    fn grouped() {
+        let grouped = operands(parse_source("(a + b) * c"), "mul");
+        let sum = match &grouped[0] {
+            AST::Sugar(Sugar::Group(sum)) => sum.item.clone(),
+            other => panic!("expected a group, found {:?}", other),
+        };
+        assert_eq!(operands(sum, "add").len(), 2);
+
+        // without parentheses, nothing is grouped
+        let ungrouped = operands(parse_source("a + b * c"), "add");
+        assert!(matches!(ungrouped[0], AST::Base(Base::Symbol(_))));
+        assert!(!matches!(ungrouped[1], AST::Sugar(Sugar::Group(_))));
+
+        // a grouped tuple is not flattened into an enclosing one
+        match parse_source("(a, b), c") {
+            AST::Base(Base::Tuple(items)) => {
+                assert_eq!(items.len(), 2);
+                assert!(matches!(items[0].item, AST::Sugar(Sugar::Group(_))));
+            }
+            other => panic!("expected a tuple, found {:?}", other),
+        }
+    }
+
+    /// Returns the argument and body of a lambda.
+    fn lambda_parts(ast: AST) -> (Pattern<SharedSymbol>, AST) {
+        match ast {
+            AST::Lambda(lambda) => (lambda.arg.item, lambda.body.item),
+            other => panic!("expected a lambda, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sections() {
+        let symbol = |pattern| match pattern {
+            Pattern::Symbol(symbol) => AST::Base(Base::Symbol(symbol)),
+            other => panic!("expected a symbol, found {:?}", other),
+        };
+        let one = AST::Base(Base::Lit(Lit::Integer(1)));
+
+        // both operands are taken, one at a time
+        let (a, body) = lambda_parts(parse_source("(+)"));
+        let (b, body) = lambda_parts(body);
+        assert_ne!(a, b);
+        assert_eq!(operands(body, "add"), vec![symbol(a), symbol(b)]);
+
+        // the left operand is taken
+        let (x, body) = lambda_parts(parse_source("(* 1)"));
+        assert_eq!(operands(body, "mul"), vec![symbol(x), one.clone()]);
+
+        // the right operand is taken
+        let (x, body) = lambda_parts(parse_source("(1 -)"));
+        assert_eq!(operands(body, "sub"), vec![one, symbol(x)]);
+
+        // the rest of a section is parsed as a whole
+        let (_, body) = lambda_parts(parse_source("(++ a ++ b)"));
+        assert!(
+            matches!(&operands(body, "concat")[1], AST::Base(Base::FFI(name, _)) if name == "concat")
+        );
+
+        // operators that are not builtin can not be sectioned
+        assert!(matches!(
+            parse_source("(a |> b)"),
+            AST::Sugar(Sugar::Group(_))
+        ));
+
+        // a leading `-` would be negation, which is an error for now
+        for source in ["(- 2)", "- 2"] {
+            let tokens = Lexer::lex(Source::source(source)).unwrap();
+            let token_tree = Reader::read(tokens).unwrap();
+            let error = Parser::parse(token_tree).unwrap_err();
+            assert!(
+                error.reason.contains("Negation is not supported"),
+                "{}",
+                error
+            );
+            assert_eq!(error.notes[0].span.contents(), "-", "{}", source);
+        }
+    }
+
+    #[test]
+    fn if_operand() {
+        let ast = parse_source("x + if c { 1 } else { 2 }");
+        let pair = match ast {
+            AST::Base(Base::FFI(name, pair)) if name == "add" => pair.item,
+            other => panic!("expected an addition, found {:?}", other),
+        };
+        match pair {
+            AST::Base(Base::Tuple(items)) => assert!(is_if(&items[1].item)),
+            other => panic!("expected a pair, found {:?}", other),
+        }
+
+        // the whole `if` is one operand, so later operators apply to it
+        let ast = parse_source("if c { 1 } else { 2 } * 3");
+        assert!(matches!(ast, AST::Base(Base::FFI(name, _)) if name == "mul"));
+    }
+
+    #[test]
+    fn if_argument() {
+        let ast = parse_source("f if c { 1 } else { 2 } y");
+        let form = match ast {
+            AST::Sugar(Sugar::Form(form)) => form,
+            other => panic!("expected a call, found {:?}", other),
+        };
+        assert_eq!(form.len(), 3);
+        assert!(is_if(&form[1].item));
+    }
+
+    #[test]
+    fn if_without_else() {
+        let ast = parse_source("if c { 1 }");
+        match ast {
+            AST::Base(Base::Match(_, arms)) => {
+                assert_eq!(arms[1].1.item, AST::Base(Base::Lit(Lit::Unit)))
+            }
+            other => panic!("expected a match, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn match_keyword() {
+        let ast = parse_source("1 + match x { 0 -> 1, n -> n }");
+        assert!(matches!(ast, AST::Base(Base::FFI(name, _)) if name == "add"));
+
+        let ast = parse_source("match x { n -> n }");
+        assert!(matches!(ast, AST::Base(Base::Match(_, arms)) if arms.len() == 1));
+    }
+
+    #[test]
+    fn record_update() {
+        let ast = parse_source("{ r | x = 1, y = f 2\nz = 3 }");
+        let (base, fields) = match ast {
+            AST::Base(Base::Update(base, fields)) => (base, fields),
+            other => panic!("expected a record update, found {:?}", other),
+        };
+        assert!(matches!(base.item, AST::Base(Base::Symbol(_))));
+        let names = fields
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["x", "y", "z"]);
+        assert!(matches!(fields[1].1.item, AST::Sugar(Sugar::Form(_))));
+    }
+
+    #[test]
+    fn record_update_errors() {
+        for source in [
+            "{ | x = 1 }",
+            "{ r | }",
+            "{ r | x }",
+            "{ r | x = 1, x = 2 }",
+            "r | x = 1",
+        ] {
+            let tokens = Lexer::lex(Source::source(source)).unwrap();
+            let token_tree = Reader::read(tokens).unwrap();
+            assert!(Parser::parse(token_tree).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn records_and_blocks() {
+        let names = |ast| match ast {
+            AST::Base(Base::Record(fields)) => {
+                fields.into_iter().map(|(name, _)| name).collect::<Vec<_>>()
+            }
+            other => panic!("expected a record, found {:?}", other),
+        };
+        assert_eq!(names(parse_source("{ x = 1, y = f 2 }")), vec!["x", "y"]);
+        assert_eq!(
+            names(parse_source("{\n  x = 1,\n  y = 2,\n}")),
+            vec!["x", "y"]
+        );
+
+        // statements are a block
+        let ast = parse_source("{ x = 1; x }");
+        assert!(matches!(ast, AST::Base(Base::Block(block)) if block.len() == 2));
+        let ast = parse_source("{ x = 1\ny = 2 }");
+        assert!(matches!(ast, AST::Base(Base::Block(block)) if block.len() == 2));
+        // not everything between the commas is a field
+        let ast = parse_source("{ x = 1, 2 }");
+        assert!(matches!(ast, AST::Base(Base::Block(_))));
+
+        // a single field is a block, unless it has a trailing comma
+        let ast = parse_source("{ x = 1 }");
+        assert!(matches!(ast, AST::Base(Base::Block(block)) if block.len() == 1));
+        assert_eq!(names(parse_source("{ x = 1, }")), vec!["x"]);
+
+        let tokens = Lexer::lex(Source::source("{ x = 1, x = 2 }")).unwrap();
+        let token_tree = Reader::read(tokens).unwrap();
+        let error = Parser::parse(token_tree).unwrap_err();
+        assert!(error.reason.contains("defined more than once"));
+    }
+
+    #[test]
+    fn stray_else() {
+        let tokens = Lexer::lex(Source::source("x else { 1 }")).unwrap();
+        let token_tree = Reader::read(tokens).unwrap();
+        assert!(Parser::parse(token_tree).is_err());
+    }
+
     #[test]
     fn test_trailing_comma() {
         test_source("((),)")
@@ -1,7 +1,11 @@
 use crate::{
     common::span::{Span, Spanned},
-    compiler::syntax::{Note, Syntax},
+    compiler::{
+        lex::Lexer,
+        syntax::{Note, Syntax},
+    },
     construct::token::{Delim, Token, TokenTree, TokenTrees, Tokens},
+    error_codes,
 };
 
 pub struct Reader {
@@ -9,42 +13,101 @@ pub struct Reader {
     index: usize,
     // stack of nested groupings
     opening: Vec<Spanned<Delim>>,
+    // unrecognized characters skipped while reading
+    unrecognized: Vec<Spanned<char>>,
+    /// A zero-length span just past the last byte of the source,
+    /// where errors about reaching the end of the source point.
+    eof: Span,
 }
 
+/// How deeply delimiters may be nested.
+pub const MAX_DEPTH: usize = 128;
+
 // TODO: return Token
 
 impl Reader {
     pub fn read(tokens: Spanned<Tokens>) -> Result<Spanned<TokenTree>, Syntax> {
+        let eof = tokens.span.end_point();
         let mut reader = Reader {
             tokens,
             index: 0,
             opening: vec![],
+            unrecognized: vec![],
+            eof,
         };
 
-        let result = reader.block()?;
-
-        // if there are still unclosed delimiters on the opening
-        // stack
-        if !reader.opening.is_empty() {
-            let still_opened = reader.opening.last().unwrap();
-            Err(Syntax::error(
-                &format!("Unclosed opening {}", still_opened.item,),
-                &still_opened.span,
-            ))
-        } else {
-            Ok(result)
+        let result = reader.block();
+
+        // report all unrecognized characters at once,
+        // as they may be the cause of any other error
+        if !reader.unrecognized.is_empty() {
+            return Err(reader.unrecognized_error());
         }
+
+        // any unclosed delimiters are reported when the end is reached
+        result
     }
 
     /// Returns the next token, advancing the lexer by 1.
+    /// Unrecognized characters are recorded and skipped,
+    /// and trivia is ignored.
     fn next_token(&mut self) -> Option<Spanned<Token>> {
-        if self.index < self.tokens.item.len() {
+        while self.index < self.tokens.item.len() {
             let token = &self.tokens.item[self.index];
             self.index += 1;
+
+            if let Token::Trivia(_) = token.item {
+                continue;
+            }
+
+            if let Token::Error(character) = token.item {
+                let spanned = Spanned::new(character, token.span.clone());
+                self.unrecognized.push(spanned);
+                continue;
+            }
+
             // We can clone here because it's not that expensive
-            Some(token.clone())
-        } else {
-            None
+            return Some(token.clone());
+        }
+
+        None
+    }
+
+    /// Builds an error with a note for each unrecognized character.
+    fn unrecognized_error(&mut self) -> Syntax {
+        let mut characters = self.unrecognized.drain(..);
+        let first = characters.next().unwrap();
+
+        let mut error = Syntax::error_with_note(
+            &Lexer::unrecognized(first.item),
+            Note::new_with_hint("not recognized", &first.span),
+        )
+        .with_code(error_codes::UNRECOGNIZED_TOKEN);
+        for character in characters {
+            let hint = format!(
+                "the character `{}` is not recognized either",
+                character.item
+            );
+            error = error.add_note(Note::new_with_hint(&hint, &character.span));
+        }
+
+        error
+    }
+
+    /// Builds an error for reaching the end of the source
+    /// before the innermost group was closed.
+    fn unexpected_eof(&self, parsing: &str) -> Syntax {
+        let reason = format!("Unexpected end of source while parsing {}", parsing);
+        let error = Syntax::error(&reason, &self.eof);
+
+        match self.opening.last() {
+            Some(opening) => error
+                .add_note(Note::new_with_hint(
+                    &format!("unclosed opening {}", opening.item),
+                    &opening.span,
+                ))
+                .with_code(error_codes::UNBALANCED_DELIMITERS),
+            None => error,
         }
     }
 
@@ -70,13 +133,7 @@ impl Reader {
         let entire_span = loop {
             let token = match self.next_token() {
                 Some(t) => t,
-                None => {
-                    // TODO: unwrap to the end of source span?
-                    return Err(Syntax::error(
-                        "Unexpected end of source while parsing form",
-                        &Spanned::build(&tokens).unwrap_or_else(|| self.tokens.span.clone()),
-                    ));
-                }
+                None => return Err(self.unexpected_eof("form")),
             };
 
             let span = token.span;
@@ -108,11 +165,10 @@ impl Reader {
             let token = match self.next_token() {
                 Some(t) => t,
                 // We didn't hit a closing `}`, so this must either be the main
-                // body, or we're missing a closing `}`. The missing closing `}`
-                // is handled by `exit_group`, so we just need to break with a
-                // realistic span.
-                // TODO: get a realistic span!
-                None => break self.tokens.span.clone(),
+                // body, which spans the entire source,
+                // or we're missing a closing `}`.
+                None if self.opening.is_empty() => break self.tokens.span.clone(),
+                None => return Err(self.unexpected_eof("block")),
             };
 
             let span = token.span;
@@ -152,8 +208,7 @@ impl Reader {
             line.push(item);
         };
 
-        if !line.is_empty() {
-            let line_span = Spanned::build(&line).unwrap();
+        if let Some(line_span) = Spanned::build(&line) {
             let spanned_line = Spanned::new(line, line_span);
             lines.push(spanned_line);
         }
@@ -162,6 +217,17 @@ impl Reader {
     }
 
     fn enter_group(&mut self, delim: Spanned<Delim>) -> Result<Spanned<TokenTree>, Syntax> {
+        if self.opening.len() >= MAX_DEPTH {
+            return Err(Syntax::error(
+                &format!(
+                    "Nesting too deep, delimiters can be nested at most {} deep",
+                    MAX_DEPTH
+                ),
+                &delim.span,
+            )
+            .with_code(error_codes::NESTING_TOO_DEEP));
+        }
+
         self.opening.push(delim.clone());
 
         let tree = match delim.item {
@@ -179,21 +245,21 @@ impl Reader {
                 &format!("Unexpected closing {}", closing_delim.item),
                 &closing_delim.span,
             )
+            .with_code(error_codes::UNBALANCED_DELIMITERS)
         })?;
 
         if opening_delim.item == closing_delim.item {
             return Ok(Span::combine(&opening_delim.span, &closing_delim.span));
         }
 
-        let error = Syntax::error(
-            &format!(
-                "Mismatched opening {} and closing {}",
-                closing_delim.item, opening_delim.item,
-            ),
-            &Span::combine(&opening_delim.span, &closing_delim.span),
-        )
+        // each delimiter is underlined on its own, rather than everything between them
+        let error = Syntax::error_no_note(&format!(
+            "Mismatched opening {} and closing {}",
+            opening_delim.item, closing_delim.item,
+        ))
         .add_note(Note::new(opening_delim.span))
-        .add_note(Note::new(closing_delim.span));
+        .add_note(Note::new(closing_delim.span))
+        .with_code(error_codes::UNBALANCED_DELIMITERS);
 
         Err(error)
     }
@@ -223,6 +289,8 @@ mod test {
                 Token::Label(_) => " X ",
                 Token::Op(_) => " + ",
                 Token::Lit(_) => " 2 ",
+                Token::Error(_) => unreachable!("error tokens are never generated"),
+                Token::Trivia(_) => unreachable!("trivia tokens are never generated"),
             };
             buffer.write_str(new).unwrap();
         }
@@ -293,6 +361,17 @@ mod test {
         }
     }
 
+    #[test]
+    fn unrecognized_characters() {
+        let tokens = Lexer::lex_recover(Source::source("x = 1 `\ny = (2, ')")).unwrap();
+        let error = Reader::read(tokens).unwrap_err();
+
+        assert_eq!(error.notes.len(), 2);
+        assert_eq!(error.notes[0].span.contents(), "`");
+        assert_eq!(error.notes[1].span.contents(), "'");
+        assert!(error.reason.contains("`"));
+    }
+
     #[test]
     fn double_open() {
         let tokens = Lexer::lex(Source::source("{{")).unwrap();
@@ -325,6 +404,19 @@ mod test {
         assert!(token_tree.is_err());
     }
 
+    #[test]
+    fn mismatched_delims() {
+        let tokens = Lexer::lex(Source::source("x = (1, 2]")).unwrap();
+        let error = Reader::read(tokens).unwrap_err();
+        assert_eq!(error.notes.len(), 2);
+        assert_eq!(error.notes[0].span.contents(), "(");
+        assert_eq!(error.notes[1].span.contents(), "]");
+        assert!(error.to_string().contains("  |     ^    ^\n"));
+        assert!(error.to_string().ends_with(
+            "Syntax Error[E0001]: Mismatched opening parenthesis and closing square brackets"
+        ));
+    }
+
     #[test]
     fn balanced_delims() {
         let source = Source::source("[(),[],{()[]}]{([][]){}}");
@@ -349,6 +441,40 @@ mod test {
         assert!(token_tree.is_err());
     }
 
+    /// Reads some source, expecting an error at the end of the source.
+    fn read_to_eof(source: &str) -> Syntax {
+        let tokens = Lexer::lex(Source::source(source)).unwrap();
+        let error = Reader::read(tokens).unwrap_err();
+
+        let eof = &error.notes[0].span;
+        assert_eq!((eof.end(), eof.len()), (source.len(), 0));
+        // the error can be shown, even though it points past the source
+        assert!(!error.to_string().is_empty());
+        error
+    }
+
+    #[test]
+    fn unterminated_form_eof() {
+        let error = read_to_eof("x = (1 + 2");
+        assert_eq!(error.reason, "Unexpected end of source while parsing form");
+        assert_eq!(error.notes[1].span.contents(), "(");
+    }
+
+    #[test]
+    fn unterminated_block_eof() {
+        let error = read_to_eof("f = x -> {\n    y = x\n    y\n");
+        assert_eq!(error.reason, "Unexpected end of source while parsing block");
+        assert_eq!(error.notes[1].span.contents(), "{");
+    }
+
+    #[test]
+    fn unterminated_nested_eof() {
+        // the innermost unclosed group is reported
+        let error = read_to_eof("[{ (a, b) [c");
+        assert_eq!(error.notes[1].span.end(), 11);
+        assert_eq!(error.notes[1].span.contents(), "[");
+    }
+
     #[test]
     fn unclosed_closing_paren() {
         let source = Source::source(")");
@@ -1,5 +1,9 @@
-use std::fmt;
+#[cfg(feature = "json")]
+use std::collections::BTreeMap;
+use std::{fmt, rc::Rc};
 
+#[cfg(feature = "json")]
+use crate::common::data::Data;
 use crate::common::span::Span;
 
 /// Represents a note attached to a Syntax error,
@@ -31,6 +35,8 @@ impl Note {
 pub struct Syntax {
     pub reason: String,
     pub notes: Vec<Note>,
+    /// A stable code identifying the kind of error, see `error_codes`.
+    pub code: Option<&'static str>,
 }
 
 impl Syntax {
@@ -51,6 +57,7 @@ impl Syntax {
         Syntax {
             reason: reason.to_string(),
             notes: vec![note],
+            code: None,
         }
     }
 
@@ -61,6 +68,7 @@ impl Syntax {
         Syntax {
             reason: reason.to_string(),
             notes: vec![],
+            code: None,
         }
     }
 
@@ -69,50 +77,231 @@ impl Syntax {
         self.notes.push(note);
         self
     }
+
+    /// Identifies the kind of error with a code from `error_codes`.
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+impl fmt::Display for Note {
+    /// Displays the span of a note, with its hint if it has one.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.is_dummy() {
+            write!(f, "{}", self.span)?;
+            if let Some(ref hint) = self.hint {
+                writeln!(f, "  |- note: {}", hint)?;
+            }
+            return Ok(());
+        }
+
+        let formatted = self.span.format();
+
+        if let Some(ref hint) = self.hint {
+            if formatted.is_multiline() {
+                writeln!(f, "{}", formatted)?;
+                writeln!(f, "{} |- note: {} ", formatted.gutter_padding(), hint)?;
+                writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
+            } else {
+                writeln!(
+                    f,
+                    "In {}:{}:{}",
+                    formatted.path,
+                    formatted.start + 1,
+                    formatted.start_col + 1
+                )?;
+                writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
+                formatted.write_before(f)?;
+                formatted.write_line(f, formatted.start + 1, &formatted.lines[0])?;
+                writeln!(
+                    f,
+                    "{} | {}{} note: {}",
+                    " ".repeat(formatted.gutter_padding()),
+                    " ".repeat(formatted.start_width),
+                    "^".repeat(formatted.carrots().unwrap()),
+                    hint,
+                )?;
+                formatted.write_after(f)?;
+                writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
+            }
+        } else {
+            write!(f, "{}", formatted)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether two notes point into the same line,
+/// so that they can be shown beneath a single copy of that line.
+fn same_line(a: &Note, b: &Note) -> bool {
+    let single = |note: &Note| !note.span.is_dummy() && note.span.lines().len() == 1;
+    single(a)
+        && single(b)
+        && Rc::ptr_eq(a.span.source(), b.span.source())
+        && a.span.line(a.span.offset()) == b.span.line(b.span.offset())
+}
+
+/// Displays several notes on the same line,
+/// writing the line once and underlining each note separately.
+/// Notes without hints share a single row of underlines,
+/// otherwise each note gets its own row, followed by its hint.
+fn write_line_notes(f: &mut fmt::Formatter<'_>, notes: &[&Note]) -> fmt::Result {
+    let formatted = notes[0].span.format();
+    let padding = " ".repeat(formatted.gutter_padding());
+    // each note, as the column it starts at and the number of carrots under it
+    let underlines = notes.iter().map(|note| {
+        let formatted = note.span.format();
+        (formatted.start_width, formatted.carrots().unwrap())
+    });
+
+    writeln!(
+        f,
+        "In {}:{}:{}",
+        formatted.path,
+        formatted.start + 1,
+        formatted.start_col + 1
+    )?;
+    writeln!(f, "{} |", padding)?;
+    formatted.write_before(f)?;
+    formatted.write_line(f, formatted.start + 1, &formatted.lines[0])?;
+
+    if notes.iter().all(|note| note.hint.is_none()) {
+        let mut row = vec![];
+        for (col, carrots) in underlines {
+            if row.len() < col + carrots {
+                row.resize(col + carrots, ' ');
+            }
+            row[col..col + carrots].fill('^');
+        }
+        writeln!(f, "{} | {}", padding, row.into_iter().collect::<String>())?;
+        return formatted.write_after(f);
+    }
+
+    for ((col, carrots), note) in underlines.zip(notes) {
+        write!(
+            f,
+            "{} | {}{}",
+            padding,
+            " ".repeat(col),
+            "^".repeat(carrots)
+        )?;
+        match &note.hint {
+            Some(hint) => writeln!(f, " note: {}", hint)?,
+            None => writeln!(f)?,
+        }
+    }
+    formatted.write_after(f)?;
+    writeln!(f, "{} |", padding)
 }
 
 impl fmt::Display for Syntax {
+    /// Displays each note in the order it appears in the source,
+    /// followed by the reason for the error.
+    /// Notes on the same line are shown together beneath that line.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for note in self.notes.iter() {
-            let formatted = note.span.format();
-
-            if let Some(ref hint) = note.hint {
-                if formatted.is_multiline() {
-                    writeln!(f, "{}", formatted)?;
-                    writeln!(f, "{} |- note: {} ", formatted.gutter_padding(), hint)?;
-                    writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
-                } else {
-                    writeln!(
-                        f,
-                        "In {}:{}:{}",
-                        formatted.path, formatted.start, formatted.start_col
-                    )?;
-                    writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
-                    writeln!(f, "{} | {}", formatted.start + 1, formatted.lines[0])?;
-                    writeln!(
-                        f,
-                        "{} | {}{} note: {}",
-                        " ".repeat(formatted.gutter_padding()),
-                        " ".repeat(formatted.start_col),
-                        "^".repeat(formatted.carrots().unwrap()),
-                        hint,
-                    )?;
-                    writeln!(f, "{} |", " ".repeat(formatted.gutter_padding()))?;
-                }
+        // notes in generated code have no position, so are shown last
+        let mut notes = self.notes.iter().collect::<Vec<_>>();
+        notes.sort_by_key(|note| (note.span.is_dummy(), note.span.path(), note.span.offset()));
+
+        let mut index = 0;
+        while index < notes.len() {
+            let mut end = index + 1;
+            while end < notes.len() && same_line(notes[index], notes[end]) {
+                end += 1;
+            }
+
+            if end - index == 1 {
+                write!(f, "{}", notes[index])?;
             } else {
-                write!(f, "{}", formatted)?;
+                write_line_notes(f, &notes[index..end])?;
             }
+            index = end;
+        }
+        match self.code {
+            Some(code) => write!(f, "Syntax Error[{}]: {}", code, self.reason),
+            None => write!(f, "Syntax Error: {}", self.reason),
         }
-        write!(f, "Syntax Error: {}", self.reason)
+    }
+}
+
+#[cfg(feature = "json")]
+impl Note {
+    /// Converts a note to a record describing its location,
+    /// for use in machine-readable diagnostics.
+    /// Lines and columns start at 1, as when the note is displayed.
+    /// Notes in generated code have no location, so these fields are `null`.
+    pub(crate) fn to_data(&self) -> Data {
+        let mut fields = BTreeMap::new();
+        let hint = match &self.hint {
+            Some(hint) => Data::string(hint.as_str()),
+            None => Data::Unit,
+        };
+        fields.insert("hint".to_string(), hint);
+
+        let span = &self.span;
+        let location = if span.is_dummy() {
+            vec![
+                ("path", Data::Unit),
+                ("start_line", Data::Unit),
+                ("start_col", Data::Unit),
+                ("end_line", Data::Unit),
+                ("end_col", Data::Unit),
+                ("snippet", Data::Unit),
+            ]
+        } else {
+            let position = |index: usize| Data::Integer(index as i64 + 1);
+            vec![
+                ("path", Data::string(span.path())),
+                ("start_line", position(span.line(span.offset()))),
+                ("start_col", position(span.col(span.offset()))),
+                ("end_line", position(span.line(span.end()))),
+                ("end_col", position(span.col(span.end()))),
+                ("snippet", Data::string(span.lines().join("\n"))),
+            ]
+        };
+        for (name, value) in location {
+            fields.insert(name.to_string(), value);
+        }
+
+        Data::record(fields)
+    }
+}
+
+/// The code of an error, or unit if it has none.
+#[cfg(feature = "json")]
+pub(crate) fn code_data(code: Option<&str>) -> Data {
+    code.map_or(Data::Unit, Data::string)
+}
+
+#[cfg(feature = "json")]
+impl Syntax {
+    /// Serializes a static error as a JSON object, e.g. for an editor:
+    /// ```plain
+    /// {"code": "...", "message": "...", "notes": [{"path": "...", ...}], "severity": "error"}
+    /// ```
+    /// The `code` is `null` if the error has no code.
+    /// Each note has a `hint`, which may be `null`,
+    /// and a location, as described by `Note::to_data`.
+    pub fn to_json(&self) -> String {
+        let mut fields = BTreeMap::new();
+        fields.insert("severity".to_string(), Data::string("error"));
+        fields.insert("code".to_string(), code_data(self.code));
+        fields.insert("message".to_string(), Data::string(self.reason.as_str()));
+        let notes = self.notes.iter().map(Note::to_data).collect();
+        fields.insert("notes".to_string(), Data::list(notes));
+
+        Data::record(fields)
+            .to_json()
+            .expect("diagnostics only contain data that can be serialized")
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::rc::Rc;
-
     use super::*;
-    use crate::common::source::Source;
+    use crate::{common::source::Source, error_codes};
 
     #[test]
     fn error() {
@@ -133,4 +322,200 @@ Syntax Error: Unexpected token '"Hello, world!"'"#;
         let result = format!("{}", error);
         assert_eq!(result, target);
     }
+
+    #[test]
+    fn context() {
+        let contents = "a = 1\nb = a +\nc = b\nd = c";
+        let source = Source::source(contents);
+        let offset = contents.find('+').unwrap();
+        let error = Syntax::error("Expected an expression", &Span::new(&source, offset, 1));
+
+        let target = r#"In ./source:2:7
+  |
+1 | a = 1
+2 | b = a +
+  |       ^
+3 | c = b
+Syntax Error: Expected an expression"#;
+
+        assert_eq!(format!("{}", error), target);
+    }
+
+    #[test]
+    fn multiline_context() {
+        let contents = "x = 1\ny = {\n  x\n}\nz = y";
+        let source = Source::source(contents);
+        let offset = contents.find('{').unwrap();
+        let error = Syntax::error("Unexpected block", &Span::new(&source, offset, 7));
+
+        let target = r#"In ./source:2:5
+  |
+1 | x = 1
+2 > y = {
+3 >   x
+4 > }
+5 | z = y
+Syntax Error: Unexpected block"#;
+
+        assert_eq!(format!("{}", error), target);
+    }
+
+    #[test]
+    fn same_line_notes() {
+        let contents = "x = (1, 2]\ny = 3";
+        let source = Source::source(contents);
+        let error = Syntax::error_no_note("Mismatched delimiters")
+            .add_note(Note::new(Span::new(&source, 9, 1)))
+            .add_note(Note::new(Span::new(&source, 4, 1)));
+
+        let target = r#"In ./source:1:5
+  |
+1 | x = (1, 2]
+  |     ^    ^
+2 | y = 3
+Syntax Error: Mismatched delimiters"#;
+
+        assert_eq!(format!("{}", error), target);
+    }
+
+    #[test]
+    fn same_line_hints() {
+        let contents = "f (a, b) c";
+        let source = Source::source(contents);
+        let error = Syntax::error_no_note("Expected a function")
+            .add_note(Note::new_with_hint(
+                "this argument",
+                &Span::new(&source, 9, 1),
+            ))
+            .add_note(Note::new_with_hint("this pair", &Span::new(&source, 2, 6)));
+
+        let target = r#"In ./source:1:3
+  |
+1 | f (a, b) c
+  |   ^^^^^^ note: this pair
+  |          ^ note: this argument
+  |
+Syntax Error: Expected a function"#;
+
+        assert_eq!(format!("{}", error), target);
+    }
+
+    #[test]
+    fn code() {
+        let source = Source::source("x = 1");
+        let error = Syntax::error("Oops", &Span::new(&source, 4, 1));
+        assert!(format!("{}", error).ends_with("\nSyntax Error: Oops"));
+        let error = error.with_code(error_codes::USE_BEFORE_ASSIGNMENT);
+        assert!(format!("{}", error).ends_with("\nSyntax Error[E0101]: Oops"));
+    }
+
+    #[test]
+    fn notes_in_order() {
+        let contents = "a = (\nb = 2\nc = ]";
+        let source = Source::source(contents);
+        let error = Syntax::error_no_note("Mismatched delimiters")
+            .add_note(Note::new(Span::new(&source, 16, 1)))
+            .add_note(Note::new(Span::new(&source, 4, 1)));
+
+        let shown = format!("{}", error);
+        let opening = shown.find("In ./source:1:5").unwrap();
+        let closing = shown.find("In ./source:3:5").unwrap();
+        assert!(opening < closing);
+    }
+
+    #[test]
+    fn point_spans() {
+        let contents = "x = (1, 2\ny = 3\n";
+        let source = Source::source(contents);
+        let point = |offset| format!("{}", Syntax::error("Oops", &Span::point(&source, offset)));
+
+        let middle = r#"In ./source:1:6
+  |
+1 | x = (1, 2
+  |      ^
+2 | y = 3
+Syntax Error: Oops"#;
+        assert_eq!(point(5), middle);
+
+        let start = r#"In ./source:2:1
+  |
+1 | x = (1, 2
+2 | y = 3
+  | ^
+Syntax Error: Oops"#;
+        assert_eq!(point(10), start);
+
+        // the trailing newline is skipped, pointing just after the last line
+        let end = r#"In ./source:2:6
+  |
+1 | x = (1, 2
+2 | y = 3
+  |      ^
+Syntax Error: Oops"#;
+        assert_eq!(point(contents.len()), end);
+
+        let hinted = Syntax::error_no_note("Oops")
+            .add_note(Note::new_with_hint("here", &Span::point(&source, 5)));
+        assert!(format!("{}", hinted).contains("\n  |      ^ note: here\n"));
+    }
+
+    #[test]
+    fn generated() {
+        let error = Syntax::error_with_note(
+            "Expected a function",
+            Note::new_with_hint("in this call", &Span::dummy()),
+        );
+
+        let target = r#"In (generated code)
+  |- note: in this call
+Syntax Error: Expected a function"#;
+
+        assert_eq!(format!("{}", error), target);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let contents = "x = (1,\n  2";
+        let source = Source::source(contents);
+        let error = Syntax::error_with_note(
+            "Unexpected end of source",
+            Note::new_with_hint("unclosed opening `(`", &Span::new(&source, 4, 7)),
+        )
+        .add_note(Note::new(Span::dummy()))
+        .with_code(error_codes::UNBALANCED_DELIMITERS);
+
+        let json = Data::from_json(&error.to_json()).unwrap();
+        let fields = match json {
+            Data::Record(fields) => fields,
+            other => panic!("expected an object, found {}", other.kind_name()),
+        };
+        assert_eq!(fields["severity"], Data::string("error"));
+        assert_eq!(fields["message"], Data::string("Unexpected end of source"));
+        assert_eq!(fields["code"], Data::string("E0001"));
+
+        let notes = match &fields["notes"] {
+            Data::List(notes) => notes,
+            other => panic!("expected an array, found {}", other.kind_name()),
+        };
+        let note = match &notes[0] {
+            Data::Record(note) => note,
+            other => panic!("expected an object, found {}", other.kind_name()),
+        };
+        assert_eq!(note["path"], Data::string("./source"));
+        assert_eq!(note["start_line"], Data::Integer(1));
+        assert_eq!(note["start_col"], Data::Integer(5));
+        assert_eq!(note["end_line"], Data::Integer(2));
+        assert_eq!(note["end_col"], Data::Integer(4));
+        assert_eq!(note["snippet"], Data::string(contents));
+        assert_eq!(note["hint"], Data::string("unclosed opening `(`"));
+
+        // generated code has no location
+        let generated = match &notes[1] {
+            Data::Record(note) => note,
+            other => panic!("expected an object, found {}", other.kind_name()),
+        };
+        assert_eq!(generated.len(), 7);
+        assert!(generated.values().all(|value| *value == Data::Unit));
+    }
 }
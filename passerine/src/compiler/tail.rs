@@ -0,0 +1,205 @@
+use crate::{
+    common::span::Spanned,
+    construct::{
+        symbol::UniqueSymbol,
+        tree::{Base, ScopedLambda, ScopedModule, SST},
+    },
+};
+
+/// Marks which calls in an `SST` are in tail position,
+/// i.e. calls whose result is immediately returned
+/// from the enclosing function.
+/// A tail call does not need a new stack frame,
+/// so this is meant for diagnostics, e.g. warning about recursion that is not tail-recursive.
+/// The compiler does not run it: the VM finds tail calls itself,
+/// as calls directly followed by a return.
+pub struct Tail;
+
+impl Tail {
+    /// Annotates each `Base::Call` in an `SST` with whether it is a tail call.
+    /// The top level of a program is not a function,
+    /// so calls there are never in tail position.
+    pub fn annotate(sst: Spanned<SST>) -> Spanned<SST> {
+        Tail::walk(sst, false)
+    }
+
+    /// Collects each call in an annotated `SST`,
+    /// spanned over the call, paired with whether it is a tail call.
+    /// Calls are listed in the order they appear in the tree.
+    pub fn calls(sst: &Spanned<SST>) -> Vec<Spanned<bool>> {
+        let mut calls = vec![];
+        Tail::collect(sst, &mut calls);
+        calls
+    }
+
+    fn walk(sst: Spanned<SST>, tail: bool) -> Spanned<SST> {
+        let Spanned { item, span } = sst;
+        let item = match item {
+            SST::Base(b) => SST::Base(Tail::walk_base(b, tail)),
+            SST::ScopedLambda(ScopedLambda { arg, body, scope }) => {
+                // the body of a function is always returned
+                let body = Tail::walk(*body, true);
+                SST::ScopedLambda(ScopedLambda::new(arg, body, scope))
+            }
+            SST::ScopedModule(ScopedModule { body, scope }) => {
+                // a module returns a record of its fields, not its body
+                let body = Tail::walk(*body, false);
+                SST::ScopedModule(ScopedModule::new(body, scope))
+            }
+        };
+        return Spanned::new(item, span);
+    }
+
+    fn walk_base(
+        b: Base<Spanned<SST>, UniqueSymbol>,
+        tail: bool,
+    ) -> Base<Spanned<SST>, UniqueSymbol> {
+        match b {
+            Base::Block(block) => {
+                // only the last expression in a block is returned
+                let last = block.len().saturating_sub(1);
+                let block = block
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, child)| Tail::walk(child, tail && index == last))
+                    .collect();
+                Base::Block(block)
+            }
+            Base::Call(fun, arg, _) => Base::Call(
+                Box::new(Tail::walk(*fun, false)),
+                Box::new(Tail::walk(*arg, false)),
+                tail,
+            ),
+            Base::Tuple(t) => Base::Tuple(t.into_iter().map(|i| Tail::walk(i, false)).collect()),
+            Base::Spread(t) => Base::spread(Tail::walk(*t, false)),
+            Base::Module(m) => Base::module(Tail::walk(*m, false)),
+            Base::Assign(p, e) => Base::assign(p, Tail::walk(*e, false)),
+            Base::FFI(n, e) => Base::ffi(&n, Tail::walk(*e, false)),
+            Base::Match(v, arms) => {
+                // the body of the arm that matches is returned
+                let arms = arms
+                    .into_iter()
+                    .map(|(pattern, body)| (pattern, Tail::walk(body, tail)))
+                    .collect();
+                Base::match_arms(Tail::walk(*v, false), arms)
+            }
+            Base::Update(b, fields) => {
+                let fields = fields
+                    .into_iter()
+                    .map(|(name, value)| (name, Tail::walk(value, false)))
+                    .collect();
+                Base::update(Tail::walk(*b, false), fields)
+            }
+            Base::Record(fields) => Base::Record(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, Tail::walk(value, false)))
+                    .collect(),
+            ),
+            // the body of a loop is always followed by the next iteration,
+            // and a break may jump past code that runs after the loop
+            Base::Loop(b) => Base::loop_body(Tail::walk(*b, false)),
+            Base::Break(v) => Base::break_value(Tail::walk(*v, false)),
+            leaf @ (Base::Symbol(_)
+            | Base::Label(_)
+            | Base::Lit(_)
+            | Base::Effect(_)
+            | Base::Continue) => leaf,
+        }
+    }
+
+    fn collect(sst: &Spanned<SST>, calls: &mut Vec<Spanned<bool>>) {
+        match &sst.item {
+            SST::Base(Base::Call(fun, arg, tail)) => {
+                calls.push(Spanned::new(*tail, sst.span.clone()));
+                Tail::collect(fun, calls);
+                Tail::collect(arg, calls);
+            }
+            SST::Base(Base::Block(items)) | SST::Base(Base::Tuple(items)) => {
+                for item in items {
+                    Tail::collect(item, calls);
+                }
+            }
+            SST::Base(Base::Spread(e))
+            | SST::Base(Base::Module(e))
+            | SST::Base(Base::Assign(_, e))
+            | SST::Base(Base::FFI(_, e))
+            | SST::Base(Base::Loop(e))
+            | SST::Base(Base::Break(e)) => Tail::collect(e, calls),
+            SST::Base(Base::Match(value, arms)) => {
+                Tail::collect(value, calls);
+                for (_, body) in arms {
+                    Tail::collect(body, calls);
+                }
+            }
+            SST::Base(Base::Update(base, fields)) => {
+                Tail::collect(base, calls);
+                for (_, value) in fields {
+                    Tail::collect(value, calls);
+                }
+            }
+            SST::Base(Base::Record(fields)) => {
+                for (_, value) in fields {
+                    Tail::collect(value, calls);
+                }
+            }
+            SST::ScopedLambda(ScopedLambda { body, .. })
+            | SST::ScopedModule(ScopedModule { body, .. }) => Tail::collect(body, calls),
+            SST::Base(
+                Base::Symbol(_) | Base::Label(_) | Base::Lit(_) | Base::Effect(_) | Base::Continue,
+            ) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{common::Source, compiler::hoist};
+
+    /// Returns the contents of each call in a source,
+    /// along with whether it is a tail call.
+    fn calls(source: &str) -> Vec<(String, bool)> {
+        let (sst, _) = hoist(Source::source(source)).unwrap();
+        Tail::calls(&Tail::annotate(sst))
+            .into_iter()
+            .map(|call| (call.span.contents(), call.item))
+            .collect()
+    }
+
+    /// Whether the call starting with some source is a tail call.
+    fn is_tail(calls: &[(String, bool)], call: &str) -> bool {
+        calls
+            .iter()
+            .find(|(contents, _)| contents.starts_with(call))
+            .unwrap_or_else(|| panic!("no call `{}` in {:?}", call, calls))
+            .1
+    }
+
+    #[test]
+    fn factorial_not_tail() {
+        let calls = calls("fact = n -> magic \"mul\" (n, fact (magic \"sub\" (n, 1)))");
+        assert!(!is_tail(&calls, "fact ("));
+    }
+
+    #[test]
+    fn accumulator_tail() {
+        let calls = calls(
+            "go = (n, acc) -> {\n    next = magic \"sub\" (n, 1)\n    go (next, magic \"mul\" (n, acc))\n}",
+        );
+        assert!(is_tail(&calls, "go ("));
+    }
+
+    #[test]
+    fn match_arm_tail() {
+        let calls = calls("count = { 0 -> 0, n -> count (n - 1) }");
+        assert!(is_tail(&calls, "count ("));
+    }
+
+    #[test]
+    fn top_level_not_tail() {
+        let calls = calls("f = x -> x\nf (f 1)");
+        assert!(!is_tail(&calls, "f (f"));
+        assert!(!is_tail(&calls, "f 1"));
+    }
+}
@@ -70,6 +70,9 @@ impl<T: Eq + Hash + Clone + Debug> VecSet<T> {
 pub struct Scope {
     pub locals: VecSet<UniqueSymbol>,
     pub nonlocals: VecSet<UniqueSymbol>,
+    /// The names of the locals and nonlocals in this scope,
+    /// as written in the source.
+    pub names: HashMap<UniqueSymbol, String>,
 }
 
 impl Scope {
@@ -77,9 +80,15 @@ impl Scope {
         Scope {
             locals: VecSet::new(),
             nonlocals: VecSet::new(),
+            names: HashMap::new(),
         }
     }
 
+    /// Returns the name of a local or nonlocal in this scope.
+    pub fn name(&self, unique_symbol: UniqueSymbol) -> Option<&str> {
+        self.names.get(&unique_symbol).map(|name| name.as_str())
+    }
+
     pub fn is_local(&self, unique_symbol: UniqueSymbol) -> bool {
         self.locals.contains(&unique_symbol)
     }
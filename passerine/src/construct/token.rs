@@ -35,6 +35,17 @@ pub enum Token {
     Label(String),
     Op(String),
     Lit(Lit),
+
+    /// A character the lexer did not recognize.
+    /// Only produced when lexing with recovery,
+    /// so that every stray character can be reported at once.
+    #[proptest(skip)]
+    Error(char),
+    /// A run of whitespace or a comment, including the leading `#`.
+    /// Only produced when lexing with trivia,
+    /// so that a formatter can put comments back where they were.
+    #[proptest(skip)]
+    Trivia(String),
 }
 
 pub type TokenTrees = Vec<Spanned<TokenTree>>;
@@ -48,6 +59,8 @@ pub enum TokenTree {
     // Grouping
     Block(Vec<Spanned<TokenTrees>>),
     List(TokenTrees),
+    /// Tokens between `(` and `)`.
+    /// The parser keeps these parentheses as a `Sugar::Group`.
     Form(TokenTrees),
 
     // Leafs
@@ -57,6 +70,44 @@ pub enum TokenTree {
     Lit(Lit),
 }
 
+impl TokenTree {
+    /// Returns whether this is a specific operator, e.g. `,`.
+    pub fn is_op(&self, name: &str) -> bool {
+        matches!(self, TokenTree::Op(op) if op == name)
+    }
+
+    /// Returns the trees inside a form, i.e. between `(` and `)`,
+    /// if this is a form.
+    pub fn as_form(&self) -> Option<&TokenTrees> {
+        match self {
+            TokenTree::Form(trees) => Some(trees),
+            _ => None,
+        }
+    }
+}
+
+/// Splits some token trees on each occurrence of an operator,
+/// leaving the operators out,
+/// i.e. splitting `a, b c, d` on `,` gives `a`, `b c`, and `d`.
+/// Like `slice::split`, there is always at least one part,
+/// and operators next to each other leave an empty part between them.
+pub fn split_on_op<'a>(
+    trees: &'a [Spanned<TokenTree>],
+    name: &str,
+) -> Vec<&'a [Spanned<TokenTree>]> {
+    trees.split(|tree| tree.item.is_op(name)).collect()
+}
+
+/// Removes separators, i.e. newlines and `;`, from a stream of tokens,
+/// along with any trivia,
+/// so that the tokens of several lines can be matched at once.
+pub fn strip_seps(tokens: Tokens) -> Tokens {
+    tokens
+        .into_iter()
+        .filter(|token| !matches!(token.item, Token::Sep | Token::Trivia(_)))
+        .collect()
+}
+
 impl Display for TokenTree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // pretty formatting for tokens
@@ -66,6 +117,7 @@ impl Display for TokenTree {
             Block(_) => "tokens grouped by curly brackets".to_string(),
             List(_) => "tokens grouped by square brackets".to_string(),
             Form(_) => "a group of tokens".to_string(),
+            Iden(i) if ResIden::try_new(i).is_some() => format!("keyword `{}`", i),
             Iden(i) => format!("identifier `{}`", i),
             Label(i) => format!("type identifier `{}`", i),
             Op(o) => format!("operator `{}`", o),
@@ -82,24 +134,61 @@ pub enum ResIden {
     Type,
     Effect,
     If,
+    Else,
     Match,
     Mod,
+    Magic,
+    Loop,
+    While,
+    Break,
+    Continue,
 }
 
+/// Every keyword, with how it is spelled.
+/// Keywords are identifiers reserved by the language,
+/// so to add one, add a variant to `ResIden` and a row here.
+const KEYWORDS: &[(&str, ResIden)] = &[
+    ("macro", ResIden::Macro),
+    ("type", ResIden::Type),
+    ("effect", ResIden::Effect),
+    ("if", ResIden::If),
+    ("else", ResIden::Else),
+    ("match", ResIden::Match),
+    ("mod", ResIden::Mod),
+    ("magic", ResIden::Magic),
+    ("loop", ResIden::Loop),
+    ("while", ResIden::While),
+    ("break", ResIden::Break),
+    ("continue", ResIden::Continue),
+];
+
 impl ResIden {
+    /// Returns the keyword an identifier is spelled as, if any.
     pub fn try_new(name: &str) -> Option<ResIden> {
-        use ResIden::*;
-        Some(match name {
-            "macro" => Macro,
-            "type" => Type,
-            "effect" => Effect,
-            "if" => If,
-            "match" => Match,
-            "mod" => Mod,
-            _ => {
-                return None;
-            }
-        })
+        KEYWORDS
+            .iter()
+            .find(|(spelling, _)| *spelling == name)
+            .map(|(_, keyword)| *keyword)
+    }
+
+    /// Returns how a keyword is spelled.
+    pub fn name(&self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|(_, keyword)| keyword == self)
+            .map(|(spelling, _)| *spelling)
+            .expect("Every keyword should have a spelling")
+    }
+
+    /// Returns every keyword, e.g. for highlighting.
+    pub fn all() -> impl Iterator<Item = ResIden> {
+        KEYWORDS.iter().map(|(_, keyword)| *keyword)
+    }
+}
+
+impl Display for ResIden {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
     }
 }
 
@@ -118,6 +207,9 @@ pub enum ResOp {
     Mul,
     Div,
     Rem,
+    Spread,
+    Concat,
+    Update,
 }
 
 impl ResOp {
@@ -137,9 +229,112 @@ impl ResOp {
             "*" => Mul,
             "/" => Div,
             "%" => Rem,
+            ".." => Spread,
+            "++" => Concat,
+            "|" => Update,
             _ => {
                 return None;
             }
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::Source,
+        compiler::{lex::Lexer, read::Reader},
+    };
+
+    /// Reads the first line of some source into token trees.
+    fn line(source: &str) -> TokenTrees {
+        let tokens = Lexer::lex(Source::source(source)).unwrap();
+        match Reader::read(tokens).unwrap().item {
+            TokenTree::Block(mut lines) => lines.remove(0).item,
+            other => panic!("expected a block, found {:?}", other),
+        }
+    }
+
+    fn shown(trees: &[Spanned<TokenTree>]) -> Vec<String> {
+        trees.iter().map(|tree| tree.item.to_string()).collect()
+    }
+
+    #[test]
+    fn split_on_ops() {
+        let trees = line("a, b c, d");
+        let parts = split_on_op(&trees, ",");
+        let parts = parts.into_iter().map(shown).collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![
+                vec!["identifier `a`"],
+                vec!["identifier `b`", "identifier `c`"],
+                vec!["identifier `d`"],
+            ]
+        );
+
+        // without the operator, everything is one part
+        assert_eq!(split_on_op(&trees, "+").len(), 1);
+        // operators next to each other leave an empty part
+        let trees = line("a = = b");
+        assert!(split_on_op(&trees, "=")[1].is_empty());
+    }
+
+    #[test]
+    fn as_form() {
+        let trees = line("f (x, y) z");
+        let form = trees[1].item.as_form().unwrap();
+        assert_eq!(split_on_op(form, ",").len(), 2);
+        assert!(trees[0].item.as_form().is_none());
+        assert!(!trees[0].item.is_op(","));
+        assert!(form[1].item.is_op(","));
+    }
+
+    #[test]
+    fn strip_separators() {
+        let tokens = Lexer::lex_with_trivia(Source::source("a # one\nb; c")).unwrap();
+        assert!(tokens.item.iter().any(|t| t.item == Token::Sep));
+
+        let stripped = strip_seps(tokens.item);
+        let items = stripped.into_iter().map(|t| t.item).collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            vec![
+                Token::Iden("a".to_string()),
+                Token::Iden("b".to_string()),
+                Token::Iden("c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_round_trip() {
+        let keywords = ResIden::all().collect::<Vec<_>>();
+        assert_eq!(keywords.len(), KEYWORDS.len());
+
+        for keyword in keywords {
+            assert_eq!(ResIden::try_new(keyword.name()), Some(keyword));
+            assert_eq!(keyword.to_string(), keyword.name());
+        }
+    }
+
+    #[test]
+    fn not_keywords() {
+        for name in ["x", "If", "iff", "mod_", "", "else if", "Match"] {
+            assert_eq!(ResIden::try_new(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn keyword_display() {
+        assert_eq!(
+            TokenTree::Iden("if".to_string()).to_string(),
+            "keyword `if`"
+        );
+        assert_eq!(
+            TokenTree::Iden("x".to_string()).to_string(),
+            "identifier `x`"
+        );
+    }
+}
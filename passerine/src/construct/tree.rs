@@ -1,7 +1,6 @@
-use std::convert::TryFrom;
-
 use crate::{
     common::{lit::Lit, span::Spanned},
+    compiler::syntax::Syntax,
     construct::{
         scope::Scope,
         symbol::{SharedSymbol, UniqueSymbol},
@@ -15,7 +14,6 @@ pub enum Pattern<S> {
     Lit(Lit),
     Label(Spanned<S>, Box<Spanned<Self>>),
     Tuple(Vec<Spanned<Self>>),
-    Chain(Vec<Spanned<Self>>),
 }
 
 impl<S> Pattern<S> {
@@ -32,34 +30,80 @@ pub enum Base<T, S> {
     Label(S),
     Lit(Lit),
     Tuple(Vec<T>),
+    /// Splices the elements of a tuple into an enclosing tuple,
+    /// i.e. the `..b` in `(a, ..b, c)`.
+    Spread(Box<T>),
     Module(Box<T>),
 
     Block(Vec<T>),
-    Call(Box<T>, Box<T>), // fun, arg
+    /// Calls a function with an argument.
+    /// The flag is whether the call is in tail position,
+    /// and is only set once the tail pass has run.
+    Call(Box<T>, Box<T>, bool), // fun, arg, tail
     Assign(Spanned<Pattern<S>>, Box<T>),
     Effect(S),
+    /// Calls a Rust function by name, i.e. `magic "name" arg`.
+    FFI(String, Box<T>),
+    /// Matches a value against each pattern in turn,
+    /// evaluating the body of the first arm that matches.
+    Match(Box<T>, Vec<(Spanned<Pattern<S>>, T)>), // value, arms
+    /// Copies a record, replacing the values of some of its fields,
+    /// i.e. `{ r | x = 1, y = 2 }`.
+    Update(Box<T>, Vec<(String, T)>), // base, fields
+    /// Builds a record from its fields, i.e. `{ x = 1, y = 2 }`.
+    Record(Vec<(String, T)>),
+    /// Evaluates its body over and over, i.e. `loop { ... }`.
+    /// A loop only finishes when it reaches a `break`,
+    /// so a loop without one never evaluates to anything.
+    Loop(Box<T>),
+    /// Finishes the innermost loop, which evaluates to the value given,
+    /// i.e. `break x`.
+    Break(Box<T>),
+    /// Starts the next iteration of the innermost loop.
+    Continue,
 }
 
 impl<T, S> Base<T, S> {
     pub fn call(fun: T, arg: T) -> Self {
-        Base::Call(Box::new(fun), Box::new(arg))
+        Base::Call(Box::new(fun), Box::new(arg), false)
     }
 
     pub fn assign(pat: Spanned<Pattern<S>>, expr: T) -> Self {
         Base::Assign(pat, Box::new(expr))
     }
 
+    pub fn spread(tuple: T) -> Self {
+        Base::Spread(Box::new(tuple))
+    }
+
     pub fn module(module: T) -> Self {
         Base::Module(Box::new(module))
     }
 
-    // pub fn ffi(name: &str, expr: T) -> Self {
-    //     Base::FFI(name.to_string(), Box::new(expr))
-    // }
+    pub fn ffi(name: &str, expr: T) -> Self {
+        Base::FFI(name.to_string(), Box::new(expr))
+    }
+
+    pub fn match_arms(value: T, arms: Vec<(Spanned<Pattern<S>>, T)>) -> Self {
+        Base::Match(Box::new(value), arms)
+    }
+
+    pub fn update(base: T, fields: Vec<(String, T)>) -> Self {
+        Base::Update(Box::new(base), fields)
+    }
+
+    pub fn loop_body(body: T) -> Self {
+        Base::Loop(Box::new(body))
+    }
+
+    pub fn break_value(value: T) -> Self {
+        Base::Break(Box::new(value))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Sugar<T> {
+    /// An expression written in parentheses, i.e. `(a + b)`.
     Group(Box<T>),
     Form(Vec<T>),
     Keyword(ResIden),
@@ -70,6 +114,9 @@ pub enum Sugar<T> {
     Comp(Box<T>, Box<T>), // arg, function
     Field(Box<T>, Box<T>), /* struct, field
                            * TODO: math operators */
+    /// A function defined by clauses,
+    /// i.e. `{ 0 -> 1, n -> n * fac (n - 1) }`.
+    Clauses(Vec<Lambda<T>>),
 }
 
 impl<T> Sugar<T> {
@@ -112,44 +159,84 @@ pub enum AST {
     Lambda(Lambda<Spanned<AST>>),
 }
 
-impl TryFrom<AST> for Pattern<SharedSymbol> {
-    type Error = String;
-
+impl Pattern<SharedSymbol> {
     /// Tries to convert an `AST` into a `Pattern`.
     /// Patterns mirror the `AST`s they are designed to
     /// destructure. During parsing, they are just
     /// parsed as `AST`s - When the compiler can
     /// determine that an AST is actually a pattern,
     /// It performs this conversion.
-    fn try_from(ast: AST) -> Result<Self, Self::Error> {
-        // if true { todo!("SharedSymbol lookup"); }
-        Ok(match ast {
+    /// If part of the `AST` can not be a pattern,
+    /// the error points at just that part.
+    pub fn from_ast(ast: Spanned<AST>) -> Result<Spanned<Self>, Syntax> {
+        let Spanned { item, span } = ast;
+        let not_pattern = |reason: &str| Err(Syntax::error(reason, &span));
+
+        let pattern = match item {
             AST::Base(Base::Symbol(s)) => Pattern::Symbol(s),
             AST::Base(Base::Lit(d)) => Pattern::Lit(d),
-            AST::Base(Base::Label(k)) => Err(format!(
-                "This Label used in a pattern does not unwrap any data.\n\
-                    To match a Label and ignore its contents, use `{:?} _`",
-                k,
-            ))?,
+            AST::Base(Base::Label(_)) => {
+                return not_pattern(&format!(
+                    "This Label used in a pattern does not unwrap any data.\n\
+                    To match a Label and ignore its contents, use `{} _`",
+                    span.contents(),
+                ))
+            }
             AST::Base(Base::Tuple(t)) => {
                 let mut patterns = vec![];
                 for item in t {
-                    patterns.push(item.try_map(Pattern::try_from)?);
+                    patterns.push(Pattern::from_ast(item)?);
                 }
                 Pattern::Tuple(patterns)
             }
 
-            // AST::Sugar(Sugar::Pattern(p)) => p,
-            AST::Sugar(Sugar::Form(f)) => {
-                let mut patterns = vec![];
-                for item in f {
-                    patterns.push(item.try_map(Pattern::try_from)?);
+            // a label applied to a pattern, i.e. `Some x`
+            AST::Sugar(Sugar::Form(mut f)) if matches!(f[0].item, AST::Base(Base::Label(_))) => {
+                if f.len() != 2 {
+                    return not_pattern(
+                        "A label wraps exactly one pattern.\n\
+                        To match several values, wrap a tuple, i.e. `Pair (a, b)`",
+                    );
                 }
-                Pattern::Chain(patterns)
+                let pattern = Pattern::from_ast(f.pop().unwrap())?;
+                let label = f.pop().unwrap();
+                match label.item {
+                    AST::Base(Base::Label(symbol)) => {
+                        Pattern::label(Spanned::new(symbol, label.span), pattern)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            AST::Sugar(Sugar::Form(_)) | AST::Base(Base::Call(_, _, _)) => {
+                return not_pattern(
+                    "Can not bind to a function call here.\n\
+                    To define a function, assign a lambda, i.e. `f = x -> ...`",
+                )
+            }
+            AST::Base(Base::FFI(_, _)) => {
+                return not_pattern("Can not bind to the result of a `magic` call")
+            }
+            AST::Sugar(Sugar::Group(e)) => Pattern::from_ast(*e)?.item,
+
+            AST::Lambda(_) | AST::Sugar(Sugar::Clauses(_)) => {
+                return not_pattern("A function can not be used as a pattern")
             }
-            AST::Sugar(Sugar::Group(e)) => e.try_map(Pattern::try_from)?.item,
-            _ => Err("Unexpected construct inside pattern")?,
-        })
+            AST::Base(Base::Block(_)) => {
+                return not_pattern("A block can not be used as a pattern")
+            }
+            AST::Base(Base::Module(_) | Base::Update(_, _) | Base::Record(_)) => {
+                return not_pattern("Records can not be used as patterns yet")
+            }
+            AST::Base(Base::Spread(_)) => {
+                return not_pattern("The spread operator `..` can not be used in a pattern")
+            }
+            AST::Base(Base::Assign(_, _)) => {
+                return not_pattern("An assignment can not be used as a pattern")
+            }
+            _ => return not_pattern("Unexpected construct inside pattern"),
+        };
+
+        Ok(Spanned::new(pattern, span))
     }
 }
 
@@ -176,8 +263,42 @@ impl<T> ScopedLambda<T> {
     }
 }
 
+/// A module along with the scope it declares,
+/// The variables declared in this scope are the fields of the module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopedModule<T> {
+    pub body: Box<T>,
+    pub scope: Scope,
+}
+
+impl<T> ScopedModule<T> {
+    pub fn new(tree: T, scope: Scope) -> Self {
+        ScopedModule {
+            body: Box::new(tree),
+            scope,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SST {
     Base(Base<Spanned<SST>, UniqueSymbol>),
     ScopedLambda(ScopedLambda<Spanned<SST>>),
+    ScopedModule(ScopedModule<Spanned<SST>>),
+}
+
+impl SST {
+    /// Whether this evaluates to the `()` left behind by an assignment,
+    /// i.e. it is an assignment or a block ending in one.
+    /// An explicit `()` is not an assignment,
+    /// so can be told apart from one, e.g. when deciding what a REPL prints.
+    pub fn is_assignment(&self) -> bool {
+        match self {
+            SST::Base(Base::Assign(_, _)) => true,
+            SST::Base(Base::Block(block)) => {
+                block.last().is_some_and(|last| last.item.is_assignment())
+            }
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,171 @@
+//! Stable codes identifying each kind of error,
+//! so that an error can be looked up in documentation or matched by tools,
+//! even if the wording of its message changes.
+//!
+//! Codes starting with `E` are static errors, i.e. `Syntax` errors:
+//! - `E00xx` are raised while lexing and reading the source.
+//! - `E01xx` are raised while resolving variables.
+//! - `E02xx` are raised while generating bytecode.
+//!
+//! Codes starting with `R` are runtime errors, i.e. `Trace`s.
+//!
+//! Once a code has been published, it must never be reused for another error.
+
+/// A delimiter was closed without being opened,
+/// closed by the wrong delimiter, or never closed at all.
+pub const UNBALANCED_DELIMITERS: &str = "E0001";
+/// A character that does not start any token.
+pub const UNRECOGNIZED_TOKEN: &str = "E0002";
+/// A string literal that is not closed before the end of the source.
+pub const UNTERMINATED_STRING: &str = "E0003";
+/// An escape code in a string literal that does not exist.
+pub const UNKNOWN_ESCAPE: &str = "E0004";
+/// A number literal that is not written correctly.
+pub const MALFORMED_NUMBER: &str = "E0005";
+/// A number literal that is too large to be represented.
+pub const NUMBER_TOO_LARGE: &str = "E0006";
+
+/// A variable used before it is assigned.
+pub const USE_BEFORE_ASSIGNMENT: &str = "E0101";
+/// Expressions, delimiters, functions or modules nested too deeply.
+pub const NESTING_TOO_DEEP: &str = "E0102";
+/// An assignment to a literal or label outside of a match.
+pub const ASSIGN_TO_CONSTANT: &str = "E0103";
+
+/// A call of something that is known to not be a function.
+pub const NOT_CALLABLE: &str = "E0201";
+/// A `break` or `continue` outside of a loop.
+pub const OUTSIDE_LOOP: &str = "E0202";
+/// A reference to an FFI function that does not exist.
+pub const UNKNOWN_FFI: &str = "E0203";
+/// A spread `..` outside of a tuple.
+pub const MISPLACED_SPREAD: &str = "E0204";
+
+/// A call of data that turned out to not be a function.
+pub const CALL_NON_FUNCTION: &str = "R0001";
+/// Data that does not match the pattern it is destructured by.
+pub const PATTERN_MISMATCH: &str = "R0002";
+/// Data that does not match any arm of a match.
+pub const NO_MATCH: &str = "R0003";
+/// An index past the end of a tuple.
+pub const INDEX_OUT_OF_BOUNDS: &str = "R0004";
+
+/// Every code, with an explanation of the errors it identifies.
+pub const CODES: &[(&str, &str)] = &[
+    (
+        UNBALANCED_DELIMITERS,
+        "Every opening delimiter, i.e. `(`, `[`, or `{`, \
+         must be closed by the matching closing delimiter, i.e. `)`, `]`, or `}`, \
+         and every closing delimiter must close an opening one.",
+    ),
+    (
+        UNRECOGNIZED_TOKEN,
+        "The source contains a character that can not start a token. \
+         Check for stray punctuation, or text pasted from elsewhere.",
+    ),
+    (
+        UNTERMINATED_STRING,
+        "A string literal must be closed by a `\"` before the end of the source.",
+    ),
+    (
+        UNKNOWN_ESCAPE,
+        "Only some characters can follow a backslash in a string literal. \
+         To include a backslash itself, escape it: `\\\\`.",
+    ),
+    (
+        MALFORMED_NUMBER,
+        "A number literal is missing its digits, e.g. `0x` or `1e`, \
+         or uses a form that is not supported, e.g. hexadecimal floats.",
+    ),
+    (
+        NUMBER_TOO_LARGE,
+        "Integers must fit in a signed 64-bit integer, \
+         and floats in a 64-bit floating-point number.",
+    ),
+    (
+        USE_BEFORE_ASSIGNMENT,
+        "A variable must be assigned before it is used. \
+         Functions may capture variables assigned later, \
+         as long as they are not called before the assignment.",
+    ),
+    (
+        NESTING_TOO_DEEP,
+        "Functions and modules can only be nested so deep. \
+         Try splitting deeply nested functions into separate definitions.",
+    ),
+    (
+        ASSIGN_TO_CONSTANT,
+        "A plain assignment can only bind variables. \
+         To compare a value against a literal or label, use `match`.",
+    ),
+    (
+        NOT_CALLABLE,
+        "Only functions can be called, \
+         but this is a literal or other value that is never a function.",
+    ),
+    (
+        OUTSIDE_LOOP,
+        "`break` and `continue` leave the innermost loop, \
+         so can only be used inside a loop, and not inside a function within that loop.",
+    ),
+    (
+        UNKNOWN_FFI,
+        "The program refers to a function provided by the host, \
+         but the host does not provide a function with that name.",
+    ),
+    (
+        MISPLACED_SPREAD,
+        "The spread operator `..` splices the items of a tuple into another tuple, \
+         so can only be used inside a tuple.",
+    ),
+    (
+        CALL_NON_FUNCTION,
+        "Only functions can be called, but the data being called is not a function.",
+    ),
+    (
+        PATTERN_MISMATCH,
+        "The data being destructured does not have the shape of the pattern, \
+         e.g. a literal pattern that is not equal to the data.",
+    ),
+    (
+        NO_MATCH,
+        "None of the arms of a match matched the data. \
+         Add an arm with a variable, e.g. `_ -> ...`, to handle everything else.",
+    ),
+    (
+        INDEX_OUT_OF_BOUNDS,
+        "A tuple was indexed past its last item.",
+    ),
+];
+
+/// Explains the errors identified by a code, if the code exists.
+pub fn explain(code: &str) -> Option<&'static str> {
+    CODES
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn unique_codes() {
+        let codes = CODES.iter().map(|(code, _)| code).collect::<HashSet<_>>();
+        assert_eq!(codes.len(), CODES.len());
+        for (code, _) in CODES {
+            assert!(code.len() == 5 && (code.starts_with('E') || code.starts_with('R')));
+        }
+    }
+
+    #[test]
+    fn explanations() {
+        assert!(explain(USE_BEFORE_ASSIGNMENT)
+            .unwrap()
+            .contains("must be assigned"));
+        assert_eq!(explain("E9999"), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! Core bitwise operations on integers.
+//! Binary operations take a tuple of two integers.
+//! These are registered under the `bits::` namespace,
+//! i.e. `magic "bits::band" (a, b)`.
+
+use crate::common::data::Data;
+
+/// Applies a binary operation to a pair of integers.
+fn binop(data: Data, name: &str, op: fn(i64, i64) -> Result<i64, String>) -> Result<Data, String> {
+    match data {
+        Data::Tuple(t) if t.len() == 2 => match (&t[0], &t[1]) {
+            (Data::Integer(a), Data::Integer(b)) => Ok(Data::Integer(op(*a, *b)?)),
+            (a, b) => Err(format!(
+                "`bits::{}` expects two integers, found '{}' and '{}'",
+                name, a, b
+            )),
+        },
+        other => Err(format!(
+            "`bits::{}` expects a pair of integers, found '{}'",
+            name, other
+        )),
+    }
+}
+
+/// Converts a shift amount, which must be less than the width of an integer.
+fn amount(by: i64) -> Result<u32, String> {
+    match u32::try_from(by) {
+        Ok(by) if by < i64::BITS => Ok(by),
+        _ if by < 0 => Err(format!("Can not shift by a negative amount, {}", by)),
+        _ => Err(format!(
+            "Can not shift a {}-bit integer by {}",
+            i64::BITS,
+            by
+        )),
+    }
+}
+
+pub fn band(data: Data) -> Result<Data, String> {
+    binop(data, "band", |a, b| Ok(a & b))
+}
+
+pub fn bor(data: Data) -> Result<Data, String> {
+    binop(data, "bor", |a, b| Ok(a | b))
+}
+
+pub fn bxor(data: Data) -> Result<Data, String> {
+    binop(data, "bxor", |a, b| Ok(a ^ b))
+}
+
+pub fn bnot(data: Data) -> Result<Data, String> {
+    match data {
+        Data::Integer(a) => Ok(Data::Integer(!a)),
+        other => Err(format!(
+            "`bits::bnot` expects an integer, found '{}'",
+            other
+        )),
+    }
+}
+
+/// Shifts left, discarding the bits shifted out.
+pub fn shl(data: Data) -> Result<Data, String> {
+    binop(data, "shl", |a, by| Ok(a << amount(by)?))
+}
+
+/// Shifts right, keeping the sign of the integer.
+pub fn shr(data: Data) -> Result<Data, String> {
+    binop(data, "shr", |a, by| Ok(a >> amount(by)?))
+}
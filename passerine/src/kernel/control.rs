@@ -0,0 +1,77 @@
+//! Core control flow.
+
+use std::collections::BTreeMap;
+
+use super::Choice;
+use crate::{
+    common::{data::Data, Inject},
+    vm::{fiber::Fiber, trace::Trace},
+};
+
+/// Chooses between two values based on a condition.
+/// Takes a tuple of `(condition, then, otherwise)`.
+pub fn if_choice(data: Data) -> Result<Data, String> {
+    let choice: Choice =
+        Inject::deserialize(data).ok_or("`if` expects a tuple of a boolean and two values")?;
+
+    Ok(if choice.cond {
+        choice.then
+    } else {
+        choice.other
+    })
+}
+
+/// Converts a runtime error to data that can be handled by a program,
+/// i.e. a record of its `kind`, `message`, and `code`,
+/// where the `code` is unit if the error has no code.
+// TODO: use a label, once labels can be constructed outside of the compiler
+pub fn trace_data(trace: &Trace) -> Data {
+    let mut fields = BTreeMap::new();
+    fields.insert("kind".to_string(), Data::string(trace.kind()));
+    fields.insert("message".to_string(), Data::string(trace.message()));
+    let code = trace.code().map(Data::string).unwrap_or(Data::Unit);
+    fields.insert("code".to_string(), code);
+    Data::record(fields)
+}
+
+/// Calls a function with unit, returning what it returns.
+/// If the function raises an error,
+/// the handler is called with the error instead, as described by `trace_data`,
+/// and what the handler returns is returned.
+/// Takes a tuple of `(function, handler)`.
+pub fn try_catch(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    let (function, handler) = match data {
+        Data::Tuple(t) if t.len() == 2 => match (&t[0], &t[1]) {
+            (Data::Closure(f), Data::Closure(h)) => (*f.clone(), *h.clone()),
+            (f, h) => {
+                return Err(format!(
+                    "`try` expects a function and a handler, found {} and {}",
+                    f.kind_name(),
+                    h.kind_name()
+                ))
+            }
+        },
+        other => {
+            return Err(format!(
+                "`try` expects a tuple of a function and a handler, found {}",
+                other.kind_name()
+            ))
+        }
+    };
+
+    match fiber.apply(function, Data::Unit) {
+        Ok(returned) => Ok(returned),
+        Err(trace) => fiber
+            .apply(handler, trace_data(&trace))
+            .map_err(|t| t.to_string()),
+    }
+}
+
+/// Suspends the fiber, handing some data to whatever resumes it,
+/// see `Fiber::resume`.
+/// Returns the value the fiber is resumed with.
+pub fn yield_value(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    fiber.suspend(data);
+    // replaced by the value the fiber is resumed with
+    Ok(Data::Unit)
+}
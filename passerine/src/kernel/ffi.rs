@@ -0,0 +1,289 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{common::data::Data, vm::fiber::Fiber};
+
+/// The signature of a Rust function callable from Passerine.
+type Function = dyn Fn(&mut Fiber, Data) -> Result<Data, String>;
+
+/// The type of some data passed to or returned from an `FFIFunction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// Any data at all.
+    Any,
+    Unit,
+    Boolean,
+    Integer,
+    Float,
+    /// Either an integer or a float.
+    Number,
+    String,
+    Function,
+    Label,
+    List,
+    Record,
+    Map,
+    /// A tuple with items of specific types.
+    Tuple(Vec<Type>),
+}
+
+impl Type {
+    /// Returns the type of some data.
+    pub fn of(data: &Data) -> Type {
+        match data {
+            Data::Unit => Type::Unit,
+            Data::Boolean(_) => Type::Boolean,
+            Data::Integer(_) => Type::Integer,
+            Data::Float(_) => Type::Float,
+            Data::String(_) => Type::String,
+            Data::Lambda(_) | Data::Closure(_) => Type::Function,
+            Data::Kind(_) | Data::Label(_, _) => Type::Label,
+            Data::List(_) => Type::List,
+            Data::Record(_) => Type::Record,
+            Data::Map(_) => Type::Map,
+            Data::Tuple(items) => Type::Tuple(items.iter().map(Type::of).collect()),
+        }
+    }
+
+    /// Whether some data is of this type.
+    pub fn check(&self, data: &Data) -> bool {
+        match (self, data) {
+            (Type::Any, _) => true,
+            (Type::Number, data) => matches!(data, Data::Integer(_) | Data::Float(_)),
+            (Type::Tuple(types), Data::Tuple(items)) => {
+                types.len() == items.len()
+                    && types.iter().zip(items.iter()).all(|(t, i)| t.check(i))
+            }
+            (Type::Tuple(_), _) => false,
+            (expected, data) => *expected == Type::of(data),
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Any => "any",
+            Type::Unit => "()",
+            Type::Boolean => "boolean",
+            Type::Integer => "integer",
+            Type::Float => "float",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Function => "function",
+            Type::Label => "label",
+            Type::List => "list",
+            Type::Record => "record",
+            Type::Map => "map",
+            Type::Tuple(types) => {
+                let types = types.iter().map(Type::to_string).collect::<Vec<_>>();
+                return write!(f, "({})", types.join(", "));
+            }
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The declared types of the arguments and result of an `FFIFunction`.
+/// A function takes a single piece of data,
+/// so several arguments are passed as a tuple,
+/// and no arguments are passed as `()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub args: Vec<Type>,
+    pub returns: Type,
+}
+
+impl Signature {
+    /// The type of the data the function is called with.
+    pub fn arg(&self) -> Type {
+        match self.args.as_slice() {
+            [] => Type::Unit,
+            [arg] => arg.clone(),
+            args => Type::Tuple(args.to_vec()),
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    /// Shows a signature, i.e. `(integer, integer) -> integer`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", self.arg(), self.returns)
+    }
+}
+
+/// A Rust function that can be called from Passerine.
+/// Takes some `Data` and returns `Data` or an error message.
+/// Functions may also call back into the `Fiber` they are called from,
+/// e.g. to apply a Passerine closure.
+///
+/// A function may declare a `Signature`,
+/// in which case its argument and result are checked whenever it is called.
+/// Functions without one must check the data they are given themselves.
+#[derive(Clone)]
+pub struct FFIFunction {
+    function: Rc<Function>,
+    signature: Option<Rc<Signature>>,
+}
+
+impl FFIFunction {
+    /// Wraps a Rust function so that it can be added to an `FFI`.
+    pub fn new(function: Box<dyn Fn(Data) -> Result<Data, String>>) -> FFIFunction {
+        FFIFunction {
+            function: Rc::new(move |_, data| function(data)),
+            signature: None,
+        }
+    }
+
+    /// Wraps a Rust function that needs access to the calling `Fiber`.
+    pub fn with_fiber(function: Box<Function>) -> FFIFunction {
+        FFIFunction {
+            function: Rc::from(function),
+            signature: None,
+        }
+    }
+
+    /// Declares the types of the arguments and result of this function.
+    pub fn with_signature(self, args: Vec<Type>, returns: Type) -> FFIFunction {
+        FFIFunction {
+            signature: Some(Rc::new(Signature { args, returns })),
+            ..self
+        }
+    }
+
+    /// Returns the declared signature of this function, if any.
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_deref()
+    }
+
+    /// Calls the wrapped function with some data,
+    /// checking the data and the result against the signature, if any.
+    #[inline]
+    pub fn call(&self, fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => return (self.function)(fiber, data),
+        };
+
+        let arg = signature.arg();
+        if !arg.check(&data) {
+            return Err(format!(
+                "Expected an argument of type `{}`, but found `{}`",
+                arg,
+                Type::of(&data)
+            ));
+        }
+        let returned = (self.function)(fiber, data)?;
+        if !signature.returns.check(&returned) {
+            return Err(format!(
+                "Expected a result of type `{}`, but the function returned `{}`",
+                signature.returns,
+                Type::of(&returned)
+            ));
+        }
+        Ok(returned)
+    }
+}
+
+impl fmt::Debug for FFIFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.signature {
+            Some(signature) => write!(f, "FFIFunction({})", signature),
+            None => write!(f, "FFIFunction(...)"),
+        }
+    }
+}
+
+impl PartialEq for FFIFunction {
+    fn eq(&self, other: &FFIFunction) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+/// A set of named Rust functions that can be called from Passerine.
+#[derive(Debug, Clone, Default)]
+pub struct FFI(HashMap<String, FFIFunction>);
+
+impl FFI {
+    /// Creates a new empty `FFI`.
+    pub fn new() -> FFI {
+        FFI(HashMap::new())
+    }
+
+    /// Adds a function to the `FFI` under a given name.
+    /// Returns an error if a function with the same name already exists.
+    pub fn add(&mut self, name: &str, function: FFIFunction) -> Result<(), String> {
+        if self.contains(name) {
+            return Err(format!(
+                "The FFI function `{}` has already been defined",
+                name
+            ));
+        }
+
+        self.0.insert(name.to_string(), function);
+        Ok(())
+    }
+
+    /// Looks up a function by name.
+    pub fn get(&self, name: &str) -> Result<FFIFunction, String> {
+        self.0
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("The FFI function `{}` does not exist", name))
+    }
+
+    /// Returns whether a function with a given name exists.
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    /// Returns the names of all functions in the `FFI`, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.0.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    /// Merges another `FFI` into this one.
+    /// Returns an error if both define a function with the same name.
+    pub fn combine(&mut self, other: FFI) -> Result<(), String> {
+        for (name, function) in other.0 {
+            self.add(&name, function)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_types() {
+        let pair = Data::tuple(vec![Data::Integer(1), Data::string("a")]);
+        assert!(Type::Tuple(vec![Type::Integer, Type::String]).check(&pair));
+        assert!(Type::Tuple(vec![Type::Integer, Type::Any]).check(&pair));
+        assert!(Type::Any.check(&pair));
+        assert!(!Type::Tuple(vec![Type::Integer]).check(&pair));
+        assert!(!Type::Tuple(vec![Type::String, Type::Integer]).check(&pair));
+        assert!(!Type::Float.check(&Data::Integer(1)));
+        assert!(Type::Number.check(&Data::Integer(1)));
+        assert!(Type::Number.check(&Data::Float(1.0)));
+        assert!(!Type::Number.check(&Data::string("1")));
+        assert!(Type::Unit.check(&Data::Unit));
+        assert_eq!(Type::of(&pair).to_string(), "(integer, string)");
+    }
+
+    #[test]
+    fn signature_display() {
+        let signature = |args| Signature {
+            args,
+            returns: Type::Integer,
+        };
+        assert_eq!(signature(vec![]).to_string(), "() -> integer");
+        assert_eq!(signature(vec![Type::Float]).to_string(), "float -> integer");
+        assert_eq!(
+            signature(vec![Type::Float, Type::List]).to_string(),
+            "(float, list) -> integer"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Core input and output.
+//! Data is shown using any show functions registered on the `Fiber`.
+//! Input is read from the `Input` of the `Fiber`, standard input by default,
+//! and output is written to its `Output`, standard output by default.
+//! The arguments a program was run with are also kept by the `Fiber`.
+
+use std::fs;
+
+use crate::{common::data::Data, vm::fiber::Fiber};
+
+/// Prints some data, followed by a newline.
+pub fn println(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    let shown = fiber.show(data).map_err(|t| t.to_string())?;
+    fiber.output().write_line(&shown).map_err(write_error)?;
+    Ok(Data::Unit)
+}
+
+/// Prints some data, without a trailing newline.
+pub fn print(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    let shown = fiber.show(data).map_err(|t| t.to_string())?;
+    fiber.output().write(&shown).map_err(write_error)?;
+    Ok(Data::Unit)
+}
+
+/// Prints the full structure of some data, followed by a newline,
+/// e.g. `Label(7, Tuple([Integer(1), Integer(2)]))`,
+/// ignoring any show functions, so that nothing is hidden.
+pub fn debug(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    fiber
+        .output()
+        .write_line(&structure(&data))
+        .map_err(write_error)?;
+    Ok(Data::Unit)
+}
+
+/// Describes the structure of some data, as printed by `debug`.
+/// Functions are described by their name and the names of the variables they capture,
+/// rather than their captured values, which may include the function itself.
+fn structure(data: &Data) -> String {
+    let items = |items: &[Data]| items.iter().map(structure).collect::<Vec<_>>().join(", ");
+    let name = |name: &Option<String>| name.clone().unwrap_or_else(|| "anonymous".to_string());
+
+    match data {
+        Data::Lambda(lambda) => format!("Function({})", name(&lambda.name)),
+        Data::Closure(closure) => format!(
+            "Closure({}, captures: [{}])",
+            name(&closure.lambda.name),
+            closure.lambda.capture_names.join(", ")
+        ),
+        Data::Label(kind, inner) => format!("Label({}, {})", kind, structure(inner)),
+        Data::Tuple(t) => format!("Tuple([{}])", items(t)),
+        Data::List(l) => format!("List([{}])", items(l)),
+        Data::Record(r) => {
+            let fields = r
+                .iter()
+                .map(|(field, value)| format!("{:?}: {}", field, structure(value)))
+                .collect::<Vec<_>>();
+            format!("Record({{{}}})", fields.join(", "))
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+fn write_error(error: std::io::Error) -> String {
+    format!("Could not write output: {}", error)
+}
+
+/// Reads the next line of input, without its trailing newline.
+/// Returns unit once there is no input left.
+/// Takes unit, i.e. `read_line ()`.
+pub fn read_line(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    if data != Data::Unit {
+        return Err(format!(
+            "`read_line` expects unit, found {}",
+            data.kind_name()
+        ));
+    }
+
+    // TODO: return a labeled EOF, once labels can be constructed outside of the compiler
+    match fiber.input().read_line() {
+        Ok(Some(line)) => Ok(Data::string(line)),
+        Ok(None) => Ok(Data::Unit),
+        Err(error) => Err(format!("Could not read a line of input: {}", error)),
+    }
+}
+
+/// Returns the arguments the program was run with, as a list of strings.
+/// Takes unit, i.e. `args ()`.
+pub fn args(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    if data != Data::Unit {
+        return Err(format!("`args` expects unit, found {}", data.kind_name()));
+    }
+
+    let args = fiber.args().iter().cloned().map(Data::string).collect();
+    Ok(Data::list(args))
+}
+
+/// Reads the whole contents of a file, given its path, as a string.
+pub fn read_file(data: Data) -> Result<Data, String> {
+    let path = match data {
+        Data::String(path) => path,
+        other => {
+            return Err(format!(
+                "`read_file` expects a path, found {}",
+                other.kind_name()
+            ))
+        }
+    };
+
+    fs::read_to_string(path.as_str())
+        .map(Data::string)
+        .map_err(|error| format!("Could not read the file '{}': {}", path, error))
+}
+
+/// Converts some data to a string, as it would be printed.
+pub fn to_string(fiber: &mut Fiber, data: Data) -> Result<Data, String> {
+    Ok(Data::string(fiber.show(data).map_err(|t| t.to_string())?))
+}
+
+/// The most digits `to_string_precision` will show after the decimal point.
+const MAX_PRECISION: i64 = 100;
+
+/// Converts a number to a string,
+/// with a fixed number of digits after the decimal point.
+/// Takes a tuple of `(number, digits)`.
+pub fn to_string_precision(data: Data) -> Result<Data, String> {
+    let (number, digits) = match data {
+        Data::Tuple(t) if t.len() == 2 => (t[0].clone(), t[1].clone()),
+        other => {
+            return Err(format!(
+                "`to_string_precision` expects a number and a number of digits, found {}",
+                other.kind_name()
+            ))
+        }
+    };
+
+    let digits = match digits {
+        Data::Integer(d) if (0..=MAX_PRECISION).contains(&d) => d as usize,
+        Data::Integer(d) => {
+            return Err(format!(
+                "`to_string_precision` can show between 0 and {} digits, not {}",
+                MAX_PRECISION, d
+            ))
+        }
+        other => {
+            return Err(format!(
+                "`to_string_precision` expects the number of digits to be an integer, found {}",
+                other.kind_name()
+            ))
+        }
+    };
+
+    let number = match number {
+        Data::Float(n) => n,
+        Data::Integer(n) => n as f64,
+        other => {
+            return Err(format!(
+                "`to_string_precision` expects a number, found {}",
+                other.kind_name()
+            ))
+        }
+    };
+    Ok(Data::string(format!("{:.*}", digits, number)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn precision(number: Data, digits: i64) -> Result<Data, String> {
+        to_string_precision(Data::tuple(vec![number, Data::Integer(digits)]))
+    }
+
+    #[test]
+    fn fixed_precision() {
+        let pi = Data::Float(std::f64::consts::PI);
+        assert_eq!(precision(pi.clone(), 2), Ok(Data::string("3.14")));
+        assert_eq!(precision(pi.clone(), 0), Ok(Data::string("3")));
+        assert_eq!(precision(Data::Float(2.5), 3), Ok(Data::string("2.500")));
+        assert_eq!(precision(Data::Integer(7), 1), Ok(Data::string("7.0")));
+
+        assert!(precision(pi.clone(), -1).is_err());
+        assert!(precision(pi, MAX_PRECISION + 1).is_err());
+        assert!(precision(Data::string("3"), 1).is_err());
+    }
+}
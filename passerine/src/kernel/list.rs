@@ -0,0 +1,110 @@
+//! Core operations on lists, and on collections in general.
+//! Lists are built from tuples, and are operated on as a whole,
+//! so no per-element calls back into Rust are needed.
+
+use crate::common::data::Data;
+
+/// Converts a tuple of items into a list, i.e. `[a, b, c]`.
+pub fn list(data: Data) -> Result<Data, String> {
+    match data {
+        Data::Unit => Ok(Data::list(vec![])),
+        Data::Tuple(items) => Ok(Data::List(items)),
+        other => Err(format!(
+            "`list` expects a tuple of items, found '{}'",
+            other
+        )),
+    }
+}
+
+/// Concatenates two lists, i.e. `a ++ b`.
+pub fn concat(data: Data) -> Result<Data, String> {
+    match data {
+        Data::Tuple(t) if t.len() == 2 => match (&t[0], &t[1]) {
+            (Data::List(a), Data::List(b)) => {
+                let mut joined = Vec::with_capacity(a.len() + b.len());
+                joined.extend_from_slice(a);
+                joined.extend_from_slice(b);
+                Ok(Data::list(joined))
+            }
+            (a, b) => Err(format!(
+                "`concat` expects two lists, found '{}' and '{}'",
+                a, b
+            )),
+        },
+        other => Err(format!(
+            "`concat` expects a pair of lists, found '{}'",
+            other
+        )),
+    }
+}
+
+/// Takes the items of a list from a start index up to,
+/// but not including, an end index.
+/// Takes a tuple of the list, the start index, and the end index.
+pub fn slice(data: Data) -> Result<Data, String> {
+    let (list, start, end) = match &data {
+        Data::Tuple(t) => match t.as_slice() {
+            [Data::List(list), Data::Integer(start), Data::Integer(end)] => (list, *start, *end),
+            _ => return Err(slice_expects(&data)),
+        },
+        _ => return Err(slice_expects(&data)),
+    };
+
+    if start < 0 || end < start || end as usize > list.len() {
+        return Err(format!(
+            "The slice {}..{} is out of range for a list of length {}",
+            start,
+            end,
+            list.len()
+        ));
+    }
+
+    Ok(Data::list(list[start as usize..end as usize].to_vec()))
+}
+
+/// The number of items in a collection,
+/// or `None` if the data is not a collection.
+/// Strings count characters, rather than bytes or graphemes,
+/// see `string` to count those instead,
+/// and records count their fields.
+fn length(data: &Data) -> Option<usize> {
+    match data {
+        Data::String(s) => Some(s.chars().count()),
+        Data::Unit => Some(0),
+        Data::Tuple(items) | Data::List(items) => Some(items.len()),
+        Data::Record(fields) => Some(fields.len()),
+        Data::Map(map) => Some(map.len()),
+        _ => None,
+    }
+}
+
+fn not_collection(name: &str, found: &Data) -> String {
+    format!(
+        "`{}` expects a string, tuple, list, record, or map, found {}",
+        name,
+        found.kind_name()
+    )
+}
+
+/// The number of items in a collection, see `length`.
+pub fn len(data: Data) -> Result<Data, String> {
+    match length(&data) {
+        Some(length) => Ok(Data::Integer(length as i64)),
+        None => Err(not_collection("len", &data)),
+    }
+}
+
+/// Whether a collection has no items, see `length`.
+pub fn is_empty(data: Data) -> Result<Data, String> {
+    match length(&data) {
+        Some(length) => Ok(Data::Boolean(length == 0)),
+        None => Err(not_collection("is_empty", &data)),
+    }
+}
+
+fn slice_expects(found: &Data) -> String {
+    format!(
+        "`slice` expects a list and two integer indices, found '{}'",
+        found
+    )
+}
@@ -1,12 +1,89 @@
+//! Core comparisons between data.
+
+use std::rc::Rc;
+
 use passerine_derive::Effect;
 
 use crate::common::Data;
 
-// TODO: implement equality rather than just deriving PartialEq on Data.
-
 // Rust hit it right on the nose with the difference between equality and
 // partial equality TODO: equality vs partial equality in passerine?
 
+/// Compares two pieces of data, as `==` does.
+/// Data is compared structurally, recursing into tuples, lists, records,
+/// maps, and labels, while functions are compared by identity:
+/// a function is only equal to itself, not to another with the same code.
+/// Floats keep their IEEE semantics, so `NaN` is not equal to itself.
+///
+/// Only data of the same kind can be compared;
+/// comparing, say, an integer with a float or a string is an error.
+/// Tuples and lists of different lengths, records with different fields,
+/// and different labels are never equal, but can still be compared.
+fn equal_data(a: &Data, b: &Data) -> Result<bool, String> {
+    let equal = match (a, b) {
+        (Data::Unit, Data::Unit) => true,
+        (Data::Float(a), Data::Float(b)) => a == b,
+        (Data::Integer(a), Data::Integer(b)) => a == b,
+        (Data::Boolean(a), Data::Boolean(b)) => a == b,
+        (Data::String(a), Data::String(b)) => a == b,
+        (Data::Kind(a), Data::Kind(b)) => a == b,
+        (Data::Lambda(a), Data::Lambda(b)) => Rc::ptr_eq(a, b),
+        // closures compare their lambda and captured variables by identity
+        (Data::Closure(a), Data::Closure(b)) => a == b,
+        (Data::Label(a, x), Data::Label(b, y)) => a == b && equal_data(x, y)?,
+        (Data::Tuple(a), Data::Tuple(b)) | (Data::List(a), Data::List(b)) => {
+            a.len() == b.len() && all_equal(a.iter().zip(b.iter()))?
+        }
+        (Data::Record(a), Data::Record(b)) => {
+            a.keys().eq(b.keys()) && all_equal(a.values().zip(b.values()))?
+        }
+        (Data::Map(a), Data::Map(b)) => {
+            if a.len() != b.len() {
+                return Ok(false);
+            }
+            for (key, x) in a.iter() {
+                match b.get(key) {
+                    Some(y) if equal_data(x, y)? => (),
+                    _ => return Ok(false),
+                }
+            }
+            true
+        }
+        (a, b) => {
+            return Err(format!(
+                "Can not compare {} '{}' with {} '{}'",
+                a.kind_name(),
+                a,
+                b.kind_name(),
+                b
+            ))
+        }
+    };
+    Ok(equal)
+}
+
+/// Whether each pair of data is equal, stopping at the first that is not.
+fn all_equal<'a>(pairs: impl Iterator<Item = (&'a Data, &'a Data)>) -> Result<bool, String> {
+    for (a, b) in pairs {
+        if !equal_data(a, b)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether two pieces of data are equal, see `equal_data`.
+/// Takes a tuple of the two pieces of data.
+pub fn equal(data: Data) -> Result<Data, String> {
+    match data {
+        Data::Tuple(t) if t.len() == 2 => Ok(Data::Boolean(equal_data(&t[0], &t[1])?)),
+        other => Err(format!(
+            "`equal` expects a pair of data to compare, found '{}'",
+            other
+        )),
+    }
+}
+
 #[derive(Effect)]
 pub struct Equal(Data, Data);
 
@@ -0,0 +1,385 @@
+//! Core arithmetic on integers and floats.
+//! Binary operations take a tuple of two numbers of the same type.
+//! Transcendental functions always return floats,
+//! promoting integers they are given.
+
+use crate::common::data::Data;
+
+/// What happens when integer arithmetic overflows an `i64`.
+/// Chosen when building the FFI, see `ffi_core_with_overflow`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wrap around, as two's complement arithmetic does.
+    Wrap,
+    /// Clamp the result to the smallest or largest integer.
+    Saturate,
+    /// Raise an error.
+    #[default]
+    Checked,
+}
+
+/// The checked, wrapping, and saturating forms of an integer operation.
+type IntOp<T> = (
+    fn(i64, T) -> Option<i64>,
+    fn(i64, T) -> i64,
+    fn(i64, T) -> i64,
+);
+
+impl Overflow {
+    /// Applies an integer operation, handling overflow according to this policy.
+    fn apply<T: Copy>(self, name: &str, op: IntOp<T>, a: i64, b: T) -> Result<i64, String> {
+        let (checked, wrapping, saturating) = op;
+        match self {
+            Overflow::Wrap => Ok(wrapping(a, b)),
+            Overflow::Saturate => Ok(saturating(a, b)),
+            Overflow::Checked => {
+                checked(a, b).ok_or_else(|| format!("Integer overflow in `{}`", name))
+            }
+        }
+    }
+    /// Converts the magnitude of a result back to an integer,
+    /// handling overflow according to this policy.
+    fn narrow(self, name: &str, n: u128) -> Result<i64, String> {
+        match self {
+            Overflow::Wrap => Ok(n as i64),
+            Overflow::Saturate => Ok(i64::try_from(n).unwrap_or(i64::MAX)),
+            Overflow::Checked => {
+                i64::try_from(n).map_err(|_| format!("Integer overflow in `{}`", name))
+            }
+        }
+    }
+}
+
+/// Applies a binary operation to a pair of numbers.
+fn binop(
+    data: Data,
+    name: &str,
+    int: impl Fn(i64, i64) -> Result<i64, String>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Data, String> {
+    let (left, right) = match data {
+        Data::Tuple(t) if t.len() == 2 => (t[0].clone(), t[1].clone()),
+        other => {
+            return Err(format!(
+                "`{}` expects a pair of numbers, found '{}'",
+                name, other
+            ))
+        }
+    };
+
+    match (left, right) {
+        (Data::Integer(a), Data::Integer(b)) => Ok(Data::Integer(int(a, b)?)),
+        (Data::Float(a), Data::Float(b)) => Ok(Data::Float(float(a, b))),
+        (a, b) => Err(format!(
+            "`{}` expects two numbers of the same type, found '{}' and '{}'",
+            name, a, b
+        )),
+    }
+}
+
+pub fn add(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<i64> = (i64::checked_add, i64::wrapping_add, i64::saturating_add);
+    binop(
+        data,
+        "add",
+        |a, b| overflow.apply("add", op, a, b),
+        |a, b| a + b,
+    )
+}
+
+pub fn sub(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<i64> = (i64::checked_sub, i64::wrapping_sub, i64::saturating_sub);
+    binop(
+        data,
+        "sub",
+        |a, b| overflow.apply("sub", op, a, b),
+        |a, b| a - b,
+    )
+}
+
+pub fn mul(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<i64> = (i64::checked_mul, i64::wrapping_mul, i64::saturating_mul);
+    binop(
+        data,
+        "mul",
+        |a, b| overflow.apply("mul", op, a, b),
+        |a, b| a * b,
+    )
+}
+
+pub fn div(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<i64> = (i64::checked_div, i64::wrapping_div, i64::saturating_div);
+    binop(
+        data,
+        "div",
+        |a, b| match b {
+            0 => Err("Division by zero".to_string()),
+            _ => overflow.apply("div", op, a, b),
+        },
+        |a, b| a / b,
+    )
+}
+
+pub fn rem(data: Data, overflow: Overflow) -> Result<Data, String> {
+    // the only remainder that overflows, `i64::MIN % -1`, is `0`
+    let op: IntOp<i64> = (i64::checked_rem, i64::wrapping_rem, i64::wrapping_rem);
+    binop(
+        data,
+        "rem",
+        |a, b| match b {
+            0 => Err("Division by zero".to_string()),
+            _ => overflow.apply("rem", op, a, b),
+        },
+        |a, b| a % b,
+    )
+}
+
+pub fn pow(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<u32> = (i64::checked_pow, i64::wrapping_pow, i64::saturating_pow);
+    binop(
+        data,
+        "pow",
+        |a, b| {
+            let b = u32::try_from(b)
+                .map_err(|_| format!("Can not raise an integer to the power {}", b))?;
+            overflow.apply("pow", op, a, b)
+        },
+        f64::powf,
+    )
+}
+
+pub fn neg(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<()> = (
+        |a, _| a.checked_neg(),
+        |a, _| a.wrapping_neg(),
+        |a, _| a.saturating_neg(),
+    );
+    match data {
+        Data::Integer(a) => Ok(Data::Integer(overflow.apply("neg", op, a, ())?)),
+        Data::Float(a) => Ok(Data::Float(-a)),
+        other => Err(format!("`neg` expects a number, found '{}'", other)),
+    }
+}
+
+pub fn abs(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let op: IntOp<()> = (
+        |a, _| a.checked_abs(),
+        |a, _| a.wrapping_abs(),
+        |a, _| a.saturating_abs(),
+    );
+    match data {
+        Data::Integer(a) => Ok(Data::Integer(overflow.apply("abs", op, a, ())?)),
+        Data::Float(a) => Ok(Data::Float(a.abs())),
+        other => Err(format!("`abs` expects a number, found '{}'", other)),
+    }
+}
+
+/// Unpacks a pair of integers.
+fn integer_pair(data: &Data, name: &str) -> Result<(i64, i64), String> {
+    match data {
+        Data::Tuple(t) if t.len() == 2 => match (&t[0], &t[1]) {
+            (Data::Integer(a), Data::Integer(b)) => Ok((*a, *b)),
+            (a, b) => Err(format!(
+                "`{}` expects two integers, found '{}' and '{}'",
+                name, a, b
+            )),
+        },
+        other => Err(format!(
+            "`{}` expects a pair of integers, found '{}'",
+            name, other
+        )),
+    }
+}
+
+fn gcd_unsigned(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// The greatest common divisor of a pair of integers, which is never negative.
+/// The greatest common divisor of `0` and `0` is `0`.
+pub fn gcd(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let (a, b) = integer_pair(&data, "gcd")?;
+    let gcd = gcd_unsigned(a.unsigned_abs(), b.unsigned_abs());
+    Ok(Data::Integer(overflow.narrow("gcd", gcd as u128)?))
+}
+
+/// The least common multiple of a pair of integers, which is never negative.
+/// The least common multiple of `0` and any integer is `0`.
+pub fn lcm(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let (a, b) = integer_pair(&data, "lcm")?;
+    let (a, b) = (a.unsigned_abs(), b.unsigned_abs());
+    let lcm = match gcd_unsigned(a, b) {
+        0 => 0,
+        gcd => (a / gcd) as u128 * b as u128,
+    };
+    Ok(Data::Integer(overflow.narrow("lcm", lcm)?))
+}
+
+pub fn factorial(data: Data, overflow: Overflow) -> Result<Data, String> {
+    let n = match data {
+        Data::Integer(n) if n >= 0 => n,
+        Data::Integer(n) => {
+            return Err(format!(
+                "`factorial` expects a non-negative integer, found '{}'",
+                n
+            ))
+        }
+        other => return Err(format!("`factorial` expects an integer, found '{}'", other)),
+    };
+
+    let op: IntOp<i64> = (i64::checked_mul, i64::wrapping_mul, i64::saturating_mul);
+    let mut product = 1;
+    for k in 2..=n {
+        product = overflow.apply("factorial", op, product, k)?;
+        // the product can not change once it wraps to zero or saturates
+        if product == 0 || product == i64::MAX {
+            break;
+        }
+    }
+    Ok(Data::Integer(product))
+}
+
+/// Converts a number to a float, promoting integers.
+fn to_float(name: &str, data: &Data) -> Result<f64, String> {
+    match data {
+        Data::Integer(a) => Ok(*a as f64),
+        Data::Float(a) => Ok(*a),
+        other => Err(format!("`{}` expects a number, found '{}'", name, other)),
+    }
+}
+
+/// Applies a function to a number as a float.
+fn unary(data: Data, name: &str, function: fn(f64) -> f64) -> Result<Data, String> {
+    Ok(Data::Float(function(to_float(name, &data)?)))
+}
+
+/// Checks that the number given to a logarithm is positive.
+fn positive(name: &str, a: f64) -> Result<f64, String> {
+    if a > 0.0 {
+        Ok(a)
+    } else {
+        Err(format!(
+            "`{}` expects a positive number, found '{}'",
+            name, a
+        ))
+    }
+}
+
+pub fn sqrt(data: Data) -> Result<Data, String> {
+    match to_float("sqrt", &data)? {
+        a if a < 0.0 => Err(format!(
+            "`sqrt` expects a non-negative number, found '{}'",
+            data
+        )),
+        a => Ok(Data::Float(a.sqrt())),
+    }
+}
+
+pub fn sin(data: Data) -> Result<Data, String> {
+    unary(data, "sin", f64::sin)
+}
+
+pub fn cos(data: Data) -> Result<Data, String> {
+    unary(data, "cos", f64::cos)
+}
+
+pub fn tan(data: Data) -> Result<Data, String> {
+    unary(data, "tan", f64::tan)
+}
+
+pub fn exp(data: Data) -> Result<Data, String> {
+    unary(data, "exp", f64::exp)
+}
+
+/// The natural logarithm of a number.
+pub fn ln(data: Data) -> Result<Data, String> {
+    let a = positive("ln", to_float("ln", &data)?)?;
+    Ok(Data::Float(a.ln()))
+}
+
+/// The logarithm of a number to some base.
+/// Takes a tuple of the base and the number.
+pub fn log(data: Data) -> Result<Data, String> {
+    let (base, a) = match &data {
+        Data::Tuple(t) if t.len() == 2 => (to_float("log", &t[0])?, to_float("log", &t[1])?),
+        other => {
+            return Err(format!(
+                "`log` expects a pair of a base and a number, found '{}'",
+                other
+            ))
+        }
+    };
+    if base <= 0.0 || base == 1.0 {
+        return Err(format!(
+            "`log` expects a positive base other than 1, found '{}'",
+            base
+        ));
+    }
+    Ok(Data::Float(positive("log", a)?.log(base)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pair(a: i64, b: i64) -> Data {
+        Data::tuple(vec![Data::Integer(a), Data::Integer(b)])
+    }
+
+    #[test]
+    fn overflow_wrap() {
+        let result = add(pair(i64::MAX, 1), Overflow::Wrap);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+        let result = mul(pair(i64::MAX, 2), Overflow::Wrap);
+        assert_eq!(result, Ok(Data::Integer(-2)));
+        let result = neg(Data::Integer(i64::MIN), Overflow::Wrap);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+    }
+
+    #[test]
+    fn overflow_saturate() {
+        let result = add(pair(i64::MAX, 1), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MAX)));
+        let result = mul(pair(i64::MIN, 2), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+        let result = pow(pair(-2, 63), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+        let result = pow(pair(-2, 65), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+        let result = rem(pair(i64::MIN, -1), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(0)));
+    }
+
+    #[test]
+    fn overflow_checked() {
+        assert_eq!(Overflow::default(), Overflow::Checked);
+        let result = add(pair(i64::MAX, 1), Overflow::Checked);
+        assert_eq!(result, Err("Integer overflow in `add`".to_string()));
+        let result = div(pair(i64::MIN, -1), Overflow::Checked);
+        assert_eq!(result, Err("Integer overflow in `div`".to_string()));
+        // operations that do not overflow are unaffected
+        let result = sub(pair(3, 4), Overflow::Checked);
+        assert_eq!(result, Ok(Data::Integer(-1)));
+        let result = abs(Data::Integer(i64::MIN), Overflow::Checked);
+        assert_eq!(result, Err("Integer overflow in `abs`".to_string()));
+        let result = factorial(Data::Integer(21), Overflow::Checked);
+        assert_eq!(result, Err("Integer overflow in `factorial`".to_string()));
+        let result = gcd(pair(i64::MIN, 0), Overflow::Checked);
+        assert_eq!(result, Err("Integer overflow in `gcd`".to_string()));
+    }
+
+    #[test]
+    fn overflow_integer_functions() {
+        let result = factorial(Data::Integer(i64::MAX), Overflow::Wrap);
+        assert_eq!(result, Ok(Data::Integer(0)));
+        let result = factorial(Data::Integer(i64::MAX), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MAX)));
+        let result = lcm(pair(i64::MAX, i64::MAX - 1), Overflow::Saturate);
+        assert_eq!(result, Ok(Data::Integer(i64::MAX)));
+        let result = gcd(pair(i64::MIN, 0), Overflow::Wrap);
+        assert_eq!(result, Ok(Data::Integer(i64::MIN)));
+    }
+}
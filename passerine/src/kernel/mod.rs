@@ -1,13 +1,132 @@
 //! This module provides the standard/core language library
 //! And compiler-magic FFI bindings.
 
-// pub mod io;
-// pub mod control;
-// pub mod logic;
+pub mod bits;
+pub mod control;
+pub mod ffi;
+pub mod io;
+pub mod list;
+pub mod logic;
+pub mod math;
+pub mod reflect;
+pub mod string;
 
 use passerine_derive::Effect;
 
-use crate::common::data::Data;
+pub use ffi::{FFIFunction, Signature, Type, FFI};
+pub use math::Overflow;
+
+use crate::{common::data::Data, vm::fiber::Fiber};
+
+/// Builds an `FFI` containing the core functions.
+/// Integer arithmetic raises an error when it overflows.
+pub fn ffi_core() -> FFI {
+    ffi_core_with_overflow(Overflow::default())
+}
+
+/// Builds an `FFI` containing the core functions,
+/// where integer arithmetic handles overflow according to a specific policy.
+/// Every core function declares its signature.
+/// Functions that take several values take them as a single tuple,
+/// i.e. `gcd (12, 18)`, apart from `slice`, which is curried.
+pub fn ffi_core_with_overflow(overflow: Overflow) -> FFI {
+    use Type::{Any, Boolean, Float, Function, Integer, List, Number, Tuple, Unit};
+    let pair = |item: Type| Tuple(vec![item.clone(), item]);
+
+    let mut ffi = FFI::new();
+    let mut add = |name, function: fn(Data, Overflow) -> Result<Data, String>, arg, returns| {
+        let function = move |data| function(data, overflow);
+        let function = FFIFunction::new(Box::new(function)).with_signature(vec![arg], returns);
+        ffi.add(name, function)
+            .expect("Core FFI functions should have unique names")
+    };
+
+    add("add", math::add, pair(Number), Number);
+    add("sub", math::sub, pair(Number), Number);
+    add("mul", math::mul, pair(Number), Number);
+    add("div", math::div, pair(Number), Number);
+    add("rem", math::rem, pair(Number), Number);
+    add("pow", math::pow, pair(Number), Number);
+    add("neg", math::neg, Number, Number);
+    add("abs", math::abs, Number, Number);
+    add("gcd", math::gcd, pair(Integer), Integer);
+    add("lcm", math::lcm, pair(Integer), Integer);
+    add("factorial", math::factorial, Integer, Integer);
+
+    let mut add = |name, function: fn(Data) -> Result<Data, String>, arg, returns| {
+        let function = FFIFunction::new(Box::new(function)).with_signature(vec![arg], returns);
+        ffi.add(name, function)
+            .expect("Core FFI functions should have unique names")
+    };
+
+    add("sqrt", math::sqrt, Number, Float);
+    add("sin", math::sin, Number, Float);
+    add("cos", math::cos, Number, Float);
+    add("tan", math::tan, Number, Float);
+    add("ln", math::ln, Number, Float);
+    add("log", math::log, pair(Number), Float);
+    add("exp", math::exp, Number, Float);
+    add("equal", logic::equal, pair(Any), Boolean);
+    add(
+        "if",
+        control::if_choice,
+        Tuple(vec![Boolean, Any, Any]),
+        Any,
+    );
+    // a list can be built from a tuple of any length
+    add("list", list::list, Any, List);
+    add("concat", list::concat, pair(List), List);
+    add("len", list::len, Any, Integer);
+    add("is_empty", list::is_empty, Any, Boolean);
+    add("bits::band", bits::band, pair(Integer), Integer);
+    add("bits::bor", bits::bor, pair(Integer), Integer);
+    add("bits::bxor", bits::bxor, pair(Integer), Integer);
+    add("bits::bnot", bits::bnot, Integer, Integer);
+    add("bits::shl", bits::shl, pair(Integer), Integer);
+    add("bits::shr", bits::shr, pair(Integer), Integer);
+    add(
+        "to_string_precision",
+        io::to_string_precision,
+        Tuple(vec![Number, Integer]),
+        Type::String,
+    );
+    add("read_file", io::read_file, Type::String, Type::String);
+    add("type_of", reflect::type_of, Any, Type::String);
+    add("str_bytes", string::str_bytes, Type::String, Integer);
+    add("str_chars", string::str_chars, Type::String, Integer);
+    #[cfg(feature = "graphemes")]
+    add(
+        "str_graphemes",
+        string::str_graphemes,
+        Type::String,
+        Integer,
+    );
+
+    let mut add = |name, function: fn(&mut Fiber, Data) -> Result<Data, String>, arg, returns| {
+        let function =
+            FFIFunction::with_fiber(Box::new(function)).with_signature(vec![arg], returns);
+        ffi.add(name, function)
+            .expect("Core FFI functions should have unique names")
+    };
+
+    add("println", io::println, Any, Unit);
+    add("print", io::print, Any, Unit);
+    add("debug", io::debug, Any, Unit);
+    add("to_string", io::to_string, Any, Type::String);
+    // returns unit once there is no input left
+    add("read_line", io::read_line, Unit, Any);
+    add("args", io::args, Unit, List);
+    add("try", control::try_catch, pair(Function), Any);
+    // returns whatever the fiber is resumed with
+    add("yield", control::yield_value, Any, Any);
+
+    let slice =
+        FFIFunction::new(Box::new(list::slice)).with_signature(vec![List, Integer, Integer], List);
+    ffi.add("slice", slice)
+        .expect("Core FFI functions should have unique names");
+
+    ffi
+}
 
 #[derive(Effect)]
 pub struct Write(Data);
@@ -24,3 +143,196 @@ pub struct Choice {
     then: Data,
     other: Data,
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::common::{Inject, Source};
+
+    #[derive(Effect, Debug, Clone, PartialEq)]
+    struct Pair<T> {
+        a: T,
+        b: T,
+    }
+
+    #[derive(Effect, Debug, Clone, PartialEq)]
+    struct Tagged<K, V>(K, V)
+    where
+        K: Clone;
+
+    #[derive(Effect, Debug, Clone, PartialEq)]
+    struct Cached {
+        value: i64,
+        #[passerine(skip)]
+        hits: usize,
+        name: String,
+    }
+
+    #[derive(Effect, Debug, Clone, PartialEq)]
+    #[passerine(record)]
+    struct Point {
+        #[passerine(rename = "horizontal")]
+        x: f64,
+        y: f64,
+        #[passerine(skip)]
+        label: Option<String>,
+    }
+
+    #[derive(Effect, Debug, Clone, PartialEq)]
+    struct Wrapper(#[passerine(skip)] Vec<bool>, i64);
+
+    /// Converts an item to data and back again.
+    fn round_trip<T: Inject + Clone>(item: &T) -> Option<T> {
+        T::deserialize(T::serialize(item.clone()))
+    }
+
+    #[test]
+    fn derive_generic() {
+        let pair = Pair { a: 1i64, b: 2 };
+        assert_eq!(
+            Inject::serialize(pair.clone()),
+            Data::tuple(vec![Data::Integer(1), Data::Integer(2)])
+        );
+        assert_eq!(round_trip(&pair), Some(pair));
+
+        // type parameters can be nested
+        let nested = Pair {
+            a: Pair { a: 1.0, b: 2.0 },
+            b: Pair { a: 3.0, b: 4.0 },
+        };
+        assert_eq!(round_trip(&nested), Some(nested));
+
+        // data of the wrong shape is rejected
+        let wrong = Data::tuple(vec![Data::Integer(1), Data::Boolean(true)]);
+        assert_eq!(<Pair<i64>>::deserialize(wrong), None);
+    }
+
+    #[test]
+    fn derive_generic_two_params() {
+        let tagged = Tagged("name".to_string(), Pair { a: true, b: false });
+        assert_eq!(
+            Inject::serialize(tagged.clone()),
+            Data::tuple(vec![
+                Data::string("name"),
+                Data::tuple(vec![Data::Boolean(true), Data::Boolean(false)]),
+            ])
+        );
+        assert_eq!(round_trip(&tagged), Some(tagged));
+    }
+
+    #[test]
+    fn derive_skip() {
+        let cached = Cached {
+            value: 3,
+            hits: 7,
+            name: "three".to_string(),
+        };
+        let data = Inject::serialize(cached.clone());
+        assert_eq!(
+            data,
+            Data::tuple(vec![Data::Integer(3), Data::string("three")])
+        );
+        // skipped fields are defaulted
+        let restored: Cached = Inject::deserialize(data).unwrap();
+        assert_eq!(restored, Cached { hits: 0, ..cached });
+
+        let wrapper = Wrapper(vec![true], 5);
+        assert_eq!(
+            Inject::serialize(wrapper),
+            Data::tuple(vec![Data::Integer(5)])
+        );
+        assert_eq!(round_trip(&Wrapper(vec![], 5)), Some(Wrapper(vec![], 5)));
+    }
+
+    #[test]
+    fn derive_rename() {
+        let point = Point {
+            x: 1.0,
+            y: 2.0,
+            label: Some("origin".to_string()),
+        };
+        let data = Inject::serialize(point.clone());
+        assert_eq!(format!("{}", data), "{horizontal = 1.0; y = 2.0}");
+
+        let restored: Point = Inject::deserialize(data).unwrap();
+        assert_eq!(
+            restored,
+            Point {
+                label: None,
+                ..point
+            }
+        );
+
+        // records missing a field or with extra fields are rejected
+        let mut fields = BTreeMap::new();
+        fields.insert("x".to_string(), Data::Float(1.0));
+        fields.insert("y".to_string(), Data::Float(2.0));
+        assert_eq!(<Point as Inject>::deserialize(Data::record(fields)), None);
+    }
+
+    #[test]
+    fn core_names() {
+        let ffi = ffi_core();
+        let names = ffi.names();
+
+        for name in ["add", "println", "if"] {
+            assert!(ffi.contains(name));
+            assert!(names.contains(&name));
+        }
+        assert!(!ffi.contains("nonexistent"));
+        let graphemes = cfg!(feature = "graphemes") as usize;
+        assert_eq!(names.len(), 44 + graphemes);
+    }
+
+    #[test]
+    fn core_calls() {
+        let ffi = ffi_core();
+        let closure = crate::compile(Source::source("()")).unwrap();
+        let fiber = &mut Fiber::init(closure);
+
+        let pair = Data::tuple(vec![Data::Integer(2), Data::Integer(3)]);
+        assert_eq!(
+            ffi.get("add").unwrap().call(fiber, pair),
+            Ok(Data::Integer(5))
+        );
+
+        let choice = Data::tuple(vec![
+            Data::Boolean(false),
+            Data::Integer(1),
+            Data::Integer(2),
+        ]);
+        assert_eq!(
+            ffi.get("if").unwrap().call(fiber, choice),
+            Ok(Data::Integer(2))
+        );
+    }
+
+    #[test]
+    fn core_signatures() {
+        let ffi = ffi_core();
+        for name in ffi.names() {
+            let function = ffi.get(name).unwrap();
+            assert!(
+                function.signature().is_some(),
+                "`{}` has no signature",
+                name
+            );
+        }
+
+        let signature = |name| ffi.get(name).unwrap().signature().unwrap().to_string();
+        assert_eq!(signature("add"), "(number, number) -> number");
+        assert_eq!(signature("gcd"), "(integer, integer) -> integer");
+        assert_eq!(signature("slice"), "(list, integer, integer) -> list");
+        assert_eq!(signature("args"), "() -> list");
+    }
+
+    #[test]
+    fn duplicate_name() {
+        let mut ffi = ffi_core();
+        let sub = |data| math::sub(data, Overflow::Checked);
+        let error = ffi.add("add", FFIFunction::new(Box::new(sub)));
+        assert!(error.is_err());
+    }
+}
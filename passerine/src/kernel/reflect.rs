@@ -0,0 +1,10 @@
+//! Core reflection, i.e. inspecting data at runtime.
+
+use crate::common::data::Data;
+
+/// Names the type of some data, e.g. `"Integer"` or `"Closure"`,
+/// for use in generic code and when debugging.
+/// Each variant of `Data` has its own name.
+pub fn type_of(data: Data) -> Result<Data, String> {
+    Ok(Data::string(data.type_name()))
+}
@@ -0,0 +1,77 @@
+//! Core string functions.
+//! The length of a string depends on what is counted:
+//! `"café"` is 5 bytes, but 4 characters,
+//! and a single emoji may be several characters, but one grapheme.
+//! `len` counts characters; these functions make the choice explicit.
+
+use crate::common::data::Data;
+
+/// Applies a way of counting to a string,
+/// raising an error naming the function if the data is not a string.
+fn count(name: &str, data: Data, counter: fn(&str) -> usize) -> Result<Data, String> {
+    match data {
+        Data::String(s) => Ok(Data::Integer(counter(&s) as i64)),
+        other => Err(format!(
+            "`{}` expects a string, found {}",
+            name,
+            other.kind_name()
+        )),
+    }
+}
+
+/// The number of bytes in a string, when encoded as UTF-8.
+pub fn str_bytes(data: Data) -> Result<Data, String> {
+    count("str_bytes", data, str::len)
+}
+
+/// The number of characters, i.e. Unicode scalar values, in a string.
+/// This is what `len` counts.
+pub fn str_chars(data: Data) -> Result<Data, String> {
+    count("str_chars", data, |s| s.chars().count())
+}
+
+/// The number of graphemes in a string,
+/// i.e. what a reader would think of as a single character,
+/// such as a letter with an accent, or an emoji made of several characters.
+#[cfg(feature = "graphemes")]
+pub fn str_graphemes(data: Data) -> Result<Data, String> {
+    use unicode_segmentation::UnicodeSegmentation;
+    count("str_graphemes", data, |s| s.graphemes(true).count())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `café`, with the accent as a separate combining character.
+    const CAFE: &str = "cafe\u{301}";
+    /// A family, made of four people joined by zero-width joiners.
+    const FAMILY: &str = "👨\u{200d}👩\u{200d}👧\u{200d}👦";
+
+    #[test]
+    fn lengths() {
+        let length = |f: fn(Data) -> Result<Data, String>, s: &str| f(Data::string(s));
+
+        assert_eq!(length(str_bytes, "café"), Ok(Data::Integer(5)));
+        assert_eq!(length(str_chars, "café"), Ok(Data::Integer(4)));
+        assert_eq!(length(str_bytes, CAFE), Ok(Data::Integer(6)));
+        assert_eq!(length(str_chars, CAFE), Ok(Data::Integer(5)));
+        assert_eq!(length(str_bytes, FAMILY), Ok(Data::Integer(25)));
+        assert_eq!(length(str_chars, FAMILY), Ok(Data::Integer(7)));
+        assert_eq!(length(str_chars, ""), Ok(Data::Integer(0)));
+
+        assert!(str_bytes(Data::Integer(1))
+            .unwrap_err()
+            .contains("`str_bytes` expects a string"));
+    }
+
+    #[test]
+    #[cfg(feature = "graphemes")]
+    fn graphemes() {
+        let length = |s: &str| str_graphemes(Data::string(s));
+        assert_eq!(length("café"), Ok(Data::Integer(4)));
+        assert_eq!(length(CAFE), Ok(Data::Integer(4)));
+        assert_eq!(length(FAMILY), Ok(Data::Integer(1)));
+        assert_eq!(length("🐦🐦"), Ok(Data::Integer(2)));
+    }
+}
@@ -24,7 +24,7 @@
 //! [passerine.io](https://www.passerine.io/#install).
 //!
 //! ## Embedding Passerine in Rust
-//! > TODO: Clean up crate visibility, create `run` function.
+//! > TODO: Clean up crate visibility.
 //!
 //! Add passerine to your `Cargo.toml`:
 //! ```toml
@@ -32,14 +32,12 @@
 //! passerine = 0.9
 //! ```
 //! Then simply:
-//! ```ignore
-//! // DISCLAIMER: The `run` function used here has not been implemented yet,
-//! //             although the underlying interface is mostly stable.
-//!
-//! use passerine;
+//! ```
+//! use passerine::{eval, Data};
 //!
 //! fn main() {
-//!     passerine::run("print \"Hello from Passerine!\"");
+//!     let result = eval("magic \"println\" \"Hello from Passerine!\"; 1 + 2");
+//!     assert_eq!(result, Ok(Data::Integer(3)));
 //! }
 //! ```
 //!
@@ -104,17 +102,19 @@
 pub use passerine_common as common;
 pub mod compiler;
 pub mod construct;
+pub mod error_codes;
 pub mod kernel;
 pub mod vm;
 
 // exported functions:
 // TODO: clean up exports
 
-use std::rc::Rc;
+use std::{fmt, rc::Rc};
 
 pub use common::{closure::Closure, Data, Inject, Source};
 pub use compiler::{
     compile_source,
+    compile_source_with_ffi,
     syntax::Syntax,
     // TODO:
     Compiler,
@@ -124,30 +124,77 @@ pub use compiler::{
     Parser,
     Reader,
 };
+pub use kernel::{ffi_core, ffi_core_with_overflow, FFIFunction, Overflow, Signature, Type, FFI};
 pub use passerine_derive::Effect;
 pub use vm::{fiber::Fiber, trace::Trace};
 
+/// The version of Passerine.
+/// Bytecode is only guaranteed to run on the version that compiled it.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 /// Compiles a [`Source`] to some bytecode.
 pub fn compile(source: Rc<Source>) -> Result<Closure, Syntax> {
     let bytecode = compile_source(source)?;
     return Ok(Closure::wrap(bytecode));
 }
 
-// /// Compiles a [`Source`] to some bytecode,
-// /// With a specific [`FFI`].
-// pub fn compile_with_ffi(
-//     source: Rc<Source>,
-//     ffi: FFI,
-// ) -> Result<Closure, Syntax> {
-//     let tokens = ThinModule::thin(source).lower()?;
-//     let ast = tokens.lower()?;
-//     let cst = ast.lower()?;
-//     let sst = cst.lower()?;
-//     let sst_ffi = Module::new(sst.repr, (sst.assoc, ffi));
-//     let bytecode = sst_ffi.lower()?;
+/// Compiles a [`Source`] to some bytecode,
+/// With a specific [`FFI`].
+pub fn compile_with_ffi(source: Rc<Source>, ffi: FFI) -> Result<Closure, Syntax> {
+    let bytecode = compile_source_with_ffi(source, ffi)?;
+    return Ok(Closure::wrap(bytecode));
+}
+
+/// An error raised while compiling or running some Passerine.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasserineError {
+    /// The source could not be compiled.
+    Syntax(Syntax),
+    /// The compiled source raised an error while running.
+    Trace(Trace),
+}
 
-//     return Ok(Closure::wrap(bytecode));
-// }
+impl From<Syntax> for PasserineError {
+    fn from(syntax: Syntax) -> Self {
+        PasserineError::Syntax(syntax)
+    }
+}
+
+impl From<Trace> for PasserineError {
+    fn from(trace: Trace) -> Self {
+        PasserineError::Trace(trace)
+    }
+}
+
+impl fmt::Display for PasserineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasserineError::Syntax(syntax) => write!(f, "{}", syntax),
+            PasserineError::Trace(trace) => write!(f, "{}", trace),
+        }
+    }
+}
+
+impl std::error::Error for PasserineError {}
+
+/// Compiles and runs a snippet of source code with the core [`FFI`],
+/// returning the value it evaluates to.
+/// Each call runs in a fresh [`Fiber`],
+/// so nothing is shared between calls.
+///
+/// ```
+/// use passerine::{eval, Data};
+///
+/// assert_eq!(eval("x = 6; x * 7"), Ok(Data::Integer(42)));
+/// assert_eq!(eval("magic \"to_string\" 1.5"), Ok(Data::string("1.5")));
+/// assert!(eval("1 / 0").is_err());
+/// ```
+pub fn eval(source: &str) -> Result<Data, PasserineError> {
+    let closure = compile(Source::source(source))?;
+    let mut fiber = Fiber::init(closure);
+    fiber.run()?;
+    Ok(fiber.stack.pop_data())
+}
 
 // /// Run a compiled [`Closure`].
 // pub fn run(closure: Closure) -> Result<(), Trace> {
@@ -1,11 +1,22 @@
-use std::mem;
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem,
+    rc::Rc,
+};
 
 use crate::{
     common::{
-        closure::Closure, data::Data, lambda::Captured, number::build_number, opcode::Opcode,
+        closure::Closure,
+        data::{Data, LabelKind},
+        lambda::Captured,
+        number::build_number,
+        opcode::Opcode,
         span::Span,
     },
-    vm::{slot::Suspend, stack::Stack, trace::Trace},
+    compiler::syntax::Note,
+    error_codes,
+    kernel::{ffi_core, FFI},
+    vm::{hook::Hooks, input::Input, output::Output, slot::Suspend, stack::Stack, trace::Trace},
 };
 
 // TODO: algebraic effects
@@ -13,6 +24,28 @@ use crate::{
 // fiber scheduling environment handles FFI, no more holding refs to rust
 // functions. TODO: convert Fiber to Fiber
 
+/// The number of items preallocated on the stack of a new `Fiber`,
+/// beyond the frame and locals of the root closure.
+/// Shallow programs never have to grow the stack.
+const STACK_MARGIN: usize = 256;
+
+/// The lifecycle of a `Fiber`.
+/// A fiber may only be run once, from the `Idle` state,
+/// though it may be suspended and resumed any number of times while it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FiberState {
+    /// Initialized, but not yet run.
+    Idle,
+    /// Currently executing bytecode.
+    Running,
+    /// Stopped by a `yield`, waiting to be resumed.
+    Suspended,
+    /// Ran to completion; the result is on the top of the stack.
+    Finished,
+    /// Stopped because of a runtime error.
+    Errored,
+}
+
 /// A `Fiber` executes bytecode lambda closures.
 /// (That's a mouthful - think bytecode + some context).
 /// Fiber initialization overhead is tiny,
@@ -23,6 +56,21 @@ pub struct Fiber {
     pub closure: Closure,
     pub stack: Stack,
     pub ip: usize,
+    state: FiberState,
+    /// The Rust functions callable from this fiber.
+    ffi: FFI,
+    /// Functions used to show labeled data, by label kind.
+    shows: HashMap<LabelKind, Closure>,
+    /// Callbacks invoked on function entry and exit, if installed.
+    hooks: Option<Hooks>,
+    /// Where input is read from, e.g. by `read_line`.
+    input: Input,
+    /// Where output is written to, e.g. by `println`.
+    output: Output,
+    /// The arguments the program was run with, e.g. from the command line.
+    args: Vec<String>,
+    /// The data passed to the last `yield`, until it is handed to the host.
+    yielded: Option<Data>,
 }
 
 unsafe impl Send for Fiber {}
@@ -34,18 +82,203 @@ unsafe impl Send for Fiber {}
 // this impl contains initialization, helper functions, and the core interpreter
 // loop the next impl contains opcode implementations
 impl Fiber {
-    /// Initialize a new Fiber.
+    /// Initialize a new Fiber, with the core FFI functions.
     /// To run the Fiber, a lambda must be passed to it through `run`.
     pub fn init(closure: Closure) -> Fiber {
+        Fiber::init_with_ffi(closure, ffi_core())
+    }
+
+    /// Initialize a new Fiber that can call the functions in a specific FFI.
+    /// The FFI should be the same one the closure was compiled with.
+    /// In debug builds, the closure's bytecode is verified first.
+    pub fn init_with_ffi(closure: Closure, ffi: FFI) -> Fiber {
+        #[cfg(debug_assertions)]
+        if let Err(error) = closure.lambda.verify() {
+            panic!("Tried to run invalid bytecode: {}", error);
+        }
+
+        Fiber::init_unverified(closure, ffi)
+    }
+
+    /// Like `init_with_ffi`, but never verifies the closure's bytecode.
+    fn init_unverified(closure: Closure, ffi: FFI) -> Fiber {
+        let capacity = 1 + closure.lambda.decls + STACK_MARGIN;
         let mut fiber = Fiber {
             closure,
-            stack: Stack::init(),
+            stack: Stack::with_capacity(capacity),
             ip: 0,
+            state: FiberState::Idle,
+            ffi,
+            shows: HashMap::new(),
+            hooks: None,
+            input: Input::default(),
+            output: Output::default(),
+            args: vec![],
+            yielded: None,
         };
         fiber.stack.declare(fiber.closure.lambda.decls);
         return fiber;
     }
 
+    /// Returns where this fiber is in its lifecycle.
+    pub fn state(&self) -> FiberState {
+        self.state
+    }
+
+    /// Registers a closure used to show data with a given label kind.
+    /// When shown, e.g. when printed, labeled data is passed to the closure,
+    /// which must return a string.
+    pub fn register_show(&mut self, kind: LabelKind, show: Closure) {
+        self.shows.insert(kind, show);
+    }
+
+    /// Installs callbacks invoked whenever a function is entered or exited,
+    /// e.g. to build a profile.
+    /// The program itself is entered when the fiber is run.
+    /// A fiber without hooks does not keep track of these events.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = Some(hooks);
+    }
+
+    /// Sets where input is read from, which is standard input by default.
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    /// The input this fiber reads from.
+    pub fn input(&mut self) -> &mut Input {
+        &mut self.input
+    }
+
+    /// Sets where output is written to, which is standard output by default.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// The output this fiber writes to.
+    pub fn output(&mut self) -> &mut Output {
+        &mut self.output
+    }
+
+    /// Sets the arguments the program is run with,
+    /// which it can get by calling `args`.
+    /// There are none by default.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// The arguments the program is run with.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// Converts some data to a string, as it would be printed.
+    /// Labeled data with a registered show closure is shown by applying it,
+    /// including labeled data nested inside other data;
+    /// everything else falls back to the default representation.
+    pub fn show(&mut self, data: Data) -> Result<String, Trace> {
+        match data {
+            Data::Label(kind, _) if self.shows.contains_key(&LabelKind(kind)) => {
+                let show = self.shows[&LabelKind(kind)].clone();
+                match self.apply(show, data)? {
+                    Data::String(string) => Ok(Rc::unwrap_or_clone(string)),
+                    other => Err(Trace::error(
+                        "Show",
+                        &format!(
+                            "The show function for label {} returned '{}', which is not a string",
+                            kind, other
+                        ),
+                        vec![],
+                    )),
+                }
+            }
+            Data::Label(kind, inner) => Ok(format!("{} {}", kind, self.show(*inner)?)),
+            Data::Tuple(items) => Ok(format!("({})", self.show_all(items.iter())?.join(", "))),
+            Data::List(items) => Ok(format!("[{}]", self.show_all(items.iter())?.join(", "))),
+            Data::Record(fields) => {
+                let mut shown = vec![];
+                for (name, value) in fields.iter() {
+                    shown.push(format!("{} = {}", name, self.show(value.clone())?));
+                }
+                Ok(format!("{{{}}}", shown.join("; ")))
+            }
+            other => Ok(other.to_string()),
+        }
+    }
+
+    /// Shows each of some data, as `show` does.
+    fn show_all<'a>(
+        &mut self,
+        items: impl Iterator<Item = &'a Data>,
+    ) -> Result<Vec<String>, Trace> {
+        items.map(|item| self.show(item.clone())).collect()
+    }
+
+    /// Calls a closure with an argument, running it to completion
+    /// before returning its result.
+    /// This can be used to call back into Passerine,
+    /// e.g. from an FFI function while this fiber is running.
+    /// If the closure raises an error, everything it left on the stack is discarded,
+    /// so that this fiber can keep running as if the call never happened.
+    pub fn apply(&mut self, closure: Closure, arg: Data) -> Result<Data, Trace> {
+        let depth = self.stack.frames.len();
+
+        // suspend the current context, as in `call`
+        let old_closure = mem::replace(&mut self.closure, closure);
+        let old_ip = mem::replace(&mut self.ip, 0);
+        self.stack.push_frame(Suspend {
+            ip: old_ip,
+            closure: old_closure,
+        });
+        self.stack.declare(self.closure.lambda.decls);
+        self.stack.push_data(arg);
+        if let Some(hooks) = &mut self.hooks {
+            hooks.call(&self.closure.lambda);
+        }
+
+        match self.run_above(depth) {
+            Ok(()) => Ok(self.stack.pop_data()),
+            Err(mut trace) => {
+                // restore each context suspended since `apply` was called,
+                // rather than the context of the fiber, as in `execute`
+                while self.stack.frames.len() > depth + 1 {
+                    self.stack.unwind_frame();
+                    let suspend = self.stack.pop_frame();
+                    self.ip = suspend.ip;
+                    self.closure = suspend.closure;
+                    trace.add_context(self.closure.lambda.index_span(self.ip - 1));
+                }
+
+                // the context suspended by `apply` itself
+                self.stack.unwind_frame();
+                let suspend = self.stack.pop_frame();
+                self.ip = suspend.ip;
+                self.closure = suspend.closure;
+                Err(trace)
+            }
+        }
+    }
+
+    /// Runs until every frame above `depth` has been returned from.
+    fn run_above(&mut self, depth: usize) -> Result<(), Trace> {
+        while self.stack.frames.len() > depth {
+            self.step()?;
+
+            // there is no way to get back to this call once the fiber is suspended
+            if self.state == FiberState::Suspended {
+                self.state = FiberState::Running;
+                self.yielded = None;
+                return Err(Trace::error(
+                    "Fiber",
+                    "Can not yield from a function called by an FFI function",
+                    vec![self.current_span()],
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Advances to the next instruction.
     #[inline]
     fn next(&mut self) {
@@ -72,12 +305,6 @@ impl Fiber {
         self.peek_byte()
     }
 
-    /// Returns whether the program has terminated
-    #[inline]
-    fn is_terminated(&mut self) -> bool {
-        self.ip >= self.closure.lambda.code.len()
-    }
-
     /// Builds the next number in the bytecode stream.
     /// See `utils::number` for more.
     #[inline]
@@ -94,6 +321,14 @@ impl Fiber {
         self.closure.lambda.index_span(self.ip)
     }
 
+    /// Builds an error for bytecode the compiler should never produce,
+    /// so that a bug in the compiler is reported
+    /// rather than crashing whatever is running the fiber.
+    fn internal_error(&self, message: &str) -> Trace {
+        let message = format!("{}; this is a bug in the compiler", message);
+        Trace::error("Internal Compiler", &message, vec![self.current_span()])
+    }
+
     // core interpreter loop
 
     /// Dissasembles and interprets a single (potentially fallible) bytecode op.
@@ -116,14 +351,30 @@ impl Fiber {
             Opcode::Call => self.call(),
             Opcode::Return => self.return_val(),
             Opcode::Closure => self.closure(),
-            Opcode::Print => self.print(),
             Opcode::Label => self.label(),
             Opcode::Tuple => self.tuple(),
+            Opcode::Record => self.record(),
             Opcode::UnData => self.un_data(),
             Opcode::UnLabel => self.un_label(),
             Opcode::UnTuple => self.un_tuple(),
+            Opcode::TupleExtend => self.tuple_extend(),
+            Opcode::Test => self.test(),
+            Opcode::TestTuple => self.test_tuple(),
+            Opcode::TestLabel => self.test_label(),
+            Opcode::Jump => self.jump(),
+            Opcode::JumpFalse => self.jump_false(),
+            Opcode::NoMatch => self.no_match(),
+            Opcode::RecordUpdate => self.record_update(),
+            Opcode::Load0 => self.load_local(0),
+            Opcode::Load1 => self.load_local(1),
+            Opcode::Load2 => self.load_local(2),
+            Opcode::Load3 => self.load_local(3),
+            Opcode::Save0 => self.save_local(0),
+            Opcode::Save1 => self.save_local(1),
+            Opcode::Save2 => self.save_local(2),
+            Opcode::Save3 => self.save_local(3),
             Opcode::Noop => self.done(),
-            _ => panic!("Opcode Not Implemented"),
+            _ => Err(self.internal_error(&format!("Opcode {:?} is not implemented", opcode))),
         }
     }
 
@@ -142,11 +393,109 @@ impl Fiber {
     /// previous lambda, Or failure, in which it returns the runtime error.
     /// In the future, fibers will allow for error handling -
     /// right now, error in Passerine are practically panics.
+    /// Returns an error without running anything if the fiber is not `Idle`,
+    /// e.g. if it is run twice, or run again from inside itself.
+    /// A program that yields must be run with `resume` instead,
+    /// as there is nothing to get back to the `yield` once `run` returns,
+    /// so yielding raises an error.
     pub fn run(&mut self) -> Result<(), Trace> {
+        self.start()?;
+
+        if self.state == FiberState::Suspended {
+            self.yielded = None;
+            let trace = Trace::error(
+                "Fiber",
+                "Can not yield from a program that is run rather than resumed",
+                vec![self.closure.lambda.index_span(self.ip - 1)],
+            );
+            return Err(self.fail(trace));
+        }
+
+        Ok(())
+    }
+
+    /// Starts running an `Idle` fiber,
+    /// until it finishes, is suspended, or raises an error.
+    fn start(&mut self) -> Result<(), Trace> {
+        if self.state != FiberState::Idle {
+            return Err(self.misuse());
+        }
+
+        self.state = FiberState::Running;
+        if let Some(hooks) = &mut self.hooks {
+            hooks.call(&self.closure.lambda);
+        }
+
         // println!("Starting\n{}", self.closure.lambda);
+        self.execute()
+    }
+
+    /// Runs another program in the root frame once this fiber has stopped,
+    /// e.g. the next line entered in a REPL.
+    /// The program can use the variables left on the stack by the programs before it,
+    /// see `Compiler::compile_continuing`, and the result of the last program is dropped.
+    /// A fiber stopped by an error keeps the variables it had declared,
+    /// though the program that raised the error may not have assigned them all.
+    pub fn continue_with(&mut self, closure: Closure) -> Result<(), Trace> {
+        match self.state {
+            FiberState::Finished => mem::drop(self.stack.pop_data()),
+            FiberState::Errored => (),
+            _ => return Err(self.misuse()),
+        }
+
+        #[cfg(debug_assertions)]
+        if let Err(error) = closure.lambda.verify() {
+            panic!("Tried to run invalid bytecode: {}", error);
+        }
+
+        // variables declared by this program come after those already declared
+        let declared = self.stack.stack.len() - 1;
+        self.stack
+            .declare(closure.lambda.decls.saturating_sub(declared));
+        self.closure = closure;
+        self.ip = 0;
+        self.state = FiberState::Idle;
+        self.run()
+    }
+
+    /// Runs a fiber until it yields, returning the data yielded,
+    /// so that a program can be used as a generator.
+    /// The value resumed with is what the `yield` the fiber is suspended at returns;
+    /// when a fiber is first resumed, it is started as with `run`, and the value is ignored.
+    /// Returns `None` once the fiber finishes, with its result on the top of the stack, as in `run`.
+    /// Each suspension can only be resumed once.
+    pub fn resume(&mut self, value: Data) -> Result<Option<Data>, Trace> {
+        match self.state {
+            FiberState::Idle => self.start()?,
+            FiberState::Suspended => {
+                // replace what `yield` returned while the fiber was suspended
+                mem::drop(self.stack.pop_data());
+                self.stack.push_data(value);
+                self.state = FiberState::Running;
+                self.execute()?;
+            }
+            _ => return Err(self.misuse()),
+        }
+
+        Ok(self.yielded.take())
+    }
+
+    /// Suspends this fiber once the current instruction is done,
+    /// handing some data to whatever runs or resumes it.
+    pub fn suspend(&mut self, data: Data) {
+        self.yielded = Some(data);
+        self.state = FiberState::Suspended;
+    }
+
+    /// Runs until the fiber finishes, is suspended, or raises an error.
+    /// If there is an error, the stack is unwound to build the trace,
+    /// and the fiber can not be run again.
+    fn execute(&mut self) -> Result<(), Trace> {
         let mut result = Ok(());
 
-        while !self.is_terminated() {
+        // every program ends in a return from the root frame,
+        // which finishes the fiber
+        while let FiberState::Running = self.state {
             // println!("before: {:#?}", self.stack.stack);
             // println!("executing: {:?}", Opcode::from_byte(self.peek_byte()));
             result = self.step();
@@ -158,17 +507,46 @@ impl Fiber {
         // println!("after: {:?}", self.stack.stack);
         // println!("---");
 
-        if let Err(mut trace) = result {
-            while self.stack.unwind_frame() {
-                self.unwind();
-                self.ip -= 1;
-                trace.add_context(self.current_span());
-            }
-
-            result = Err(trace);
+        if let Err(trace) = result {
+            return Err(self.fail(trace));
         };
 
-        return result;
+        Ok(())
+    }
+
+    /// Stops the fiber because of an error,
+    /// unwinding the stack to add the context of each call to the trace.
+    /// The fiber can not be run again.
+    fn fail(&mut self, mut trace: Trace) -> Trace {
+        while self.stack.frames.len() > 1 {
+            self.stack.unwind_frame();
+            self.unwind();
+            self.ip -= 1;
+            trace.add_context(self.current_span());
+        }
+        // the root frame keeps its variables, see `continue_with`
+        self.stack.stack.truncate(1 + self.closure.lambda.decls);
+
+        self.state = FiberState::Errored;
+        trace
+    }
+
+    /// The error raised when this fiber is run or resumed, but can not be.
+    fn misuse(&self) -> Trace {
+        let message = match self.state {
+            FiberState::Idle => "This fiber has not been run yet",
+            FiberState::Running => {
+                "This fiber is already running, and can not be run again from inside itself"
+            }
+            FiberState::Suspended => "This fiber is suspended; resume it to keep running it",
+            FiberState::Finished => {
+                "This fiber has already finished running; create a new fiber to run it again"
+            }
+            FiberState::Errored => {
+                "This fiber stopped because of an error, and can not be run again"
+            }
+        };
+        Trace::error("Fiber", message, vec![])
     }
 
     /// Load a constant and push it onto the stack.
@@ -205,12 +583,20 @@ impl Fiber {
         self.done()
     }
 
+    /// Save the topmost value on the stack into a variable,
+    /// whose index is part of the opcode rather than an operand.
+    #[inline]
+    fn save_local(&mut self, index: usize) -> Result<(), Trace> {
+        self.stack.set_local(index);
+        self.done()
+    }
+
     /// Save the topmost value on the stack into a captured variable.
     #[inline]
     fn save_cap(&mut self) -> Result<(), Trace> {
         let index = self.next_number();
         let data = self.stack.pop_data();
-        mem::drop(self.closure.captures[index].replace(data));
+        mem::drop(self.closure.captures[index].replace(Some(data)));
         self.done()
     }
 
@@ -218,16 +604,42 @@ impl Fiber {
     #[inline]
     fn load(&mut self) -> Result<(), Trace> {
         let index = self.next_number();
-        let mut data = self.stack.local_data(index);
+        let data = self.stack.local_data(index);
+        self.stack.push_data(data);
+        self.done()
+    }
+
+    /// Push a copy of a variable's value onto the stack,
+    /// whose index is part of the opcode rather than an operand.
+    #[inline]
+    fn load_local(&mut self, index: usize) -> Result<(), Trace> {
+        let data = self.stack.local_data(index);
         self.stack.push_data(data);
         self.done()
     }
 
     /// Load a captured variable from the current closure.
+    /// Raises an error if the variable has not been assigned yet,
+    /// e.g. if a function is called before a variable it captures is defined.
     #[inline]
     fn load_cap(&mut self) -> Result<(), Trace> {
         let index = self.next_number();
-        let data = self.closure.captures[index].borrow().to_owned();
+        let data = match self.closure.captures[index].borrow().to_owned() {
+            Some(data) => data,
+            None => {
+                let name = self.closure.lambda.capture_names.get(index);
+                let message = match name {
+                    Some(name) => {
+                        format!("The variable `{}` was referenced before assignment", name)
+                    }
+                    None => "A variable was referenced before assignment".to_string(),
+                };
+                return Err(
+                    Trace::error("Reference", &message, vec![self.current_span()])
+                        .with_code(error_codes::USE_BEFORE_ASSIGNMENT),
+                );
+            }
+        };
         self.stack.push_data(data);
         self.done()
     }
@@ -249,19 +661,17 @@ impl Fiber {
         self.done()
     }
 
-    #[inline]
-    fn print(&mut self) -> Result<(), Trace> {
-        let data = self.stack.pop_data();
-        println!("{}", data);
-        self.stack.push_data(data);
-        self.done()
-    }
-
     #[inline]
     fn label(&mut self) -> Result<(), Trace> {
         let kind = match self.stack.pop_data() {
             Data::Kind(n) => n,
-            _ => unreachable!(),
+            other => {
+                let message = format!(
+                    "Expected a kind to label data with, found {}",
+                    other.kind_name()
+                );
+                return Err(self.internal_error(&message));
+            }
         };
         let data = self.stack.pop_data();
         self.stack.push_data(Data::Label(kind, Box::new(data)));
@@ -277,7 +687,109 @@ impl Fiber {
         }
 
         items.reverse();
-        self.stack.push_data(Data::Tuple(items));
+        self.stack.push_data(Data::tuple(items));
+        self.done()
+    }
+
+    /// Pops `n` pairs of field names and values off the stack,
+    /// i.e. `[F, "a", 1, "b", 2]` becomes `[F]`.
+    fn fields(&mut self, n: usize) -> Result<Vec<(String, Data)>, Trace> {
+        let mut fields = vec![];
+        for _ in 0..n {
+            let value = self.stack.pop_data();
+            let name = match self.stack.pop_data() {
+                Data::String(name) => Rc::unwrap_or_clone(name),
+                other => {
+                    let message =
+                        format!("Expected the name of a field, found {}", other.kind_name());
+                    return Err(self.internal_error(&message));
+                }
+            };
+            fields.push((name, value));
+        }
+
+        fields.reverse();
+        Ok(fields)
+    }
+
+    /// Builds a record from `n` pairs of field names and values,
+    /// i.e. `[F, "a", 1, "b", 2]` becomes `[F, {a = 1; b = 2}]`.
+    #[inline]
+    fn record(&mut self) -> Result<(), Trace> {
+        let n = self.next_number();
+        let record = self.fields(n)?.into_iter().collect::<BTreeMap<_, _>>();
+        self.stack.push_data(Data::record(record));
+        self.done()
+    }
+
+    /// Copies a record, replacing the values of `n` of its fields,
+    /// i.e. `[F, {a = 1; b = 2}, "a", 3]` becomes `[F, {a = 3; b = 2}]`.
+    /// Updating a field the record does not have is an error,
+    /// so a typo in a field name is not silently turned into a new field.
+    #[inline]
+    fn record_update(&mut self) -> Result<(), Trace> {
+        let n = self.next_number();
+        let fields = self.fields(n)?;
+        let mut record = match self.stack.pop_data() {
+            Data::Record(record) => record,
+            other => {
+                return Err(Trace::error(
+                    "Type",
+                    &format!(
+                        "The data '{}' is not a record and can not be updated",
+                        other
+                    ),
+                    vec![self.current_span()],
+                ))
+            }
+        };
+
+        for (name, value) in fields {
+            match record.get_mut(&name) {
+                Some(field) => *field = value,
+                None => {
+                    return Err(Trace::error(
+                        "Record",
+                        &format!(
+                            "The record '{}' has no field `{}` to update",
+                            Data::Record(record),
+                            name
+                        ),
+                        vec![self.current_span()],
+                    ))
+                }
+            }
+        }
+
+        self.stack.push_data(Data::Record(record));
+        self.done()
+    }
+
+    /// Splices the topmost tuple into the tuple below it,
+    /// i.e. `[F, (a, b), (c, d)]` becomes `[F, (a, b, c, d)]`.
+    fn tuple_extend(&mut self) -> Result<(), Trace> {
+        let spliced = match self.stack.pop_data() {
+            Data::Tuple(t) => Rc::unwrap_or_clone(t),
+            Data::Unit => vec![],
+            other => {
+                return Err(Trace::error(
+                    "Type",
+                    &format!("The data '{}' is not a tuple and can not be spread", other),
+                    vec![self.current_span()],
+                ))
+            }
+        };
+
+        let mut base = match self.stack.pop_data() {
+            Data::Tuple(t) => Rc::unwrap_or_clone(t),
+            other => {
+                let message = format!("Expected a tuple to extend, found {}", other.kind_name());
+                return Err(self.internal_error(&message));
+            }
+        };
+
+        base.extend(spliced);
+        self.stack.push_data(Data::tuple(base));
         self.done()
     }
 
@@ -285,34 +797,125 @@ impl Fiber {
         let expected = self.stack.pop_data();
         let data = self.stack.pop_data();
 
-        if data != expected {
+        if !data.matches(&expected) {
             return Err(Trace::error(
                 "Pattern Matching",
                 &format!(
-                    "The data '{}' does not match the expected data '{}'",
-                    data, expected
+                    "Expected {} '{}', but found {} '{}'",
+                    expected.kind_name(),
+                    expected,
+                    data.kind_name(),
+                    data,
                 ),
                 vec![self.current_span()],
-            ));
+            )
+            .with_code(error_codes::PATTERN_MISMATCH));
         }
 
         self.done()
     }
 
+    /// Tests whether some data matches the expected data,
+    /// as `un_data` would, but pushes the result rather than failing.
+    fn test(&mut self) -> Result<(), Trace> {
+        let expected = self.stack.pop_data();
+        let data = self.stack.pop_data();
+        self.stack.push_data(Data::Boolean(data.matches(&expected)));
+        self.done()
+    }
+
+    /// Tests whether the topmost value is a tuple of a certain length,
+    /// leaving the value on the stack.
+    fn test_tuple(&mut self) -> Result<(), Trace> {
+        let length = self.next_number();
+        let data = self.stack.pop_data();
+        let is_tuple = matches!(&data, Data::Tuple(t) if t.len() == length);
+        self.stack.push_data(data);
+        self.stack.push_data(Data::Boolean(is_tuple));
+        self.done()
+    }
+
+    /// Tests whether the value beneath a kind is a label of that kind,
+    /// i.e. `[Some 1, Kind(Some)]` becomes `[Some 1, true]`.
+    #[inline]
+    fn test_label(&mut self) -> Result<(), Trace> {
+        let kind = match self.stack.pop_data() {
+            Data::Kind(n) => n,
+            other => {
+                let message = format!(
+                    "Expected a kind to test a label against, found {}",
+                    other.kind_name()
+                );
+                return Err(self.internal_error(&message));
+            }
+        };
+        let data = self.stack.pop_data();
+        let is_label = matches!(&data, Data::Label(n, _) if *n == kind);
+        self.stack.push_data(data);
+        self.stack.push_data(Data::Boolean(is_label));
+        self.done()
+    }
+
+    /// Moves the instruction pointer to the target of the jump.
+    fn jump(&mut self) -> Result<(), Trace> {
+        self.ip = self.next_number();
+        Ok(())
+    }
+
+    /// Jumps if the topmost value is false.
+    fn jump_false(&mut self) -> Result<(), Trace> {
+        let target = self.next_number();
+        match self.stack.pop_data() {
+            Data::Boolean(false) => self.ip = target,
+            Data::Boolean(true) => self.next(),
+            other => {
+                return Err(Trace::error(
+                    "Jump",
+                    &format!("Expected a boolean to jump on, found '{}'", other),
+                    vec![self.current_span()],
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Raises an error because no arm of a match matched.
+    fn no_match(&mut self) -> Result<(), Trace> {
+        let data = self.stack.pop_data();
+        Err(Trace::error(
+            "Pattern Matching",
+            &format!("The data '{}' does not match any of the patterns", data),
+            vec![self.current_span()],
+        )
+        .with_code(error_codes::NO_MATCH))
+    }
+
     fn un_label(&mut self) -> Result<(), Trace> {
         let kind = match self.stack.pop_data() {
             Data::Kind(n) => n,
-            _ => unreachable!(),
+            other => {
+                let message = format!(
+                    "Expected a kind to match a label on, found {}",
+                    other.kind_name()
+                );
+                return Err(self.internal_error(&message));
+            }
         };
 
+        // nested labels are unwrapped one layer at a time, outermost first
         let d = match self.stack.pop_data() {
             Data::Label(n, d) if n == kind => d,
             other => {
+                let found = match other {
+                    Data::Label(n, _) => format!("a label of kind {}", n),
+                    other => other.kind_name().to_string(),
+                };
                 return Err(Trace::error(
                     "Pattern Matching",
-                    &format!("The data '{}' does not match the Label '{}'", other, kind),
+                    &format!("Expected a label of kind {}, but found {}", kind, found),
                     vec![self.current_span()],
-                ))
+                )
+                .with_code(error_codes::PATTERN_MISMATCH));
             }
         };
 
@@ -329,7 +932,8 @@ impl Fiber {
                     "Pattern Matching",
                     &format!("The data '{}' is not a tuple", other),
                     vec![self.current_span()],
-                ))
+                )
+                .with_code(error_codes::PATTERN_MISMATCH))
             }
         };
 
@@ -344,7 +948,8 @@ impl Fiber {
                     index
                 ),
                 vec![self.current_span()],
-            ));
+            )
+            .with_code(error_codes::INDEX_OUT_OF_BOUNDS));
         }
 
         let data = t[index].clone();
@@ -364,24 +969,29 @@ impl Fiber {
                     "Call",
                     &format!("The data '{}' is not a function and can not be called", o),
                     vec![self.current_span()],
-                ))
+                )
+                .with_code(error_codes::CALL_NON_FUNCTION))
             }
         };
         let arg = self.stack.pop_data();
 
-        // TODO: make all programs end in return,
-        // so bounds check (i.e. is_terminated) is never required
         self.next();
-        let tail_call =
-            !self.is_terminated() && Opcode::Return == Opcode::from_byte(self.peek_byte());
+        self.follow_jump_to_return();
+        let tail_call = Opcode::Return == Opcode::from_byte(self.peek_byte());
 
         // clear the stack if there's a tail call
         // we must do this before we suspend the calling context
         if tail_call {
             let locals = self.next_number();
-            for _ in 0..locals {
-                self.del()?;
+            self.stack.pop_locals(locals);
+        }
+
+        // a tail call leaves the calling function before entering the next
+        if let Some(hooks) = &mut self.hooks {
+            if tail_call {
+                hooks.return_val(&self.closure.lambda);
             }
+            hooks.call(&fun.lambda);
         }
 
         // suspend the calling context
@@ -409,19 +1019,44 @@ impl Fiber {
         Ok(())
     }
 
+    /// If the next instruction jumps straight to a return,
+    /// e.g. at the end of an arm of a match, moves to that return,
+    /// so that a call before the jump is treated as a tail call.
+    fn follow_jump_to_return(&mut self) {
+        if Opcode::from_byte(self.peek_byte()) != Opcode::Jump {
+            return;
+        }
+
+        let (target, _) = build_number(&self.closure.lambda.code[self.ip + 1..]);
+        let code = &self.closure.lambda.code;
+        if Opcode::from_byte(code[target]) == Opcode::Return {
+            self.ip = target;
+        }
+    }
+
     /// Return a value from a function.
     /// End the execution of the current lambda.
     /// Takes the number of locals on the stack
     /// Relpaces the last frame with the value on the top of the stack.
     /// Expects the stack to be a `[..., Frame, Local 1, ..., Local N, Data]`
+    /// Returning from the root frame finishes the fiber,
+    /// leaving the result of the program on top of the stack.
     fn return_val(&mut self) -> Result<(), Trace> {
         // the value to be returned
         let val = self.stack.pop_data();
 
         // clear all locals
         let locals = self.next_number();
-        for _ in 0..locals {
-            self.del()?;
+        self.stack.pop_locals(locals);
+
+        if let Some(hooks) = &mut self.hooks {
+            hooks.return_val(&self.closure.lambda);
+        }
+
+        if self.stack.frames.len() == 1 {
+            self.stack.push_data(val);
+            self.state = FiberState::Finished;
+            return Ok(());
         }
 
         // restore suspended callee
@@ -434,12 +1069,21 @@ impl Fiber {
         Ok(())
     }
 
+    /// Wraps a lambda constant in a closure, capturing its environment.
+    /// Every closure made from the same constant shares its lambda,
+    /// so only the captures are built each time this runs.
     fn closure(&mut self) -> Result<(), Trace> {
         let index = self.next_number();
 
         let lambda = match self.closure.lambda.constants[index].clone() {
             Data::Lambda(lambda) => lambda,
-            _ => unreachable!("Expected a lambda to be wrapped with a closure"),
+            other => {
+                let message = format!(
+                    "Expected a lambda to close over, found {}",
+                    other.kind_name()
+                );
+                return Err(self.internal_error(&message));
+            }
         };
 
         let mut closure = Closure::wrap(lambda);
@@ -456,20 +1100,1710 @@ impl Fiber {
         self.done()
     }
 
+    /// Calls a Rust function by name, passing the topmost value as an argument.
     fn ffi_call(&mut self) -> Result<(), Trace> {
-        panic!("FFI is depracated");
-        // let index = self.next_number();
-        // let ffi_function = &self.closure.lambda.ffi[index];
-        //
-        // let argument = self.stack.pop_data();
-        // let returned = match ffi_function.call(argument) {
-        //     Ok(d) => d,
-        //     Err(e) => return Err(Trace::error(
-        //         "FFI Call", &e, vec![self.current_span()],
-        //     )),
-        // };
-        //
-        // self.stack.push_data(returned);
-        // self.done()
+        let index = self.next_number();
+        let name = self.closure.lambda.ffi[index].clone();
+        let function = self
+            .ffi
+            .get(&name)
+            .map_err(|e| Trace::error("FFI Call", &e, vec![self.current_span()]))?;
+
+        let argument = self.stack.pop_data();
+        let returned = function.call(self, argument).map_err(|e| {
+            let hint = format!("in a call to the FFI function `{}`", name);
+            Trace::error("FFI Call", &e, vec![])
+                .add_note(Note::new_with_hint(&hint, &self.current_span()))
+        })?;
+
+        self.stack.push_data(returned);
+        self.done()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, collections::BTreeMap, env, fs, io::Cursor, rc::Rc};
+
+    use super::*;
+    use crate::{
+        common::{lambda::Lambda, number::split_number, Source},
+        compiler::{compile_source, compile_source_with_ffi},
+        kernel::{ffi_core_with_overflow, FFIFunction, Overflow, Type},
+        vm::output::Output,
+    };
+
+    fn run(source: &str) -> Result<Data, Trace> {
+        let mut fiber = fiber(source);
+        fiber.run()?;
+        Ok(fiber.stack.pop_data())
+    }
+
+    fn integers(numbers: &[i64]) -> Data {
+        Data::tuple(numbers.iter().map(|n| Data::Integer(*n)).collect())
+    }
+
+    fn fiber(source: &str) -> Fiber {
+        let lambda = compile_source(Source::source(source)).unwrap();
+        // everything the compiler produces should pass verification
+        lambda.verify().unwrap();
+        Fiber::init(Closure::wrap(lambda))
+    }
+
+    /// Matches some data against a literal pattern,
+    /// as the compiler does for a pattern like `1.0 = x`.
+    fn match_literal(data: Data, expected: Data) -> Result<(), Trace> {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        for constant in [data, expected] {
+            let index = lambda.index_data(constant);
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(index));
+        }
+        lambda.emit(Opcode::UnData);
+        let index = lambda.index_data(Data::Unit);
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(index));
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+
+        lambda.verify().unwrap();
+        Fiber::init(Closure::wrap(Rc::new(lambda))).run()
+    }
+
+    /// Builds bytecode that saves a value in a variable,
+    /// then loads and discards it many times, before returning it.
+    /// Loads either use an operand, or have the index of the variable in the opcode.
+    fn repeated_loads(specialized: bool, times: usize) -> Fiber {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        lambda.decls = 1;
+        let index = lambda.index_data(Data::Integer(7));
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(index));
+        lambda.emit(Opcode::Save0);
+        for _ in 0..times {
+            if specialized {
+                lambda.emit(Opcode::Load0);
+            } else {
+                lambda.emit(Opcode::Load);
+                lambda.emit_bytes(&mut split_number(0));
+            }
+            lambda.emit(Opcode::Del);
+        }
+        lambda.emit(Opcode::Load0);
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(1));
+
+        lambda.verify().unwrap();
+        Fiber::init(Closure::wrap(Rc::new(lambda)))
+    }
+
+    /// The opcodes of some bytecode in order, skipping over their operands.
+    fn opcodes(lambda: &Lambda) -> Vec<Opcode> {
+        let mut opcodes = vec![];
+        let mut index = 0;
+        while index < lambda.code.len() {
+            let opcode = Opcode::from_byte(lambda.code[index]);
+            let bounds = lambda.bounds(opcode).unwrap();
+            let (_, consumed) = lambda.args_safe(index + 1, &bounds).unwrap();
+            opcodes.push(opcode);
+            index += 1 + consumed;
+        }
+        opcodes
+    }
+
+    /// Runs hand-built bytecode that loads some constants,
+    /// then runs a single instruction the compiler would never produce there.
+    fn malformed(constants: Vec<Data>, op: Opcode, operand: Option<usize>) -> Trace {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        for constant in constants {
+            let index = lambda.index_data(constant);
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(index));
+        }
+        lambda.emit(op);
+        if let Some(operand) = operand {
+            lambda.emit_bytes(&mut split_number(operand));
+        }
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+
+        // bytecode this broken would not pass verification
+        let mut fiber = Fiber::init_unverified(Closure::wrap(Rc::new(lambda)), ffi_core());
+        let error = fiber.run().unwrap_err();
+        assert_eq!(fiber.state(), FiberState::Errored);
+        error
+    }
+
+    /// Unwraps some labeled data, one `UnLabel` per kind,
+    /// as the compiler does for a pattern like `Ok (Some x)`.
+    fn unwrap_labels(data: Data, kinds: &[usize]) -> Result<Data, Trace> {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        let constants = [data]
+            .into_iter()
+            .chain(kinds.iter().map(|k| Data::Kind(*k)));
+        for (i, constant) in constants.enumerate() {
+            let index = lambda.index_data(constant);
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(index));
+            if i > 0 {
+                lambda.emit(Opcode::UnLabel);
+            }
+        }
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+
+        lambda.verify().unwrap();
+        let mut fiber = Fiber::init(Closure::wrap(Rc::new(lambda)));
+        fiber.run()?;
+        Ok(fiber.stack.pop_data())
+    }
+
+    /// Compares two pieces of data with `Test`, as a match arm does.
+    fn test_data(data: Data, expected: Data) -> bool {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        for constant in [data, expected] {
+            let index = lambda.index_data(constant);
+            lambda.emit(Opcode::Con);
+            lambda.emit_bytes(&mut split_number(index));
+        }
+        lambda.emit(Opcode::Test);
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+
+        lambda.verify().unwrap();
+        let mut fiber = Fiber::init(Closure::wrap(Rc::new(lambda)));
+        fiber.run().unwrap();
+        match fiber.stack.pop_data() {
+            Data::Boolean(b) => b,
+            other => panic!("expected a boolean, found {:?}", other),
+        }
+    }
+
+    fn label(kind: usize, data: Data) -> Data {
+        Data::Label(kind, Box::new(data))
+    }
+
+    #[test]
+    fn nested_label_match() {
+        // i.e. `Ok (Some x) = Ok (Some 5)`
+        let nested = || label(1, label(2, Data::Integer(5)));
+        assert_eq!(unwrap_labels(nested(), &[1, 2]), Ok(Data::Integer(5)));
+        assert_eq!(
+            unwrap_labels(nested(), &[1]),
+            Ok(label(2, Data::Integer(5)))
+        );
+
+        let cases = [
+            (
+                vec![1, 3],
+                "Expected a label of kind 3, but found a label of kind 2",
+            ),
+            (
+                vec![2, 1],
+                "Expected a label of kind 2, but found a label of kind 1",
+            ),
+            (
+                vec![1, 2, 3],
+                "Expected a label of kind 3, but found an integer",
+            ),
+        ];
+        for (kinds, message) in cases {
+            let error = unwrap_labels(nested(), &kinds).unwrap_err();
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn nested_label_equality() {
+        let nested = |outer, inner, data| label(outer, label(inner, data));
+        let five = || Data::Integer(5);
+
+        assert_eq!(nested(1, 2, five()), nested(1, 2, five()));
+        assert!(test_data(nested(1, 2, five()), nested(1, 2, five())));
+
+        // kinds are compared before payloads, at every level
+        for other in [
+            nested(2, 2, five()),
+            nested(1, 3, five()),
+            nested(1, 2, Data::Integer(6)),
+            nested(1, 2, Data::Float(5.0)),
+            label(1, five()),
+            label(1, label(2, label(3, five()))),
+        ] {
+            assert_ne!(nested(1, 2, five()), other);
+            assert!(!test_data(nested(1, 2, five()), other));
+        }
+
+        // matching uses total float equality inside labels too
+        let nan = || nested(1, 2, Data::Float(f64::NAN));
+        assert!(test_data(nan(), nan()));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Tried to run invalid bytecode")]
+    fn init_verifies() {
+        let mut lambda = Lambda::empty();
+        lambda.emit_span(&Span::dummy());
+        lambda.emit(Opcode::Con);
+        lambda.emit_bytes(&mut split_number(0));
+        lambda.emit(Opcode::Return);
+        lambda.emit_bytes(&mut split_number(0));
+        Fiber::init(Closure::wrap(Rc::new(lambda)));
+    }
+
+    #[test]
+    fn malformed_bytecode() {
+        let integer = || Data::Integer(1);
+        let errors = [
+            (
+                malformed(vec![integer(), integer()], Opcode::Label, None),
+                "Expected a kind to label data with, found an integer",
+            ),
+            (
+                malformed(vec![integer(), integer()], Opcode::UnLabel, None),
+                "Expected a kind to match a label on, found an integer",
+            ),
+            (
+                malformed(vec![integer(), integer()], Opcode::TestLabel, None),
+                "Expected a kind to test a label against, found an integer",
+            ),
+            (
+                malformed(vec![integer()], Opcode::Closure, Some(0)),
+                "Expected a lambda to close over, found an integer",
+            ),
+            (
+                malformed(vec![integer(), Data::Unit], Opcode::Record, Some(1)),
+                "Expected the name of a field, found an integer",
+            ),
+            (
+                malformed(vec![integer(), Data::Unit], Opcode::TupleExtend, None),
+                "Expected a tuple to extend, found an integer",
+            ),
+            (
+                malformed(vec![integer(), integer()], Opcode::Add, None),
+                "Opcode Add is not implemented",
+            ),
+        ];
+
+        for (error, message) in errors {
+            let shown = error.to_string();
+            assert!(
+                shown.contains("Runtime Internal Compiler Error"),
+                "{}",
+                shown
+            );
+            assert!(shown.contains(message), "{}", shown);
+        }
+    }
+
+    #[test]
+    fn match_float_literals() {
+        let nan = || Data::Float(f64::NAN);
+        let inf = || Data::Float(f64::INFINITY);
+
+        assert!(match_literal(nan(), nan()).is_ok());
+        assert!(match_literal(inf(), inf()).is_ok());
+        assert!(match_literal(Data::Float(f64::NEG_INFINITY), inf()).is_err());
+        assert!(match_literal(Data::Float(1.0), nan()).is_err());
+        assert!(match_literal(nan(), Data::Float(1.0)).is_err());
+    }
+
+    #[test]
+    fn match_literal_kinds() {
+        let sources = [
+            ("x = 7; (7, _) = (x, 0); x", Data::Integer(7)),
+            ("x = 2.5; (2.5, _) = (x, 0); x", Data::Float(2.5)),
+            ("x = \"hi\"; (\"hi\", _) = (x, 0); x", Data::string("hi")),
+            ("x = True; (True, _) = (x, 0); x", Data::Boolean(true)),
+            ("match 2.5 { 1.5 -> 1, 2.5 -> 2, _ -> 3 }", Data::Integer(2)),
+            (
+                "match \"b\" { \"a\" -> 1, \"b\" -> 2, _ -> 3 }",
+                Data::Integer(2),
+            ),
+            ("match False { True -> 1, False -> 2 }", Data::Integer(2)),
+            ("match 3 { 1 -> 1, 3 -> 2, _ -> 3 }", Data::Integer(2)),
+        ];
+
+        for (source, expected) in sources {
+            let mut fiber = fiber(source);
+            assert_eq!(fiber.run(), Ok(()), "{}", source);
+            assert_eq!(fiber.stack.pop_data(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn match_numbers_strictly() {
+        let cases = [
+            ("match 1.0 { 1 -> 1, 1.0 -> 2, _ -> 3 }", 2),
+            ("match 1 { 1.0 -> 1, 1 -> 2, _ -> 3 }", 2),
+            ("match 0 { 0.0 -> 1, _ -> 2 }", 2),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(Data::Integer(expected)), "{}", source);
+        }
+
+        let trace = run("(1, _) = (1.0, 0)").unwrap_err().to_string();
+        assert!(trace.contains("Expected an integer '1', but found a float '1.0'"));
+        let trace = run("(1.0, _) = (1, 0)").unwrap_err().to_string();
+        assert!(trace.contains("Expected a float '1.0', but found an integer '1'"));
+    }
+
+    #[test]
+    fn match_literal_mismatch() {
+        let cases = [
+            (
+                "(7, _) = (8, 0)",
+                "Expected an integer '7', but found an integer '8'",
+            ),
+            (
+                "(2.5, _) = (1.5, 0)",
+                "Expected a float '2.5', but found a float '1.5'",
+            ),
+            (
+                "(\"hi\", _) = (\"ho\", 0)",
+                "Expected a string 'hi', but found a string 'ho'",
+            ),
+            (
+                "(True, _) = (False, 0)",
+                "Expected a boolean 'true', but found a boolean 'false'",
+            ),
+            (
+                "(1, _) = (\"1\", 0)",
+                "Expected an integer '1', but found a string '1'",
+            ),
+            (
+                "(1, _) = (1.0, 0)",
+                "Expected an integer '1', but found a float '1.0'",
+            ),
+        ];
+
+        for (source, message) in cases {
+            let error = fiber(source).run().unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn profiling_hooks() {
+        let mut fiber = fiber(
+            "
+            inc = x -> x + 1
+            double = x -> x * 2
+            fac = { 0 -> 1, n -> n * fac (n - 1) }
+            a = double (inc 1)
+            b = inc (fac 3)
+            inc b
+            ",
+        );
+
+        let calls = Rc::new(RefCell::new(HashMap::new()));
+        let returns = Rc::new(RefCell::new(0));
+        let counts = calls.clone();
+        let total = returns.clone();
+        fiber.set_hooks(Hooks {
+            on_call: Some(Box::new(move |event| {
+                let name = event.name.unwrap_or("<program>").to_string();
+                *counts.borrow_mut().entry(name).or_insert(0) += 1;
+            })),
+            on_return: Some(Box::new(move |_| *total.borrow_mut() += 1)),
+        });
+
+        assert_eq!(fiber.run(), Ok(()));
+        assert_eq!(fiber.stack.pop_data(), Data::Integer(8));
+
+        let calls = calls.borrow();
+        assert_eq!(calls["<program>"], 1);
+        assert_eq!(calls["inc"], 3);
+        assert_eq!(calls["double"], 1);
+        assert_eq!(calls["fac"], 4);
+        assert_eq!(*returns.borrow(), calls.values().sum::<usize>());
+    }
+
+    #[test]
+    fn programs_end_in_return() {
+        let programs = [
+            ("", Data::Unit),
+            ("1", Data::Integer(1)),
+            ("x = 1; y = 2; (x, y)", integers(&[1, 2])),
+            ("f = x -> x; f 3", Data::Integer(3)),
+            (
+                "fac = { 0 -> 1, n -> n * fac (n - 1) }\nfac 4",
+                Data::Integer(24),
+            ),
+            ("m = mod { a = 1 }; m; 5", Data::Integer(5)),
+        ];
+
+        for (source, expected) in programs {
+            let mut fiber = fiber(source);
+            let code = &fiber.closure.lambda.code;
+            let last = (0..code.len())
+                .rev()
+                .find(|i| code[*i] == Opcode::Return as u8);
+            assert!(last.is_some(), "{}", source);
+
+            assert_eq!(fiber.run(), Ok(()), "{}", source);
+            assert_eq!(fiber.state(), FiberState::Finished);
+            // only the root frame and the result are left
+            assert_eq!(fiber.stack.stack.len(), 2, "{}", source);
+            assert_eq!(fiber.stack.pop_data(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn double_run() {
+        let mut fiber = fiber("x = 1; x");
+        assert_eq!(fiber.state(), FiberState::Idle);
+        assert!(fiber.run().is_ok());
+        assert_eq!(fiber.state(), FiberState::Finished);
+
+        let trace = fiber.run().unwrap_err();
+        assert!(trace.to_string().contains("already finished"));
+        assert_eq!(fiber.stack.pop_data(), Data::Integer(1));
+    }
+
+    #[test]
+    fn nested_run() {
+        let mut fiber = fiber("1");
+        // as if `run` were called from inside an FFI callback
+        fiber.state = FiberState::Running;
+
+        let trace = fiber.run().unwrap_err();
+        assert!(trace.to_string().contains("already running"));
+        assert_eq!(fiber.ip, 0);
+    }
+
+    #[test]
+    fn run_after_error() {
+        let mut fiber = fiber("(1, ..2)");
+        assert!(fiber.run().is_err());
+        assert_eq!(fiber.state(), FiberState::Errored);
+
+        let trace = fiber.run().unwrap_err();
+        assert!(trace.to_string().contains("because of an error"));
+    }
+
+    #[test]
+    fn capture_before_assignment() {
+        // the function is called before the variable it captures is assigned
+        let error = run("f = () -> y; z = f (); y = 2; z").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("`y` was referenced before assignment"),
+            "{}",
+            error
+        );
+        assert_eq!(error.code(), Some(error_codes::USE_BEFORE_ASSIGNMENT));
+
+        // but it can be called once the variable is assigned
+        assert_eq!(run("f = () -> y; y = 2; f ()"), Ok(Data::Integer(2)));
+    }
+
+    #[test]
+    fn capture_free_closure_shared() {
+        let result = run("make = () -> x -> x; (make (), make ())").unwrap();
+        let closures = match result {
+            Data::Tuple(closures) => closures,
+            other => panic!("expected a tuple, found {:?}", other),
+        };
+
+        match (&closures[0], &closures[1]) {
+            (Data::Closure(a), Data::Closure(b)) => {
+                assert!(std::rc::Rc::ptr_eq(&a.lambda, &b.lambda))
+            }
+            other => panic!("expected two closures, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn module_record() {
+        let result = run("y = 3; m = mod { a = 1; b = (a, y) }; m").unwrap();
+        let fields = match &result {
+            Data::Record(fields) => fields.values().cloned().collect::<Vec<_>>(),
+            other => panic!("expected a record, found {:?}", other),
+        };
+
+        assert_eq!(fields, vec![Data::Integer(1), integers(&[1, 3])]);
+        assert_eq!(format!("{}", result), "{a = 1; b = (1, 3)}");
+    }
+
+    #[test]
+    fn module_field_names() {
+        let result = run("mod { a = 1; b = 2 }").unwrap();
+        let fields = match result {
+            Data::Record(fields) => fields,
+            other => panic!("expected a record, found {:?}", other),
+        };
+
+        let names = fields.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b"]);
+        assert_eq!(fields["a"], Data::Integer(1));
+        assert_eq!(fields["b"], Data::Integer(2));
+
+        // names survive destructuring and capture by closures
+        let result = run("mod { (a, b) = (1, 2); f = () -> a }").unwrap();
+        assert!(matches!(result, Data::Record(ref r) if r.keys().eq(["a", "b", "f"].iter())));
+    }
+
+    #[test]
+    fn record_literal() {
+        let result = run("x = 2; r = { a = 1, b = x + 1 }\n{ r | b = 4 }");
+        assert_eq!(format!("{}", result.unwrap()), "{a = 1; b = 4}");
+
+        // the value of a field can be a call, even one that is not in tail position
+        let result = run("f = n -> n; { a = f 1, b = (f 2, f 3) }");
+        assert_eq!(format!("{}", result.unwrap()), "{a = 1; b = (2, 3)}");
+    }
+
+    #[test]
+    fn record_update() {
+        let result = run("m = mod { a = 1; b = 2 }\n{ m | b = 3 }");
+        assert_eq!(format!("{}", result.unwrap()), "{a = 1; b = 3}");
+
+        // the original record is unchanged
+        let result = run("m = mod { a = 1; b = 2 }\nn = { m | a = 4 }\nm");
+        assert_eq!(format!("{}", result.unwrap()), "{a = 1; b = 2}");
+    }
+
+    #[test]
+    fn record_update_unknown_field() {
+        let trace = run("m = mod { a = 1 }\n{ m | c = 3 }").unwrap_err();
+        assert!(trace.to_string().contains("has no field `c` to update"));
+
+        let trace = run("{ 7 | c = 3 }").unwrap_err();
+        assert!(trace.to_string().contains("is not a record"));
+    }
+
+    #[test]
+    fn call_non_function() {
+        let trace = run("f = 3; f 4").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("is not a function and can not be called"));
+        assert_eq!(trace.code(), Some(error_codes::CALL_NON_FUNCTION));
+        assert!(trace.to_string().contains("Runtime Call Error[R0001]: "));
+    }
+
+    /// Whether a number is less than five, for use as a loop condition.
+    const BELOW_FIVE: &str = "below_five = n -> match n { 5 -> False, n -> True }\n";
+
+    #[test]
+    fn while_counts() {
+        let source =
+            "i = 0\ntotal = 0\nwhile (below_five i) {\n    i = i + 1\n    total = total + i\n}";
+        let result = run(&format!("{}{}\n(i, total)", BELOW_FIVE, source));
+        assert_eq!(result.unwrap(), integers(&[5, 15]));
+
+        // a while loop evaluates to unit
+        let result = run(&format!(
+            "{}i = 0\nwhile (below_five i) {{ i = i + 1 }}",
+            BELOW_FIVE
+        ));
+        assert_eq!(result.unwrap(), Data::Unit);
+    }
+
+    #[test]
+    fn loop_break_value() {
+        let source =
+            "i = 0\nloop {\n    i = i + 1\n    if (below_five i) { () } else { break i * 10 }\n}";
+        let result = run(&format!("{}{}", BELOW_FIVE, source));
+        assert_eq!(result.unwrap(), Data::Integer(50));
+
+        // values left on the stack by enclosing expressions are dropped
+        let result = run("x = (1, 2, loop { (3, break 4) })\nx");
+        assert_eq!(result.unwrap(), integers(&[1, 2, 4]));
+        let result = run("loop { break }");
+        assert_eq!(result.unwrap(), Data::Unit);
+    }
+
+    #[test]
+    fn loop_break_in_match() {
+        // a break ends an arm, rather than taking the arms after it as a tuple
+        let result = run("loop { match 1 { 3 -> break 0, n -> break 5 } }");
+        assert_eq!(result.unwrap(), Data::Integer(5));
+        let result = run("loop { match 1 { 1 -> break 7, x -> () } }");
+        assert_eq!(result.unwrap(), Data::Integer(7));
+        let result = run("loop { match 1 { 1 -> break (7, 8), x -> () } }");
+        assert_eq!(result.unwrap(), integers(&[7, 8]));
+
+        // values beneath the match are dropped
+        let result = run("loop { x = (1, match 2 { 2 -> break 7, _ -> 0 }) }");
+        assert_eq!(result.unwrap(), Data::Integer(7));
+        let result = run("loop { (1, match (2, 3) { (2, y) -> break y, _ -> 0 }) }");
+        assert_eq!(result.unwrap(), Data::Integer(3));
+
+        let source = "i = 0; loop { i = i + 1; (1, match i { 1 -> continue, n -> break (n, 2) }) }";
+        assert_eq!(run(source).unwrap(), integers(&[2, 2]));
+    }
+
+    #[test]
+    fn loop_break_in_call() {
+        let result = run("loop { (1, (x -> x) (break 5)) }");
+        assert_eq!(result.unwrap(), Data::Integer(5));
+        let result = run("loop { (1, (x -> y -> y) 2 (break 5)) }");
+        assert_eq!(result.unwrap(), Data::Integer(5));
+        let result = run("loop { (1, (x -> y -> y) (break 5) 2) }");
+        assert_eq!(result.unwrap(), Data::Integer(5));
+
+        let source = "i = 0; loop { i = i + 1; (1, (x -> x) (if (equal (i, 1)) { continue } else { break i })) }";
+        assert_eq!(run(source).unwrap(), Data::Integer(2));
+
+        // a loop can not be left from inside a function,
+        // even one in the value of a break
+        let error = compile_source(Source::source("loop { break (1, x -> break x) }")).unwrap_err();
+        assert!(error.reason.contains("only be used inside a loop"));
+    }
+
+    #[test]
+    fn loop_continue() {
+        // only the odd numbers are added
+        let source = "odd = n -> match n { 1 -> True, 3 -> True, n -> False }\ni = 0\ntotal = 0\n\
+            while (below_five i) {\n    i = i + 1\n    if (odd i) { () } else { continue }\n    total = total + i\n}\ntotal";
+        let result = run(&format!("{}{}", BELOW_FIVE, source));
+        assert_eq!(result.unwrap(), Data::Integer(4));
+    }
+
+    #[test]
+    fn nested_loops() {
+        let source = "i = 0\ntotal = 0\nwhile (below_five i) {\n    i = i + 1\n    j = 0\n    \
+            loop {\n        j = j + 1\n        total = total + 1\n        if (below_five j) { () } else { break }\n    }\n}\ntotal";
+        let result = run(&format!("{}{}", BELOW_FIVE, source));
+        assert_eq!(result.unwrap(), Data::Integer(25));
+    }
+
+    fn point_fiber(source: &str) -> Fiber {
+        let mut ffi = ffi_core();
+        ffi.add(
+            "point",
+            FFIFunction::new(Box::new(|_| {
+                Ok(Data::Label(
+                    7,
+                    Box::new(Data::tuple(vec![Data::Integer(1), Data::Integer(2)])),
+                ))
+            })),
+        )
+        .unwrap();
+
+        let lambda = compile_source_with_ffi(Source::source(source), ffi.clone()).unwrap();
+        Fiber::init_with_ffi(Closure::wrap(lambda), ffi)
+    }
+
+    /// Builds a fiber with an FFI function `scale` that declares its signature.
+    /// The function returns the wrong type when scaling by zero.
+    fn typed_fiber(source: &str) -> Fiber {
+        let mut ffi = ffi_core();
+        let scale = FFIFunction::new(Box::new(|data| match data {
+            Data::Tuple(t) => match (&t[0], &t[1]) {
+                (Data::Float(x), Data::Integer(0)) => Ok(Data::string(x.to_string())),
+                (Data::Float(x), Data::Integer(by)) => Ok(Data::Float(x * *by as f64)),
+                _ => unreachable!("arguments are checked against the signature"),
+            },
+            _ => unreachable!("arguments are checked against the signature"),
+        }))
+        .with_signature(vec![Type::Float, Type::Integer], Type::Float);
+        ffi.add("scale", scale).unwrap();
+
+        let lambda = compile_source_with_ffi(Source::source(source), ffi.clone()).unwrap();
+        Fiber::init_with_ffi(Closure::wrap(lambda), ffi)
+    }
+
+    #[test]
+    fn ffi_signature_well_typed() {
+        let mut fiber = typed_fiber("magic \"scale\" (1.5, 2)");
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::Float(3.0));
+    }
+
+    #[test]
+    fn ffi_signature_mistyped() {
+        let trace = typed_fiber("magic \"scale\" (1, 2)").run().unwrap_err();
+        let message = trace.to_string();
+        assert!(message.contains(
+            "Expected an argument of type `(float, integer)`, but found `(integer, integer)`"
+        ));
+        assert!(message.contains("in a call to the FFI function `scale`"));
+
+        let trace = typed_fiber("magic \"scale\" 1.5").run().unwrap_err();
+        assert!(trace.to_string().contains("but found `float`"));
+
+        // results are checked too
+        let trace = typed_fiber("magic \"scale\" (1.5, 0)").run().unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("Expected a result of type `float`, but the function returned `string`"));
+    }
+
+    #[test]
+    fn nested_tuple() {
+        let result = run("(1, 2), 3").unwrap();
+        let pair = Data::tuple(vec![Data::Integer(1), Data::Integer(2)]);
+        assert_eq!(result, Data::tuple(vec![pair, Data::Integer(3)]));
+    }
+
+    #[test]
+    fn closures_share_lambda() {
+        // each iteration of the loop closes over a different `n`
+        let result = run("
+            count = { 0 -> (), n -> (x -> n, count (n - 1)) }
+            count 3
+        ")
+        .unwrap();
+
+        let mut closures = vec![];
+        let mut rest = result;
+        while let Data::Tuple(pair) = rest {
+            let mut pair = Rc::unwrap_or_clone(pair);
+            rest = pair.pop().unwrap();
+            match pair.pop().unwrap() {
+                Data::Closure(closure) => closures.push(*closure),
+                other => panic!("expected a closure, found {:?}", other),
+            }
+        }
+        assert_eq!(closures.len(), 3);
+
+        // the code is compiled once, only the captures are rebuilt
+        for closure in closures.iter() {
+            assert!(Rc::ptr_eq(&closure.lambda, &closures[0].lambda));
+        }
+        let captured = |closure: &Closure| closure.captures[0].borrow().clone().unwrap();
+        let values = closures.iter().map(captured).collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![Data::Integer(3), Data::Integer(2), Data::Integer(1)]
+        );
+    }
+
+    #[test]
+    fn preallocated_stack() {
+        // not a tail call, so each call keeps its frame on the stack
+        let source = "
+            count = { 0 -> 0, n -> 1 + count (n - 1) }
+            count 20
+        ";
+
+        let mut preallocated = fiber(source);
+        let capacity = preallocated.stack.stack.capacity();
+        preallocated.run().unwrap();
+        assert_eq!(preallocated.stack.pop_data(), Data::Integer(20));
+        assert_eq!(preallocated.stack.stack.capacity(), capacity);
+
+        // an empty stack has to grow while the program runs
+        let mut growing = fiber(source);
+        growing.stack = Stack::init();
+        growing.stack.declare(growing.closure.lambda.decls);
+        let capacity = growing.stack.stack.capacity();
+        growing.run().unwrap();
+        assert_eq!(growing.stack.pop_data(), Data::Integer(20));
+        assert!(growing.stack.stack.capacity() > capacity);
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let run_with = |overflow| {
+            let ffi = ffi_core_with_overflow(overflow);
+            let source = Source::source("9223372036854775807 + 1");
+            let lambda = compile_source_with_ffi(source, ffi.clone()).unwrap();
+            let mut fiber = Fiber::init_with_ffi(Closure::wrap(lambda), ffi);
+            fiber.run().map(|()| fiber.stack.pop_data())
+        };
+
+        assert_eq!(run_with(Overflow::Wrap), Ok(Data::Integer(i64::MIN)));
+        assert_eq!(run_with(Overflow::Saturate), Ok(Data::Integer(i64::MAX)));
+        let error = run_with(Overflow::Checked).unwrap_err();
+        assert!(error.to_string().contains("Integer overflow in `add`"));
+        // checked arithmetic is the default
+        assert_eq!(run("9223372036854775807 + 1").unwrap_err(), error);
+    }
+
+    #[test]
+    fn operators_without_spaces() {
+        // `,..` is lexed as `,` followed by `..`
+        assert_eq!(run("a=(1,2); (0,..a)"), Ok(integers(&[0, 1, 2])));
+        assert_eq!(run("a=(1,2); (..a,..a)"), Ok(integers(&[1, 2, 1, 2])));
+    }
+
+    #[test]
+    fn ffi_call() {
+        assert_eq!(run("magic \"add\" (1, 2)"), Ok(Data::Integer(3)));
+    }
+
+    #[test]
+    fn type_of() {
+        let cases = [
+            ("1", "Integer"),
+            ("1.5", "Float"),
+            ("False", "Boolean"),
+            ("\"hi\"", "String"),
+            ("()", "Unit"),
+            ("(1, \"two\")", "Tuple"),
+            ("[1, 2]", "List"),
+            ("{ a = 1, }", "Record"),
+            ("mod { a = 1 }", "Record"),
+            ("x -> x", "Closure"),
+            ("{ y = 1; x -> y }", "Closure"),
+        ];
+        for (value, name) in cases {
+            let source = format!("magic \"type_of\" ({})", value);
+            assert_eq!(run(&source), Ok(Data::string(name)), "{}", source);
+        }
+
+        // data that can not be written in a program yet
+        let lambda = Rc::new(Lambda::empty());
+        let cases = [
+            (Data::Lambda(lambda), "Lambda"),
+            (Data::Kind(0), "Kind"),
+            (Data::Label(0, Box::new(Data::Unit)), "Label"),
+            (Data::map(vec![]).unwrap(), "Map"),
+        ];
+        for (data, name) in cases {
+            assert_eq!(
+                crate::kernel::reflect::type_of(data),
+                Ok(Data::string(name))
+            );
+        }
+    }
+
+    #[test]
+    fn read_lines() {
+        let source = "
+            first = magic \"read_line\" ()
+            second = magic \"read_line\" ()
+            (first, second, magic \"read_line\" ())
+        ";
+        let mut fiber = fiber(source);
+        fiber.set_input(Input::from_reader(Box::new(Cursor::new("one\ntwo\n"))));
+        fiber.run().unwrap();
+        assert_eq!(
+            fiber.stack.pop_data(),
+            Data::tuple(vec![Data::string("one"), Data::string("two"), Data::Unit])
+        );
+
+        let trace = run("magic \"read_line\" 1").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("Expected an argument of type `()`, but found `integer`"));
+    }
+
+    #[test]
+    fn program_args() {
+        let source = "
+            args = magic \"args\" ()
+            magic \"concat\" (magic \"list\" (\"echo\", \"-n\"), args)
+        ";
+        let mut fiber = fiber(source);
+        fiber.set_args(vec!["one".to_string(), "two words".to_string()]);
+        fiber.run().unwrap();
+        assert_eq!(
+            fiber.stack.pop_data(),
+            Data::list(vec![
+                Data::string("echo"),
+                Data::string("-n"),
+                Data::string("one"),
+                Data::string("two words"),
+            ])
+        );
+
+        // a program run without arguments gets an empty list
+        assert_eq!(run("magic \"args\" ()"), Ok(Data::list(vec![])));
+        let trace = run("magic \"args\" 1").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("Expected an argument of type `()`, but found `integer`"));
+    }
+
+    #[test]
+    fn small_locals() {
+        // the first four variables are loaded and saved without an operand
+        let source = "
+            a = 1; b = 2; c = 3; d = 4; e = 5; f = 6
+            (f, e, d, c, b, a)
+        ";
+        let opcodes = opcodes(&compile_source(Source::source(source)).unwrap());
+        for opcode in [Opcode::Load0, Opcode::Save3, Opcode::Load, Opcode::Save] {
+            assert!(opcodes.contains(&opcode), "{:?} in {:?}", opcode, opcodes);
+        }
+        assert_eq!(run(source), Ok(integers(&[6, 5, 4, 3, 2, 1])));
+
+        for specialized in [true, false] {
+            let mut fiber = repeated_loads(specialized, 10);
+            fiber.run().unwrap();
+            assert_eq!(fiber.stack.pop_data(), Data::Integer(7));
+        }
+    }
+
+    /// Checks that loads without an operand are not slower than loads with one.
+    /// Run with `cargo test --release -- --ignored load_speed`.
+    #[test]
+    #[ignore]
+    fn load_speed() {
+        const LOADS: usize = 1_000_000;
+        let elapsed = [false, true].map(|specialized| {
+            let mut fiber = repeated_loads(specialized, LOADS);
+            let start = std::time::Instant::now();
+            fiber.run().unwrap();
+            start.elapsed()
+        });
+        // leave some room for noise
+        assert!(
+            elapsed[1] <= elapsed[0] * 2,
+            "{:?} for {} loads of Load0, {:?} of Load 0",
+            elapsed[1],
+            LOADS,
+            elapsed[0]
+        );
+    }
+
+    #[test]
+    fn operator_sections() {
+        let cases = [
+            ("(+) 1 2", Data::Integer(3)),
+            ("(-) 10 3", Data::Integer(7)),
+            ("(+ 1) 2", Data::Integer(3)),
+            ("(/ 2) 10", Data::Integer(5)),
+            ("(10 -) 3", Data::Integer(7)),
+            ("(2 **) 3", Data::Integer(8)),
+            ("(== 2) (1 + 1)", Data::Boolean(true)),
+            (
+                "([0] ++) [1]",
+                Data::list(vec![Data::Integer(0), Data::Integer(1)]),
+            ),
+            // operands of a section are not confused with variables
+            ("x = 5; (x -) 1", Data::Integer(4)),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(expected), "{}", source);
+        }
+    }
+
+    #[test]
+    fn fold_section() {
+        let source = "
+            fold = f -> acc -> n -> match n {
+                0 -> acc,
+                n -> fold f (f acc n) (n - 1),
+            }
+            (fold (+) 0 4, fold (*) 1 4)
+        ";
+        assert_eq!(run(source), Ok(integers(&[10, 24])));
+    }
+
+    #[test]
+    fn counting_generator() {
+        let source = "
+            count = n -> {
+                magic \"yield\" n
+                count (n + 1)
+            }
+            count 1
+        ";
+        let mut generator = fiber(source);
+        for expected in 1..=3 {
+            let yielded = generator.resume(Data::Unit).unwrap();
+            assert_eq!(yielded, Some(Data::Integer(expected)));
+            assert_eq!(generator.state(), FiberState::Suspended);
+        }
+
+        // a suspended fiber can only be resumed, not run
+        assert!(generator
+            .run()
+            .unwrap_err()
+            .to_string()
+            .contains("resume it"));
+    }
+
+    #[test]
+    fn resume_with_values() {
+        let source = "
+            x = magic \"yield\" \"first\"
+            y = magic \"yield\" (x * 2)
+            (x, y)
+        ";
+        let mut generator = fiber(source);
+        assert_eq!(
+            generator.resume(Data::Unit),
+            Ok(Some(Data::string("first")))
+        );
+        assert_eq!(
+            generator.resume(Data::Integer(21)),
+            Ok(Some(Data::Integer(42)))
+        );
+
+        // once finished, the result is on the stack, as when run
+        assert_eq!(generator.resume(Data::Integer(5)), Ok(None));
+        assert_eq!(generator.state(), FiberState::Finished);
+        assert_eq!(generator.stack.pop_data(), integers(&[21, 5]));
+        assert!(generator.resume(Data::Unit).is_err());
+    }
+
+    #[test]
+    fn yield_without_resume() {
+        // nothing can resume a fiber that is run,
+        // so a yield is an error rather than a result that was never computed
+        let source = "
+            f = n -> magic \"yield\" n
+            x = f 1
+            (x, 2)
+        ";
+        let mut run = fiber(source);
+        let trace = run.run().unwrap_err();
+        assert!(
+            trace.to_string().contains("rather than resumed"),
+            "{}",
+            trace
+        );
+        assert_eq!(run.state(), FiberState::Errored);
+        assert!(run.resume(Data::Unit).is_err());
+
+        // the same program runs to the end when resumed
+        let mut generator = fiber(source);
+        assert_eq!(generator.resume(Data::Unit), Ok(Some(Data::Integer(1))));
+        assert_eq!(generator.resume(Data::Integer(5)), Ok(None));
+        assert_eq!(generator.stack.pop_data(), integers(&[5, 2]));
+    }
+
+    #[test]
+    fn yield_inside_ffi_call() {
+        // the function passed to `try` is called by an FFI function,
+        // so there is nothing to resume once it yields
+        let source = "magic \"try\" (() -> magic \"yield\" 1, error -> error)";
+        let caught = match run(source).unwrap() {
+            Data::Record(fields) => fields["message"].clone(),
+            other => panic!("expected a record, found {:?}", other),
+        };
+        assert_eq!(
+            caught,
+            Data::string("Can not yield from a function called by an FFI function")
+        );
+    }
+
+    #[test]
+    fn try_success() {
+        // the handler is not called if nothing goes wrong
+        let source = "
+            handler = error -> \"caught\"
+            x = magic \"try\" (() -> magic \"add\" (1, 2), handler)
+            (x, 4)
+        ";
+        assert_eq!(run(source), Ok(integers(&[3, 4])));
+    }
+
+    #[test]
+    fn try_catch() {
+        let source = "
+            divide = (a, b) -> magic \"div\" (a, b)
+            handler = error -> (\"caught\", error)
+            caught = magic \"try\" (() -> divide (1, 0), handler)
+            (caught, divide (6, 3))
+        ";
+        let mut fields = BTreeMap::new();
+        fields.insert("kind".to_string(), Data::string("FFI Call"));
+        fields.insert("message".to_string(), Data::string("Division by zero"));
+        fields.insert("code".to_string(), Data::Unit);
+        let caught = Data::tuple(vec![Data::string("caught"), Data::record(fields)]);
+
+        // the fiber keeps running after the error is handled
+        assert_eq!(run(source), Ok(Data::tuple(vec![caught, Data::Integer(2)])));
+
+        // errors raised by the handler are not caught
+        let source = "magic \"try\" (() -> magic \"div\" (1, 0), e -> magic \"div\" (2, 0))";
+        let trace = run(source).unwrap_err();
+        assert!(trace.to_string().contains("Division by zero"));
+
+        let trace = run("magic \"try\" (1, 2)").unwrap_err();
+        assert!(trace.to_string().contains(
+            "Expected an argument of type `(function, function)`, but found `(integer, integer)`"
+        ));
+    }
+
+    #[test]
+    fn read_files() {
+        let path = env::temp_dir().join("passerine-read-file.txt");
+        fs::write(&path, "contents\nof a file").unwrap();
+        let source = format!("magic \"read_file\" {:?}", path.display().to_string());
+        assert_eq!(run(&source), Ok(Data::string("contents\nof a file")));
+        fs::remove_file(&path).unwrap();
+
+        let trace = run(&source).unwrap_err();
+        assert!(trace.to_string().contains("Could not read the file"));
+        let trace = run("magic \"read_file\" 7").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("Expected an argument of type `string`, but found `integer`"));
+    }
+
+    #[test]
+    fn print_through_ffi() {
+        let source =
+            "magic \"println\" (1, \"two\"); magic \"print\" \"three\"; magic \"println\" (); 3";
+        let written = Written::default();
+        let mut fiber = fiber(source);
+        fiber.set_output(Output::from_writer(Box::new(written.clone())));
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::Integer(3));
+        assert_eq!(written.text(), "(1, two)\nthree()\n");
+
+        // printing shows labeled data with the fiber's show functions
+        let closure = |source| match run(source).unwrap() {
+            Data::Closure(closure) => *closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+        let written = Written::default();
+        let mut fiber = point_fiber("magic \"println\" (magic \"point\" ())");
+        fiber.register_show(LabelKind(7), closure("x -> \"a point\""));
+        fiber.set_output(Output::from_writer(Box::new(written.clone())));
+        fiber.run().unwrap();
+        assert_eq!(written.text(), "a point\n");
+
+        // a show function must return a string
+        let written = Written::default();
+        let mut fiber = point_fiber("magic \"println\" (magic \"point\" ())");
+        fiber.register_show(LabelKind(7), closure("x -> 0"));
+        fiber.set_output(Output::from_writer(Box::new(written.clone())));
+        let trace = fiber.run().unwrap_err().to_string();
+        assert!(trace.contains("string"), "{}", trace);
+        assert_eq!(written.text(), "");
+    }
+
+    /// A writer that can still be read from after it has been given to a fiber.
+    #[derive(Clone, Default)]
+    struct Written(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for Written {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Written {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn debug_structure() {
+        let show = match run("x -> \"a point\"").unwrap() {
+            Data::Closure(closure) => *closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+
+        // printing summarizes labeled data, but debugging shows its structure
+        let source = "p = magic \"point\" (); magic \"println\" p; magic \"debug\" p";
+        let mut point = point_fiber(source);
+        point.register_show(LabelKind(7), show);
+        let written = Written::default();
+        point.set_output(Output::from_writer(Box::new(written.clone())));
+        point.run().unwrap();
+        assert_eq!(
+            written.text(),
+            "a point\nLabel(7, Tuple([Integer(1), Integer(2)]))\n"
+        );
+
+        let source = "
+            double = x -> x + x
+            magic \"debug\" (double, y -> double y, { a = [1.5], b = \"b\", })
+        ";
+        let mut fiber = fiber(source);
+        let written = Written::default();
+        fiber.set_output(Output::from_writer(Box::new(written.clone())));
+        fiber.run().unwrap();
+        // functions show what they capture, rather than being hidden
+        assert_eq!(
+            written.text(),
+            "Tuple([\
+                Closure(double, captures: []), \
+                Closure(anonymous, captures: [double]), \
+                Record({\"a\": List([Float(1.5)]), \"b\": String(\"b\")})\
+            ])\n"
+        );
+    }
+
+    #[test]
+    fn custom_show() {
+        let show = match run("x -> \"a point\"").unwrap() {
+            Data::Closure(closure) => *closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+
+        let mut fiber = point_fiber("magic \"to_string\" (magic \"point\" ())");
+        fiber.register_show(LabelKind(7), show);
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::string("a point"));
+    }
+
+    #[test]
+    fn nested_show() {
+        let show = match run("x -> \"a point\"").unwrap() {
+            Data::Closure(closure) => *closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+
+        let source = "p = magic \"point\" (); magic \"to_string\" ([p, p], { a = p, b = 1 })";
+        let mut fiber = point_fiber(source);
+        fiber.register_show(LabelKind(7), show);
+        fiber.run().unwrap();
+        assert_eq!(
+            fiber.stack.pop_data(),
+            Data::string("([a point, a point], {a = a point; b = 1})")
+        );
+    }
+
+    #[test]
+    fn apply_error_unwinds() {
+        let closure = |source| match run(source).unwrap() {
+            Data::Closure(closure) => *closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+        let fails = closure("f = x -> x 1; g = x -> (f x, 2); g");
+        let works = closure("x -> (x, x)");
+
+        let mut fiber = fiber("()");
+        let frames = fiber.stack.frames.len();
+        let stack = fiber.stack.stack.len();
+
+        let trace = fiber.apply(fails, Data::Integer(3)).unwrap_err();
+        assert!(trace.to_string().contains("is not a function"));
+        // the calls made by the failing closure are part of the trace
+        assert!(trace.to_string().contains("f x"));
+
+        // the fiber is back where it was before the call
+        assert_eq!(fiber.stack.frames.len(), frames);
+        assert_eq!(fiber.stack.stack.len(), stack);
+        assert_eq!(fiber.ip, 0);
+
+        let result = fiber.apply(works, Data::Integer(4));
+        assert_eq!(result, Ok(integers(&[4, 4])));
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::Unit);
+    }
+
+    #[test]
+    fn default_show() {
+        let mut fiber = point_fiber("magic \"to_string\" (magic \"point\" ())");
+        fiber.run().unwrap();
+        assert_eq!(fiber.stack.pop_data(), Data::string("7 (1, 2)"));
+    }
+
+    #[test]
+    fn environment_snapshot() {
+        let snapshots = Rc::new(RefCell::new(vec![]));
+        let snapshot = FFIFunction::new(Box::new({
+            let snapshots = Rc::clone(&snapshots);
+            move |data| match data {
+                Data::Closure(closure) => {
+                    snapshots.borrow_mut().push(closure.environment());
+                    Ok(Data::Unit)
+                }
+                _ => Err("Expected a closure".to_string()),
+            }
+        }));
+
+        let mut ffi = ffi_core();
+        ffi.add("snapshot", snapshot).unwrap();
+        let source = "x = 1; f = () -> x; magic \"snapshot\" f; x = 2; f";
+        let lambda = compile_source_with_ffi(Source::source(source), ffi.clone()).unwrap();
+        let mut fiber = Fiber::init_with_ffi(Closure::wrap(lambda), ffi);
+        fiber.run().unwrap();
+
+        let closure = match fiber.stack.pop_data() {
+            Data::Closure(closure) => closure,
+            other => panic!("expected a closure, found {:?}", other),
+        };
+        let before = vec![("x".to_string(), Data::Integer(1))];
+        let after = vec![("x".to_string(), Data::Integer(2))];
+        assert_eq!(snapshots.borrow()[0], before);
+        assert_eq!(closure.environment(), after);
+    }
+
+    fn list(numbers: &[i64]) -> Data {
+        Data::list(numbers.iter().map(|n| Data::Integer(*n)).collect())
+    }
+
+    #[test]
+    fn string_lengths() {
+        // `len` counts characters, like `str_chars`
+        let cases = [("café", 5, 4), ("🐦 tweet", 10, 7), ("", 0, 0)];
+        for (string, bytes, chars) in cases {
+            let source = format!(
+                "s = {:?}; (magic \"str_bytes\" s, magic \"str_chars\" s, magic \"len\" s)",
+                string
+            );
+            assert_eq!(run(&source), Ok(integers(&[bytes, chars, chars])));
+        }
+
+        let trace = run("magic \"str_chars\" [1]").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("Expected an argument of type `string`, but found `list`"));
+    }
+
+    #[test]
+    #[cfg(feature = "graphemes")]
+    fn string_graphemes() {
+        let source = "(magic \"str_graphemes\" \"cafe\u{301}\", magic \"str_graphemes\" \"👍🏽\")";
+        assert_eq!(run(source), Ok(integers(&[4, 1])));
+    }
+
+    #[test]
+    fn collection_lengths() {
+        let cases = [
+            ("\"abc\"", 3),
+            ("\"héllo\"", 5),
+            ("[1, 2, 3]", 3),
+            ("[]", 0),
+            ("()", 0),
+            ("(1, (2, 3))", 2),
+            ("{ a = 1, b = 2 }", 2),
+        ];
+        for (collection, length) in cases {
+            let source = format!("magic \"len\" {}", collection);
+            assert_eq!(run(&source), Ok(Data::Integer(length)), "{}", source);
+            let source = format!("magic \"is_empty\" {}", collection);
+            assert_eq!(run(&source), Ok(Data::Boolean(length == 0)), "{}", source);
+        }
+
+        let trace = run("magic \"len\" 5").unwrap_err().to_string();
+        assert!(
+            trace.contains("`len` expects a string, tuple, list, record, or map, found an integer")
+        );
+        let trace = run("magic \"is_empty\" (x -> x)").unwrap_err().to_string();
+        assert!(trace.contains("`is_empty` expects"));
+    }
+
+    #[test]
+    fn list_literal() {
+        assert_eq!(run("[1, 2, 3]"), Ok(list(&[1, 2, 3])));
+        assert_eq!(run("[1]"), Ok(list(&[1])));
+        assert_eq!(run("[]"), Ok(list(&[])));
+    }
+
+    #[test]
+    fn list_concat() {
+        assert_eq!(run("[1, 2] ++ [3]"), Ok(list(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn list_slice() {
+        let result = run("lst = [1, 2, 3, 4]; slice lst 1 3");
+        assert_eq!(result, Ok(list(&[2, 3])));
+
+        // the arguments are curried, so can be passed one at a time
+        let result = run("from_one = n -> slice [1, 2, 3, 4] 1 n; from_one 4");
+        assert_eq!(result, Ok(list(&[2, 3, 4])));
+
+        // a definition replaces the builtin
+        assert_eq!(run("slice = x -> x; slice 7"), Ok(Data::Integer(7)));
+    }
+
+    #[test]
+    fn list_slice_out_of_range() {
+        let trace = run("slice [1, 2, 3, 4] 2 5").unwrap_err();
+        assert!(trace.to_string().contains("length 4"));
+        assert!(trace.to_string().contains("the FFI function `slice`"));
+    }
+
+    #[test]
+    fn bit_operations() {
+        let cases = [
+            ("magic \"bits::band\" (12, 10)", 8),
+            ("magic \"bits::bor\" (12, 10)", 14),
+            ("magic \"bits::bxor\" (12, 10)", 6),
+            ("magic \"bits::bnot\" 0", -1),
+            ("magic \"bits::shl\" (3, 4)", 48),
+            ("magic \"bits::shr\" (48, 4)", 3),
+            ("magic \"bits::shr\" (0 - 16, 2)", -4),
+            ("magic \"bits::shl\" (1, 63)", i64::MIN),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(Data::Integer(expected)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn bit_shift_errors() {
+        let trace = run("magic \"bits::shl\" (1, 0 - 1)").unwrap_err();
+        assert!(trace.to_string().contains("negative amount"));
+        let trace = run("magic \"bits::shr\" (1, 64)").unwrap_err();
+        assert!(trace.to_string().contains("64-bit integer by 64"));
+    }
+
+    #[test]
+    fn bit_type_errors() {
+        let trace = run("magic \"bits::band\" (1, 2.0)").unwrap_err();
+        let expected =
+            "Expected an argument of type `(integer, integer)`, but found `(integer, float)`";
+        assert!(trace.to_string().contains(expected));
+        let trace = run("magic \"bits::bor\" 1").unwrap_err();
+        let expected = "Expected an argument of type `(integer, integer)`, but found `integer`";
+        assert!(trace.to_string().contains(expected));
+        let trace = run("magic \"bits::bnot\" \"x\"").unwrap_err();
+        let expected = "Expected an argument of type `integer`, but found `string`";
+        assert!(trace.to_string().contains(expected));
+    }
+
+    #[test]
+    fn transcendental() {
+        let cases = [
+            ("magic \"sqrt\" 2", 2.0_f64.sqrt()),
+            ("magic \"sqrt\" 2.25", 1.5),
+            ("magic \"sin\" 1", 1.0_f64.sin()),
+            ("magic \"cos\" 0.5", 0.5_f64.cos()),
+            ("magic \"tan\" 1", 1.0_f64.tan()),
+            ("magic \"exp\" 1", std::f64::consts::E),
+            ("magic \"ln\" (magic \"exp\" 2)", 2.0),
+            ("magic \"log\" (2, 1024)", 10.0),
+            ("magic \"log\" (10.0, 0.001)", -3.0),
+            ("magic \"abs\" (0.0 - 2.5)", 2.5),
+        ];
+        for (source, expected) in cases {
+            match run(source) {
+                Ok(Data::Float(f)) => assert!((f - expected).abs() < 1e-9, "{}", source),
+                other => panic!("{} evaluated to {:?}", source, other),
+            }
+        }
+        assert_eq!(run("magic \"abs\" (0 - 3)"), Ok(Data::Integer(3)));
+    }
+
+    #[test]
+    fn transcendental_domain_errors() {
+        let cases = [
+            (
+                "magic \"sqrt\" (0 - 1)",
+                "expects a non-negative number, found '-1'",
+            ),
+            ("magic \"ln\" 0", "expects a positive number, found '0'"),
+            ("magic \"log\" (10, 0.0 - 1.0)", "expects a positive number"),
+            ("magic \"log\" (1, 5)", "positive base other than 1"),
+            (
+                "magic \"sin\" \"x\"",
+                "Expected an argument of type `number`, but found `string`",
+            ),
+        ];
+        for (source, message) in cases {
+            let trace = run(source).unwrap_err().to_string();
+            assert!(trace.contains(message), "{}", trace);
+        }
+    }
+
+    #[test]
+    fn integer_functions() {
+        let cases = [
+            ("magic \"gcd\" (12, 18)", 6),
+            ("magic \"gcd\" (0 - 12, 18)", 6),
+            ("magic \"gcd\" (7, 0)", 7),
+            ("magic \"gcd\" (0, 0)", 0),
+            ("magic \"lcm\" (4, 6)", 12),
+            ("magic \"lcm\" (0 - 4, 6)", 12),
+            ("magic \"lcm\" (0, 6)", 0),
+            ("magic \"factorial\" 0", 1),
+            ("magic \"factorial\" 5", 120),
+            ("magic \"factorial\" 20", 2_432_902_008_176_640_000),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(Data::Integer(expected)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn integer_function_errors() {
+        let cases = [
+            (
+                "magic \"factorial\" (0 - 1)",
+                "expects a non-negative integer",
+            ),
+            (
+                "magic \"factorial\" 2.0",
+                "Expected an argument of type `integer`, but found `float`",
+            ),
+            ("magic \"factorial\" 21", "Integer overflow in `factorial`"),
+            (
+                "magic \"gcd\" (1, 2.0)",
+                "Expected an argument of type `(integer, integer)`, but found `(integer, float)`",
+            ),
+            (
+                "magic \"lcm\" 4",
+                "Expected an argument of type `(integer, integer)`, but found `integer`",
+            ),
+        ];
+        for (source, message) in cases {
+            let trace = run(source).unwrap_err().to_string();
+            assert!(trace.contains(message), "{}", trace);
+        }
+    }
+
+    #[test]
+    fn equality() {
+        let cases = [
+            ("1 == 1", true),
+            ("1 == 2", false),
+            ("\"a\" == \"a\"", true),
+            ("(1, (2, \"b\")) == (1, (2, \"b\"))", true),
+            ("(1, 2) == (1, 2, 3)", false),
+            ("[1, 2] == [1, 3]", false),
+            (
+                "mod { a = 1; b = (2, 3) } == mod { a = 1; b = (2, 3) }",
+                true,
+            ),
+            ("mod { a = 1; b = 2 } == mod { a = 1; b = 3 }", false),
+            ("mod { a = 1 } == mod { b = 1 }", false),
+            ("(0.0 / 0.0) == (0.0 / 0.0)", false),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(Data::Boolean(expected)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn equality_of_functions() {
+        let cases = [
+            ("f = x -> x; f == f", true),
+            ("f = x -> x; g = f; (f, 1) == (g, 1)", true),
+            ("f = x -> x; g = x -> x; f == g", false),
+            ("make = y -> x -> y; (make 1) == (make 1)", false),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source), Ok(Data::Boolean(expected)), "{}", source);
+        }
+    }
+
+    #[test]
+    fn equality_incomparable() {
+        let trace = run("1 == 1.0").unwrap_err().to_string();
+        assert!(trace.contains("Can not compare an integer '1' with a float '1.0'"));
+        let trace = run("(1, \"a\") == (1, 2)").unwrap_err().to_string();
+        assert!(trace.contains("Can not compare a string 'a' with an integer '2'"));
+    }
+
+    #[test]
+    fn loads_share_data() {
+        let source = format!(
+            "s = \"{}\"; t = (s, 1, 2); f = () -> (s, t); (s, t, f ())",
+            "x".repeat(10_000)
+        );
+        let items = match run(&source).unwrap() {
+            Data::Tuple(items) => items,
+            other => panic!("expected a tuple, found {:?}", other),
+        };
+        let (string, tuple, captured) = match &items[..] {
+            [Data::String(s), Data::Tuple(t), Data::Tuple(c)] => (s, t, c),
+            other => panic!("expected a string and two tuples, found {:?}", other),
+        };
+
+        // loading a local or a captured variable copies a pointer, not the data
+        assert!(matches!(&tuple[0], Data::String(s) if Rc::ptr_eq(s, string)));
+        assert!(matches!(&captured[0], Data::String(s) if Rc::ptr_eq(s, string)));
+        assert!(matches!(&captured[1], Data::Tuple(t) if Rc::ptr_eq(t, tuple)));
+    }
+
+    #[test]
+    fn factorial_clauses() {
+        let result = run("fac = { 0 -> 1, n -> n * fac (n - 1) }\nfac 5");
+        assert_eq!(result, Ok(Data::Integer(120)));
+    }
+
+    #[test]
+    fn tuple_clauses() {
+        let result = run("f = { (0, x) -> x, (n, x) -> f (n - 1, x + 1) }\nf (3, 10)");
+        assert_eq!(result, Ok(Data::Integer(13)));
+    }
+
+    #[test]
+    fn label_clauses() {
+        let source = "unwrap = { Some x -> x, None _ -> 0 }\n\
+                      (unwrap (Some 3), unwrap (None ()))";
+        let expected = Data::tuple(vec![Data::Integer(3), Data::Integer(0)]);
+        assert_eq!(run(source), Ok(expected));
+    }
+
+    #[test]
+    fn label_match_arms() {
+        let source = |value| {
+            format!(
+                "x = {}; match x {{ Ok (Some n) -> n, Ok (None _) -> 0, Err (a, b) -> a + b }}",
+                value
+            )
+        };
+        let cases = [("Ok (Some 7)", 7), ("Ok (None ())", 0), ("Err (1, 2)", 3)];
+        for (value, expected) in cases {
+            let source = source(value);
+            assert_eq!(run(&source), Ok(Data::Integer(expected)), "{}", source);
+        }
+
+        // labeled data is kept, so can be matched again later
+        let result = run("x = Some 1; match x { None y -> y, Some y -> (x, y) }");
+        match result.unwrap() {
+            Data::Tuple(items) => {
+                assert!(matches!(&items[0], Data::Label(_, data) if **data == Data::Integer(1)));
+                assert_eq!(items[1], Data::Integer(1));
+            }
+            other => panic!("expected a tuple, found {:?}", other),
+        }
+
+        let trace = run("x = Other 1; match x { Some y -> y }").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("does not match any of the patterns"));
+    }
+
+    #[test]
+    fn label_errors() {
+        let error = |source| {
+            compile_source(Source::source(source))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("x = Some").contains("A label must be applied to some data"));
+        assert!(error("match 1 { Pair a b -> a }").contains("A label wraps exactly one pattern"));
+        assert!(error("Some x = Some 1").contains("Can not assign to a label outside of a match"));
+    }
+
+    #[test]
+    fn if_operand() {
+        let result = run("x = True\n1 + if x { 2 } else { 3 }");
+        assert_eq!(result, Ok(Data::Integer(3)));
+        let result = run("1 + if False { 2 } else { 3 }");
+        assert_eq!(result, Ok(Data::Integer(4)));
+    }
+
+    #[test]
+    fn no_matching_clause() {
+        let trace = run("f = { 0 -> 1, 1 -> 2 }\nf 2").unwrap_err();
+        assert!(trace
+            .to_string()
+            .contains("does not match any of the patterns"));
+    }
+
+    #[test]
+    fn spread_middle() {
+        let result = run("b = (2, 3); (1, ..b, 4)");
+        assert_eq!(result, Ok(integers(&[1, 2, 3, 4])));
+    }
+
+    #[test]
+    fn spread_start() {
+        let result = run("b = (1, 2); (..b, 3)");
+        assert_eq!(result, Ok(integers(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn spread_end() {
+        let result = run("b = (2, 3); (1, ..b)");
+        assert_eq!(result, Ok(integers(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn spread_non_tuple() {
+        let trace = run("(1, ..2)").unwrap_err();
+        assert!(trace.to_string().contains("can not be spread"));
     }
 }
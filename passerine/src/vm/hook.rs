@@ -0,0 +1,66 @@
+use std::{fmt, rc::Rc, time::Instant};
+
+use crate::common::lambda::Lambda;
+
+/// A function being entered or exited by a `Fiber`.
+/// Passed to the fiber's hooks, e.g. to build a profile.
+#[derive(Debug)]
+pub struct Event<'a> {
+    /// The name of the function, if it was assigned to a variable.
+    pub name: Option<&'a str>,
+    /// The function itself,
+    /// which can be used to tell apart functions with the same name.
+    pub lambda: &'a Rc<Lambda>,
+    /// When the function was entered or exited.
+    pub time: Instant,
+}
+
+/// A callback invoked by a `Fiber` with an `Event`.
+pub type Hook = Box<dyn FnMut(&Event)>;
+
+/// Callbacks invoked by a `Fiber` as it runs.
+/// A tail call exits the calling function before entering the called one,
+/// so every call is matched by exactly one return.
+/// Calls to FFI functions are not reported.
+#[derive(Default)]
+pub struct Hooks {
+    /// Invoked when a function is entered.
+    pub on_call: Option<Hook>,
+    /// Invoked when a function is exited.
+    pub on_return: Option<Hook>,
+}
+
+impl Hooks {
+    /// Invokes the `on_call` hook, if any, for a function being entered.
+    pub fn call(&mut self, lambda: &Rc<Lambda>) {
+        if let Some(hook) = &mut self.on_call {
+            hook(&Event::now(lambda));
+        }
+    }
+
+    /// Invokes the `on_return` hook, if any, for a function being exited.
+    pub fn return_val(&mut self, lambda: &Rc<Lambda>) {
+        if let Some(hook) = &mut self.on_return {
+            hook(&Event::now(lambda));
+        }
+    }
+}
+
+impl<'a> Event<'a> {
+    fn now(lambda: &'a Rc<Lambda>) -> Event<'a> {
+        Event {
+            name: lambda.name.as_deref(),
+            lambda,
+            time: Instant::now(),
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_call", &self.on_call.is_some())
+            .field("on_return", &self.on_return.is_some())
+            .finish()
+    }
+}
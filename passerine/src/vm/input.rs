@@ -0,0 +1,71 @@
+use std::{
+    fmt,
+    io::{self, BufRead},
+};
+
+/// Where a `Fiber` reads input from, e.g. when `read_line` is called.
+/// Reads from standard input unless another reader is given,
+/// e.g. an in-memory buffer in tests.
+#[derive(Default)]
+pub struct Input {
+    /// The reader to read from, or `None` for standard input.
+    reader: Option<Box<dyn BufRead>>,
+}
+
+impl Input {
+    /// Reads input from a specific reader, rather than standard input.
+    pub fn from_reader(reader: Box<dyn BufRead>) -> Input {
+        Input {
+            reader: Some(reader),
+        }
+    }
+
+    /// Reads the next line, without its trailing newline.
+    /// Returns `None` once there is no input left.
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        // standard input is locked per line, rather than held for as long as the fiber,
+        // so that the host can still read from it between runs
+        let read = match &mut self.reader {
+            Some(reader) => reader.read_line(&mut line)?,
+            None => io::stdin().read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reader {
+            Some(_) => write!(f, "Input(reader)"),
+            None => write!(f, "Input(stdin)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let mut input = Input::from_reader(Box::new(Cursor::new("one\r\ntwo\n\nthree")));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("one"));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("two"));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some(""));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("three"));
+        assert_eq!(input.read_line().unwrap(), None);
+    }
+}
@@ -4,6 +4,10 @@
 
 pub mod fiber;
 
+pub mod hook;
+pub mod input;
+pub mod output;
+
 pub mod slot;
 pub mod stack;
 pub mod tag;
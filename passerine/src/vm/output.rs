@@ -0,0 +1,49 @@
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+/// Where a `Fiber` writes output to, e.g. when `println` is called.
+/// Writes to standard output unless another writer is given,
+/// e.g. an in-memory buffer in tests.
+#[derive(Default)]
+pub struct Output {
+    /// The writer to write to, or `None` for standard output.
+    writer: Option<Box<dyn Write>>,
+}
+
+impl Output {
+    /// Writes output to a specific writer, rather than standard output.
+    pub fn from_writer(writer: Box<dyn Write>) -> Output {
+        Output {
+            writer: Some(writer),
+        }
+    }
+
+    /// Writes some text, as is.
+    pub fn write(&mut self, text: &str) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.write_all(text.as_bytes()),
+            // `print!`, rather than writing to `io::stdout` directly,
+            // so that output can still be captured, e.g. by the test harness
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes some text, followed by a newline.
+    pub fn write_line(&mut self, text: &str) -> io::Result<()> {
+        self.write(&format!("{}\n", text))
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.writer {
+            Some(_) => write!(f, "Output(writer)"),
+            None => write!(f, "Output(stdout)"),
+        }
+    }
+}
@@ -27,26 +27,34 @@ pub enum Slot {
     // Uninitialized Data
     NotInit,
 
-    // Refers to a capture stored in the current closure
-    Ref(Rc<RefCell<Data>>),
+    // Refers to a capture stored in the current closure,
+    // which is `None` until the variable is assigned
+    Ref(Rc<RefCell<Option<Data>>>),
 }
 
 impl Slot {
     pub fn data(self) -> Data {
         match self {
             Slot::Data(d) => d,
-            Slot::Ref(r) => r.borrow().to_owned(),
+            Slot::Ref(r) => match r.borrow().to_owned() {
+                Some(d) => d,
+                None => unreachable!("expected data on top of stack, found NotInit"),
+            },
             Slot::Frame | Slot::Suspend(_) | Slot::NotInit => {
                 unreachable!("expected data on top of stack, found {:?}", self)
             }
         }
     }
 
-    pub fn reference(self) -> Rc<RefCell<Data>> {
+    pub fn reference(self) -> Rc<RefCell<Option<Data>>> {
         match self {
-            Slot::Data(d) => Rc::new(RefCell::new(d)),
+            Slot::Data(d) => Rc::new(RefCell::new(Some(d))),
             Slot::Ref(r) => r,
-            Slot::Frame | Slot::Suspend(_) | Slot::NotInit => {
+            // a variable captured before it is assigned, e.g. by a recursive function.
+            // It stays uninitialized until assigned,
+            // so loading it too early raises an error, see `Fiber::load_cap`.
+            Slot::NotInit => Rc::new(RefCell::new(None)),
+            Slot::Frame | Slot::Suspend(_) => {
                 unreachable!("expected reference on top of stack, found {:?}", self)
             }
         }
@@ -24,9 +24,17 @@ pub struct Stack {
 impl Stack {
     /// Create a new `Stack` with a single frame.
     pub fn init() -> Stack {
+        Stack::with_capacity(1)
+    }
+
+    /// Create a new `Stack` with a single frame,
+    /// with room for at least `capacity` items before it has to grow.
+    pub fn with_capacity(capacity: usize) -> Stack {
+        let mut stack = Vec::with_capacity(capacity.max(1));
+        stack.push(Tagged::frame());
         Stack {
             frames: vec![0],
-            stack: vec![Tagged::frame()],
+            stack,
         }
     }
 
@@ -124,6 +132,15 @@ impl Stack {
         }
     }
 
+    /// Pops the locals of the current frame off the top of the `Stack`.
+    /// A local may not be initialized if it is only bound
+    /// on a path that was not taken, e.g. in another arm of a match.
+    #[inline]
+    pub fn pop_locals(&mut self, locals: usize) {
+        let len = self.stack.len();
+        self.stack.truncate(len - locals);
+    }
+
     /// Truncates the stack to the last frame.
     /// Returns `true` if the stack can not be unwound further.
     #[inline]
@@ -135,17 +152,10 @@ impl Stack {
     /// returns a copy of the `Slot` of a local variable on the stack.
     pub fn local_slot(&mut self, index: usize) -> Slot {
         let local_index = self.frame_index() + index + 1;
-
-        // a little bit of shuffling involved
-        // I know that something better than this can be done
-        let slot = self.swap(local_index, Tagged::not_init()).slot();
-        let copy = slot.clone();
-        mem::drop(self.swap(local_index, Tagged::new(slot)));
-
-        return copy;
+        self.stack[local_index].copy()
     }
 
-    pub fn local_ref(&mut self, index: usize) -> Rc<RefCell<Data>> {
+    pub fn local_ref(&mut self, index: usize) -> Rc<RefCell<Option<Data>>> {
         let local_index = self.frame_index() + index + 1;
 
         // a little bit of shuffling involved
@@ -163,14 +173,8 @@ impl Stack {
     /// Returns a copy of the `Data` stored in a local variable on the stack.
     pub fn local_data(&mut self, index: usize) -> Data {
         let local_index = self.frame_index() + index + 1;
-
-        // a little bit of shuffling involved
-        // I know that something better than this can be done
-        let data = self.swap(local_index, Tagged::not_init()).slot().data();
-        let copy = data.clone();
-        mem::drop(self.swap(local_index, Tagged::new(Slot::Data(data))));
-
-        return copy;
+        // strings, tuples, and lists are shared, so copying them is cheap
+        self.stack[local_index].copy().data()
     }
 
     /// Sets a local - note that this function doesn't do much.
@@ -195,18 +199,17 @@ impl Stack {
 
         // replace the old value with the new one if on the heap
         let tagged = match slot {
-            // if it's data we just grab it
-            Slot::Data(_) => self.stack.pop().unwrap(),
+            // if it's data or a declared local, we just grab it
+            Slot::Data(_) | Slot::NotInit => self.stack.pop().unwrap(),
             // if it is on the heap, we replace in the old value
             Slot::Ref(ref cell) => {
                 // TODO: check types?
-                mem::drop(cell.replace(self.pop_data()));
+                mem::drop(cell.replace(Some(self.pop_data())));
                 Tagged::new(slot)
             },
             // if it's anything else, we're sad.
             Slot::Frame => unreachable!("Expected data, found frame"),
             Slot::Suspend(_) => unreachable!("Expected data, found *suspended* frame buried deep in the stack, which makes even less sense, because this should be a local variable"),
-            Slot::NotInit => unreachable!("Expected data, found data. Wait! It's unitialized? Maybe this is allowed?"),
         };
 
         mem::drop(self.swap(local_index, tagged))
@@ -215,12 +215,12 @@ mod test {
         let x = "It's kind of a dead giveaway, isn't it?".to_string();
 
         for item in &[s, three, x] {
-            let data = Data::String(item.clone());
+            let data = Data::string(item.clone());
             let wrapped = Tagged::new(Slot::Data(data));
             // println!("{:#b}", u64::from(wrapped));
             match wrapped.copy().data() {
                 Data::String(s) => {
-                    assert_eq!(item, &s)
+                    assert_eq!(item, &*s)
                 }
                 _ => {
                     // println!("{:#b}", u64::from(wrapped));
@@ -247,9 +247,9 @@ mod test {
             Data::Boolean(true),
             Data::Boolean(false),
             Data::Unit,
-            Data::String("Hello, World!".to_string()),
-            Data::String("".to_string()),
-            Data::String("Whoop 😋".to_string()),
+            Data::string("Hello, World!"),
+            Data::string(""),
+            Data::string("Whoop 😋"),
         ];
 
         for test in tests {
@@ -280,7 +280,7 @@ mod test {
         let location = "This is a string".to_string();
 
         // drop dereferenced data
-        let tagged = Tagged::new(Slot::Data(Data::String(location)));
+        let tagged = Tagged::new(Slot::Data(Data::string(location)));
         let pointer = tagged.0 & P_MASK;
         let untagged = tagged.copy().data();
         // println!("-- Casting...");
@@ -296,7 +296,7 @@ mod test {
         let location = "This is a string".to_string();
 
         // drop tagged data
-        let tagged = Tagged::new(Slot::Data(Data::String(location)));
+        let tagged = Tagged::new(Slot::Data(Data::string(location)));
         let pointer = tagged.0 & P_MASK;
         let data = unsafe { Box::from_raw(pointer as *mut Data) };
         // println!("-- Dropping...");
@@ -1,6 +1,10 @@
+#[cfg(feature = "json")]
+use std::collections::BTreeMap;
 use std::fmt;
 
-use crate::common::span::Span;
+#[cfg(feature = "json")]
+use crate::{common::data::Data, compiler::syntax::code_data};
+use crate::{common::span::Span, compiler::syntax::Note};
 
 /// Represents a runtime error, i.e. a traceback
 #[derive(Debug, PartialEq, Eq)]
@@ -8,6 +12,10 @@ pub struct Trace {
     kind: String, // TODO: enum?
     message: String,
     spans: Vec<Span>,
+    /// Supplementary context, rendered beneath the stack trace.
+    notes: Vec<Note>,
+    /// A stable code identifying the kind of error, see `error_codes`.
+    code: Option<&'static str>,
 }
 
 impl Trace {
@@ -17,15 +25,77 @@ impl Trace {
             kind: kind.to_string(),
             message: message.to_string(),
             spans,
+            notes: vec![],
+            code: None,
         }
     }
 
+    /// Adds a note with supplementary context to the error,
+    /// i.e. a specific location with a hint as to what went wrong.
+    pub fn add_note(mut self, note: Note) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    /// Identifies the kind of error with a code from `error_codes`.
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// The code identifying the kind of error, if it has one.
+    pub fn code(&self) -> Option<&'static str> {
+        self.code
+    }
+
+    /// The kind of error, e.g. `"FFI Call"`.
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The message describing what went wrong.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
     /// Used to add context (i.e. function calls) while unwinding the stack.
     pub fn add_context(&mut self, span: Span) {
         self.spans.push(span);
     }
 }
 
+#[cfg(feature = "json")]
+impl Trace {
+    /// Serializes a runtime error as a JSON object, e.g. for an editor:
+    /// ```plain
+    /// {"code": "...", "frames": [...], "kind": "...", "message": "...", "notes": [...], "severity": "error"}
+    /// ```
+    /// The `code` is `null` if the error has no code.
+    /// Frames are ordered most recent call last, as when displayed.
+    /// Frames and notes are serialized as described by `Note::to_data`;
+    /// frames never have a hint.
+    pub fn to_json(&self) -> String {
+        let mut fields = BTreeMap::new();
+        fields.insert("severity".to_string(), Data::string("error"));
+        fields.insert("code".to_string(), code_data(self.code));
+        fields.insert("kind".to_string(), Data::string(self.kind.as_str()));
+        fields.insert("message".to_string(), Data::string(self.message.as_str()));
+        let frames = self
+            .spans
+            .iter()
+            .rev()
+            .map(|span| Note::new(span.clone()).to_data())
+            .collect();
+        fields.insert("frames".to_string(), Data::list(frames));
+        let notes = self.notes.iter().map(Note::to_data).collect();
+        fields.insert("notes".to_string(), Data::list(notes));
+
+        Data::record(fields)
+            .to_json()
+            .expect("diagnostics only contain data that can be serialized")
+    }
+}
+
 impl fmt::Display for Trace {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // TODO: better message?
@@ -35,7 +105,14 @@ impl fmt::Display for Trace {
             fmt::Display::fmt(span, f)?;
         }
 
-        write!(f, "Runtime {} Error: {}", self.kind, self.message)
+        for note in self.notes.iter() {
+            fmt::Display::fmt(note, f)?;
+        }
+
+        match self.code {
+            Some(code) => write!(f, "Runtime {} Error[{}]: {}", self.kind, code, self.message),
+            None => write!(f, "Runtime {} Error: {}", self.kind, self.message),
+        }
     }
 }
 
@@ -45,4 +122,116 @@ mod test {
 
     use super::*;
     use crate::common::source::Source;
+
+    #[test]
+    fn generated() {
+        let source = Source::source("f 1");
+        let trace = Trace::error(
+            "Call",
+            "Expected a function",
+            vec![Span::dummy(), Span::new(&source, 0, 3)],
+        );
+
+        let result = format!("{}", trace);
+        assert!(result.contains("In (generated code)\n"));
+        assert!(result.contains("1 | f 1"));
+    }
+
+    #[test]
+    fn context() {
+        let contents = (1..=10)
+            .map(|n| format!("x{} = {}", n, n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let source = Source::source(&contents);
+        let offset = contents.find("x9").unwrap();
+        let trace = Trace::error(
+            "Call",
+            "Expected a function",
+            vec![Span::new(&source, offset, 2)],
+        );
+
+        let result = format!("{}", trace);
+        assert!(result.contains(" 8 | x8 = 8\n 9 | x9 = 9\n   | ^^\n10 | x10 = 10\n"));
+    }
+
+    #[test]
+    fn note() {
+        let source = Source::source("average (1, 0)");
+        let trace = Trace::error(
+            "FFI Call",
+            "Division by zero",
+            vec![Span::new(&source, 0, 14)],
+        )
+        .add_note(Note::new_with_hint(
+            "while computing the average",
+            &Span::new(&source, 8, 6),
+        ));
+
+        let result = format!("{}", trace);
+        // lines and columns count from one
+        let header = result.find("In ./source:1:9\n").unwrap();
+        let note = result
+            .find("^^^^^^ note: while computing the average")
+            .unwrap();
+        assert!(header < note);
+        let error = result
+            .find("Runtime FFI Call Error: Division by zero")
+            .unwrap();
+        assert!(note < error);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        let source = Source::source("f = x -> x / 0\nf 1");
+        let trace = Trace::error(
+            "FFI Call",
+            "Division by zero",
+            vec![Span::new(&source, 9, 5), Span::new(&source, 15, 3)],
+        )
+        .add_note(Note::new_with_hint(
+            "divided here",
+            &Span::new(&source, 13, 1),
+        ));
+
+        let json = Data::from_json(&trace.to_json()).unwrap();
+        let fields = match json {
+            Data::Record(fields) => fields,
+            other => panic!("expected an object, found {}", other.kind_name()),
+        };
+        assert_eq!(fields["severity"], Data::string("error"));
+        assert_eq!(fields["kind"], Data::string("FFI Call"));
+        assert_eq!(fields["message"], Data::string("Division by zero"));
+
+        let location = |line, start_col, end_col, snippet: &str, hint| {
+            let mut fields = BTreeMap::new();
+            fields.insert("path".to_string(), Data::string("./source"));
+            fields.insert("start_line".to_string(), Data::Integer(line));
+            fields.insert("start_col".to_string(), Data::Integer(start_col));
+            fields.insert("end_line".to_string(), Data::Integer(line));
+            fields.insert("end_col".to_string(), Data::Integer(end_col));
+            fields.insert("snippet".to_string(), Data::string(snippet));
+            fields.insert("hint".to_string(), hint);
+            Data::record(fields)
+        };
+        // most recent call last
+        assert_eq!(
+            fields["frames"],
+            Data::list(vec![
+                location(2, 1, 4, "f 1", Data::Unit),
+                location(1, 10, 15, "f = x -> x / 0", Data::Unit),
+            ])
+        );
+        assert_eq!(
+            fields["notes"],
+            Data::list(vec![location(
+                1,
+                14,
+                15,
+                "f = x -> x / 0",
+                Data::string("divided here")
+            )])
+        );
+    }
 }
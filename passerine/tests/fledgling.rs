@@ -246,3 +246,23 @@ fn snippets(dir: &str) {
 fn test_snippets() {
     snippets("./tests/snippets")
 }
+
+/// The compiler and the VM share the datastructures defined in `passerine-common`,
+/// so what one produces can be used directly by the other, and by embedders.
+#[test]
+fn common_types() {
+    let source: Rc<passerine_common::Source> = passerine_common::Source::source("x = 1.5; (x, 2)");
+    let closure: passerine_common::Closure = compile(source.clone()).unwrap();
+
+    let lambda: &passerine_common::lambda::Lambda = &closure.lambda;
+    let span: passerine_common::Span = lambda.index_span(lambda.code.len() - 1);
+    assert!(Rc::ptr_eq(span.source(), &source));
+
+    let mut fiber = Fiber::init(closure);
+    fiber.run().unwrap();
+    let data: passerine_common::Data = fiber.stack.pop_data();
+    assert_eq!(
+        data,
+        passerine_common::Data::tuple(vec![Data::real(1.5), Data::int(2)])
+    );
+}